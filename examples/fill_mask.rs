@@ -0,0 +1,61 @@
+// Copyright 2019-present, the HuggingFace Inc. team, The Google AI Language Team and Facebook, Inc.
+// Copyright 2019 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate failure;
+extern crate dirs;
+
+use std::path::PathBuf;
+use tch::Device;
+use failure::err_msg;
+use rust_bert::pipelines::fill_mask::{FillMaskModel, FillMaskConfig};
+use rust_bert::pipelines::common::ModelType;
+
+
+fn main() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("distilbert");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+    if !config_path.is_file() | !vocab_path.is_file() | !weights_path.is_file() {
+        return Err(
+            err_msg("Could not find required resources to run example. \
+                          Please run ../utils/download_dependencies_distilbert.py \
+                          in a Python environment with dependencies listed in ../requirements.txt"));
+    }
+
+//    Set-up model
+    let device = Device::cuda_if_available();
+    let fill_mask_config = FillMaskConfig { model_type: ModelType::DistilBert, ..Default::default() };
+    let fill_mask_model = FillMaskModel::new(vocab_path,
+                                             None,
+                                             config_path,
+                                             weights_path,
+                                             fill_mask_config,
+                                             device)?;
+
+//    Define input
+    let input = ["Looks like one [MASK] is missing", "It was a very nice and [MASK] day", "Paris is the [MASK] of [MASK]."];
+
+//    Run model
+    let output = fill_mask_model.predict(&input, 3);
+    for sentence_predictions in output {
+        for mask_prediction in sentence_predictions {
+            println!("{:?}", mask_prediction);
+        }
+    }
+
+    Ok(())
+}
@@ -16,7 +16,7 @@ extern crate dirs;
 use std::path::PathBuf;
 use tch::Device;
 use failure::err_msg;
-use rust_bert::pipelines::ner::NERModel;
+use rust_bert::pipelines::ner::{NERModel, TaggingScheme, ScoreAggregation};
 
 
 fn main() -> failure::Fallible<()> {
@@ -53,5 +53,11 @@ fn main() -> failure::Fallible<()> {
         println!("{:?}", entity);
     }
 
+//    Run model, merging sub-word tokens and consecutive tags into entity spans
+    let output = ner_model.predict_entities(&input, TaggingScheme::IOB1, ScoreAggregation::Average);
+    for entity in output {
+        println!("{:?}", entity);
+    }
+
     Ok(())
 }
\ No newline at end of file
@@ -38,8 +38,11 @@ fn main() -> failure::Fallible<()> {
 //    Set-up Question Answering model
     let device = Device::Cpu;
     let qa_model = QuestionAnsweringModel::new(vocab_path,
+                                               None,
                                                config_path,
-                                               weights_path, device)?;
+                                               weights_path,
+                                               Default::default(),
+                                               device)?;
 
 //    Define input
     let question_1 = String::from("Where does Amy live ?");
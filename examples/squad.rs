@@ -39,8 +39,11 @@ fn main() -> failure::Fallible<()> {
 //    Set-up Question Answering model
     let device = Device::cuda_if_available();
     let qa_model = QuestionAnsweringModel::new(vocab_path,
+                                               None,
                                                config_path,
-                                               weights_path, device)?;
+                                               weights_path,
+                                               Default::default(),
+                                               device)?;
 
 //    Define input
     let mut squad_path = PathBuf::from(env::var("squad_dataset")
@@ -0,0 +1,62 @@
+// Copyright 2019-present, the HuggingFace Inc. team, The Google AI Language Team and Facebook, Inc.
+// Copyright 2019 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate failure;
+extern crate dirs;
+
+use std::path::PathBuf;
+use tch::Device;
+use failure::err_msg;
+use rust_bert::pipelines::token_classification::{TokenClassificationModel, TokenClassificationConfig};
+use rust_bert::pipelines::common::ModelType;
+
+
+fn main() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("bert-ner");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+    if !config_path.is_file() | !vocab_path.is_file() | !weights_path.is_file() {
+        return Err(
+            err_msg("Could not find required resources to run example. \
+                          Please run ../utils/download_dependencies_bert_ner.py \
+                          in a Python environment with dependencies listed in ../requirements.txt"));
+    }
+
+//    Set-up model
+    let device = Device::cuda_if_available();
+    let token_classification_config = TokenClassificationConfig { model_type: ModelType::Bert, lower_case: false, ..Default::default() };
+    let token_classification_model = TokenClassificationModel::new(vocab_path,
+                                                                   None,
+                                                                   config_path,
+                                                                   weights_path,
+                                                                   token_classification_config,
+                                                                   device)?;
+
+//    Define input
+    let input = [
+        "My name is Amy. I live in Paris.",
+        "Paris is a city in France."
+    ];
+
+//    Run model, returning a label for every word
+    let output = token_classification_model.predict(&input, true, true);
+    for token in output {
+        println!("{:?}", token);
+    }
+
+    Ok(())
+}
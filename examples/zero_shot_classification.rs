@@ -0,0 +1,70 @@
+// Copyright 2020 The Facebook AI Research Team Authors
+// Copyright 2020-present, the HuggingFace Inc. team.
+// Copyright 2020 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate failure;
+extern crate dirs;
+
+use std::path::PathBuf;
+use tch::Device;
+use failure::err_msg;
+use rust_bert::pipelines::zero_shot_classification::ZeroShotClassificationModel;
+
+
+fn main() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("bart-large-mnli");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+    if !config_path.is_file() | !vocab_path.is_file() | !merges_path.is_file() | !weights_path.is_file() {
+        return Err(
+            err_msg("Could not find required resources to run example. \
+                          Please run ../utils/download-dependencies_bart_mnli.py \
+                          in a Python environment with dependencies listed in ../requirements.txt"));
+    }
+
+//    Set-up model
+    let device = Device::cuda_if_available();
+    let mut sequence_classification_model = ZeroShotClassificationModel::new(vocab_path,
+                                                                             merges_path,
+                                                                             config_path,
+                                                                             weights_path, device)?;
+
+//    Define input
+    let input_sentence = "Who are you voting for in 2020?";
+    let input_sequence_2 = "The prime minister has announced a stimulus package which was widely criticized by the opposition.";
+    let candidate_labels = &["politics", "public health", "economics", "sports"];
+
+//    Run model
+    let output = sequence_classification_model.predict(&[input_sentence, input_sequence_2],
+                                                       candidate_labels,
+                                                       Some(Box::new(|label: &str| format!("This example is about {}.", label))),
+                                                       128);
+    for label_scores in output {
+        println!("{:?}", label_scores);
+    }
+
+    let output = sequence_classification_model.predict_multilabel(&[input_sentence, input_sequence_2],
+                                                                  candidate_labels,
+                                                                  None,
+                                                                  128);
+    for label_scores in output {
+        println!("{:?}", label_scores);
+    }
+
+    Ok(())
+}
@@ -573,9 +573,9 @@ impl BartForSequenceClassification {
             all_encoder_hidden_states, all_encoder_attentions) =
             self.borrow_mut().base_model.forward_t(Some(input_ids), attention_mask, decoder_input_ids, encoder_outputs, decoder_attention_mask, train);
 
-        let eos_mask = input_ids.eq(self.eos_token_id);
+        let eos_mask = input_ids.eq(self.eos_token_id).unsqueeze(-1).expand_as(&decoder_outputs);
         let sentence_representation = decoder_outputs
-            .index_select(0, &eos_mask)
+            .masked_select(&eos_mask)
             .view((decoder_outputs.size()[0], -1, *decoder_outputs.size().last().unwrap()))
             .select(1, -1);
 
@@ -11,7 +11,12 @@
 //!     - Sentiment Analysis
 //!     - Named Entity Recognition
 //!     - Question-Answering
-//!     - Language Generation.
+//!     - Language Generation
+//!     - Zero-shot classification.
+//!     - Fill mask
+//!     - Sentence embeddings
+//!     - Sequence classification
+//!     - Token classification (e.g. Part-of-Speech tagging)
 //!
 //! More information on these can be found in the [`pipelines` module](./pipelines/index.html)
 //! ```no_run
@@ -29,8 +34,11 @@
 //!
 //! let device = Device::cuda_if_available();
 //! let qa_model = QuestionAnsweringModel::new(vocab_path,
+//!                                            None,
 //!                                            config_path,
-//!                                            weights_path, device)?;
+//!                                            weights_path,
+//!                                            Default::default(),
+//!                                            device)?;
 //!
 //! let question = String::from("Where does Amy live ?");
 //! let context = String::from("Amy lives in Amsterdam");
@@ -24,11 +24,12 @@ use crate::gpt2::Gpt2Config;
 use crate::pipelines::generation::LMHeadModel;
 
 /// # GPT Base model
-/// Base architecture for GPT model. Usually complemented with a task-specific head, such as a language model head. As opposed to GPT2, GPT does not give the possibility to re-use past activations as an input.
+/// Base architecture for GPT model. Usually complemented with a task-specific head, such as a language model head.
 /// It is made of the following blocks:
 /// - `tokens_embed`: `token` embeddings
 /// - `positions_embed`: `position` embeddings
 /// - `h`: Encoder (transformer) made of a vector of layers. Each layer is made of a multi-head attention layer, layer-normalization layers and a MLP made of linear layers.
+/// - `output_past`: flag indicating if the model should return a past state. This can be fed back to the model to avoid re-computing the keys and values of previous positions.
 /// - `output_hidden_states`: flag indicating if the model should return all hidden states (as opposed to only the last layer)
 /// - `output_attentions`: flag indicating if the model should return activation weights
 pub struct OpenAiGptModel {
@@ -36,6 +37,7 @@ pub struct OpenAiGptModel {
     positions_embed: nn::Embedding,
     drop: Dropout,
     h: Vec<Block>,
+    output_past: bool,
     output_hidden_states: bool,
     output_attentions: bool,
 }
@@ -83,11 +85,15 @@ impl OpenAiGptModel {
             Some(value) => value,
             None => false
         };
+        let output_past = match config.output_past {
+            Some(value) => value,
+            None => true
+        };
         let output_hidden_states = match config.output_hidden_states {
             Some(value) => value,
             None => false
         };
-        OpenAiGptModel { tokens_embed, positions_embed, drop, h, output_hidden_states, output_attentions }
+        OpenAiGptModel { tokens_embed, positions_embed, drop, h, output_past, output_hidden_states, output_attentions }
     }
 
     /// Forward pass through the model
@@ -95,6 +101,7 @@ impl OpenAiGptModel {
     /// # Arguments
     ///
     /// * `input_ids` - Optional input tensor of shape (*batch size*, *sequence_length*). If None, pre-computed embeddings must be provided (see `input_embeds`)
+    /// * `layer_past` - Optional vector of length *n_layer* containing the past keys and values of each layer of shape (*2*, *batch size*, *number of heads*, *past_sequence_length*, *hidden size per head*). When provided, these are concatenated with the current input keys and values.
     /// * `attention_mask` - Optional mask of shape (*batch size*, *sequence_length*). Masked position have value 0, non-masked value 1. If None set to 1
     /// * `input_embeds` - Optional pre-computed input embeddings of shape (*batch size*, *sequence_length*, *hidden_size*). If None, input ids must be provided (see `input_ids`)
     /// * `token_type_ids` - Optional token type ids used to indicate the portion of the input the token belongs to. If not None, token type embeddings will be added to the token and position embeddings.
@@ -104,6 +111,7 @@ impl OpenAiGptModel {
     /// # Returns
     ///
     /// * `output` - `Tensor` of shape (*batch size*, *sequence_length*, *hidden_size*) representing the activations of the last hidden state
+    /// * `past` - `Option<Vec<Tensor>>` of length *n_layer* containing the past keys and values of each layer of shape (*2*, *batch size*, *number of heads*, *past_sequence_length*, *hidden size per head*)
     /// * `hidden_states` - `Option<Vec<Tensor>>` of length *num_hidden_layers* with shape (*batch size*, *sequence_length*, *hidden_size*)
     /// * `attentions` - `Option<Vec<Tensor>>` of length *num_hidden_layers* with shape (*batch size*, *sequence_length*, *hidden_size*)
     ///
@@ -124,13 +132,18 @@ impl OpenAiGptModel {
     ///# let gpt_model: OpenAiGptModel = OpenAiGptModel::new(&vs.root(), &config);
     ///  let (batch_size, sequence_length, past_sequence_length) = (64, 128, 56);
     ///  let input_tensor = Tensor::rand(&[batch_size, sequence_length], (Int64, device));
+    ///  let mut past: Vec<Tensor> = Vec::with_capacity(config.n_layer as usize);
+    ///  for _ in 0..config.n_layer as usize {
+    ///    past.push(Tensor::rand(&[2, batch_size, config.n_head, past_sequence_length, config.n_embd / config.n_head], (Double, device)))
+    /// }
     ///  let attention_mask = Tensor::zeros(&[batch_size, sequence_length], (Int64, device));
     ///  let token_type_ids = Tensor::ones(&[batch_size, sequence_length], (Int64, device));
     ///  let position_ids = Tensor::arange(sequence_length, (Int64, device)).expand(&[batch_size, sequence_length], true);
     ///
-    ///  let (output, hidden_states, attentions) = no_grad(|| {
+    ///  let (output, past, hidden_states, attentions) = no_grad(|| {
     ///    gpt_model
     ///         .forward_t(&Some(input_tensor),
+    ///                    &Some(past),
     ///                    &Some(attention_mask),
     ///                    &Some(token_type_ids),
     ///                    &Some(position_ids),
@@ -142,11 +155,12 @@ impl OpenAiGptModel {
     ///
     pub fn forward_t(&self,
                      input_ids: &Option<Tensor>,
+                     layer_past: &Option<Vec<Tensor>>,
                      attention_mask: &Option<Tensor>,
                      token_type_ids: &Option<Tensor>,
                      position_ids: &Option<Tensor>,
                      input_embeds: &Option<Tensor>,
-                     train: bool) -> Result<(Tensor, Option<Vec<Tensor>>, Option<Vec<Tensor>>), &'static str> {
+                     train: bool) -> Result<(Tensor, Option<Vec<Tensor>>, Option<Vec<Tensor>>, Option<Vec<Tensor>>), &'static str> {
         let (input_embeddings, seq_length) = match input_ids {
             Some(input_value) => match input_embeds {
                 Some(_) => { return Err("Only one of input ids or input embeddings may be set"); }
@@ -158,9 +172,21 @@ impl OpenAiGptModel {
             }
         };
 
+        let (layer_past, layer_past_length) = match layer_past {
+            Some(value) => {
+                assert_eq!(value.len(), self.h.len(), "Past activations vector must be of length equal to the number of layers");
+                (value.iter().map(|v| Some(v.copy())).collect::<Vec<Option<Tensor>>>(), value[0].size()[3])
+            }
+            None => {
+                let mut out = Vec::with_capacity(self.h.len());
+                out.resize_with(self.h.len(), || None::<Tensor>);
+                (out, 0)
+            }
+        };
+
         let position_ids = match position_ids {
             Some(value) => value.copy(),
-            None => Tensor::arange(seq_length, (Int64, input_embeddings.device())).unsqueeze(0)
+            None => Tensor::arange1(layer_past_length, seq_length + layer_past_length, (Int64, input_embeddings.device())).unsqueeze(0)
         };
 
         let attention_mask: Option<Tensor> = match attention_mask {
@@ -182,28 +208,33 @@ impl OpenAiGptModel {
             None => Tensor::zeros_like(&position_embeds)
         };
         let mut hidden_state: Tensor = (input_embeddings + position_embeds + token_type_embeds).apply_t(&self.drop, train);
+        let mut all_presents: Option<Vec<Tensor>> = if self.output_past { Some(vec!()) } else { None };
         let mut all_hidden_states: Option<Vec<Tensor>> = if self.output_hidden_states { Some(vec!()) } else { None };
         let mut all_attentions: Option<Vec<Tensor>> = if self.output_attentions { Some(vec!()) } else { None };
 
-        let mut layers = self.h.iter();
+        let mut layer_iter = self.h.iter().zip(layer_past);
         loop {
-            match layers.next() {
-                Some(layer) => {
+            match layer_iter.next() {
+                Some(layer_values) => {
+                    let (layer, past) = layer_values;
                     if let Some(hidden_states) = all_hidden_states.borrow_mut() {
                         hidden_states.push(hidden_state.as_ref().copy());
                     };
 
-                    let temp = layer.forward_t(&hidden_state, &attention_mask, train);
+                    let temp = layer.forward_t(&hidden_state, &past, &attention_mask, train);
                     hidden_state = temp.0;
+                    if let Some(presents) = all_presents.borrow_mut() {
+                        presents.push(temp.1.as_ref().copy());
+                    };
                     if let Some(attentions) = all_attentions.borrow_mut() {
-                        attentions.push(temp.1.as_ref().unwrap().copy());
+                        attentions.push(temp.2.as_ref().unwrap().copy());
                     };
                 }
                 None => break
             };
         };
 
-        Ok((hidden_state, all_hidden_states, all_attentions))
+        Ok((hidden_state, all_presents, all_hidden_states, all_attentions))
     }
 }
 
@@ -254,7 +285,7 @@ impl LMHeadModel for OpenAIGPTLMHeadModel {
     /// # Arguments
     ///
     /// * `input_ids` - Optional input tensor of shape (*batch size*, *sequence_length*). If None, pre-computed embeddings must be provided (see `input_embeds`)
-    /// * `_layer_past` - Unused for GPT
+    /// * `layer_past` - Optional vector of size *n_layer* containing the past keys and values of each layer of shape (*2*, *batch size*, *number of heads*, *past_sequence_length*, *hidden size per head*). When provided, these are concatenated with the current input keys and values.
     /// * `attention_mask` - Optional mask of shape (*batch size*, *sequence_length*). Masked position have value 0, non-masked value 1. If None set to 1
     /// * `input_embeds` - Optional pre-computed input embeddings of shape (*batch size*, *sequence_length*, *hidden_size*). If None, input ids must be provided (see `input_ids`)
     /// * `token_type_ids` - Optional token type ids used to indicate the portion of the input the token belongs to. If not None, token type embeddings will be added to the token and position embeddings.
@@ -268,7 +299,7 @@ impl LMHeadModel for OpenAIGPTLMHeadModel {
     ///
     /// * `output` - `Tensor` of shape (*batch size*, *sequence_length*, *vocab_size*) representing the logits for each vocab item and position
     /// * `encoder_hidden_states` - None
-    /// * `past` - None
+    /// * `past` - `Option<Vec<Tensor>>` of length *n_layer* containing the past keys and values of each layer of shape (*2*, *batch size*, *number of heads*, *past_sequence_length*, *hidden size per head*)
     /// * `hidden_states` - `Option<Vec<Tensor>>` of length *num_hidden_layers* with shape (*batch size*, *sequence_length*, *hidden_size*)
     /// * `attentions` - `Option<Vec<Tensor>>` of length *num_hidden_layers* with shape (*batch size*, *sequence_length*, *hidden_size*)
     ///
@@ -311,7 +342,7 @@ impl LMHeadModel for OpenAIGPTLMHeadModel {
     ///
     fn forward_t(&mut self,
                  input_ids: &Option<Tensor>,
-                 _layer_past: &Option<Vec<Tensor>>,
+                 layer_past: &Option<Vec<Tensor>>,
                  attention_mask: &Option<Tensor>,
                  token_type_ids: &Option<Tensor>,
                  position_ids: &Option<Tensor>,
@@ -320,8 +351,10 @@ impl LMHeadModel for OpenAIGPTLMHeadModel {
                  _decoder_input_ids: &Option<Tensor>,
                  train: bool) -> Result<(Tensor, Option<Tensor>, Option<Vec<Tensor>>, Option<Vec<Tensor>>, Option<Vec<Tensor>>), &'static str> {
         let (output,
+            past,
             all_hidden_states,
             all_attentions) = self.transformer.forward_t(input_ids,
+                                                         layer_past,
                                                          attention_mask,
                                                          token_type_ids,
                                                          position_ids,
@@ -329,6 +362,6 @@ impl LMHeadModel for OpenAIGPTLMHeadModel {
                                                          train)?;
 
         let lm_logits = output.apply(&self.lm_head);
-        Ok((lm_logits, None, None, all_hidden_states, all_attentions))
+        Ok((lm_logits, None, past, all_hidden_states, all_attentions))
     }
 }
\ No newline at end of file
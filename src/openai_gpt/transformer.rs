@@ -35,12 +35,12 @@ impl Block {
         Block { ln_1, attn, ln_2, mlp }
     }
 
-    pub fn forward_t(&self, x: &Tensor, attention_mask: &Option<Tensor>, train: bool)
-                     -> (Tensor, Option<Tensor>) {
-        let (output, _, attentions) = self.attn.forward_t(x, &None, attention_mask, train);
+    pub fn forward_t(&self, x: &Tensor, layer_past: &Option<Tensor>, attention_mask: &Option<Tensor>, train: bool)
+                     -> (Tensor, Tensor, Option<Tensor>) {
+        let (output, present, attentions) = self.attn.forward_t(x, layer_past, attention_mask, train);
         let x = (x + output).apply(&self.ln_1);
         let m = self.mlp.forward_t(&x, train);
         let x = (x + m).apply(&self.ln_2);
-        (x, attentions)
+        (x, present, attentions)
     }
 }
\ No newline at end of file
@@ -0,0 +1,349 @@
+// Copyright 2019-present, the HuggingFace Inc. team, The Google AI Language Team and Facebook, Inc.
+// Copyright 2019 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Common blocks for generic pipelines (e.g. token classification or sequence classification)
+//! Provides Enums holding configuration or tokenization resources that can be used to create
+//! generic pipelines. The model component is defined in the generic pipeline itself as the
+//! pre-processing, forward pass and postprocessing differs between pipelines while basic config and
+//! tokenization objects don't.
+
+use std::path::Path;
+use std::collections::HashMap;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+use rust_tokenizers::{BertTokenizer, RobertaTokenizer, Tokenizer, TokenizedInput, TruncationStrategy, Vocab, BertVocab, RobertaVocab};
+use crate::bert::BertConfig;
+use crate::distilbert::DistilBertConfig;
+use crate::bart::BartConfig;
+use crate::Config;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// # Identifies the type of model
+pub enum ModelType {
+    Bert,
+    DistilBert,
+    Roberta,
+    Bart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// # Character offsets of a token or span in the original input text
+pub struct Offset {
+    /// Index (in characters) of the first character of the span
+    pub begin: usize,
+    /// Index (in characters) following the last character of the span
+    pub end: usize,
+}
+
+/// # Abstraction that holds a model configuration, can be of any of the supported models
+pub enum ConfigOption {
+    /// Bert configuration (also used for RoBERTa models)
+    Bert(BertConfig),
+    /// DistilBert configuration
+    DistilBert(DistilBertConfig),
+    /// Bart configuration
+    Bart(BartConfig),
+}
+
+/// # Abstraction that holds a particular tokenizer, can be of any of the supported models
+pub enum TokenizerOption {
+    /// Bert Tokenizer (also used for DistilBERT models)
+    Bert(BertTokenizer),
+    /// Roberta Tokenizer (also used for BART models)
+    Roberta(RobertaTokenizer),
+}
+
+impl ConfigOption {
+    /// Interface method to load a configuration from file
+    pub fn from_file(model_type: ModelType, path: &Path) -> Self {
+        match model_type {
+            ModelType::Bert | ModelType::Roberta => ConfigOption::Bert(BertConfig::from_file(path)),
+            ModelType::DistilBert => ConfigOption::DistilBert(DistilBertConfig::from_file(path)),
+            ModelType::Bart => ConfigOption::Bart(BartConfig::from_file(path)),
+        }
+    }
+
+    /// Returns the maximum number of positions (`max_position_embeddings`) supported by the model
+    pub fn get_max_len(&self) -> i64 {
+        match self {
+            Self::Bert(config) => config.max_position_embeddings,
+            Self::DistilBert(config) => config.max_position_embeddings,
+            Self::Bart(config) => config.max_position_embeddings,
+        }
+    }
+
+    /// Returns the label mapping (`id2label`) of the configuration. Panics if the configuration does not contain a label mapping.
+    pub fn get_label_mapping(&self) -> HashMap<i64, String> {
+        match self {
+            Self::Bert(config) => config.id2label.as_ref().expect("No label dictionary (id2label) provided in configuration file").clone(),
+            Self::DistilBert(config) => config.id2label
+                .as_ref()
+                .expect("No label dictionary (id2label) provided in configuration file")
+                .iter()
+                .map(|(&key, value)| (key as i64, value.to_owned()))
+                .collect(),
+            Self::Bart(config) => config.id2label.as_ref().expect("No label dictionary (id2label) provided in configuration file").clone(),
+        }
+    }
+}
+
+impl TokenizerOption {
+    /// Interface method to load a tokenizer from file. A merges file is required for RoBERTa and BART models.
+    pub fn from_file(model_type: ModelType, vocab_path: &str, merges_path: Option<&str>, lower_case: bool) -> Self {
+        match model_type {
+            ModelType::Bert | ModelType::DistilBert => TokenizerOption::Bert(BertTokenizer::from_file(vocab_path, lower_case)),
+            ModelType::Roberta | ModelType::Bart => TokenizerOption::Roberta(RobertaTokenizer::from_file(vocab_path,
+                                                                                        merges_path.expect("No merges specified!"),
+                                                                                        lower_case)),
+        }
+    }
+
+    /// Returns the model type
+    pub fn model_type(&self) -> ModelType {
+        match *self {
+            Self::Bert(_) => ModelType::Bert,
+            Self::Roberta(_) => ModelType::Roberta,
+        }
+    }
+
+    /// Interface method
+    pub fn encode_list(&self, text_list: Vec<&str>, max_len: usize, truncation_strategy: &TruncationStrategy, stride: usize) -> Vec<TokenizedInput> {
+        match *self {
+            Self::Bert(ref tokenizer) => tokenizer.encode_list(text_list, max_len, truncation_strategy, stride),
+            Self::Roberta(ref tokenizer) => tokenizer.encode_list(text_list, max_len, truncation_strategy, stride),
+        }
+    }
+
+    /// Interface method
+    pub fn encode_pair_list(&self, text_pair_list: Vec<(&str, &str)>, max_len: usize, truncation_strategy: &TruncationStrategy, stride: usize) -> Vec<TokenizedInput> {
+        match *self {
+            Self::Bert(ref tokenizer) => tokenizer.encode_pair_list(text_pair_list, max_len, truncation_strategy, stride),
+            Self::Roberta(ref tokenizer) => tokenizer.encode_pair_list(text_pair_list, max_len, truncation_strategy, stride),
+        }
+    }
+
+    /// Interface method to tokenization
+    pub fn tokenize(&self, text: &str) -> Vec<String> {
+        match *self {
+            Self::Bert(ref tokenizer) => tokenizer.tokenize(text),
+            Self::Roberta(ref tokenizer) => tokenizer.tokenize(text),
+        }
+    }
+
+    /// Interface method to convert tokens to ids
+    pub fn convert_tokens_to_ids(&self, tokens: &Vec<String>) -> Vec<i64> {
+        match *self {
+            Self::Bert(ref tokenizer) => tokenizer.convert_tokens_to_ids(tokens),
+            Self::Roberta(ref tokenizer) => tokenizer.convert_tokens_to_ids(tokens),
+        }
+    }
+
+    /// Interface method to decoding
+    pub fn decode(&self, token_ids: Vec<i64>, skip_special_tokens: bool, clean_up_tokenization_spaces: bool) -> String {
+        match *self {
+            Self::Bert(ref tokenizer) => tokenizer.decode(token_ids, skip_special_tokens, clean_up_tokenization_spaces),
+            Self::Roberta(ref tokenizer) => tokenizer.decode(token_ids, skip_special_tokens, clean_up_tokenization_spaces),
+        }
+    }
+
+    /// Interface method to build input with special tokens
+    pub fn build_input_with_special_tokens(&self, tokens_1: Vec<i64>, tokens_2: Option<Vec<i64>>) -> (Vec<i64>, Vec<i8>, Vec<i8>) {
+        match *self {
+            Self::Bert(ref tokenizer) => tokenizer.build_input_with_special_tokens(tokens_1, tokens_2),
+            Self::Roberta(ref tokenizer) => tokenizer.build_input_with_special_tokens(tokens_1, tokens_2),
+        }
+    }
+
+    /// Interface method to convert a token id to its string representation
+    pub fn id_to_token(&self, token_id: i64) -> String {
+        match *self {
+            Self::Bert(ref tokenizer) => tokenizer.vocab().id_to_token(&token_id),
+            Self::Roberta(ref tokenizer) => tokenizer.vocab().id_to_token(&token_id),
+        }
+    }
+
+    /// Returns the padding token id
+    pub fn get_pad_id(&self) -> i64 {
+        match *self {
+            Self::Bert(ref tokenizer) => tokenizer.vocab().token_to_id(BertVocab::pad_value()),
+            Self::Roberta(ref tokenizer) => tokenizer.vocab().token_to_id(RobertaVocab::pad_value()),
+        }
+    }
+
+    /// Returns the separator token id
+    pub fn get_sep_id(&self) -> i64 {
+        match *self {
+            Self::Bert(ref tokenizer) => tokenizer.vocab().token_to_id(BertVocab::sep_value()),
+            Self::Roberta(ref tokenizer) => tokenizer.vocab().token_to_id(RobertaVocab::sep_value()),
+        }
+    }
+
+    /// Returns the mask token id
+    pub fn get_mask_id(&self) -> i64 {
+        match *self {
+            Self::Bert(ref tokenizer) => tokenizer.vocab().token_to_id(BertVocab::mask_value()),
+            Self::Roberta(ref tokenizer) => tokenizer.vocab().token_to_id(RobertaVocab::mask_value()),
+        }
+    }
+
+    /// Returns the unknown token string representation (e.g. `[UNK]` for BERT and `<unk>` for RoBERTa)
+    pub fn get_unknown_value(&self) -> &'static str {
+        match *self {
+            Self::Bert(_) => BertVocab::unknown_value(),
+            Self::Roberta(_) => RobertaVocab::unknown_value(),
+        }
+    }
+
+    /// Returns the ids of the special tokens of the vocabulary (e.g. `[CLS]`, `[SEP]` or padding), excluding the unknown token
+    pub fn get_special_ids(&self) -> Vec<i64> {
+        let unknown_value = self.get_unknown_value();
+        let special_values = match *self {
+            Self::Bert(ref tokenizer) => &tokenizer.vocab().special_values,
+            Self::Roberta(ref tokenizer) => &tokenizer.vocab().special_values,
+        };
+        special_values
+            .iter()
+            .filter(|(token, _)| token.as_str() != unknown_value)
+            .map(|(_, &token_id)| token_id)
+            .collect()
+    }
+
+    /// Returns the mask token string representation (e.g. `[MASK]` for BERT and `<mask>` for RoBERTa)
+    pub fn get_mask_value(&self) -> &'static str {
+        match *self {
+            Self::Bert(_) => BertVocab::mask_value(),
+            Self::Roberta(_) => RobertaVocab::mask_value(),
+        }
+    }
+}
+
+/// Aligns the string representation of tokens with the original text, returning the character offsets of each token.
+/// The tokenizers do not keep track of offsets, they are therefore recovered by searching for each token in the text, moving
+/// forward from the end of the previous token. Continuation prefixes (`##` for BERT) and surrounding whitespace are removed
+/// before searching. Unknown tokens are mapped to the next whitespace-delimited word.
+/// For lower casing tokenizers, the text and tokens are normalized as done by the uncased BERT tokenizer (lower casing, NFD decomposition
+/// and removal of combining marks such as accents) before searching, and the offsets are mapped back to the characters of the original text.
+///
+/// # Arguments
+///
+/// * `text` - Original input text
+/// * `tokens` - String representation of the tokens, `None` for special tokens (e.g. `[CLS]`, `[SEP]` or padding)
+/// * `unknown_token` - String representation of the unknown token for the tokenizer used
+/// * `lower_case` - Flag indicating if the tokenizer lower cases the input (and strips accents)
+///
+/// # Returns
+///
+/// * `Vec<Option<Offset>>` offsets of each token (`None` for special tokens or tokens that could not be aligned)
+pub(crate) fn get_token_offsets(text: &str, tokens: &[Option<String>], unknown_token: &str, lower_case: bool) -> Vec<Option<Offset>> {
+    let normalize = |c: char| -> Vec<char> {
+        if lower_case {
+            c.to_lowercase().nfd().filter(|&c| !is_combining_mark(c)).collect()
+        } else {
+            vec!(c)
+        }
+    };
+//    Normalized characters of the text, with the index of the original character each of them comes from
+    let (text_chars, original_indices): (Vec<char>, Vec<usize>) = text
+        .chars()
+        .enumerate()
+        .flat_map(|(index, c)| normalize(c).into_iter().map(move |normalized| (normalized, index)))
+        .unzip();
+    let text_length = text_chars.len();
+//    The span ends at the original character of the next normalized character, so that removed combining marks are included
+    let original_length = text.chars().count();
+    let to_offset = |begin: usize, end: usize| Offset {
+        begin: original_indices[begin],
+        end: if end < text_length { original_indices[end].max(original_indices[end - 1] + 1) } else { original_length },
+    };
+    let mut cursor = 0usize;
+
+    tokens
+        .iter()
+        .map(|token| {
+            let token = token.as_ref()?;
+            while (cursor < text_length) && text_chars[cursor].is_whitespace() {
+                cursor += 1;
+            }
+            if token == unknown_token {
+                let begin = cursor;
+                while (cursor < text_length) && !text_chars[cursor].is_whitespace() {
+                    cursor += 1;
+                }
+                return if cursor > begin { Some(to_offset(begin, cursor)) } else { None };
+            }
+            let piece: Vec<char> = token.trim_start_matches("##").trim().chars().flat_map(normalize).collect();
+            if piece.is_empty() | (piece.len() > text_length) {
+                return None;
+            }
+            let begin = (cursor..=(text_length - piece.len()))
+                .find(|&start| text_chars[start..start + piece.len()] == piece[..])?;
+            cursor = begin + piece.len();
+            Some(to_offset(begin, cursor))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(values: &[&str]) -> Vec<Option<String>> {
+        values.iter().map(|value| Some(value.to_string())).collect()
+    }
+
+    #[test]
+    fn token_offsets_cased() {
+        let offsets = get_token_offsets("Hello Wörld", &tokens(&["Hello", "W", "##ö", "##rld"]), "[UNK]", false);
+
+        assert_eq!(offsets, vec!(Some(Offset { begin: 0, end: 5 }),
+                                 Some(Offset { begin: 6, end: 7 }),
+                                 Some(Offset { begin: 7, end: 8 }),
+                                 Some(Offset { begin: 8, end: 11 })));
+    }
+
+    #[test]
+    fn token_offsets_uncased_strips_accents() {
+        let text = "Café Müller in São Paulo";
+        let offsets = get_token_offsets(text, &tokens(&["cafe", "muller", "in", "sao", "paulo"]), "[UNK]", true);
+
+        let spans = offsets
+            .iter()
+            .map(|offset| offset.map(|offset| text.chars().skip(offset.begin).take(offset.end - offset.begin).collect::<String>()))
+            .collect::<Vec<Option<String>>>();
+        assert_eq!(spans, vec!(Some(String::from("Café")),
+                               Some(String::from("Müller")),
+                               Some(String::from("in")),
+                               Some(String::from("São")),
+                               Some(String::from("Paulo"))));
+    }
+
+    #[test]
+    fn token_offsets_uncased_decomposed_input() {
+//        The accent is a separate combining character in the original text
+        let text = "Cafe\u{301} noir";
+        let offsets = get_token_offsets(text, &tokens(&["cafe", "noir"]), "[UNK]", true);
+
+        assert_eq!(offsets, vec!(Some(Offset { begin: 0, end: 5 }), Some(Offset { begin: 6, end: 10 })));
+    }
+
+    #[test]
+    fn token_offsets_special_and_unknown_tokens() {
+        let offsets = get_token_offsets("a ☃☃ b", &vec!(None, Some(String::from("a")), Some(String::from("[UNK]")), Some(String::from("b")), None),
+                                        "[UNK]", true);
+
+        assert_eq!(offsets, vec!(None,
+                                 Some(Offset { begin: 0, end: 1 }),
+                                 Some(Offset { begin: 2, end: 4 }),
+                                 Some(Offset { begin: 5, end: 6 }),
+                                 None));
+    }
+}
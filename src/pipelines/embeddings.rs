@@ -0,0 +1,392 @@
+// Copyright 2019-present, the HuggingFace Inc. team, The Google AI Language Team and Facebook, Inc.
+// Copyright 2019 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Sentence embeddings pipeline
+//! Extracts a fixed-size vector for each input sentence from the hidden states of a base transformer encoder.
+//! The following encoders are supported (selected using the `model_type` field of the `EmbeddingsConfig`):
+//! - `ModelType::Bert`: `BertModel` (weights stored under the `bert` prefix, e.g. from a `BertForMaskedLM` checkpoint)
+//! - `ModelType::DistilBert`: `DistilBertModel`
+//! - `ModelType::Roberta`: the `BertModel<RobertaEmbeddings>` encoder of `RobertaForMaskedLM` (requires a merges file)
+//!
+//! The token hidden states are pooled into a sentence embedding using one of the `PoolingStrategy`:
+//! - `Cls`: hidden state of the first token (`[CLS]` for BERT, `<s>` for RoBERTa)
+//! - `Mean`: average of the hidden states of the non-padded tokens
+//! - `Max`: element-wise maximum of the hidden states of the non-padded tokens
+//!
+//! The pooled hidden states are by default the output of the last layer. Specific layers may be selected (and averaged) using
+//! the `layers` field of the configuration, in which case the model is loaded with `output_hidden_states` turned on.
+//! The embeddings may optionally be L2-normalized, making the dot product of two embeddings equal to their cosine similarity.
+//!
+//! ```no_run
+//!# use std::path::PathBuf;
+//!# use tch::Device;
+//! use rust_bert::pipelines::embeddings::{EmbeddingsModel, EmbeddingsConfig, PoolingStrategy};
+//! use rust_bert::pipelines::common::ModelType;
+//!# fn main() -> failure::Fallible<()> {
+//!# let mut home: PathBuf = dirs::home_dir().unwrap();
+//!# home.push("rustbert");
+//!# home.push("distilbert");
+//!# let config_path = &home.as_path().join("config.json");
+//!# let vocab_path = &home.as_path().join("vocab.txt");
+//!# let weights_path = &home.as_path().join("model.ot");
+//! let device = Device::cuda_if_available();
+//! let embeddings_config = EmbeddingsConfig {
+//!     model_type: ModelType::DistilBert,
+//!     pooling: PoolingStrategy::Mean,
+//!     normalize: true,
+//!     ..Default::default()
+//! };
+//! let embeddings_model = EmbeddingsModel::new(vocab_path,
+//!                                             None,
+//!                                             config_path,
+//!                                             weights_path,
+//!                                             embeddings_config,
+//!                                             device)?;
+//!
+//! let input = ["This is an example sentence", "Each sentence is converted"];
+//! let output: Vec<Vec<f32>> = embeddings_model.encode(&input);
+//!# Ok(())
+//!# }
+//! ```
+
+use std::path::Path;
+use tch::{Device, Tensor, no_grad};
+use tch::nn::VarStore;
+use tch::kind::Kind::{Int64, Float};
+use rust_tokenizers::TruncationStrategy;
+use crate::pipelines::common::{ModelType, ConfigOption, TokenizerOption};
+use crate::bert::{BertModel, BertEmbeddings};
+use crate::distilbert::DistilBertModel;
+use crate::roberta::RobertaEmbeddings;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// # Pooling strategy used to build a sentence embedding from the token hidden states
+pub enum PoolingStrategy {
+    /// Hidden state of the first token of the sequence
+    Cls,
+    /// Mean of the hidden states of the non-padded tokens
+    Mean,
+    /// Element-wise maximum of the hidden states of the non-padded tokens
+    Max,
+}
+
+/// # Configuration for EmbeddingsModel
+pub struct EmbeddingsConfig {
+    /// Model type used for the encoder (default: `ModelType::Bert`)
+    pub model_type: ModelType,
+    /// Flag indicating if the tokenizer should lower case the input (default: true). Should be set to false for cased and RoBERTa models.
+    pub lower_case: bool,
+    /// Maximum sequence length, longer inputs will be truncated (default: 128)
+    pub max_length: usize,
+    /// Pooling strategy applied to the token hidden states (default: `PoolingStrategy::Mean`)
+    pub pooling: PoolingStrategy,
+    /// Flag indicating if the sentence embeddings should be L2-normalized (default: false)
+    pub normalize: bool,
+    /// Optional hidden layers to average before pooling (default: None, using the output of the last layer).
+    /// Index 0 refers to the embeddings layer output and index *num_hidden_layers* to the last layer. Negative values index from the end (-1 for the last layer).
+    pub layers: Option<Vec<i64>>,
+}
+
+impl Default for EmbeddingsConfig {
+    fn default() -> EmbeddingsConfig {
+        EmbeddingsConfig {
+            model_type: ModelType::Bert,
+            lower_case: true,
+            max_length: 128,
+            pooling: PoolingStrategy::Mean,
+            normalize: false,
+            layers: None,
+        }
+    }
+}
+
+/// # Abstraction that holds one particular base encoder, for any of the supported models
+pub enum EncoderOption {
+    /// Base BERT encoder
+    Bert(BertModel<BertEmbeddings>),
+    /// Base DistilBERT encoder
+    DistilBert(DistilBertModel),
+    /// Base RoBERTa encoder
+    Roberta(BertModel<RobertaEmbeddings>),
+}
+
+impl EncoderOption {
+    /// Instantiate a new base encoder of the supplied type. The variable store paths follow the naming of the masked language
+    /// model checkpoints (`bert`, `distilbert` and `roberta` prefixes).
+    ///
+    /// # Arguments
+    ///
+    /// * `model_type` - `ModelType` indicating the model type to load (must match with the actual data to be loaded)
+    /// * `p` - `tch::nn::Path` path to the model file to load (e.g. model.ot)
+    /// * `config` - A configuration (the model type of the configuration must be compatible with the value for
+    /// `model_type`)
+    ///
+    pub fn new(model_type: ModelType, p: &tch::nn::Path, config: &ConfigOption) -> Self {
+        match model_type {
+            ModelType::Bert => {
+                if let ConfigOption::Bert(config) = config {
+                    EncoderOption::Bert(BertModel::new(&(p / "bert"), config))
+                } else {
+                    panic!("You can only supply a BertConfig for Bert!");
+                }
+            }
+            ModelType::DistilBert => {
+                if let ConfigOption::DistilBert(config) = config {
+                    EncoderOption::DistilBert(DistilBertModel::new(p, config))
+                } else {
+                    panic!("You can only supply a DistilBertConfig for DistilBert!");
+                }
+            }
+            ModelType::Roberta => {
+                if let ConfigOption::Bert(config) = config {
+                    EncoderOption::Roberta(BertModel::new(&(p / "roberta"), config))
+                } else {
+                    panic!("You can only supply a BertConfig for Roberta!");
+                }
+            }
+            ModelType::Bart => panic!("BART is not supported for sentence embeddings!"),
+        }
+    }
+
+    /// Returns the `ModelType` for this EncoderOption
+    pub fn model_type(&self) -> ModelType {
+        match *self {
+            Self::Bert(_) => ModelType::Bert,
+            Self::Roberta(_) => ModelType::Roberta,
+            Self::DistilBert(_) => ModelType::DistilBert,
+        }
+    }
+
+    /// Interface method to forward_t() of the particular models. Returns the last hidden state of shape (*batch size*, *sequence_length*, *hidden_size*)
+    /// and the intermediate hidden states if the configuration has `output_hidden_states` set to true.
+    pub fn forward_t(&self, input_ids: Option<Tensor>, mask: Option<Tensor>, train: bool) -> (Tensor, Option<Vec<Tensor>>) {
+        match *self {
+            Self::Bert(ref model) => {
+                let (output, _, all_hidden_states, _) = model
+                    .forward_t(input_ids, mask, None, None, None, &None, &None, train)
+                    .expect("Error in bert forward_t");
+                (output, all_hidden_states)
+            }
+            Self::DistilBert(ref model) => {
+                let (output, all_hidden_states, _) = model
+                    .forward_t(input_ids, mask, None, train)
+                    .expect("Error in distilbert forward_t");
+                (output, all_hidden_states)
+            }
+            Self::Roberta(ref model) => {
+                let (output, _, all_hidden_states, _) = model
+                    .forward_t(input_ids, mask, None, None, None, &None, &None, train)
+                    .expect("Error in roberta forward_t");
+                (output, all_hidden_states)
+            }
+        }
+    }
+}
+
+/// # EmbeddingsModel to extract sentence embeddings
+pub struct EmbeddingsModel {
+    tokenizer: TokenizerOption,
+    encoder: EncoderOption,
+    max_length: usize,
+    pooling: PoolingStrategy,
+    normalize: bool,
+    layers: Option<Vec<i64>>,
+    var_store: VarStore,
+}
+
+impl EmbeddingsModel {
+    /// Build a new `EmbeddingsModel`
+    ///
+    /// # Arguments
+    ///
+    /// * `vocab_path` - Path to the model vocabulary, expected to have a structure following the [Transformers library](https://github.com/huggingface/transformers) convention
+    /// * `merges_path` - Optional path to the bpe merges, required for RoBERTa models
+    /// * `config_path` - Path to the model configuration, expected to have a structure following the [Transformers library](https://github.com/huggingface/transformers) convention
+    /// * `weights_path` - Path to the model weight files. These need to be converted form the `.bin` to `.ot` format using the utility script provided.
+    /// * `embeddings_config` - `EmbeddingsConfig` defining the backend architecture, pooling strategy and layer selection
+    /// * `device` - Device to run the model on, e.g. `Device::Cpu` or `Device::Cuda(0)`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    /// use tch::Device;
+    /// use std::path::{Path, PathBuf};
+    /// use rust_bert::pipelines::embeddings::{EmbeddingsModel, EmbeddingsConfig, PoolingStrategy};
+    ///
+    /// let mut home: PathBuf = dirs::home_dir().unwrap();
+    /// let config_path = &home.as_path().join("config.json");
+    /// let vocab_path = &home.as_path().join("vocab.txt");
+    /// let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::Cpu;
+    /// let embeddings_config = EmbeddingsConfig { pooling: PoolingStrategy::Cls, layers: Some(vec!(-1, -2, -3, -4)), ..Default::default() };
+    /// let embeddings_model =  EmbeddingsModel::new(vocab_path,
+    ///                                              None,
+    ///                                              config_path,
+    ///                                              weights_path,
+    ///                                              embeddings_config,
+    ///                                              device)?;
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn new(vocab_path: &Path, merges_path: Option<&Path>, config_path: &Path, weights_path: &Path,
+               embeddings_config: EmbeddingsConfig, device: Device)
+               -> failure::Fallible<EmbeddingsModel> {
+        let model_type = embeddings_config.model_type;
+        let tokenizer = TokenizerOption::from_file(model_type,
+                                                   vocab_path.to_str().unwrap(),
+                                                   merges_path.map(|path| path.to_str().unwrap()),
+                                                   embeddings_config.lower_case);
+        let mut config = ConfigOption::from_file(model_type, config_path);
+        if embeddings_config.layers.is_some() {
+            match config {
+                ConfigOption::Bert(ref mut config) => config.output_hidden_states = Some(true),
+                ConfigOption::DistilBert(ref mut config) => config.output_hidden_states = true,
+                ConfigOption::Bart(ref mut config) => config.output_hidden_states = Some(true),
+            }
+        }
+        let mut var_store = VarStore::new(device);
+        let encoder = EncoderOption::new(model_type, &var_store.root(), &config);
+        var_store.load(weights_path)?;
+        Ok(EmbeddingsModel {
+            tokenizer,
+            encoder,
+            max_length: embeddings_config.max_length,
+            pooling: embeddings_config.pooling,
+            normalize: embeddings_config.normalize,
+            layers: embeddings_config.layers,
+            var_store,
+        })
+    }
+
+    fn prepare_for_model(&self, input: Vec<&str>) -> Tensor {
+        let tokenized_input = self.tokenizer.encode_list(input,
+                                                         self.max_length,
+                                                         &TruncationStrategy::LongestFirst,
+                                                         0);
+        let max_len = tokenized_input.iter().map(|input| input.token_ids.len()).max().unwrap();
+        let pad_id = self.tokenizer.get_pad_id();
+        let tokenized_input = tokenized_input
+            .into_iter()
+            .map(|input| input.token_ids)
+            .map(|mut input| {
+                input.extend(vec![pad_id; max_len - input.len()]);
+                input
+            })
+            .map(|input| Tensor::of_slice(&(input)))
+            .collect::<Vec<_>>();
+        Tensor::stack(tokenized_input.as_slice(), 0).to(self.var_store.device())
+    }
+
+    /// Average of the selected hidden layers. The list of hidden states returned by the encoders contains the input of each layer,
+    /// the output of the last layer is therefore appended to get all *num_hidden_layers + 1* hidden states.
+    fn select_layers(&self, output: Tensor, all_hidden_states: Option<Vec<Tensor>>) -> Tensor {
+        match &self.layers {
+            Some(layers) => {
+                let mut hidden_states = all_hidden_states.expect("Hidden states not returned by the model");
+                hidden_states.push(output);
+                let num_hidden_states = hidden_states.len() as i64;
+                let selected_hidden_states = layers
+                    .iter()
+                    .map(|&layer| {
+                        let layer_index = if layer < 0 { num_hidden_states + layer } else { layer };
+                        assert!((layer_index >= 0) & (layer_index < num_hidden_states),
+                                "Layer index {} out of range for a model with {} hidden states", layer, num_hidden_states);
+                        hidden_states[layer_index as usize].shallow_clone()
+                    })
+                    .collect::<Vec<Tensor>>();
+                Tensor::stack(selected_hidden_states.as_slice(), 0).mean1(&[0], false, Float)
+            }
+            None => output
+        }
+    }
+
+    fn pool(&self, hidden_states: &Tensor, attention_mask: &Tensor) -> Tensor {
+        match self.pooling {
+            PoolingStrategy::Cls => hidden_states.select(1, 0),
+            PoolingStrategy::Mean => {
+                let mask = attention_mask.unsqueeze(-1).to_kind(Float);
+                let summed_hidden_states = (hidden_states * &mask).sum1(&[1], false, Float);
+                let token_counts = mask.sum1(&[1], false, Float).clamp_min(1e-9);
+                summed_hidden_states / token_counts
+            }
+            PoolingStrategy::Max => {
+                let padding_mask = attention_mask.eq(0).unsqueeze(-1);
+                hidden_states
+                    .masked_fill(&padding_mask, std::f64::NEG_INFINITY)
+                    .max2(1, false).0
+            }
+        }
+    }
+
+    /// Extracts sentence embeddings for a set of input sentences
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - `&[&str]` Array of texts to encode.
+    ///
+    /// # Returns
+    /// * `Vec<Vec<f32>>` One embedding vector of size *hidden_size* for each input
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    ///# use tch::Device;
+    ///# use std::path::{Path, PathBuf};
+    ///# use rust_bert::pipelines::embeddings::EmbeddingsModel;
+    ///#
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    ///# let device = Device::Cpu;
+    /// let embeddings_model =  EmbeddingsModel::new(vocab_path,
+    ///                                              None,
+    ///                                              config_path,
+    ///                                              weights_path,
+    ///                                              Default::default(),
+    ///                                              device)?;
+    /// let input = ["This is an example sentence", "Each sentence is converted"];
+    /// let output = embeddings_model.encode(&input);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn encode(&self, input: &[&str]) -> Vec<Vec<f32>> {
+        let input_tensor = self.prepare_for_model(input.to_vec());
+        let attention_mask = input_tensor.ne(self.tokenizer.get_pad_id()).to_kind(Int64);
+
+        let embeddings = no_grad(|| {
+            let (output, all_hidden_states) = self.encoder.forward_t(Some(input_tensor), Some(attention_mask.shallow_clone()), false);
+            let hidden_states = self.select_layers(output, all_hidden_states);
+            let embeddings = self.pool(&hidden_states, &attention_mask);
+            let embeddings = if self.normalize {
+                let norm = (&embeddings * &embeddings).sum1(&[-1], true, Float).sqrt().clamp_min(1e-12);
+                embeddings / norm
+            } else {
+                embeddings
+            };
+            embeddings.to_kind(Float).detach().to(Device::Cpu)
+        });
+
+        (0..embeddings.size()[0])
+            .map(|sentence_idx| embeddings
+                .get(sentence_idx)
+                .iter::<f64>()
+                .unwrap()
+                .map(|value| value as f32)
+                .collect::<Vec<f32>>())
+            .collect()
+    }
+}
@@ -0,0 +1,363 @@
+// Copyright 2019-present, the HuggingFace Inc. team, The Google AI Language Team and Facebook, Inc.
+// Copyright 2019 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Fill-mask pipeline
+//! Predicts the most likely tokens for each masked position of the input sentences. The masked positions are identified using the tokenizer mask token
+//! (`[MASK]` for BERT and DistilBERT, `<mask>` for RoBERTa). Several masks may be provided in a single input.
+//! The backend architecture is selected using the `model_type` field of the `FillMaskConfig`:
+//! - `ModelType::Bert`: `BertForMaskedLM`
+//! - `ModelType::DistilBert`: `DistilBertModelMaskedLM`
+//! - `ModelType::Roberta`: `RobertaForMaskedLM` (requires a merges file)
+//!
+//! All resources for these models can be downloaded using the Python utility scripts included in this repository
+//! (e.g. python /utils/download-dependencies_distilbert.py, with the dependencies downloaded under ~/rustbert/distilbert)
+//!
+//! ```no_run
+//!# use std::path::PathBuf;
+//!# use tch::Device;
+//! use rust_bert::pipelines::fill_mask::{FillMaskModel, FillMaskConfig};
+//! use rust_bert::pipelines::common::ModelType;
+//!# fn main() -> failure::Fallible<()> {
+//!# let mut home: PathBuf = dirs::home_dir().unwrap();
+//!# home.push("rustbert");
+//!# home.push("distilbert");
+//!# let config_path = &home.as_path().join("config.json");
+//!# let vocab_path = &home.as_path().join("vocab.txt");
+//!# let weights_path = &home.as_path().join("model.ot");
+//! let device = Device::cuda_if_available();
+//! let fill_mask_config = FillMaskConfig { model_type: ModelType::DistilBert, ..Default::default() };
+//! let fill_mask_model = FillMaskModel::new(vocab_path,
+//!                                          None,
+//!                                          config_path,
+//!                                          weights_path,
+//!                                          fill_mask_config,
+//!                                          device)?;
+//!
+//! let input = ["Looks like one [MASK] is missing", "It was a very nice and [MASK] day"];
+//! let output = fill_mask_model.predict(&input, 3);
+//!# Ok(())
+//!# }
+//! ```
+//!
+//! Output (top candidate only): \
+//! ```no_run
+//!# use rust_bert::pipelines::fill_mask::{MaskPrediction, MaskedToken};
+//!# let output =
+//! [
+//!     [MaskPrediction { position: 4, candidates: vec![MaskedToken { token: String::from("person"), id: 2711, score: 0.0996, sequence: String::from("looks like one person is missing") }] }],
+//!     [MaskPrediction { position: 7, candidates: vec![MaskedToken { token: String::from("sunny"), id: 11559, score: 0.2310, sequence: String::from("it was a very nice and sunny day") }] }],
+//! ]
+//!# ;
+//! ```
+
+use std::path::Path;
+use tch::{Device, Tensor, no_grad};
+use tch::nn::VarStore;
+use tch::kind::Kind::{Int64, Float};
+use rust_tokenizers::TruncationStrategy;
+use crate::pipelines::common::{ModelType, ConfigOption, TokenizerOption};
+use crate::bert::BertForMaskedLM;
+use crate::distilbert::DistilBertModelMaskedLM;
+use crate::roberta::RobertaForMaskedLM;
+
+/// # Configuration for FillMaskModel
+pub struct FillMaskConfig {
+    /// Model type used for the masked language model (default: `ModelType::Bert`)
+    pub model_type: ModelType,
+    /// Flag indicating if the tokenizer should lower case the input (default: true). Should be set to false for cased and RoBERTa models.
+    pub lower_case: bool,
+    /// Maximum sequence length, longer inputs will be truncated (default: 128)
+    pub max_length: usize,
+}
+
+impl Default for FillMaskConfig {
+    fn default() -> FillMaskConfig {
+        FillMaskConfig {
+            model_type: ModelType::Bert,
+            lower_case: true,
+            max_length: 128,
+        }
+    }
+}
+
+/// # Abstraction that holds one particular masked language model, for any of the supported models
+pub enum MaskedLMOption {
+    /// Masked language model based on BERT
+    Bert(BertForMaskedLM),
+    /// Masked language model based on DistilBERT
+    DistilBert(DistilBertModelMaskedLM),
+    /// Masked language model based on RoBERTa
+    Roberta(RobertaForMaskedLM),
+}
+
+impl MaskedLMOption {
+    /// Instantiate a new masked language model of the supplied type.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_type` - `ModelType` indicating the model type to load (must match with the actual data to be loaded)
+    /// * `p` - `tch::nn::Path` path to the model file to load (e.g. model.ot)
+    /// * `config` - A configuration (the model type of the configuration must be compatible with the value for
+    /// `model_type`)
+    ///
+    pub fn new(model_type: ModelType, p: &tch::nn::Path, config: &ConfigOption) -> Self {
+        match model_type {
+            ModelType::Bert => {
+                if let ConfigOption::Bert(config) = config {
+                    MaskedLMOption::Bert(BertForMaskedLM::new(p, config))
+                } else {
+                    panic!("You can only supply a BertConfig for Bert!");
+                }
+            }
+            ModelType::DistilBert => {
+                if let ConfigOption::DistilBert(config) = config {
+                    MaskedLMOption::DistilBert(DistilBertModelMaskedLM::new(p, config))
+                } else {
+                    panic!("You can only supply a DistilBertConfig for DistilBert!");
+                }
+            }
+            ModelType::Roberta => {
+                if let ConfigOption::Bert(config) = config {
+                    MaskedLMOption::Roberta(RobertaForMaskedLM::new(p, config))
+                } else {
+                    panic!("You can only supply a BertConfig for Roberta!");
+                }
+            }
+            ModelType::Bart => panic!("BART is not supported for fill-mask!"),
+        }
+    }
+
+    /// Returns the `ModelType` for this MaskedLMOption
+    pub fn model_type(&self) -> ModelType {
+        match *self {
+            Self::Bert(_) => ModelType::Bert,
+            Self::Roberta(_) => ModelType::Roberta,
+            Self::DistilBert(_) => ModelType::DistilBert,
+        }
+    }
+
+    /// Interface method to forward_t() of the particular models. Returns the vocabulary logits of shape (*batch size*, *sequence_length*, *vocab_size*)
+    pub fn forward_t(&self, input_ids: Option<Tensor>, mask: Option<Tensor>, train: bool) -> Tensor {
+        match *self {
+            Self::Bert(ref model) => model.forward_t(input_ids, mask, None, None, None, &None, &None, train).0,
+            Self::DistilBert(ref model) => model.forward_t(input_ids, mask, None, train).expect("Error in distilbert forward_t").0,
+            Self::Roberta(ref model) => model.forward_t(input_ids, mask, None, None, None, &None, &None, train).0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// # Candidate token for a masked position generated by a `FillMaskModel`
+pub struct MaskedToken {
+    /// String representation of the candidate token
+    pub token: String,
+    /// Token id in the vocabulary
+    pub id: i64,
+    /// Probability of the candidate token
+    pub score: f64,
+    /// Input sequence completed with the candidate token (other masked positions are filled with their most likely token)
+    pub sequence: String,
+}
+
+#[derive(Debug, Clone)]
+/// # Predictions for a single masked position
+pub struct MaskPrediction {
+    /// Position of the masked token in the tokenized input (including special tokens)
+    pub position: usize,
+    /// Top-k candidates for the masked position, sorted by decreasing score
+    pub candidates: Vec<MaskedToken>,
+}
+
+/// # FillMaskModel to predict masked tokens
+pub struct FillMaskModel {
+    tokenizer: TokenizerOption,
+    masked_lm: MaskedLMOption,
+    max_length: usize,
+    var_store: VarStore,
+}
+
+impl FillMaskModel {
+    /// Build a new `FillMaskModel`
+    ///
+    /// # Arguments
+    ///
+    /// * `vocab_path` - Path to the model vocabulary, expected to have a structure following the [Transformers library](https://github.com/huggingface/transformers) convention
+    /// * `merges_path` - Optional path to the bpe merges, required for RoBERTa models
+    /// * `config_path` - Path to the model configuration, expected to have a structure following the [Transformers library](https://github.com/huggingface/transformers) convention
+    /// * `weights_path` - Path to the model weight files. These need to be converted form the `.bin` to `.ot` format using the utility script provided.
+    /// * `fill_mask_config` - `FillMaskConfig` defining the backend architecture and tokenization settings
+    /// * `device` - Device to run the model on, e.g. `Device::Cpu` or `Device::Cuda(0)`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    /// use tch::Device;
+    /// use std::path::{Path, PathBuf};
+    /// use rust_bert::pipelines::fill_mask::{FillMaskModel, FillMaskConfig};
+    /// use rust_bert::pipelines::common::ModelType;
+    ///
+    /// let mut home: PathBuf = dirs::home_dir().unwrap();
+    /// let config_path = &home.as_path().join("config.json");
+    /// let vocab_path = &home.as_path().join("vocab.txt");
+    /// let merges_path = &home.as_path().join("merges.txt");
+    /// let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::Cpu;
+    /// let fill_mask_config = FillMaskConfig { model_type: ModelType::Roberta, lower_case: false, ..Default::default() };
+    /// let fill_mask_model =  FillMaskModel::new(vocab_path,
+    ///                                           Some(merges_path),
+    ///                                           config_path,
+    ///                                           weights_path,
+    ///                                           fill_mask_config,
+    ///                                           device)?;
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn new(vocab_path: &Path, merges_path: Option<&Path>, config_path: &Path, weights_path: &Path,
+               fill_mask_config: FillMaskConfig, device: Device)
+               -> failure::Fallible<FillMaskModel> {
+        let model_type = fill_mask_config.model_type;
+        let tokenizer = TokenizerOption::from_file(model_type,
+                                                   vocab_path.to_str().unwrap(),
+                                                   merges_path.map(|path| path.to_str().unwrap()),
+                                                   fill_mask_config.lower_case);
+        let mut var_store = VarStore::new(device);
+        let config = ConfigOption::from_file(model_type, config_path);
+        let masked_lm = MaskedLMOption::new(model_type, &var_store.root(), &config);
+        var_store.load(weights_path)?;
+        Ok(FillMaskModel { tokenizer, masked_lm, max_length: fill_mask_config.max_length, var_store })
+    }
+
+    fn prepare_for_model(&self, input: Vec<&str>) -> Vec<Vec<i64>> {
+        let tokenized_input = self.tokenizer.encode_list(input,
+                                                         self.max_length,
+                                                         &TruncationStrategy::LongestFirst,
+                                                         0);
+        let max_len = tokenized_input.iter().map(|input| input.token_ids.len()).max().unwrap();
+        let pad_id = self.tokenizer.get_pad_id();
+        tokenized_input
+            .into_iter()
+            .map(|input| input.token_ids)
+            .map(|mut input| {
+                input.extend(vec![pad_id; max_len - input.len()]);
+                input
+            })
+            .collect::<Vec<_>>()
+    }
+
+    /// Predict the masked tokens of a set of input sentences
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - `&[&str]` Array of texts containing one or more mask tokens (`[MASK]` for BERT and DistilBERT, `<mask>` for RoBERTa).
+    /// * `top_k` - Number of candidates to return for each masked position.
+    ///
+    /// # Returns
+    /// * `Vec<Vec<MaskPrediction>>` For each input, vector of predictions for each masked position (in the order they appear in the input).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    ///# use tch::Device;
+    ///# use std::path::{Path, PathBuf};
+    ///# use rust_bert::pipelines::fill_mask::FillMaskModel;
+    ///#
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    ///# let device = Device::Cpu;
+    /// let fill_mask_model =  FillMaskModel::new(vocab_path,
+    ///                                           None,
+    ///                                           config_path,
+    ///                                           weights_path,
+    ///                                           Default::default(),
+    ///                                           device)?;
+    /// let input = ["Paris is the [MASK] of [MASK]."];
+    /// let output = fill_mask_model.predict(&input, 5);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn predict(&self, input: &[&str], top_k: usize) -> Vec<Vec<MaskPrediction>> {
+        let token_ids = self.prepare_for_model(input.to_vec());
+        let input_tensor = Tensor::stack(&token_ids
+            .iter()
+            .map(|input| Tensor::of_slice(input))
+            .collect::<Vec<_>>(), 0).to(self.var_store.device());
+        let attention_mask = input_tensor.ne(self.tokenizer.get_pad_id()).to_kind(Int64);
+
+        let output = no_grad(|| {
+            self.masked_lm
+                .forward_t(Some(input_tensor), Some(attention_mask), false)
+                .softmax(-1, Float)
+                .detach()
+                .to(Device::Cpu)
+        });
+
+        let mask_id = self.tokenizer.get_mask_id();
+        let pad_id = self.tokenizer.get_pad_id();
+        let mut predictions: Vec<Vec<MaskPrediction>> = Vec::with_capacity(token_ids.len());
+        for (sentence_idx, sentence_token_ids) in token_ids.iter().enumerate() {
+            let mask_positions: Vec<usize> = sentence_token_ids
+                .iter()
+                .enumerate()
+                .filter(|(_, &token_id)| token_id == mask_id)
+                .map(|(position, _)| position)
+                .collect();
+
+            let mut candidates: Vec<(usize, Vec<(i64, f64)>)> = Vec::with_capacity(mask_positions.len());
+            for &position in mask_positions.iter() {
+                let (scores, indices) = output
+                    .get(sentence_idx as i64)
+                    .get(position as i64)
+                    .topk(top_k as i64, -1, true, true);
+                let position_candidates = indices
+                    .iter::<i64>()
+                    .unwrap()
+                    .zip(scores.iter::<f64>().unwrap())
+                    .collect::<Vec<(i64, f64)>>();
+                candidates.push((position, position_candidates));
+            }
+
+//            Other masked positions are filled with their most likely token when building the completed sequence
+            let mut best_token_ids = sentence_token_ids
+                .iter()
+                .filter(|&&token_id| token_id != pad_id)
+                .cloned()
+                .collect::<Vec<i64>>();
+            for (position, position_candidates) in candidates.iter() {
+                best_token_ids[*position] = position_candidates[0].0;
+            }
+
+            let mut sentence_predictions: Vec<MaskPrediction> = Vec::with_capacity(candidates.len());
+            for (position, position_candidates) in candidates.into_iter() {
+                let mut completed_token_ids = best_token_ids.clone();
+                let mut masked_tokens: Vec<MaskedToken> = Vec::with_capacity(position_candidates.len());
+                for (token_id, score) in position_candidates {
+                    completed_token_ids[position] = token_id;
+                    masked_tokens.push(MaskedToken {
+                        token: self.tokenizer.decode(vec!(token_id), true, true).trim().to_owned(),
+                        id: token_id,
+                        score,
+                        sequence: self.tokenizer.decode(completed_token_ids.clone(), true, true),
+                    });
+                }
+                sentence_predictions.push(MaskPrediction { position, candidates: masked_tokens });
+            }
+            predictions.push(sentence_predictions);
+        }
+        predictions
+    }
+}
@@ -14,9 +14,23 @@
 
 //! # Natural Language Generation pipeline
 //! Generate language based on a prompt. GPT2 and GPT available as base models.
-//! Include techniques such as beam search, top-k and nucleus sampling, temperature setting and repetition penalty.
+//! Include techniques such as beam search, diverse (group) beam search, top-k, nucleus, locally typical, min-p, epsilon and eta sampling,
+//! temperature setting and repetition penalty.
+//! Tokens can be streamed as soon as they are generated using `generate_stream`, which also allows the consumer to stop the generation early.
+//! The scores of the next token candidates can be modified by attaching an ordered list of `LogitsProcessor` to the generator
+//! (e.g. frequency and presence penalties, logit bias or banned tokens).
+//! Generation can be constrained by banning token sequences (`bad_words_ids`), forcing words or phrases to appear with beam search (`force_words_ids`)
+//! or restricting the tokens allowed at each step given the prefix generated so far (`prefix_allowed_tokens`).
+//! The generated text can also be constrained to match a regular expression or a JSON schema (see the `grammar` module).
+//! The generated token ids, their log-probabilities, the sequence scores and finish reasons are available through `generate_with_details`.
+//! Sampling is reproducible when a `seed` is set in the configuration or passed to `generate_with_seed`.
+//! GPT2 generation can be accelerated by speculative decoding with a smaller draft model (`GPT2Generator::generate_speculative`).
+//! Concurrent generation requests can be served with a `ContinuousBatchingScheduler`, which admits and retires requests at each decoding step.
+//! The keys and values of a prompt prefix shared by several generations can be computed once with `cache_prefix` and reused with `generate_with_prefix`, the maximum length being measured from the end of the prefix.
+//! The generation of each sequence can be ended by stop sequences, a maximum number of new tokens, a time budget or custom `StoppingCriteria`.
 //! Supports batch generation of sentences from several prompts. Sequences will be left-padded with the model's padding token if present, the unknown token otherwise.
-//! This may impact the results and it is recommended to submit prompts of similar length for best results.
+//! Padded positions are masked, excluded from the position ids and not counted in the maximum length of greedy decoding and sampling,
+//! so that greedy decoding of a batch returns the same sequences as the generation of each prompt alone.
 //! All resources for this model can be downloaded using the Python utility script included in this repository.
 //! 1. Set-up a Python virtual environment and install dependencies (in ./requirements.txt)
 //! 2. Run the conversion script python /utils/download-dependencies_gpt2.py (or /utils/download-dependencies_openaigpt.py)
@@ -69,16 +83,22 @@
 
 
 use tch::{Tensor, Device, nn, no_grad};
+use tch::kind::Kind::{Int64, Float, Bool};
+use std::collections::{HashMap, VecDeque};
+use std::cmp::{min, max};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use std::marker::PhantomData;
 use rust_tokenizers::{Tokenizer, OpenAiGptTokenizer, OpenAiGptVocab, Vocab, Gpt2Tokenizer, Gpt2Vocab, RobertaTokenizer, RobertaVocab, TruncationStrategy};
 use std::path::Path;
-use tch::kind::Kind::Int64;
 use self::ordered_float::OrderedFloat;
 use itertools::Itertools;
 use crate::openai_gpt::OpenAIGPTLMHeadModel;
 use crate::gpt2::{Gpt2Config, GPT2LMHeadModel};
 use crate::Config;
-use crate::pipelines::generation::private_generation_utils::PrivateLanguageGenerator;
+use crate::pipelines::generation::private_generation_utils::{PrivateLanguageGenerator, TokenStreamer, SequenceDetails, SeededRng, get_position_ids, sample};
 use crate::bart::{BartConfig, BartForConditionalGeneration};
+use crate::pipelines::grammar::{ByteLevelVocab, GrammarProcessor};
 
 extern crate ordered_float;
 
@@ -86,7 +106,7 @@ extern crate ordered_float;
 pub struct GenerateConfig {
     /// Minimum sequence length (default: 0)
     pub min_length: u64,
-    /// Maximum sequence length (default: 20)
+    /// Maximum sequence length (default: 20). The left padding of the shorter prompts of a batch is not counted for greedy decoding and sampling, but is counted for beam search.
     pub max_length: u64,
     /// Sampling flag. If true, will perform top-k and/or nucleus sampling on generated tokens, otherwise greedy (deterministic) decoding (default: true)
     pub do_sample: bool,
@@ -100,6 +120,18 @@ pub struct GenerateConfig {
     pub top_k: u64,
     /// Top_p value for [Nucleus sampling, Holtzman et al.](http://arxiv.org/abs/1904.09751). Keep top tokens until cumulative probability reaches top_p (default: 0.9)
     pub top_p: f64,
+    /// Probability mass for [locally typical sampling, Meister et al.](https://arxiv.org/abs/2202.00666). Keep the tokens whose information content is closest
+    /// to the entropy of the distribution until their cumulative probability reaches typical_p. Values lower than 1 will enable the feature (default: 1.0)
+    pub typical_p: f64,
+    /// Min-p sampling threshold. Keep the tokens whose probability is at least min_p times the probability of the most likely token.
+    /// Values higher than 0 will enable the feature (default: 0.0)
+    pub min_p: f64,
+    /// Epsilon sampling threshold ([Hewitt et al.](https://arxiv.org/abs/2210.15191)). Keep the tokens whose probability is at least epsilon_cutoff.
+    /// Values higher than 0 will enable the feature (default: 0.0)
+    pub epsilon_cutoff: f64,
+    /// Eta sampling threshold ([Hewitt et al.](https://arxiv.org/abs/2210.15191)). Keep the tokens whose probability is at least
+    /// min(eta_cutoff, sqrt(eta_cutoff) * exp(-entropy)). Values higher than 0 will enable the feature (default: 0.0)
+    pub eta_cutoff: f64,
     /// Repetition penalty (mostly useful for CTRL decoders). Values higher than 1 will penalize tokens that have been already generated. (default: 1.0)
     pub repetition_penalty: f64,
     /// Exponential penalty based on the length of the hypotheses generated (default: 1.0)
@@ -108,6 +140,29 @@ pub struct GenerateConfig {
     pub no_repeat_ngram_size: u64,
     /// Number of sequences to return for each prompt text (default: 1)
     pub num_return_sequences: u64,
+    /// Number of groups the beams are split into for diverse beam search ([Vijayakumar et al.](https://arxiv.org/abs/1610.02424)). Must divide `num_beams`.
+    /// Values higher than 1 turn on this feature, which does not support sampling (default: 1)
+    pub num_beam_groups: u64,
+    /// Penalty subtracted from the score of a token for each time it was selected by the previous beam groups at the same step.
+    /// Only used for diverse beam search (default: 0.0)
+    pub diversity_penalty: f64,
+    /// Token id sequences that cannot be generated. The last token of a sequence is banned when the preceding tokens were just generated (default: None)
+    pub bad_words_ids: Option<Vec<Vec<i64>>>,
+    /// Words or phrases (as token id sequences) that must appear in the generated sequences. Each constraint is a list of alternatives, one of which must be generated.
+    /// Requires beam search without sampling. If the constraints cannot be satisfied within `max_length`, unconstrained hypotheses are returned (default: None)
+    pub force_words_ids: Option<Vec<Vec<Vec<i64>>>>,
+    /// Callback restricting the tokens allowed at each step. It is called with the index of the prompt and the tokens generated so far (excluding the prompt)
+    /// and returns the allowed token ids, which must not be empty (default: None)
+    pub prefix_allowed_tokens: Option<Rc<dyn Fn(usize, &[i64]) -> Vec<i64>>>,
+    /// Seed for the random number generator used for sampling. When set, sampling draws from a random stream local to each generate call,
+    /// yielding identical outputs on CPU for identical seeds. When not set, the global `tch` random generator is used (default: None)
+    pub seed: Option<u64>,
+    /// Maximum number of tokens to generate for each sequence, independently of the prompt length. Generation still ends at `max_length` (default: None)
+    pub max_new_tokens: Option<u64>,
+    /// Strings ending the generation of a sequence. The output is trimmed at the start of the first stop sequence found (default: None)
+    pub stop_sequences: Option<Vec<String>>,
+    /// Time budget for a generate call, after which the generation of all sequences ends (default: None)
+    pub max_time: Option<Duration>,
 }
 
 impl Default for GenerateConfig {
@@ -121,10 +176,23 @@ impl Default for GenerateConfig {
             temperature: 1.0,
             top_k: 0,
             top_p: 0.9,
+            typical_p: 1.0,
+            min_p: 0.0,
+            epsilon_cutoff: 0.0,
+            eta_cutoff: 0.0,
             repetition_penalty: 1.0,
             length_penalty: 1.0,
             no_repeat_ngram_size: 3,
             num_return_sequences: 1,
+            num_beam_groups: 1,
+            diversity_penalty: 0.0,
+            bad_words_ids: None,
+            force_words_ids: None,
+            prefix_allowed_tokens: None,
+            seed: None,
+            max_new_tokens: None,
+            stop_sequences: None,
+            max_time: None,
         }
     }
 }
@@ -133,10 +201,16 @@ impl GenerateConfig {
     fn validate(&self) {
         assert!(self.temperature > 0f64, "temperature must positive");
         assert!((self.top_p >= 0f64) & (self.top_p <= 1f64), "top_p must be 0 and 1");
+        assert!((self.typical_p > 0f64) & (self.typical_p <= 1f64), "typical_p must be strictly greater than 0 and lower than 1");
+        assert!((self.min_p >= 0f64) & (self.min_p <= 1f64), "min_p must be between 0 and 1");
+        assert!((self.epsilon_cutoff >= 0f64) & (self.epsilon_cutoff < 1f64), "epsilon_cutoff must be between 0 and 1");
+        assert!((self.eta_cutoff >= 0f64) & (self.eta_cutoff < 1f64), "eta_cutoff must be between 0 and 1");
         assert!(self.repetition_penalty >= 1f64, "repetition_penalty must be greater than 1");
         assert!(self.length_penalty > 0f64, "length_penalty must be strictly greater than 0");
         assert!(self.num_return_sequences > 0u64, "num_return_sequences must be strictly greater than 0");
         assert!(self.num_beams > 0u64, "num_beams must be strictly greater than 0");
+        assert!(self.num_beam_groups > 0u64, "num_beam_groups must be strictly greater than 0");
+        assert!(self.diversity_penalty >= 0f64, "diversity_penalty must be positive");
 
         if !self.do_sample {
             if self.num_beams == 1 {
@@ -145,6 +219,648 @@ impl GenerateConfig {
                 assert!(self.num_beams >= self.num_return_sequences, "num_return_sequences must be lower than the number of beams")
             }
         }
+
+        if self.num_beam_groups > 1 {
+            assert!(!self.do_sample, "diverse beam search (num_beam_groups > 1) does not support sampling");
+            assert_eq!(self.num_beams % self.num_beam_groups, 0, "num_beams must be a multiple of num_beam_groups");
+            assert!(self.force_words_ids.is_none(), "diverse beam search (num_beam_groups > 1) does not support force_words_ids");
+        }
+
+        if let Some(bad_words_ids) = &self.bad_words_ids {
+            assert!(bad_words_ids.iter().all(|bad_word| !bad_word.is_empty()), "bad_words_ids must not contain empty sequences");
+        }
+        if let Some(force_words_ids) = &self.force_words_ids {
+            assert!((self.num_beams > 1) & !self.do_sample, "force_words_ids requires beam search without sampling");
+            assert!(force_words_ids.iter().all(|constraint| !constraint.is_empty() & constraint.iter().all(|phrase| !phrase.is_empty())),
+                    "force_words_ids must not contain empty constraints or phrases");
+        }
+        if let Some(max_new_tokens) = self.max_new_tokens {
+            assert!(max_new_tokens > 0, "max_new_tokens must be strictly greater than 0");
+        }
+        if let Some(stop_sequences) = &self.stop_sequences {
+            assert!(stop_sequences.iter().all(|stop_sequence| !stop_sequence.is_empty()), "stop_sequences must not contain empty strings");
+        }
+    }
+}
+
+/// # Logits processor
+/// Modifies the scores of the candidates for the next token before it is selected. Processors attached to a generator
+/// (see `LanguageGenerator::set_logits_processors`) are applied in order after the built-in processors derived from the `GenerateConfig`
+/// (`RepetitionPenaltyProcessor`, `NoRepeatNGramProcessor` and `MinLengthProcessor`) and before top-k/top-p filtering.
+pub trait LogitsProcessor {
+    /// Processes the next token scores in place.
+    ///
+    /// # Arguments
+    ///
+    /// * `scores` - Tensor of shape (*batch size x number of beams*, *vocab_size*) with the next token scores (logits for greedy decoding and sampling, log-probabilities for beam search,
+    /// except for the `RepetitionPenaltyProcessor` which always processes the logits)
+    /// * `input_ids` - Tensor of shape (*batch size x number of beams*, *current length*) with the sequences generated so far. For decoder-only models these include the prompt.
+    /// * `prompt_length` - Number of tokens at the beginning of `input_ids` that were not generated
+    fn process(&self, scores: &mut Tensor, input_ids: &Tensor, prompt_length: i64);
+
+    /// Indicates if a generated sequence satisfies the constraint enforced by the processor (e.g. a complete match of a grammar).
+    /// Sequences stopped by the maximum length or the time budget before satisfying it are reported with `FinishReason::Incomplete`.
+    ///
+    /// # Arguments
+    ///
+    /// * `generated_ids` - Generated token ids, excluding the prompt
+    fn is_complete(&self, _generated_ids: &[i64]) -> bool {
+        true
+    }
+}
+
+/// # Repetition penalty
+/// Reduces the scores of the tokens already present in the sequence ([Keskar et al.](https://arxiv.org/abs/1909.05858)).
+pub struct RepetitionPenaltyProcessor {
+    penalty: f64,
+}
+
+impl RepetitionPenaltyProcessor {
+    /// Build a new `RepetitionPenaltyProcessor`
+    ///
+    /// # Arguments
+    ///
+    /// * `penalty` - Repetition penalty, values higher than 1 penalize the tokens already generated
+    pub fn new(penalty: f64) -> RepetitionPenaltyProcessor {
+        assert!(penalty >= 1f64, "repetition_penalty must be greater than 1");
+        RepetitionPenaltyProcessor { penalty }
+    }
+}
+
+impl LogitsProcessor for RepetitionPenaltyProcessor {
+    fn process(&self, scores: &mut Tensor, input_ids: &Tensor, _prompt_length: i64) {
+        let previous_scores = scores.gather(1, input_ids, false);
+//        Negative scores are multiplied by the penalty, positive scores divided by it
+        let negative_scores = previous_scores.lt(0f64).to_kind(previous_scores.kind());
+        let factor = negative_scores * (self.penalty - 1f64 / self.penalty) + 1f64 / self.penalty;
+        *scores = scores.scatter(1, input_ids, &(previous_scores * factor));
+    }
+}
+
+/// # No repeat n-gram
+/// Prevents the generation of n-grams already present in the sequence.
+pub struct NoRepeatNGramProcessor {
+    ngram_size: i64,
+}
+
+impl NoRepeatNGramProcessor {
+    /// Build a new `NoRepeatNGramProcessor`
+    ///
+    /// # Arguments
+    ///
+    /// * `ngram_size` - Size of the n-grams that cannot be repeated (strictly greater than 0)
+    pub fn new(ngram_size: i64) -> NoRepeatNGramProcessor {
+        assert!(ngram_size > 0, "no_repeat_ngram_size must be strictly greater than 0");
+        NoRepeatNGramProcessor { ngram_size }
+    }
+}
+
+impl LogitsProcessor for NoRepeatNGramProcessor {
+    fn process(&self, scores: &mut Tensor, input_ids: &Tensor, _prompt_length: i64) {
+        let banned_tokens = get_banned_tokens(input_ids, self.ngram_size, *input_ids.size().last().unwrap());
+        ban_tokens(scores, banned_tokens);
+    }
+}
+
+fn ban_tokens(scores: &mut Tensor, banned_tokens: Vec<Vec<i64>>) {
+    for (batch_index, index_banned_token) in (0..banned_tokens.len() as i64).zip(banned_tokens) {
+        let _ = scores.get(batch_index).index_fill_(0, &Tensor::of_slice(&index_banned_token).to_device(scores.device()), std::f64::NEG_INFINITY);
+    }
+}
+
+fn get_banned_tokens(input_ids: &Tensor, no_repeat_ngram_size: i64, cur_len: i64) -> Vec<Vec<i64>> {
+//        Ported from hugging face's transformers and fairseq (https://github.com/pytorch/fairseq/blob/master/fairseq/sequence_generator.py)
+    if cur_len + 1 < no_repeat_ngram_size {
+        vec!(vec!())
+    } else {
+        let input_ids = input_ids.to(Device::Cpu);
+        let num_hypothesis = *input_ids.size().first().unwrap();
+        let mut banned_tokens: Vec<Vec<i64>> = Vec::with_capacity(num_hypothesis as usize);
+        for hypothesis_index in 0..num_hypothesis {
+            let hypothesis_input_ids = input_ids.get(hypothesis_index);
+            let mut generated_ngram: HashMap<Vec<i64>, Vec<i64>> = HashMap::new();
+            let input: Vec<i64> = (0..hypothesis_input_ids.size1().unwrap()).collect();
+            let hypothesis_input_ids = hypothesis_input_ids
+                .iter::<i64>()
+                .unwrap()
+                .collect::<Vec<i64>>();
+            let query = &hypothesis_input_ids[cur_len as usize + 1 - no_repeat_ngram_size as usize..].to_vec();
+            let ngram_indices: Vec<(i64, i64)> = input
+                .windows(no_repeat_ngram_size as usize)
+                .map(|win| (*win.first().unwrap(), *win.last().unwrap()))
+                .collect();
+            for ngram in ngram_indices.into_iter() {
+                let ngram = &hypothesis_input_ids[ngram.0 as usize..ngram.1 as usize + 1];
+                let key = ngram[..no_repeat_ngram_size as usize - 1].to_vec();
+                let value = *ngram.last().unwrap();
+                if generated_ngram.contains_key(&key) {
+                    generated_ngram.get_mut(&key).unwrap().push(value)
+                } else {
+                    generated_ngram.insert(key, vec!(value));
+                }
+            }
+            let hypothesis_banned_tokens = match generated_ngram.get(query) {
+                Some(banned_tokens) => banned_tokens.clone(),
+                None => vec!()
+            };
+            banned_tokens.push(hypothesis_banned_tokens);
+        }
+        banned_tokens
+    }
+}
+
+/// # Bad words
+/// Prevents the generation of the given token id sequences: the last token of a sequence is banned when the sequence generated so far ends
+/// with the preceding tokens. Single token sequences are always banned.
+pub struct BadWordsProcessor {
+    bad_words_ids: Vec<Vec<i64>>,
+}
+
+impl BadWordsProcessor {
+    /// Build a new `BadWordsProcessor`
+    ///
+    /// # Arguments
+    ///
+    /// * `bad_words_ids` - Token id sequences that cannot be generated
+    pub fn new(bad_words_ids: Vec<Vec<i64>>) -> BadWordsProcessor {
+        assert!(bad_words_ids.iter().all(|bad_word| !bad_word.is_empty()), "bad_words_ids must not contain empty sequences");
+        BadWordsProcessor { bad_words_ids }
+    }
+}
+
+impl LogitsProcessor for BadWordsProcessor {
+    fn process(&self, scores: &mut Tensor, input_ids: &Tensor, _prompt_length: i64) {
+        let banned_tokens = get_bad_words_tokens(input_ids, &self.bad_words_ids);
+        ban_tokens(scores, banned_tokens);
+    }
+}
+
+fn get_bad_words_tokens(input_ids: &Tensor, bad_words_ids: &[Vec<i64>]) -> Vec<Vec<i64>> {
+    let input_ids = input_ids.to(Device::Cpu);
+    let num_hypothesis = *input_ids.size().first().unwrap();
+    let mut banned_tokens: Vec<Vec<i64>> = Vec::with_capacity(num_hypothesis as usize);
+    for hypothesis_index in 0..num_hypothesis {
+        let hypothesis_input_ids = input_ids
+            .get(hypothesis_index)
+            .iter::<i64>()
+            .unwrap()
+            .collect::<Vec<i64>>();
+        let hypothesis_banned_tokens = bad_words_ids
+            .iter()
+            .filter(|bad_word| hypothesis_input_ids.ends_with(&bad_word[..bad_word.len() - 1]))
+            .map(|bad_word| *bad_word.last().unwrap())
+            .collect::<Vec<i64>>();
+        banned_tokens.push(hypothesis_banned_tokens);
+    }
+    banned_tokens
+}
+
+/// # Prefix allowed tokens
+/// Restricts the tokens that can be generated to the tokens returned by a callback given the prompt index and the tokens generated so far.
+struct PrefixAllowedTokensProcessor {
+    prefix_allowed_tokens: Rc<dyn Fn(usize, &[i64]) -> Vec<i64>>,
+    num_sequences_per_prompt: i64,
+}
+
+impl LogitsProcessor for PrefixAllowedTokensProcessor {
+    fn process(&self, scores: &mut Tensor, input_ids: &Tensor, prompt_length: i64) {
+        let generated_ids = input_ids.slice(1, prompt_length, *input_ids.size().last().unwrap(), 1).to(Device::Cpu);
+        let mask = scores.ones_like() * std::f64::NEG_INFINITY;
+        for sequence_index in 0..*scores.size().first().unwrap() {
+            let prefix = generated_ids
+                .get(sequence_index)
+                .iter::<i64>()
+                .unwrap()
+                .collect::<Vec<i64>>();
+            let allowed_tokens = (self.prefix_allowed_tokens)((sequence_index / self.num_sequences_per_prompt) as usize, &prefix);
+            assert!(!allowed_tokens.is_empty(), "prefix_allowed_tokens must allow at least one token");
+            let _ = mask.get(sequence_index).index_fill_(0, &Tensor::of_slice(&allowed_tokens).to_device(scores.device()), 0f64);
+        }
+        *scores = &*scores + mask;
+    }
+}
+
+/// # Minimum length
+/// Prevents the generation of the end of sequence tokens until the minimum sequence length is reached.
+pub struct MinLengthProcessor {
+    min_length: i64,
+    eos_token_ids: Vec<i64>,
+}
+
+impl MinLengthProcessor {
+    /// Build a new `MinLengthProcessor`
+    ///
+    /// # Arguments
+    ///
+    /// * `min_length` - Minimum sequence length
+    /// * `eos_token_ids` - End of sequence token ids
+    pub fn new(min_length: i64, eos_token_ids: Vec<i64>) -> MinLengthProcessor {
+        MinLengthProcessor { min_length, eos_token_ids }
+    }
+}
+
+impl LogitsProcessor for MinLengthProcessor {
+    fn process(&self, scores: &mut Tensor, input_ids: &Tensor, _prompt_length: i64) {
+        if *input_ids.size().last().unwrap() < self.min_length {
+            let _ = scores.index_fill_(1, &Tensor::of_slice(&self.eos_token_ids).to(scores.device()), std::f64::NEG_INFINITY);
+        }
+    }
+}
+
+/// # Top-k and nucleus filtering
+/// Keeps the `top_k` tokens with the highest scores and/or the top tokens until their cumulative probability reaches `top_p`
+/// ([Holtzman et al.](http://arxiv.org/abs/1904.09751)). Applied to the sampling distribution when `do_sample` is set.
+pub struct TopKTopPProcessor {
+    top_k: i64,
+    top_p: f64,
+    min_tokens_to_keep: i64,
+}
+
+impl TopKTopPProcessor {
+    /// Build a new `TopKTopPProcessor`
+    ///
+    /// # Arguments
+    ///
+    /// * `top_k` - Number of tokens to keep. Value higher than 0 will enable the feature
+    /// * `top_p` - Cumulative probability threshold (between 0 and 1). Value lower than 1 will enable the feature
+    /// * `min_tokens_to_keep` - Minimum number of tokens kept, regardless of `top_k` and `top_p`
+    pub fn new(top_k: i64, top_p: f64, min_tokens_to_keep: i64) -> TopKTopPProcessor {
+        assert!((top_p >= 0f64) & (top_p <= 1f64), "top_p must be 0 and 1");
+        TopKTopPProcessor { top_k, top_p, min_tokens_to_keep }
+    }
+}
+
+impl LogitsProcessor for TopKTopPProcessor {
+    fn process(&self, scores: &mut Tensor, _input_ids: &Tensor, _prompt_length: i64) {
+//        Nucleus and top-k filtering introduced by Holtzman et al. (http://arxiv.org/abs/1904.09751)
+//        Ported from https://gist.github.com/thomwolf/1a5a29f6962089e871b94cbd09daf317
+        let vocab_size = *scores.size().last().unwrap();
+        if self.top_k > 0 {
+            let top_k = vocab_size - min(max(self.top_k, self.min_tokens_to_keep), vocab_size);
+            let (_, indices_to_remove) = scores.topk(top_k, -1, false, false);
+            for index in 0..*scores.size().first().unwrap() {
+                let _ = scores.get(index).index_fill_(0, &indices_to_remove.get(index), std::f64::NEG_INFINITY);
+            }
+        }
+
+        if self.top_p < 1f64 {
+            let (sorted_logits, sorted_indices) = scores.sort(-1, true);
+            let cumulative_probabilities = sorted_logits.softmax(-1, Float).cumsum(-1, Float);
+            let mut sorted_indices_to_remove = cumulative_probabilities.ge(self.top_p).to_kind(Int64);
+            if self.min_tokens_to_keep > 1 {
+                let _ = sorted_indices_to_remove.index_fill_(1, &Tensor::arange1(0, self.min_tokens_to_keep + 1, (Int64, scores.device())), 0);
+            }
+            let _ = sorted_indices_to_remove.index_copy_(1,
+                                                         &Tensor::arange1(1, vocab_size, (Int64, scores.device())),
+                                                         &sorted_indices_to_remove.slice(1, 0, vocab_size - 1, 1).copy());
+            let _ = sorted_indices_to_remove.index_fill_(1, &Tensor::of_slice(&[0]).to_kind(Int64).to_device(sorted_indices_to_remove.device()), 0);
+            let indices_to_remove = sorted_indices_to_remove.scatter(1, &sorted_indices, &sorted_indices_to_remove).to_kind(Bool);
+            let _ = scores.masked_fill_(&indices_to_remove, std::f64::NEG_INFINITY);
+        }
+    }
+}
+
+/// # Locally typical sampling
+/// Keeps the tokens whose information content (negative log-probability) is closest to the entropy of the distribution, until their
+/// cumulative probability reaches `mass` ([Meister et al.](https://arxiv.org/abs/2202.00666)).
+pub struct TypicalProcessor {
+    mass: f64,
+    min_tokens_to_keep: i64,
+}
+
+impl TypicalProcessor {
+    /// Build a new `TypicalProcessor`
+    ///
+    /// # Arguments
+    ///
+    /// * `mass` - Cumulative probability of the typical tokens kept (between 0 and 1)
+    /// * `min_tokens_to_keep` - Minimum number of tokens kept, regardless of `mass`
+    pub fn new(mass: f64, min_tokens_to_keep: i64) -> TypicalProcessor {
+        assert!((mass > 0f64) & (mass <= 1f64), "typical_p must be strictly greater than 0 and lower than 1");
+        TypicalProcessor { mass, min_tokens_to_keep }
+    }
+}
+
+impl LogitsProcessor for TypicalProcessor {
+    fn process(&self, scores: &mut Tensor, _input_ids: &Tensor, _prompt_length: i64) {
+        let vocab_size = *scores.size().last().unwrap();
+        let log_probabilities = scores.log_softmax(-1, Float);
+        let shifted_scores = (-&log_probabilities - entropy(&log_probabilities)).abs();
+        let (sorted_shifted_scores, sorted_indices) = shifted_scores.sort(-1, false);
+        let cumulative_probabilities = log_probabilities.gather(-1, &sorted_indices, false).exp().cumsum(-1, Float);
+//        The most typical tokens are kept up to (and including) the one reaching the probability mass
+        let last_index = cumulative_probabilities
+            .lt(self.mass)
+            .sum1(&[-1], true, Int64)
+            .clamp(min(max(self.min_tokens_to_keep, 1), vocab_size) - 1, vocab_size - 1);
+        let indices_to_remove = shifted_scores.gt1(&sorted_shifted_scores.gather(-1, &last_index, false));
+        let _ = scores.masked_fill_(&indices_to_remove, std::f64::NEG_INFINITY);
+    }
+}
+
+/// # Min-p sampling
+/// Keeps the tokens whose probability is at least `min_p` times the probability of the most likely token.
+pub struct MinPProcessor {
+    min_p: f64,
+    min_tokens_to_keep: i64,
+}
+
+impl MinPProcessor {
+    /// Build a new `MinPProcessor`
+    ///
+    /// # Arguments
+    ///
+    /// * `min_p` - Minimum probability relative to the most likely token (between 0 and 1)
+    /// * `min_tokens_to_keep` - Minimum number of tokens kept, regardless of `min_p`
+    pub fn new(min_p: f64, min_tokens_to_keep: i64) -> MinPProcessor {
+        assert!((min_p >= 0f64) & (min_p <= 1f64), "min_p must be between 0 and 1");
+        MinPProcessor { min_p, min_tokens_to_keep }
+    }
+}
+
+impl LogitsProcessor for MinPProcessor {
+    fn process(&self, scores: &mut Tensor, _input_ids: &Tensor, _prompt_length: i64) {
+        let probabilities = scores.softmax(-1, Float);
+        let (top_probabilities, _) = probabilities.max2(-1, true);
+        let indices_to_remove = probabilities.lt1(&(top_probabilities * self.min_p));
+        remove_tokens(scores, &indices_to_remove, self.min_tokens_to_keep);
+    }
+}
+
+/// # Epsilon sampling
+/// Keeps the tokens whose probability is at least `epsilon` ([Hewitt et al.](https://arxiv.org/abs/2210.15191)).
+pub struct EpsilonProcessor {
+    epsilon: f64,
+    min_tokens_to_keep: i64,
+}
+
+impl EpsilonProcessor {
+    /// Build a new `EpsilonProcessor`
+    ///
+    /// # Arguments
+    ///
+    /// * `epsilon` - Minimum token probability (between 0 and 1)
+    /// * `min_tokens_to_keep` - Minimum number of tokens kept, regardless of `epsilon`
+    pub fn new(epsilon: f64, min_tokens_to_keep: i64) -> EpsilonProcessor {
+        assert!((epsilon >= 0f64) & (epsilon < 1f64), "epsilon_cutoff must be between 0 and 1");
+        EpsilonProcessor { epsilon, min_tokens_to_keep }
+    }
+}
+
+impl LogitsProcessor for EpsilonProcessor {
+    fn process(&self, scores: &mut Tensor, _input_ids: &Tensor, _prompt_length: i64) {
+        let indices_to_remove = scores.softmax(-1, Float).lt(self.epsilon);
+        remove_tokens(scores, &indices_to_remove, self.min_tokens_to_keep);
+    }
+}
+
+/// # Eta sampling
+/// Keeps the tokens whose probability is at least `min(epsilon, sqrt(epsilon) * exp(-entropy))`, the threshold adapting to the entropy
+/// of the distribution ([Hewitt et al.](https://arxiv.org/abs/2210.15191)).
+pub struct EtaProcessor {
+    epsilon: f64,
+    min_tokens_to_keep: i64,
+}
+
+impl EtaProcessor {
+    /// Build a new `EtaProcessor`
+    ///
+    /// # Arguments
+    ///
+    /// * `epsilon` - Upper bound of the probability threshold (between 0 and 1)
+    /// * `min_tokens_to_keep` - Minimum number of tokens kept, regardless of the threshold
+    pub fn new(epsilon: f64, min_tokens_to_keep: i64) -> EtaProcessor {
+        assert!((epsilon >= 0f64) & (epsilon < 1f64), "eta_cutoff must be between 0 and 1");
+        EtaProcessor { epsilon, min_tokens_to_keep }
+    }
+}
+
+impl LogitsProcessor for EtaProcessor {
+    fn process(&self, scores: &mut Tensor, _input_ids: &Tensor, _prompt_length: i64) {
+        let log_probabilities = scores.log_softmax(-1, Float);
+        let eta = ((-entropy(&log_probabilities)).exp() * self.epsilon.sqrt()).clamp_max(self.epsilon);
+        let indices_to_remove = log_probabilities.exp().lt1(&eta);
+        remove_tokens(scores, &indices_to_remove, self.min_tokens_to_keep);
+    }
+}
+
+/// Entropy of the distributions given by the log-probabilities of shape (*batch size*, *vocab_size*), with shape (*batch size*, 1)
+fn entropy(log_probabilities: &Tensor) -> Tensor {
+    let probabilities = log_probabilities.exp();
+//    Tokens with a null probability do not contribute to the entropy (avoids `0 * -inf`)
+    let log_probabilities = log_probabilities.masked_fill(&probabilities.eq(0f64), 0f64);
+    -(probabilities * log_probabilities).sum1(&[-1], true, Float)
+}
+
+/// Removes the tokens flagged by `indices_to_remove`, except the `min_tokens_to_keep` tokens with the highest scores
+fn remove_tokens(scores: &mut Tensor, indices_to_remove: &Tensor, min_tokens_to_keep: i64) {
+    let min_tokens_to_keep = min(max(min_tokens_to_keep, 1), *scores.size().last().unwrap());
+    let (top_scores, _) = scores.topk(min_tokens_to_keep, -1, true, true);
+    let lowest_kept_score = top_scores.select(-1, min_tokens_to_keep - 1).unsqueeze(-1);
+    let indices_to_remove = indices_to_remove.to_kind(Int64) * scores.lt1(&lowest_kept_score).to_kind(Int64);
+    let _ = scores.masked_fill_(&indices_to_remove.to_kind(Bool), std::f64::NEG_INFINITY);
+}
+
+/// # Frequency and presence penalties
+/// Subtracts from the score of each token `frequency_penalty` times the number of times it was generated, and `presence_penalty`
+/// if it was generated at least once. Only the generated tokens are taken into account (not the prompt).
+pub struct FrequencyPresencePenaltyProcessor {
+    frequency_penalty: f64,
+    presence_penalty: f64,
+}
+
+impl FrequencyPresencePenaltyProcessor {
+    /// Build a new `FrequencyPresencePenaltyProcessor`
+    ///
+    /// # Arguments
+    ///
+    /// * `frequency_penalty` - Penalty applied per occurrence of a token in the generated sequence
+    /// * `presence_penalty` - Penalty applied once to every token present in the generated sequence
+    pub fn new(frequency_penalty: f64, presence_penalty: f64) -> FrequencyPresencePenaltyProcessor {
+        FrequencyPresencePenaltyProcessor { frequency_penalty, presence_penalty }
+    }
+}
+
+impl LogitsProcessor for FrequencyPresencePenaltyProcessor {
+    fn process(&self, scores: &mut Tensor, input_ids: &Tensor, prompt_length: i64) {
+        let current_length = *input_ids.size().last().unwrap();
+        if current_length <= prompt_length {
+            return;
+        }
+        let generated_ids = input_ids.slice(1, prompt_length, current_length, 1);
+        let token_counts = scores
+            .zeros_like()
+            .scatter_add(1, &generated_ids, &generated_ids.ones_like().to_kind(scores.kind()));
+        let token_presence = token_counts.gt(0f64).to_kind(scores.kind());
+        *scores = &*scores - token_counts * self.frequency_penalty - token_presence * self.presence_penalty;
+    }
+}
+
+/// # Logit bias
+/// Adds a bias to the scores of the given token ids. Large negative (positive) values effectively ban (force) a token.
+pub struct LogitBiasProcessor {
+    logit_bias: HashMap<i64, f64>,
+}
+
+impl LogitBiasProcessor {
+    /// Build a new `LogitBiasProcessor`
+    ///
+    /// # Arguments
+    ///
+    /// * `logit_bias` - Map from token id to the bias added to its score
+    pub fn new(logit_bias: HashMap<i64, f64>) -> LogitBiasProcessor {
+        LogitBiasProcessor { logit_bias }
+    }
+}
+
+impl LogitsProcessor for LogitBiasProcessor {
+    fn process(&self, scores: &mut Tensor, _input_ids: &Tensor, _prompt_length: i64) {
+        let vocab_size = *scores.size().last().unwrap();
+        let mut bias = vec!(0f64; vocab_size as usize);
+        for (token_id, token_bias) in self.logit_bias.iter() {
+            assert!((*token_id >= 0) & (*token_id < vocab_size), "logit bias token id {} is out of the vocabulary", token_id);
+            bias[*token_id as usize] = *token_bias;
+        }
+        let bias = Tensor::of_slice(&bias).to_kind(scores.kind()).to_device(scores.device());
+        *scores = &*scores + bias.unsqueeze(0);
+    }
+}
+
+/// # Banned tokens
+/// Prevents the generation of the given token ids.
+pub struct BannedTokensProcessor {
+    banned_token_ids: Vec<i64>,
+}
+
+impl BannedTokensProcessor {
+    /// Build a new `BannedTokensProcessor`
+    ///
+    /// # Arguments
+    ///
+    /// * `banned_token_ids` - Token ids that cannot be generated
+    pub fn new(banned_token_ids: Vec<i64>) -> BannedTokensProcessor {
+        BannedTokensProcessor { banned_token_ids }
+    }
+}
+
+impl LogitsProcessor for BannedTokensProcessor {
+    fn process(&self, scores: &mut Tensor, _input_ids: &Tensor, _prompt_length: i64) {
+        if !self.banned_token_ids.is_empty() {
+            let _ = scores.index_fill_(1, &Tensor::of_slice(&self.banned_token_ids).to_device(scores.device()), std::f64::NEG_INFINITY);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// # Decision of a `StoppingCriteria` to end the generation of a sequence
+pub struct StopSignal {
+    /// Reason reported for the end of the generation
+    pub finish_reason: FinishReason,
+    /// Length (in bytes) of the generated text to keep, the rest of the generated text being trimmed from the output. `None` keeps the full text.
+    pub text_length: Option<usize>,
+}
+
+/// # Stopping criteria
+/// Decides whether the generation of a sequence should end after a new token was generated. The sequences of a batch (and the beams for beam search)
+/// are checked and stopped independently. Criteria attached to a generator (see `LanguageGenerator::set_stopping_criteria`) are checked after the
+/// built-in criteria derived from the `GenerateConfig` (`StopSequencesCriteria`, `MaxNewTokensCriteria` and `MaxTimeCriteria`).
+/// The generation of a sequence always ends when an end of sequence token is generated or `max_length` is reached.
+pub trait StoppingCriteria {
+    /// Checks whether the generation of a sequence should end.
+    ///
+    /// # Arguments
+    ///
+    /// * `generated_ids` - Token ids generated so far for the sequence (excluding the prompt), ending with the token just generated
+    /// * `generated_text` - Text decoded from `generated_ids`, skipping special tokens
+    /// * `elapsed` - Time elapsed since the start of the generation
+    ///
+    /// # Returns
+    /// * `Option<StopSignal>` `None` to continue the generation of the sequence, the reason for stopping and the text to keep otherwise
+    fn check(&self, generated_ids: &[i64], generated_text: &str, elapsed: Duration) -> Option<StopSignal>;
+}
+
+/// # Stop sequences
+/// Stops the generation of a sequence when its generated text contains one of the stop sequences. The output is trimmed at the start of
+/// the first stop sequence found (the stop sequence is not included in the output).
+pub struct StopSequencesCriteria {
+    stop_sequences: Vec<String>,
+}
+
+impl StopSequencesCriteria {
+    /// Build a new `StopSequencesCriteria`
+    ///
+    /// # Arguments
+    ///
+    /// * `stop_sequences` - Strings ending the generation (e.g. "\n\n" or "User:")
+    pub fn new(stop_sequences: Vec<String>) -> StopSequencesCriteria {
+        assert!(stop_sequences.iter().all(|stop_sequence| !stop_sequence.is_empty()), "stop_sequences must not contain empty strings");
+        StopSequencesCriteria { stop_sequences }
+    }
+}
+
+impl StoppingCriteria for StopSequencesCriteria {
+    fn check(&self, _generated_ids: &[i64], generated_text: &str, _elapsed: Duration) -> Option<StopSignal> {
+        self.stop_sequences
+            .iter()
+            .filter_map(|stop_sequence| generated_text.find(stop_sequence.as_str()))
+            .min()
+            .map(|position| StopSignal { finish_reason: FinishReason::StopSequence, text_length: Some(position) })
+    }
+}
+
+/// # Maximum number of new tokens
+/// Stops the generation of a sequence once a number of tokens was generated, independently of the prompt length.
+pub struct MaxNewTokensCriteria {
+    max_new_tokens: usize,
+}
+
+impl MaxNewTokensCriteria {
+    /// Build a new `MaxNewTokensCriteria`
+    ///
+    /// # Arguments
+    ///
+    /// * `max_new_tokens` - Maximum number of generated tokens (strictly greater than 0)
+    pub fn new(max_new_tokens: usize) -> MaxNewTokensCriteria {
+        assert!(max_new_tokens > 0, "max_new_tokens must be strictly greater than 0");
+        MaxNewTokensCriteria { max_new_tokens }
+    }
+}
+
+impl StoppingCriteria for MaxNewTokensCriteria {
+    fn check(&self, generated_ids: &[i64], _generated_text: &str, _elapsed: Duration) -> Option<StopSignal> {
+        if generated_ids.len() >= self.max_new_tokens {
+            Some(StopSignal { finish_reason: FinishReason::MaxLength, text_length: None })
+        } else {
+            None
+        }
+    }
+}
+
+/// # Time budget
+/// Stops the generation once the time elapsed since its start exceeds a budget. The budget is checked after each generation step,
+/// the generation may therefore exceed it by the duration of a step.
+pub struct MaxTimeCriteria {
+    max_time: Duration,
+}
+
+impl MaxTimeCriteria {
+    /// Build a new `MaxTimeCriteria`
+    ///
+    /// # Arguments
+    ///
+    /// * `max_time` - Time budget for the generation
+    pub fn new(max_time: Duration) -> MaxTimeCriteria {
+        MaxTimeCriteria { max_time }
+    }
+}
+
+impl StoppingCriteria for MaxTimeCriteria {
+    fn check(&self, _generated_ids: &[i64], _generated_text: &str, elapsed: Duration) -> Option<StopSignal> {
+        if elapsed >= self.max_time {
+            Some(StopSignal { finish_reason: FinishReason::TimeLimit, text_length: None })
+        } else {
+            None
+        }
     }
 }
 
@@ -160,6 +876,8 @@ pub struct OpenAIGenerator {
     is_encoder_decoder: bool,
     vocab_size: i64,
     decoder_start_id: Option<i64>,
+    logits_processors: Vec<Box<dyn LogitsProcessor>>,
+    stopping_criteria: Vec<Box<dyn StoppingCriteria>>,
 }
 
 impl OpenAIGenerator {
@@ -218,8 +936,10 @@ impl OpenAIGenerator {
         let is_encoder_decoder = false;
         let vocab_size = config.vocab_size;
         let decoder_start_id = None;
+        let logits_processors = vec!();
+        let stopping_criteria = vec!();
 
-        Ok(OpenAIGenerator { model, tokenizer, var_store, generate_config, bos_token_id, eos_token_ids, pad_token_id, is_encoder_decoder, vocab_size, decoder_start_id })
+        Ok(OpenAIGenerator { model, tokenizer, var_store, generate_config, bos_token_id, eos_token_ids, pad_token_id, is_encoder_decoder, vocab_size, decoder_start_id, logits_processors, stopping_criteria })
     }
 }
 
@@ -234,6 +954,28 @@ impl PrivateLanguageGenerator<OpenAIGPTLMHeadModel, OpenAiGptVocab, OpenAiGptTok
     fn is_encoder_decoder(&self) -> bool { self.is_encoder_decoder }
     fn get_vocab_size(&self) -> i64 { self.vocab_size }
     fn get_decoder_start_id(&self) -> Option<i64> { self.decoder_start_id }
+    fn get_logits_processors(&self) -> &Vec<Box<dyn LogitsProcessor>> { &self.logits_processors }
+    fn get_logits_processors_mut(&mut self) -> &mut Vec<Box<dyn LogitsProcessor>> { &mut self.logits_processors }
+    fn get_stopping_criteria(&self) -> &Vec<Box<dyn StoppingCriteria>> { &self.stopping_criteria }
+    fn get_stopping_criteria_mut(&mut self) -> &mut Vec<Box<dyn StoppingCriteria>> { &mut self.stopping_criteria }
+
+    fn prepare_inputs_for_generation<'a>(&self,
+                                         input_ids: Tensor,
+                                         _encoder_outputs: Option<&'a Tensor>,
+                                         past: Option<Vec<Tensor>>,
+                                         attention_mask: Tensor)
+                                         -> (Option<Tensor>, Option<&'a Tensor>, Option<Tensor>, Option<Vec<Tensor>>, Option<Tensor>, Option<Tensor>) {
+        let position_ids = get_position_ids(&attention_mask);
+        let attention_mask = Some(attention_mask.to_kind(Float));
+        match past.as_ref().map(|past| past[0].size()[3]) {
+            Some(past_length) => {
+                let sequence_length = *input_ids.size().last().unwrap();
+                (Some(input_ids.slice(1, past_length, sequence_length, 1)), None, None, past, attention_mask,
+                 Some(position_ids.slice(1, past_length, sequence_length, 1)))
+            }
+            None => (Some(input_ids), None, None, past, attention_mask, Some(position_ids))
+        }
+    }
 }
 
 impl LanguageGenerator<OpenAIGPTLMHeadModel, OpenAiGptVocab, OpenAiGptTokenizer> for OpenAIGenerator {}
@@ -250,6 +992,8 @@ pub struct GPT2Generator {
     is_encoder_decoder: bool,
     vocab_size: i64,
     decoder_start_id: Option<i64>,
+    logits_processors: Vec<Box<dyn LogitsProcessor>>,
+    stopping_criteria: Vec<Box<dyn StoppingCriteria>>,
 }
 
 impl GPT2Generator {
@@ -308,33 +1052,134 @@ impl GPT2Generator {
         let is_encoder_decoder = false;
         let vocab_size = config.vocab_size;
         let decoder_start_id = None;
+        let logits_processors = vec!();
+        let stopping_criteria = vec!();
 
-        Ok(GPT2Generator { model, tokenizer, var_store, generate_config, bos_token_id, eos_token_ids, pad_token_id, is_encoder_decoder, vocab_size, decoder_start_id })
+        Ok(GPT2Generator { model, tokenizer, var_store, generate_config, bos_token_id, eos_token_ids, pad_token_id, is_encoder_decoder, vocab_size, decoder_start_id, logits_processors, stopping_criteria })
     }
-}
-
-impl PrivateLanguageGenerator<GPT2LMHeadModel, Gpt2Vocab, Gpt2Tokenizer> for GPT2Generator {
-    fn get_model(&mut self) -> &mut GPT2LMHeadModel { &mut self.model }
-    fn get_tokenizer(&self) -> &Gpt2Tokenizer { &self.tokenizer }
-    fn get_var_store(&self) -> &nn::VarStore { &self.var_store }
-    fn get_config(&self) -> &GenerateConfig { &self.generate_config }
-    fn get_bos_id(&self) -> &Option<i64> { &self.bos_token_id }
-    fn get_eos_ids(&self) -> &Option<Vec<i64>> { &self.eos_token_ids }
-    fn get_pad_id(&self) -> &Option<i64> { &self.pad_token_id }
-    fn is_encoder_decoder(&self) -> bool { self.is_encoder_decoder }
-    fn get_vocab_size(&self) -> i64 { self.vocab_size }
-    fn get_decoder_start_id(&self) -> Option<i64> { self.decoder_start_id }
 
-    fn prepare_inputs_for_generation<'a>(&self,
-                                         input_ids: Tensor,
-                                         _encoder_outputs: Option<&'a Tensor>,
+    /// Generate text with speculative decoding: a smaller draft model sharing the GPT2 vocabulary (e.g. DistilGPT2) proposes `num_speculative_tokens`
+    /// tokens, which are verified by the generator's model in a single forward pass using its cache. Proposed tokens are accepted by rejection sampling,
+    /// so that the generated tokens follow the same distribution as when generating with the generator's model alone
+    /// (greedy decoding returns the same tokens as `generate`).
+    ///
+    /// The prompts are generated one at a time and the generation settings of the generator are used, except beam search, multiple return sequences,
+    /// allowed tokens callbacks and stopping criteria (other than `max_new_tokens`) which are not supported.
+    ///
+    /// # Arguments
+    ///
+    /// * `draft_model` - `&mut GPT2LMHeadModel` Draft model proposing the tokens
+    /// * `prompt_texts` - `Option<Vec<&str>>` Optional vector of text prompts. An empty prompt starts the generation from the beginning of sequence token.
+    /// * `num_speculative_tokens` - `u64` Number of tokens proposed by the draft model at each step
+    ///
+    /// # Returns
+    /// * `SpeculativeOutput` Generated texts and number of proposed and accepted tokens
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# use std::path::PathBuf;
+    ///# use tch::{Device, nn};
+    ///# fn main() -> failure::Fallible<()> {
+    /// use rust_bert::pipelines::generation::{GenerateConfig, GPT2Generator};
+    /// use rust_bert::gpt2::{Gpt2Config, GPT2LMHeadModel};
+    /// use rust_bert::Config;
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# home.push("rustbert");
+    ///# let config_path = &home.as_path().join("gpt2").join("config.json");
+    ///# let vocab_path = &home.as_path().join("gpt2").join("vocab.txt");
+    ///# let merges_path = &home.as_path().join("gpt2").join("merges.txt");
+    ///# let weights_path = &home.as_path().join("gpt2").join("model.ot");
+    ///# let draft_config_path = &home.as_path().join("distilgpt2").join("config.json");
+    ///# let draft_weights_path = &home.as_path().join("distilgpt2").join("model.ot");
+    /// let device = Device::cuda_if_available();
+    /// let generate_config = GenerateConfig {
+    ///    max_length: 30,
+    ///    num_beams: 1,
+    ///    ..Default::default()
+    /// };
+    /// let mut gpt2_generator = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+    ///                                             generate_config, device)?;
+    ///
+    /// let mut draft_var_store = nn::VarStore::new(device);
+    /// let draft_config = Gpt2Config::from_file(draft_config_path);
+    /// let mut draft_model = GPT2LMHeadModel::new(&draft_var_store.root(), &draft_config);
+    /// draft_var_store.load(draft_weights_path)?;
+    ///
+    /// let output = gpt2_generator.generate_speculative(&mut draft_model, Some(vec!("The dog")), 4);
+    /// println!("{} (acceptance rate: {})", output.texts[0], output.acceptance_rate);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn generate_speculative(&mut self, draft_model: &mut GPT2LMHeadModel, prompt_texts: Option<Vec<&str>>, num_speculative_tokens: u64)
+                                -> SpeculativeOutput {
+        let config = &self.generate_config;
+        assert!(num_speculative_tokens > 0, "num_speculative_tokens must be strictly positive");
+        assert_eq!(config.num_beams, 1, "speculative decoding does not support beam search, num_beams must be set to 1");
+        assert_eq!(config.num_return_sequences, 1, "speculative decoding generates a single sequence per prompt");
+        assert!(config.prefix_allowed_tokens.is_none(), "speculative decoding does not support prefix_allowed_tokens");
+        assert!(config.stop_sequences.is_none() & config.max_time.is_none() & self.stopping_criteria.is_empty(),
+                "speculative decoding does not support stopping criteria other than max_new_tokens");
+        let max_length = config.max_length;
+
+        let prompts_ids = match prompt_texts {
+            Some(prompt_texts) => prompt_texts
+                .into_iter()
+                .map(|prompt_text| self.encode_prompt_text(vec!(prompt_text), max_length, None).0
+                    .get(0)
+                    .to(Device::Cpu)
+                    .iter::<i64>()
+                    .unwrap()
+                    .collect::<Vec<i64>>())
+                .collect::<Vec<Vec<i64>>>(),
+            None => vec!(vec!(self.bos_token_id.expect("A model with a BOS token must be used to start generation with an empty input")))
+        };
+
+        let (sequences, num_draft_tokens, num_accepted_tokens) = no_grad(|| {
+            self.generate_speculative_indices(draft_model, prompts_ids, num_speculative_tokens as i64)
+        });
+        let texts = sequences
+            .into_iter()
+            .map(|sequence| self.tokenizer.decode(sequence, true, true))
+            .collect::<Vec<String>>();
+        let acceptance_rate = if num_draft_tokens > 0 { num_accepted_tokens as f64 / num_draft_tokens as f64 } else { 0f64 };
+        SpeculativeOutput { texts, num_draft_tokens, num_accepted_tokens, acceptance_rate }
+    }
+}
+
+impl PrivateLanguageGenerator<GPT2LMHeadModel, Gpt2Vocab, Gpt2Tokenizer> for GPT2Generator {
+    fn get_model(&mut self) -> &mut GPT2LMHeadModel { &mut self.model }
+    fn get_tokenizer(&self) -> &Gpt2Tokenizer { &self.tokenizer }
+    fn get_var_store(&self) -> &nn::VarStore { &self.var_store }
+    fn get_config(&self) -> &GenerateConfig { &self.generate_config }
+    fn get_bos_id(&self) -> &Option<i64> { &self.bos_token_id }
+    fn get_eos_ids(&self) -> &Option<Vec<i64>> { &self.eos_token_ids }
+    fn get_pad_id(&self) -> &Option<i64> { &self.pad_token_id }
+    fn is_encoder_decoder(&self) -> bool { self.is_encoder_decoder }
+    fn get_vocab_size(&self) -> i64 { self.vocab_size }
+    fn get_decoder_start_id(&self) -> Option<i64> { self.decoder_start_id }
+    fn get_logits_processors(&self) -> &Vec<Box<dyn LogitsProcessor>> { &self.logits_processors }
+    fn get_logits_processors_mut(&mut self) -> &mut Vec<Box<dyn LogitsProcessor>> { &mut self.logits_processors }
+    fn get_stopping_criteria(&self) -> &Vec<Box<dyn StoppingCriteria>> { &self.stopping_criteria }
+    fn get_stopping_criteria_mut(&mut self) -> &mut Vec<Box<dyn StoppingCriteria>> { &mut self.stopping_criteria }
+
+    fn prepare_inputs_for_generation<'a>(&self,
+                                         input_ids: Tensor,
+                                         _encoder_outputs: Option<&'a Tensor>,
                                          past: Option<Vec<Tensor>>,
-                                         _attention_mask: Tensor)
-                                         -> (Option<Tensor>, Option<&'a Tensor>, Option<Tensor>, Option<Vec<Tensor>>) {
-        if past.is_some() {
-            (Some(input_ids.select(1, -1).unsqueeze(-1)), None, None, past)
-        } else {
-            (Some(input_ids), None, None, past)
+                                         attention_mask: Tensor)
+                                         -> (Option<Tensor>, Option<&'a Tensor>, Option<Tensor>, Option<Vec<Tensor>>, Option<Tensor>, Option<Tensor>) {
+        let position_ids = get_position_ids(&attention_mask);
+        let attention_mask = Some(attention_mask.to_kind(Float));
+//        Only the tokens that are not cached yet are fed to the model (the last generated token, or the prompts following a cached prefix)
+        match past.as_ref().map(|past| past[0].size()[3]) {
+            Some(past_length) => {
+                let sequence_length = *input_ids.size().last().unwrap();
+                (Some(input_ids.slice(1, past_length, sequence_length, 1)), None, None, past, attention_mask,
+                 Some(position_ids.slice(1, past_length, sequence_length, 1)))
+            }
+            None => (Some(input_ids), None, None, past, attention_mask, Some(position_ids))
         }
     }
 }
@@ -353,6 +1198,8 @@ pub struct BartGenerator {
     is_encoder_decoder: bool,
     vocab_size: i64,
     decoder_start_id: Option<i64>,
+    logits_processors: Vec<Box<dyn LogitsProcessor>>,
+    stopping_criteria: Vec<Box<dyn StoppingCriteria>>,
 }
 
 impl BartGenerator {
@@ -417,8 +1264,10 @@ impl BartGenerator {
         let vocab_size = config.vocab_size;
         let is_encoder_decoder = true;
         let decoder_start_id = Some(2);
+        let logits_processors = vec!();
+        let stopping_criteria = vec!();
 
-        Ok(BartGenerator { model, tokenizer, var_store, generate_config, bos_token_id, eos_token_ids, pad_token_id, is_encoder_decoder, vocab_size, decoder_start_id })
+        Ok(BartGenerator { model, tokenizer, var_store, generate_config, bos_token_id, eos_token_ids, pad_token_id, is_encoder_decoder, vocab_size, decoder_start_id, logits_processors, stopping_criteria })
     }
 
     fn force_token_id_generation(&self, scores: &mut Tensor, token_ids: &[i64]) {
@@ -441,6 +1290,10 @@ impl PrivateLanguageGenerator<BartForConditionalGeneration, RobertaVocab, Robert
     fn is_encoder_decoder(&self) -> bool { self.is_encoder_decoder }
     fn get_vocab_size(&self) -> i64 { self.vocab_size }
     fn get_decoder_start_id(&self) -> Option<i64> { self.decoder_start_id }
+    fn get_logits_processors(&self) -> &Vec<Box<dyn LogitsProcessor>> { &self.logits_processors }
+    fn get_logits_processors_mut(&mut self) -> &mut Vec<Box<dyn LogitsProcessor>> { &mut self.logits_processors }
+    fn get_stopping_criteria(&self) -> &Vec<Box<dyn StoppingCriteria>> { &self.stopping_criteria }
+    fn get_stopping_criteria_mut(&mut self) -> &mut Vec<Box<dyn StoppingCriteria>> { &mut self.stopping_criteria }
 
     fn prepare_scores_for_generation(&self, scores: &mut Tensor, current_length: i64, max_length: i64) {
         if current_length == 1 {
@@ -459,11 +1312,11 @@ impl PrivateLanguageGenerator<BartForConditionalGeneration, RobertaVocab, Robert
                                          encoder_outputs: Option<&'a Tensor>,
                                          _past: Option<Vec<Tensor>>,
                                          _attention_mask: Tensor)
-                                         -> (Option<Tensor>, Option<&'a Tensor>, Option<Tensor>, Option<Vec<Tensor>>) {
-        (None, encoder_outputs, Some(input_ids), None)
+                                         -> (Option<Tensor>, Option<&'a Tensor>, Option<Tensor>, Option<Vec<Tensor>>, Option<Tensor>, Option<Tensor>) {
+        (None, encoder_outputs, Some(input_ids), None, None, None)
     }
 
-    fn encode_prompt_text(&self, prompt_text: Vec<&str>, max_len: u64, pad_token_id: Option<i64>) -> Tensor {
+    fn encode_prompt_text(&self, prompt_text: Vec<&str>, max_len: u64, pad_token_id: Option<i64>) -> (Tensor, Tensor) {
         let tokens = self.get_tokenizer().encode_list(prompt_text,
                                                       max_len as usize,
                                                       &TruncationStrategy::LongestFirst,
@@ -481,6 +1334,16 @@ impl PrivateLanguageGenerator<BartForConditionalGeneration, RobertaVocab, Robert
             None => self.get_tokenizer().vocab().token_to_id(RobertaVocab::unknown_value())
         };
 
+        let attention_mask = token_ids
+            .iter()
+            .map(|input| {
+                let mut temp = vec![0i64; max_len - input.len()];
+                temp.extend(vec![1i64; input.len()]);
+                temp
+            })
+            .map(|mask| Tensor::of_slice(&mask).to(self.get_var_store().device()))
+            .collect::<Vec<Tensor>>();
+
         let token_ids = token_ids
             .into_iter()
             .map(|input| {
@@ -491,7 +1354,7 @@ impl PrivateLanguageGenerator<BartForConditionalGeneration, RobertaVocab, Robert
             .map(|tokens| Tensor::of_slice(&tokens).to(self.get_var_store().device()))
             .collect::<Vec<Tensor>>();
 
-        Tensor::stack(&token_ids, 0)
+        (Tensor::stack(&token_ids, 0), Tensor::stack(&attention_mask, 0))
     }
 
     fn reorder_cache(&mut self, _past: Option<Vec<Tensor>>, encoder_outputs: Option<Tensor>, beam_indices: &Tensor) -> (Option<Vec<Tensor>>, Option<Tensor>) {
@@ -513,17 +1376,628 @@ impl PrivateLanguageGenerator<BartForConditionalGeneration, RobertaVocab, Robert
 
 impl LanguageGenerator<BartForConditionalGeneration, RobertaVocab, RobertaTokenizer> for BartGenerator {}
 
+/// # Decoder-only language generator supported by the `ContinuousBatchingScheduler`
+/// The model caches keys and values (`layer_past`) of shape (*2*, *batch size*, *number of heads*, *past_sequence_length*, *hidden size per head*)
+/// for each layer, so that the scheduler can insert and evict rows of the batched cache as requests join and leave the batch.
+pub trait DecoderOnlyLanguageGenerator<T: LMHeadModel, V: Vocab, U: Tokenizer<V>>: LanguageGenerator<T, V, U> {}
+
+impl DecoderOnlyLanguageGenerator<OpenAIGPTLMHeadModel, OpenAiGptVocab, OpenAiGptTokenizer> for OpenAIGenerator {}
+
+impl DecoderOnlyLanguageGenerator<GPT2LMHeadModel, Gpt2Vocab, Gpt2Tokenizer> for GPT2Generator {}
+
+/// Generation request managed by a `ContinuousBatchingScheduler`
+struct ScheduledRequest {
+    request_id: usize,
+    config: GenerateConfig,
+    token_ids: Vec<i64>,
+    prompt_length: usize,
+    token_logprobs: Vec<f64>,
+//    Row of the request in the attention mask of the batched cache (0 for padded positions)
+    past_mask: Vec<i64>,
+    logits_processors: Vec<Box<dyn LogitsProcessor>>,
+    sampling_processors: Vec<Box<dyn LogitsProcessor>>,
+    stopping_criteria: Vec<Box<dyn StoppingCriteria>>,
+    rng: Option<SeededRng>,
+//    Set when the request is admitted in the running batch
+    start_time: Option<Instant>,
+}
+
+#[derive(Debug, Clone)]
+/// # Generation request completed by a `ContinuousBatchingScheduler`
+pub struct CompletedRequest {
+    /// Identifier of the request, as returned by `ContinuousBatchingScheduler::submit`
+    pub request_id: usize,
+    /// Generated sequence (with a `prompt_index` of 0)
+    pub output: GeneratedOutput,
+}
+
+/// # Continuous batching scheduler for concurrent generation requests
+/// Serves generation requests with a decoder-only language generator (`GPT2Generator` or `OpenAIGenerator`). Requests are submitted at any time, and each call to `step`:
+/// - admits waiting requests into the running batch (up to `max_batch_size` requests),
+/// - runs a forward pass of the model on the last token of the requests being decoded, and on the prompts of the newly admitted requests,
+/// - retires the finished requests.
+///
+/// The running requests share a batched cache of keys and values (`layer_past`): the prompts of the admitted requests are inserted as new rows
+/// of the cache, and the rows of the finished requests are evicted. Padded positions of the cache are masked,
+/// so that each request generates the same sequence as when generated alone under greedy decoding.
+/// Each request is generated with its own `GenerateConfig`, except for beam search and multiple return sequences which are not supported.
+/// The time limit (`max_time`) of a request applies from its admission in the running batch.
+/// The logits processors and stopping criteria attached to the generator apply to all requests.
+///
+/// # Example
+///
+/// ```no_run
+///# use std::path::PathBuf;
+///# use tch::Device;
+///# fn main() -> failure::Fallible<()> {
+/// use rust_bert::pipelines::generation::{GenerateConfig, GPT2Generator, ContinuousBatchingScheduler};
+///# let mut home: PathBuf = dirs::home_dir().unwrap();
+///# home.push("rustbert");
+///# home.push("gpt2");
+///# let config_path = &home.as_path().join("config.json");
+///# let vocab_path = &home.as_path().join("vocab.txt");
+///# let merges_path = &home.as_path().join("merges.txt");
+///# let weights_path = &home.as_path().join("model.ot");
+/// let device = Device::cuda_if_available();
+/// let gpt2_generator = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+///                                         GenerateConfig::default(), device)?;
+/// let mut scheduler = ContinuousBatchingScheduler::new(gpt2_generator, 8);
+///
+/// let greedy_config = GenerateConfig { max_length: 30, do_sample: false, num_beams: 1, ..Default::default() };
+/// let sampling_config = GenerateConfig { max_length: 50, num_beams: 1, top_k: 50, ..Default::default() };
+/// let first_request = scheduler.submit("The dog", greedy_config);
+/// let second_request = scheduler.submit("The cat was", sampling_config);
+/// while !scheduler.is_idle() {
+///     for completed_request in scheduler.step() {
+///         println!("{}: {}", completed_request.request_id, completed_request.output.text);
+///     }
+/// }
+///# Ok(())
+///# }
+/// ```
+///
+pub struct ContinuousBatchingScheduler<T: LMHeadModel, V: Vocab, U: Tokenizer<V>, G: DecoderOnlyLanguageGenerator<T, V, U>> {
+    generator: G,
+    max_batch_size: usize,
+    waiting: VecDeque<ScheduledRequest>,
+    running: Vec<ScheduledRequest>,
+//    Batched cache of the running requests (one row per request, in the order of `running`)
+    past: Option<Vec<Tensor>>,
+    next_request_id: usize,
+    phantom: PhantomData<(T, V, U)>,
+}
+
+impl<T: LMHeadModel, V: Vocab, U: Tokenizer<V>, G: DecoderOnlyLanguageGenerator<T, V, U>> ContinuousBatchingScheduler<T, V, U, G> {
+    /// Build a new `ContinuousBatchingScheduler`
+    ///
+    /// # Arguments
+    ///
+    /// * `generator` - Decoder-only language generator (`GPT2Generator` or `OpenAIGenerator`). Its generation configuration is not used, each request providing its own.
+    /// * `max_batch_size` - Maximum number of requests generated concurrently
+    ///
+    pub fn new(generator: G, max_batch_size: usize) -> ContinuousBatchingScheduler<T, V, U, G> {
+        assert!(max_batch_size > 0, "max_batch_size must be strictly positive");
+        ContinuousBatchingScheduler { generator, max_batch_size, waiting: VecDeque::new(), running: vec!(), past: None, next_request_id: 0, phantom: PhantomData }
+    }
+
+    /// Submit a generation request. The request waits until it can be admitted in the running batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt_text` - Text prompt. An empty prompt starts the generation from the beginning of sequence token.
+    /// * `config` - `GenerateConfig` generation settings of the request (`num_beams` and `num_return_sequences` must be set to 1)
+    ///
+    /// # Returns
+    /// * `usize` Identifier of the request
+    ///
+    pub fn submit(&mut self, prompt_text: &str, config: GenerateConfig) -> usize {
+        config.validate();
+        assert_eq!(config.num_beams, 1, "continuous batching does not support beam search, num_beams must be set to 1");
+        assert_eq!(config.num_return_sequences, 1, "continuous batching generates a single sequence per request");
+
+        let mut token_ids = self.generator.encode_prompt_text(vec!(prompt_text), config.max_length, None).0
+            .get(0)
+            .to(Device::Cpu)
+            .iter::<i64>()
+            .unwrap()
+            .collect::<Vec<i64>>();
+        if token_ids.is_empty() {
+            token_ids.push(self.generator.get_bos_id().expect("A model with a BOS token must be used to start generation with an empty input"));
+        }
+        let logits_processors = self.generator.build_logits_processors(&config, 1);
+        let sampling_processors = self.generator.build_sampling_processors(&config, 1);
+        let stopping_criteria = self.generator.build_stopping_criteria(&config);
+        let rng = config.seed.map(SeededRng::new);
+
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        self.waiting.push_back(ScheduledRequest {
+            request_id,
+            config,
+            prompt_length: token_ids.len(),
+            token_ids,
+            token_logprobs: vec!(),
+            past_mask: vec!(),
+            logits_processors,
+            sampling_processors,
+            stopping_criteria,
+            rng,
+            start_time: None,
+        });
+        request_id
+    }
+
+    /// Number of requests waiting to be admitted in the running batch
+    pub fn num_waiting(&self) -> usize {
+        self.waiting.len()
+    }
+
+    /// Number of requests in the running batch
+    pub fn num_running(&self) -> usize {
+        self.running.len()
+    }
+
+    /// Returns true if there is no waiting or running request
+    pub fn is_idle(&self) -> bool {
+        self.waiting.is_empty() & self.running.is_empty()
+    }
+
+    /// Runs a decoding step: admits the waiting requests that fit in the running batch, generates the next token of every running request
+    /// and retires the finished requests. A request whose prompt already reaches its `max_length` is completed when admitted, without generating any token.
+    ///
+    /// # Returns
+    /// * `Vec<CompletedRequest>` Requests completed at this step
+    ///
+    pub fn step(&mut self) -> Vec<CompletedRequest> {
+        let mut completed_requests = vec!();
+        let mut admitted = vec!();
+        while self.running.len() + admitted.len() < self.max_batch_size {
+            match self.waiting.pop_front() {
+                Some(mut request) => {
+                    request.start_time = Some(Instant::now());
+                    if request.token_ids.len() >= request.config.max_length as usize {
+                        completed_requests.push(self.complete(request, StopSignal { finish_reason: FinishReason::MaxLength, text_length: None }));
+                    } else {
+                        admitted.push(request);
+                    }
+                }
+                None => break
+            }
+        }
+        if self.running.is_empty() & admitted.is_empty() {
+            return completed_requests;
+        }
+
+        let device = self.generator.get_var_store().device();
+        let eos_token_ids = self.generator.get_eos_ids().clone().unwrap_or_default();
+        let mut next_token_logits = vec!();
+        if !self.running.is_empty() {
+            next_token_logits.push(no_grad(|| self.decode(device)));
+        }
+        if !admitted.is_empty() {
+            next_token_logits.push(no_grad(|| self.prefill(&mut admitted, device)));
+            self.running.extend(admitted);
+        }
+        let next_token_logits = Tensor::cat(&next_token_logits, 0);
+
+        let running = std::mem::replace(&mut self.running, vec!());
+        let batch_size = running.len();
+        let mut kept_rows = vec!();
+        for (batch_index, mut request) in running.into_iter().enumerate() {
+//            Next token selection, as done by `generate` for greedy decoding and sampling
+            let prompt_length = request.prompt_length as i64;
+            let input_ids = Tensor::of_slice(&request.token_ids).unsqueeze(0).to(device);
+            let mut scores = next_token_logits.get(batch_index as i64).unsqueeze(0);
+            self.generator.process_logits(&request.logits_processors, &mut scores, &input_ids, prompt_length);
+            let next_token = if request.config.do_sample {
+                if request.config.temperature > 1f64 {
+                    scores = scores / request.config.temperature;
+                }
+                for sampling_processor in request.sampling_processors.iter() {
+                    sampling_processor.process(&mut scores, &input_ids, prompt_length);
+                }
+                let probabilities = scores.softmax(-1, Float);
+                sample(&probabilities, 1, request.rng.as_mut()).int64_value(&[0, 0])
+            } else {
+                scores.argmax(-1, false).int64_value(&[0])
+            };
+            request.token_logprobs.push(scores.log_softmax(-1, Float).double_value(&[0, next_token]));
+            request.token_ids.push(next_token);
+
+//            The maximum length is checked for the next step, so that the request never exceeds it
+            let stop_signal = if eos_token_ids.contains(&next_token) {
+                Some(StopSignal { finish_reason: FinishReason::Eos, text_length: None })
+            } else {
+                match self.generator.check_stopping_criteria(&request.stopping_criteria, &request.token_ids[request.prompt_length..], request.start_time.unwrap()) {
+                    Some(stop_signal) => Some(stop_signal),
+                    None => if request.token_ids.len() >= request.config.max_length as usize {
+                        Some(StopSignal { finish_reason: FinishReason::MaxLength, text_length: None })
+                    } else {
+                        None
+                    }
+                }
+            };
+            match stop_signal {
+                Some(stop_signal) => completed_requests.push(self.complete(request, stop_signal)),
+                None => {
+                    kept_rows.push(batch_index as i64);
+                    self.running.push(request);
+                }
+            }
+        }
+        if kept_rows.len() < batch_size {
+            self.evict(&kept_rows, device);
+        }
+        completed_requests
+    }
+
+    /// Runs decoding steps until all the submitted requests are completed
+    ///
+    /// # Returns
+    /// * `Vec<CompletedRequest>` Completed requests, in order of completion
+    ///
+    pub fn run(&mut self) -> Vec<CompletedRequest> {
+        let mut completed_requests = vec!();
+        while !self.is_idle() {
+            completed_requests.extend(self.step());
+        }
+        completed_requests
+    }
+
+    /// Forward pass of the model on the last generated token of each running request, attending to the batched cache.
+    /// Appends the keys and values of these tokens to the cache and returns the scores of the next token of each request.
+    fn decode(&mut self, device: Device) -> Tensor {
+        let mut input_ids = Vec::with_capacity(self.running.len());
+        let mut position_ids = Vec::with_capacity(self.running.len());
+        for request in self.running.iter_mut() {
+            input_ids.push(*request.token_ids.last().unwrap());
+            position_ids.push(request.token_ids.len() as i64 - 1);
+            request.past_mask.push(1);
+        }
+        let input_ids = Tensor::of_slice(&input_ids).unsqueeze(-1).to(device);
+        let position_ids = Tensor::of_slice(&position_ids).unsqueeze(-1).to(device);
+        let attention_mask = self.attention_mask(&self.running, device);
+
+        let (logits, _, past, _, _) = self.generator.get_model().forward_t(&Some(input_ids),
+                                                                          &self.past,
+                                                                          &Some(attention_mask),
+                                                                          &None,
+                                                                          &Some(position_ids),
+                                                                          &None,
+                                                                          None,
+                                                                          &None,
+                                                                          false).unwrap();
+        self.past = past;
+        logits.select(1, -1)
+    }
+
+    /// Forward pass of the model on the prompts of the admitted requests, left-padded to the longest of these. Inserts the keys and values
+    /// of these prompts as new rows of the batched cache (left-padding the shorter of the two caches) and returns the scores of the next token of each request.
+    fn prefill(&mut self, admitted: &mut [ScheduledRequest], device: Device) -> Tensor {
+        let input_length = admitted.iter().map(|request| request.token_ids.len()).max().unwrap();
+        let mut input_ids = Vec::with_capacity(admitted.len());
+        let mut position_ids = Vec::with_capacity(admitted.len());
+        for request in admitted.iter_mut() {
+            let padding = input_length - request.token_ids.len();
+            let mut request_input_ids = vec![0i64; padding];
+            request_input_ids.extend_from_slice(&request.token_ids);
+            request.past_mask = vec![0i64; padding];
+            request.past_mask.extend(vec![1i64; request.token_ids.len()]);
+            let request_position_ids = (0..input_length)
+                .map(|position| position.saturating_sub(padding) as i64)
+                .collect::<Vec<i64>>();
+
+            input_ids.push(Tensor::of_slice(&request_input_ids));
+            position_ids.push(Tensor::of_slice(&request_position_ids));
+        }
+        let input_ids = Tensor::stack(&input_ids, 0).to(device);
+        let position_ids = Tensor::stack(&position_ids, 0).to(device);
+        let attention_mask = self.attention_mask(admitted, device);
+
+        let (logits, _, past, _, _) = self.generator.get_model().forward_t(&Some(input_ids),
+                                                                          &None,
+                                                                          &Some(attention_mask),
+                                                                          &None,
+                                                                          &Some(position_ids),
+                                                                          &None,
+                                                                          None,
+                                                                          &None,
+                                                                          false).unwrap();
+        let past = past.unwrap();
+
+        self.past = Some(match self.past.take() {
+            Some(running_past) => {
+                let running_length = self.running.first().map_or(0, |request| request.past_mask.len());
+                let past_length = max(running_length, input_length);
+                for request in self.running.iter_mut() {
+                    request.past_mask.splice(0..0, vec![0i64; past_length - running_length]);
+                }
+                for request in admitted.iter_mut() {
+                    request.past_mask.splice(0..0, vec![0i64; past_length - input_length]);
+                }
+                running_past
+                    .iter()
+                    .zip(past.iter())
+                    .map(|(running_layer_past, layer_past)| {
+                        let running_layer_past = Self::left_pad(running_layer_past, past_length - running_length, device);
+                        let layer_past = Self::left_pad(layer_past, past_length - input_length, device);
+                        Tensor::cat(&[running_layer_past, layer_past], 1)
+                    })
+                    .collect()
+            }
+            None => past
+        });
+        logits.select(1, -1)
+    }
+
+    /// Removes the rows of the finished requests from the batched cache, keeping the rows `kept_rows` of the running requests,
+    /// and drops the cached positions that are masked for all of the running requests
+    fn evict(&mut self, kept_rows: &[i64], device: Device) {
+        if self.running.is_empty() {
+            self.past = None;
+            return;
+        }
+        let past_length = self.running[0].past_mask.len();
+        let kept_positions = (0..past_length)
+            .filter(|position| self.running.iter().any(|request| request.past_mask[*position] == 1))
+            .map(|position| position as i64)
+            .collect::<Vec<i64>>();
+        for request in self.running.iter_mut() {
+            request.past_mask = kept_positions.iter().map(|position| request.past_mask[*position as usize]).collect();
+        }
+
+        let rows = Tensor::of_slice(kept_rows).to(device);
+        let positions = Tensor::of_slice(&kept_positions).to(device);
+        self.past = self.past.take().map(|past| past
+            .iter()
+            .map(|layer_past| {
+                let layer_past = layer_past.index_select(1, &rows);
+                if kept_positions.len() < past_length { layer_past.index_select(3, &positions) } else { layer_past }
+            })
+            .collect());
+    }
+
+    /// Attention mask of the given requests, covering the batched cache
+    fn attention_mask(&self, requests: &[ScheduledRequest], device: Device) -> Tensor {
+        let attention_mask = requests.iter().map(|request| Tensor::of_slice(&request.past_mask)).collect::<Vec<Tensor>>();
+        Tensor::stack(&attention_mask, 0).to_kind(Float).to(device)
+    }
+
+    /// Left-pads the cached keys and values of a layer with `padding` positions
+    fn left_pad(layer_past: &Tensor, padding: usize, device: Device) -> Tensor {
+        if padding == 0 {
+            return layer_past.shallow_clone();
+        }
+        let size = layer_past.size();
+        let padding = Tensor::zeros(&[size[0], size[1], size[2], padding as i64, size[4]], (layer_past.kind(), device));
+        Tensor::cat(&[&padding, layer_past], 3)
+    }
+
+    fn complete(&self, request: ScheduledRequest, stop_signal: StopSignal) -> CompletedRequest {
+        let token_ids = request.token_ids[request.prompt_length..].to_vec();
+        let score = request.token_logprobs.iter().sum();
+        let mut sequence_details = [SequenceDetails {
+            prompt_length: request.prompt_length,
+            token_ids: token_ids.clone(),
+            token_logprobs: request.token_logprobs.clone(),
+            score,
+            finish_reason: stop_signal.finish_reason,
+            text_length: stop_signal.text_length,
+        }];
+        self.generator.mark_incomplete_sequences(&mut sequence_details);
+        let finish_reason = sequence_details[0].finish_reason;
+        let sequence = Tensor::of_slice(&request.token_ids).unsqueeze(0);
+        let text = self.generator.decode_sequences(&sequence, &sequence_details).remove(0);
+        CompletedRequest {
+            request_id: request.request_id,
+            output: GeneratedOutput {
+                prompt_index: 0,
+                text,
+                token_ids,
+                token_logprobs: request.token_logprobs,
+                score,
+                finish_reason,
+            },
+        }
+    }
+}
+
 mod private_generation_utils {
     use rust_tokenizers::{Vocab, Tokenizer, TruncationStrategy};
-    use tch::{nn, Tensor, Device};
+    use tch::{nn, Tensor, Device, no_grad};
     use rust_tokenizers::preprocessing::tokenizer::tokenization_utils::truncate_sequences;
-    use std::collections::HashMap;
-    use tch::kind::Kind::{Int64, Float, Bool};
-    use std::cmp::{min, max};
-    use crate::pipelines::generation::{BeamHypotheses, GenerateConfig, LMHeadModel};
+    use tch::kind::Kind::{Int64, Float, Double, Bool};
+    use std::cmp::{min, max, Reverse};
+    use std::collections::{HashSet, BTreeMap};
+    use crate::pipelines::generation::{BeamHypotheses, BeamHypothesis, GenerateConfig, LMHeadModel, StreamToken, FinishReason, LogitsProcessor,
+                                       RepetitionPenaltyProcessor, NoRepeatNGramProcessor, BadWordsProcessor, MinLengthProcessor,
+                                       PrefixAllowedTokensProcessor, TopKTopPProcessor, TypicalProcessor, MinPProcessor, EpsilonProcessor,
+                                       EtaProcessor, StoppingCriteria, StopSignal, StopSequencesCriteria,
+                                       MaxNewTokensCriteria, MaxTimeCriteria, PrefixCache};
+    use std::time::Instant;
     use itertools::Itertools;
     use super::ordered_float::OrderedFloat;
 
+    /// Generated token ids (excluding the prompt), log-probabilities and score of a generated sequence, and length of the generated text to keep
+    /// if the sequence was stopped by a stopping criteria. The generated tokens start at `prompt_length` in the decoded sequence.
+    pub struct SequenceDetails {
+        pub prompt_length: usize,
+        pub token_ids: Vec<i64>,
+        pub token_logprobs: Vec<f64>,
+        pub score: f64,
+        pub finish_reason: FinishReason,
+        pub text_length: Option<usize>,
+    }
+
+    /// Tracks the tokens and text already streamed for each sequence and decodes the new tokens incrementally.
+    pub struct TokenStreamer<'a> {
+        callback: &'a mut dyn FnMut(&[StreamToken]) -> bool,
+        streamed_ids: Vec<Vec<i64>>,
+        streamed_text: Vec<String>,
+    }
+
+    impl<'a> TokenStreamer<'a> {
+        pub fn new(callback: &'a mut dyn FnMut(&[StreamToken]) -> bool) -> TokenStreamer<'a> {
+            TokenStreamer { callback, streamed_ids: vec!(), streamed_text: vec!() }
+        }
+
+        /// Streams the last token of the sequences generated so far (`None` for sequences that are no longer generated).
+        /// Returns `false` if the consumer requested the generation to stop.
+        pub fn emit<V: Vocab, U: Tokenizer<V>>(&mut self, tokenizer: &U, sequences: Vec<Option<Vec<i64>>>) -> bool {
+            if self.streamed_ids.len() < sequences.len() {
+                self.streamed_ids.resize(sequences.len(), vec!());
+                self.streamed_text.resize(sequences.len(), String::new());
+            }
+            let mut tokens = vec!();
+            for (sequence_index, sequence) in sequences.into_iter().enumerate() {
+                let sequence = match sequence {
+                    Some(sequence) if !sequence.is_empty() => sequence,
+                    _ => continue
+                };
+                let token_id = *sequence.last().unwrap();
+                let previous_ids = &self.streamed_ids[sequence_index];
+                let extends_previous = (previous_ids.len() + 1 == sequence.len()) & sequence.starts_with(previous_ids);
+                let text = tokenizer.decode(sequence.clone(), true, false);
+                let previous_text = &self.streamed_text[sequence_index];
+                let restart = !extends_previous | !text.starts_with(previous_text.as_str());
+//              Incomplete multi-byte characters are held back until the following tokens complete them
+                let (new_text, streamed_text) = if text.ends_with('\u{FFFD}') {
+                    (String::new(), if restart { String::new() } else { previous_text.clone() })
+                } else if restart {
+                    (text.clone(), text)
+                } else {
+                    (text[previous_text.len()..].to_owned(), text.clone())
+                };
+                self.streamed_ids[sequence_index] = sequence;
+                self.streamed_text[sequence_index] = streamed_text;
+                tokens.push(StreamToken { sequence_index, token_id, text: new_text, restart });
+            }
+            if tokens.is_empty() {
+                true
+            } else {
+                (self.callback)(&tokens)
+            }
+        }
+    }
+
+    /// Returns the progress of a generated sequence towards the forced words constraints (number of constraint tokens generated),
+    /// whether all constraints are fulfilled and the tokens that would advance the constraints not fulfilled yet.
+    fn get_constraints_progress(force_words_ids: &[Vec<Vec<i64>>], generated_ids: &[i64]) -> (usize, bool, Vec<i64>) {
+        let mut progress = 0;
+        let mut constraints_fulfilled = true;
+        let mut advancing_tokens = vec!();
+        for constraint in force_words_ids {
+            let max_phrase_length = constraint.iter().map(|phrase| phrase.len()).max().unwrap();
+            if constraint.iter().any(|phrase| generated_ids.windows(phrase.len()).any(|window| window == phrase.as_slice())) {
+                progress += max_phrase_length;
+                continue;
+            }
+            constraints_fulfilled = false;
+            let mut constraint_progress = 0;
+            for phrase in constraint {
+//            Longest prefix of the phrase ending the generated sequence
+                let matched_length = (1..phrase.len())
+                    .rev()
+                    .find(|length| generated_ids.ends_with(&phrase[..*length]))
+                    .unwrap_or(0);
+                constraint_progress = max(constraint_progress, matched_length);
+                if !advancing_tokens.contains(&phrase[matched_length]) {
+                    advancing_tokens.push(phrase[matched_length]);
+                }
+            }
+            progress += constraint_progress;
+        }
+        (progress, constraints_fulfilled, advancing_tokens)
+    }
+
+    /// Random number generator (SplitMix64) local to a generate call, used for reproducible sampling.
+    pub struct SeededRng {
+        state: u64,
+    }
+
+    impl SeededRng {
+        pub fn new(seed: u64) -> SeededRng {
+            SeededRng { state: seed }
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = self.state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        }
+
+        /// Returns a uniformly distributed number in [0, 1)
+        fn next_f64(&mut self) -> f64 {
+            (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+        }
+    }
+
+    /// Draws `num_samples` indices without replacement from each row of `probabilities`. Uses the seeded generator if provided,
+    /// and the global `tch` generator otherwise.
+    pub fn sample(probabilities: &Tensor, num_samples: i64, rng: Option<&mut SeededRng>) -> Tensor {
+        match rng {
+            Some(rng) => {
+                let num_rows = probabilities.size()[0];
+                assert!(num_samples <= probabilities.size()[1], "cannot draw more samples than the vocabulary size");
+                let mut samples: Vec<i64> = Vec::with_capacity((num_rows * num_samples) as usize);
+                for row in 0..num_rows {
+//                Probabilities are copied to the CPU in double precision so that sampling does not depend on the device
+                    let mut row_probabilities: Vec<f64> = probabilities.get(row).to_kind(Double).to(Device::Cpu).iter::<f64>().unwrap().collect();
+                    for _ in 0..num_samples {
+                        let total: f64 = row_probabilities.iter().sum();
+                        assert!(total > 0f64, "not enough tokens with a non-zero probability to sample from");
+                        let threshold = rng.next_f64() * total;
+                        let mut cumulative = 0f64;
+                        let mut sampled_index = None;
+                        for (index, probability) in row_probabilities.iter().enumerate() {
+                            if *probability > 0f64 {
+                                cumulative += probability;
+                                sampled_index = Some(index);
+                                if cumulative > threshold {
+                                    break;
+                                }
+                            }
+                        }
+                        let sampled_index = sampled_index.unwrap();
+                        row_probabilities[sampled_index] = 0f64;
+                        samples.push(sampled_index as i64);
+                    }
+                }
+                Tensor::of_slice(&samples).view((num_rows, num_samples)).to(probabilities.device())
+            }
+            None => probabilities.multinomial(num_samples, false)
+        }
+    }
+
+    /// Position ids of left-padded sequences given their attention mask: positions are counted from the first non-padded token,
+    /// so that a padded sequence is encoded as if it was not padded
+    pub fn get_position_ids(attention_mask: &Tensor) -> Tensor {
+        (attention_mask.cumsum(-1, Int64) - 1).clamp_min(0)
+    }
+
+    /// Draws a number uniformly distributed in [0, 1). Uses the seeded generator if provided, and the global `tch` generator otherwise.
+    fn uniform(rng: Option<&mut SeededRng>) -> f64 {
+        match rng {
+            Some(rng) => rng.next_f64(),
+            None => Tensor::rand(&[1], (Double, Device::Cpu)).double_value(&[0])
+        }
+    }
+
+    /// Forward pass of a language model on a single sequence of tokens following the cached `past`, returning the scores at each input position
+    /// (shape (*sequence_length*, *vocab_size*)) and the updated cache
+    fn forward_with_past<M: LMHeadModel>(model: &mut M, input_ids: &[i64], past: Option<Vec<Tensor>>, device: Device) -> (Tensor, Option<Vec<Tensor>>) {
+        let input_ids = Tensor::of_slice(input_ids).view((1, -1)).to(device);
+        let (logits, _, past, _, _) = model.forward_t(&Some(input_ids), &past, &None, &None, &None, &None, None, &None, false).unwrap();
+        (logits.get(0), past)
+    }
+
+    /// Truncates a cache of shape (*2*, *batch size*, *number of heads*, *past_sequence_length*, *hidden size per head*) per layer to its first `length` positions
+    fn truncate_past(past: Option<Vec<Tensor>>, length: usize) -> Option<Vec<Tensor>> {
+        past.map(|past| past.iter().map(|layer_past| layer_past.slice(3, 0, length as i64, 1)).collect())
+    }
+
     pub trait PrivateLanguageGenerator<T: LMHeadModel, V: Vocab, U: Tokenizer<V>> {
         fn get_model(&mut self) -> &mut T;
         fn get_tokenizer(&self) -> &U;
@@ -535,21 +2009,28 @@ mod private_generation_utils {
         fn is_encoder_decoder(&self) -> bool;
         fn get_vocab_size(&self) -> i64;
         fn get_decoder_start_id(&self) -> Option<i64>;
+        fn get_logits_processors(&self) -> &Vec<Box<dyn LogitsProcessor>>;
+        fn get_logits_processors_mut(&mut self) -> &mut Vec<Box<dyn LogitsProcessor>>;
+        fn get_stopping_criteria(&self) -> &Vec<Box<dyn StoppingCriteria>>;
+        fn get_stopping_criteria_mut(&mut self) -> &mut Vec<Box<dyn StoppingCriteria>>;
 
         fn prepare_scores_for_generation(&self, _scores: &mut Tensor, _current_length: i64, _max_length: i64) {}
 
         fn encode(&mut self, _input_ids: &Tensor, _attention_mask: Option<&Tensor>) -> Option<Tensor> { None }
 
+        /// Prepares the inputs of the model forward pass at each generation step: input ids, encoder outputs, decoder input ids, cache,
+        /// attention mask and position ids
         fn prepare_inputs_for_generation<'a>(&self,
                                              input_ids: Tensor,
                                              _encoder_outputs: Option<&'a Tensor>,
                                              past: Option<Vec<Tensor>>,
-                                             _attention_mask: Tensor)
-                                             -> (Option<Tensor>, Option<&'a Tensor>, Option<Tensor>, Option<Vec<Tensor>>) {
-            (Some(input_ids), None, None, past)
+                                             attention_mask: Tensor)
+                                             -> (Option<Tensor>, Option<&'a Tensor>, Option<Tensor>, Option<Vec<Tensor>>, Option<Tensor>, Option<Tensor>) {
+            let position_ids = get_position_ids(&attention_mask);
+            (Some(input_ids), None, None, past, Some(attention_mask.to_kind(Float)), Some(position_ids))
         }
 
-        fn encode_prompt_text(&self, prompt_text: Vec<&str>, max_len: u64, pad_token_id: Option<i64>) -> Tensor {
+        fn encode_prompt_text(&self, prompt_text: Vec<&str>, max_len: u64, pad_token_id: Option<i64>) -> (Tensor, Tensor) {
             let tokens = self.get_tokenizer().tokenize_list(prompt_text);
             let token_ids = tokens
                 .into_iter()
@@ -578,6 +2059,16 @@ mod private_generation_utils {
                 None => self.get_tokenizer().vocab().token_to_id(V::unknown_value())
             };
 
+            let attention_mask = token_ids
+                .iter()
+                .map(|input| {
+                    let mut temp = vec![0i64; max_len - input.len()];
+                    temp.extend(vec![1i64; input.len()]);
+                    temp
+                })
+                .map(|mask| Tensor::of_slice(&mask).to(self.get_var_store().device()))
+                .collect::<Vec<Tensor>>();
+
             let token_ids = token_ids
                 .into_iter()
                 .map(|input| {
@@ -588,158 +2079,361 @@ mod private_generation_utils {
                 .map(|tokens| Tensor::of_slice(&tokens).to(self.get_var_store().device()))
                 .collect::<Vec<Tensor>>();
 
-            Tensor::stack(&token_ids, 0)
+            (Tensor::stack(&token_ids, 0), Tensor::stack(&attention_mask, 0))
         }
 
-        fn enforce_repetition_penalty(&self, next_token_logits: &mut Tensor, batch_size: i64, num_beams: u64, prev_output_tokens: &Tensor, repetition_penalty: f64) {
-            for i in 0..(batch_size * num_beams as i64) {
-                for token_position in 0..prev_output_tokens.get(i).size()[0] {
-                    let token = prev_output_tokens.get(i).int64_value(&[token_position]);
-                    let updated_value = &next_token_logits.double_value(&[i, token]);
-                    if updated_value < &0f64 {
-                        &next_token_logits.get(i).index_fill_(0, &Tensor::of_slice(&[token]).to_kind(Int64).to_device(next_token_logits.device()), updated_value * repetition_penalty);
-                    } else {
-                        &next_token_logits.get(i).index_fill_(0, &Tensor::of_slice(&[token]).to_kind(Int64).to_device(next_token_logits.device()), updated_value / repetition_penalty);
-                    }
-                }
+        fn process_logits(&self, logits_processors: &[Box<dyn LogitsProcessor>], scores: &mut Tensor, input_ids: &Tensor, prompt_length: i64) {
+            for logits_processor in logits_processors.iter().chain(self.get_logits_processors().iter()) {
+                logits_processor.process(scores, input_ids, prompt_length);
             }
         }
 
-        fn get_banned_tokens(&self, input_ids: &Tensor, no_repeat_ngram_size: i64, cur_len: i64) -> Vec<Vec<i64>> {
-//        Ported from hugging face's transformers and fairseq (https://github.com/pytorch/fairseq/blob/master/fairseq/sequence_generator.py)
-            if cur_len + 1 < no_repeat_ngram_size {
-                vec!(vec!())
-            } else {
-                let input_ids = input_ids.to(Device::Cpu);
-                let num_hypothesis = *input_ids.size().first().unwrap();
-                let mut banned_tokens: Vec<Vec<i64>> = Vec::with_capacity(num_hypothesis as usize);
-                for hypothesis_index in 0..num_hypothesis {
-                    let hypothesis_input_ids = input_ids.get(hypothesis_index);
-                    let mut generated_ngram: HashMap<Vec<i64>, Vec<i64>> = HashMap::new();
-                    let input: Vec<i64> = (0..hypothesis_input_ids.size1().unwrap()).collect();
-                    let hypothesis_input_ids = hypothesis_input_ids
-                        .iter::<i64>()
-                        .unwrap()
-                        .collect::<Vec<i64>>();
-                    let query = &hypothesis_input_ids[cur_len as usize + 1 - no_repeat_ngram_size as usize..].to_vec();
-                    let ngram_indices: Vec<(i64, i64)> = input
-                        .windows(no_repeat_ngram_size as usize)
-                        .map(|win| (*win.first().unwrap(), *win.last().unwrap()))
-                        .collect();
-                    for ngram in ngram_indices.into_iter() {
-                        let ngram = &hypothesis_input_ids[ngram.0 as usize..ngram.1 as usize + 1];
-                        let key = ngram[..no_repeat_ngram_size as usize - 1].to_vec();
-                        let value = *ngram.last().unwrap();
-                        if generated_ngram.contains_key(&key) {
-                            generated_ngram.get_mut(&key).unwrap().push(value)
-                        } else {
-                            generated_ngram.insert(key, vec!(value));
-                        }
-                    }
-                    let hypothesis_banned_tokens = match generated_ngram.get(query) {
-                        Some(banned_tokens) => banned_tokens.clone(),
-                        None => vec!()
-                    };
-                    banned_tokens.push(hypothesis_banned_tokens);
-                }
-                banned_tokens
+        /// Builds the logits processors set by the generation configuration (repetition penalty, banned n-grams and words, minimum length and allowed tokens)
+        fn build_logits_processors(&self, config: &GenerateConfig, num_sequences_per_prompt: i64) -> Vec<Box<dyn LogitsProcessor>> {
+            let mut logits_processors = self.build_penalty_processors(config);
+            logits_processors.extend(self.build_constraint_processors(config, num_sequences_per_prompt));
+            logits_processors
+        }
+
+        /// Builds the processors scaling the logits according to their sign (repetition penalty)
+        fn build_penalty_processors(&self, config: &GenerateConfig) -> Vec<Box<dyn LogitsProcessor>> {
+            let mut penalty_processors: Vec<Box<dyn LogitsProcessor>> = vec!();
+            if config.repetition_penalty > 1f64 {
+                penalty_processors.push(Box::new(RepetitionPenaltyProcessor::new(config.repetition_penalty)));
             }
+            penalty_processors
         }
 
-        fn top_k_top_p_filtering(&self, logits: &mut Tensor, top_k: i64, top_p: f64, min_tokens_to_keep: i64) {
-//        Nucleus and top-k filtering introduced by Holtzman et al. (http://arxiv.org/abs/1904.09751)
-//        Ported from https://gist.github.com/thomwolf/1a5a29f6962089e871b94cbd09daf317
-            let vocab_size = *logits.size().last().unwrap();
-            if top_k > 0 {
-                let top_k = vocab_size - min(max(top_k, min_tokens_to_keep), vocab_size);
-                let (_, indices_to_remove) = logits.topk(top_k, -1, false, false);
-                for index in 0..*logits.size().first().unwrap() {
-                    &logits.get(index).index_fill_(0, &indices_to_remove.get(index), std::f64::NEG_INFINITY);
+        /// Builds the processors restricting the tokens that can be generated (banned n-grams and words, minimum length and allowed tokens)
+        fn build_constraint_processors(&self, config: &GenerateConfig, num_sequences_per_prompt: i64) -> Vec<Box<dyn LogitsProcessor>> {
+            let mut logits_processors: Vec<Box<dyn LogitsProcessor>> = vec!();
+            if config.no_repeat_ngram_size > 0 {
+                logits_processors.push(Box::new(NoRepeatNGramProcessor::new(config.no_repeat_ngram_size as i64)));
+            }
+            if let Some(bad_words_ids) = &config.bad_words_ids {
+                logits_processors.push(Box::new(BadWordsProcessor::new(bad_words_ids.clone())));
+            }
+            if let Some(eos_ids) = self.get_eos_ids() {
+                if config.min_length > 0 {
+                    logits_processors.push(Box::new(MinLengthProcessor::new(config.min_length as i64, eos_ids.clone())));
                 }
             }
+            if let Some(prefix_allowed_tokens) = &config.prefix_allowed_tokens {
+                let prefix_allowed_tokens = prefix_allowed_tokens.clone();
+                logits_processors.push(Box::new(PrefixAllowedTokensProcessor { prefix_allowed_tokens, num_sequences_per_prompt }));
+            }
+            logits_processors
+        }
 
-            if top_p < 1f64 {
-                let (sorted_logits, sorted_indices) = logits.sort(-1, true);
-                let cumulative_probabilities = sorted_logits.softmax(-1, Float).cumsum(-1, Float);
-                let mut sorted_indices_to_remove = cumulative_probabilities.ge(top_p).to_kind(Int64);
-                if min_tokens_to_keep > 1 {
-                    &sorted_indices_to_remove.index_fill_(1, &Tensor::arange1(0, min_tokens_to_keep + 1, (Int64, logits.device())), 0);
-                }
-                let _ = sorted_indices_to_remove.index_copy_(1,
-                                                             &Tensor::arange1(1, vocab_size, (Int64, logits.device())),
-                                                             &sorted_indices_to_remove.slice(1, 0, vocab_size - 1, 1).copy());
-                let _ = sorted_indices_to_remove.index_fill_(1, &Tensor::of_slice(&[0]).to_kind(Int64).to_device(sorted_indices_to_remove.device()), 0);
-                let indices_to_remove = sorted_indices_to_remove.scatter(1, &sorted_indices, &sorted_indices_to_remove).to_kind(Bool);
-                let _ = logits.masked_fill_(&indices_to_remove, std::f64::NEG_INFINITY);
+        /// Builds the stopping criteria set by the generation configuration (stop sequences, maximum number of new tokens and time budget)
+        fn build_stopping_criteria(&self, config: &GenerateConfig) -> Vec<Box<dyn StoppingCriteria>> {
+            let mut stopping_criteria: Vec<Box<dyn StoppingCriteria>> = vec!();
+            if let Some(stop_sequences) = &config.stop_sequences {
+                stopping_criteria.push(Box::new(StopSequencesCriteria::new(stop_sequences.clone())));
+            }
+            if let Some(max_new_tokens) = config.max_new_tokens {
+                stopping_criteria.push(Box::new(MaxNewTokensCriteria::new(max_new_tokens as usize)));
+            }
+            if let Some(max_time) = config.max_time {
+                stopping_criteria.push(Box::new(MaxTimeCriteria::new(max_time)));
             }
+            stopping_criteria
         }
 
-        fn generate_no_beam_search(&mut self, input_ids: Tensor, encoder_outputs: Option<Tensor>,
-                                   cur_len: i64, min_length: i64, max_length: i64, do_sample: bool,
-                                   temperature: f64, top_k: i64, top_p: f64, repetition_penalty: f64, no_repeat_ngram_size: i64,
-                                   pad_token_id: Option<i64>, eos_token_ids: Option<Vec<i64>>,
-                                   batch_size: i64, attention_mask: Tensor) -> Tensor {
-            let mut unfinished_sentences = Tensor::ones(&[batch_size], (Int64, self.get_var_store().device()));
-            let mut sentence_lengths: Tensor = Tensor::ones(&[batch_size], (Int64, self.get_var_store().device())) * max_length as i64;
-            let mut attention_mask = attention_mask.copy();
-            let mut input_ids = input_ids.copy();
-            let mut past: Option<Vec<Tensor>> = None;
-            let mut outputs: Tensor;
-            let mut current_length = cur_len;
+        /// Builds the processors truncating the distribution the tokens are sampled from (top-k, top-p, typical, min-p, epsilon and eta sampling)
+        fn build_sampling_processors(&self, config: &GenerateConfig, min_tokens_to_keep: i64) -> Vec<Box<dyn LogitsProcessor>> {
+            let mut sampling_processors: Vec<Box<dyn LogitsProcessor>> = vec!(Box::new(TopKTopPProcessor::new(config.top_k as i64, config.top_p, min_tokens_to_keep)));
+            if config.typical_p < 1f64 {
+                sampling_processors.push(Box::new(TypicalProcessor::new(config.typical_p, min_tokens_to_keep)));
+            }
+            if config.min_p > 0f64 {
+                sampling_processors.push(Box::new(MinPProcessor::new(config.min_p, min_tokens_to_keep)));
+            }
+            if config.epsilon_cutoff > 0f64 {
+                sampling_processors.push(Box::new(EpsilonProcessor::new(config.epsilon_cutoff, min_tokens_to_keep)));
+            }
+            if config.eta_cutoff > 0f64 {
+                sampling_processors.push(Box::new(EtaProcessor::new(config.eta_cutoff, min_tokens_to_keep)));
+            }
+            sampling_processors
+        }
 
-            while current_length < max_length {
-                let (prepared_input,
-                    prepared_encoder_output,
-                    prepared_decoder_input,
-                    prepared_past) = self.prepare_inputs_for_generation(input_ids.copy(),
-                                                                        encoder_outputs.as_ref(),
-                                                                        past,
-                                                                        attention_mask.copy());
-                let temp = self.get_model().forward_t(&prepared_input,
-                                                      &prepared_past,
-                                                      &None,
-                                                      &None,
-                                                      &None,
-                                                      &None,
-                                                      prepared_encoder_output,
-                                                      &prepared_decoder_input,
-                                                      false).unwrap();
-                outputs = temp.0;
-                past = temp.2;
-                let mut next_token_logits = outputs.select(1, -1);
-//            Reduce probability for repeated inputs
-                if repetition_penalty > 1f64 {
-                    self.enforce_repetition_penalty(&mut next_token_logits, batch_size, 1, &input_ids, repetition_penalty)
-                }
-//            Get banned tokens and set their probability to 0
-                let banned_tokens = self.get_banned_tokens(&input_ids, no_repeat_ngram_size as i64, current_length as i64);
-                for (batch_index, index_banned_token) in (0..banned_tokens.len() as i64).zip(banned_tokens) {
-                    &next_token_logits.get(batch_index).index_fill_(0, &Tensor::of_slice(&index_banned_token).to_device(next_token_logits.device()), std::f64::NEG_INFINITY);
+        /// Processes the scores of the token following `sequence` as done by `generate_no_beam_search`
+        fn process_speculative_scores(&self, mut scores: Tensor, sequence: &[i64], prompt_length: i64,
+                                      logits_processors: &[Box<dyn LogitsProcessor>], sampling_processors: &[Box<dyn LogitsProcessor>]) -> Tensor {
+            let config = self.get_config();
+            let input_ids = Tensor::of_slice(sequence).view((1, -1)).to(scores.device());
+            self.process_logits(logits_processors, &mut scores, &input_ids, prompt_length);
+            if config.do_sample {
+                if config.temperature > 1f64 {
+                    scores = scores / config.temperature;
                 }
-//            Do not allow eos token if min length is not reached
-                if (&eos_token_ids.is_some()) & (current_length < min_length) {
-                    &next_token_logits.index_fill_(1, &Tensor::of_slice(eos_token_ids.as_ref().unwrap()).to(next_token_logits.device()), std::f64::NEG_INFINITY);
+                for sampling_processor in sampling_processors.iter() {
+                    sampling_processor.process(&mut scores, &input_ids, prompt_length);
                 }
+            }
+            scores
+        }
+
+        /// Speculative decoding ([Leviathan et al.](https://arxiv.org/abs/2211.17192)) of each prompt: the draft model proposes `num_speculative_tokens`
+        /// tokens which are verified with a single forward pass of the model. Returns the generated sequences (including the prompts), the number of
+        /// tokens proposed by the draft model and the number of proposed tokens accepted.
+        fn generate_speculative_indices<D: LMHeadModel>(&mut self, draft_model: &mut D, prompts_ids: Vec<Vec<i64>>, num_speculative_tokens: i64)
+                                                        -> (Vec<Vec<i64>>, usize, usize) {
+            let config = self.get_config();
+            let do_sample = config.do_sample;
+            let max_length = config.max_length as usize;
+            let max_new_tokens = config.max_new_tokens;
+            let mut rng = config.seed.map(SeededRng::new);
+            let eos_token_ids = self.get_eos_ids().clone().unwrap_or_default();
+            let logits_processors = self.build_logits_processors(config, 1);
+            let sampling_processors = self.build_sampling_processors(config, 1);
+            let device = self.get_var_store().device();
+
+            let mut num_draft_tokens = 0usize;
+            let mut num_accepted_tokens = 0usize;
+            let mut output = Vec::with_capacity(prompts_ids.len());
+            for prompt_ids in prompts_ids {
+                let prompt_length = prompt_ids.len();
+                let max_length = match max_new_tokens {
+                    Some(max_new_tokens) => min(max_length, prompt_length + max_new_tokens as usize),
+                    None => max_length
+                };
+                let mut sequence = prompt_ids;
+//            The caches of both models hold the keys and values of the first `cache_length` tokens of the sequence
+                let mut past: Option<Vec<Tensor>> = None;
+                let mut draft_past: Option<Vec<Tensor>> = None;
+                let mut cache_length = 0usize;
+                let mut draft_cache_length = 0usize;
+
+                while sequence.len() < max_length {
+//                The draft model proposes tokens one at a time. Fewer tokens are proposed close to the maximum length.
+                    let num_proposals = min(num_speculative_tokens as usize, max_length - sequence.len() - 1);
+                    let mut context = sequence.clone();
+                    let mut draft_probabilities: Vec<Tensor> = Vec::with_capacity(num_proposals);
+                    for _ in 0..num_proposals {
+                        let (logits, new_draft_past) = forward_with_past(draft_model, &context[draft_cache_length..], draft_past, device);
+                        draft_cache_length = if new_draft_past.is_some() { context.len() } else { 0 };
+                        draft_past = new_draft_past;
+                        let scores = self.process_speculative_scores(logits.get(-1).unsqueeze(0), &context, prompt_length as i64,
+                                                                     &logits_processors, &sampling_processors);
+                        let draft_token = if do_sample {
+                            let probabilities = scores.softmax(-1, Float);
+                            let draft_token = sample(&probabilities, 1, rng.as_mut()).int64_value(&[0, 0]);
+                            draft_probabilities.push(probabilities.get(0));
+                            draft_token
+                        } else {
+                            scores.argmax(-1, false).int64_value(&[0])
+                        };
+                        context.push(draft_token);
+                    }
+                    num_draft_tokens += num_proposals;
+
+//                The model scores all the proposed tokens in a single forward pass. The scores at the position of the i-th proposed token
+//                give the distribution of the (i+1)-th one, the scores of the last position give the distribution of an additional token.
+                    let (logits, new_past) = forward_with_past(self.get_model(), &context[cache_length..], past, device);
+                    cache_length = if new_past.is_some() { context.len() } else { 0 };
+                    past = new_past;
+                    let first_position = *logits.size().first().unwrap() - num_proposals as i64 - 1;
+                    let mut num_accepted = 0usize;
+                    let mut next_token = None;
+                    for proposal_index in 0..=num_proposals {
+                        let proposal_position = sequence.len() + proposal_index;
+                        let scores = self.process_speculative_scores(logits.get(first_position + proposal_index as i64).unsqueeze(0),
+                                                                     &context[..proposal_position], prompt_length as i64,
+                                                                     &logits_processors, &sampling_processors);
+                        if do_sample {
+                            let probabilities = scores.softmax(-1, Float).get(0);
+                            if proposal_index < num_proposals {
+//                            A token proposed with probability q is accepted with probability min(1, p / q), p being its probability for the model
+                                let draft_token = context[proposal_position];
+                                let probability = probabilities.double_value(&[draft_token]);
+                                let draft_probability = draft_probabilities[proposal_index].double_value(&[draft_token]);
+                                if uniform(rng.as_mut()) * draft_probability < probability {
+                                    num_accepted += 1;
+                                    continue;
+                                }
+//                            A rejected token is replaced by a sample from max(0, p - q) (normalized), so that the tokens follow the distribution of the model
+                                let residual_probabilities = (probabilities - &draft_probabilities[proposal_index]).relu();
+                                next_token = Some(sample(&residual_probabilities.unsqueeze(0), 1, rng.as_mut()).int64_value(&[0, 0]));
+                            } else {
+                                next_token = Some(sample(&probabilities.unsqueeze(0), 1, rng.as_mut()).int64_value(&[0, 0]));
+                            }
+                        } else {
+                            let token = scores.argmax(-1, false).int64_value(&[0]);
+                            if context.get(proposal_position) == Some(&token) {
+                                num_accepted += 1;
+                                continue;
+                            }
+                            next_token = Some(token);
+                        }
+                        break;
+                    }
+                    num_accepted_tokens += num_accepted;
+
+                    let round_start = sequence.len();
+                    sequence.extend_from_slice(&context[round_start..round_start + num_accepted]);
+                    sequence.push(next_token.unwrap());
+//                Cached positions of the rejected tokens are dropped. The last token is fed to both models at the next round.
+                    cache_length = min(cache_length, sequence.len() - 1);
+                    past = truncate_past(past, cache_length);
+                    draft_cache_length = min(draft_cache_length, sequence.len() - 1);
+                    draft_past = truncate_past(draft_past, draft_cache_length);
+
+                    if let Some(eos_position) = sequence[round_start..].iter().position(|token| eos_token_ids.contains(token)) {
+                        sequence.truncate(round_start + eos_position + 1);
+                        break;
+                    }
+                }
+                output.push(sequence);
+            }
+            (output, num_draft_tokens, num_accepted_tokens)
+        }
+
+        fn has_stopping_criteria(&self, stopping_criteria: &[Box<dyn StoppingCriteria>]) -> bool {
+            !stopping_criteria.is_empty() | !self.get_stopping_criteria().is_empty()
+        }
+
+        fn check_stopping_criteria(&self, stopping_criteria: &[Box<dyn StoppingCriteria>], generated_ids: &[i64], start_time: Instant) -> Option<StopSignal> {
+            if !self.has_stopping_criteria(stopping_criteria) {
+                return None;
+            }
+            let generated_text = self.get_tokenizer().decode(generated_ids.to_vec(), true, true);
+            let elapsed = start_time.elapsed();
+            stopping_criteria
+                .iter()
+                .chain(self.get_stopping_criteria().iter())
+                .find_map(|criteria| criteria.check(generated_ids, &generated_text, elapsed))
+        }
+
+        /// Decodes the generated sequences, trimming the text of the sequences stopped by a stopping criteria
+        fn decode_sequences(&self, decoded: &Tensor, details: &[SequenceDetails]) -> Vec<String> {
+            let num_sequences = *decoded.size().first().unwrap();
+            let mut output = Vec::with_capacity(num_sequences as usize);
+            for sequence_index in 0..num_sequences {
+                let sequence_ids = decoded
+                    .as_ref()
+                    .get(sequence_index)
+                    .iter::<i64>()
+                    .unwrap()
+                    .collect::<Vec<i64>>();
+                let sequence_details = &details[sequence_index as usize];
+                let text = match sequence_details.text_length {
+                    Some(text_length) => self.decode_truncated(&sequence_ids[..sequence_details.prompt_length], &sequence_details.token_ids, text_length),
+                    None => self.get_tokenizer().decode(sequence_ids, true, true)
+                };
+                output.push(text);
+            }
+            output
+        }
+
+        /// Decodes a prompt followed by the generated tokens, keeping only the first `text_length` bytes of the text decoded from the generated tokens
+        /// (position in the generated text at which a stopping criteria trimmed the output). The generated tokens decoded entirely before that position
+        /// are decoded with the prompt, and the kept part of the next token is taken from the generated text.
+        fn decode_truncated(&self, prompt_ids: &[i64], generated_ids: &[i64], text_length: usize) -> String {
+            let generated_text = self.get_tokenizer().decode(generated_ids.to_vec(), true, true);
+            let (num_kept_tokens, kept_tokens_length) = (0..=generated_ids.len())
+                .map(|num_tokens| (num_tokens, self.get_tokenizer().decode(generated_ids[..num_tokens].to_vec(), true, true)))
+                .take_while(|(_, text)| (text.len() <= text_length) & generated_text.starts_with(text.as_str()))
+                .map(|(num_tokens, text)| (num_tokens, text.len()))
+                .last()
+                .unwrap_or((0, 0));
+            let mut sequence_ids = prompt_ids.to_vec();
+            sequence_ids.extend_from_slice(&generated_ids[..num_kept_tokens]);
+            let mut text = self.get_tokenizer().decode(sequence_ids, true, true);
+            text.push_str(generated_text.get(kept_tokens_length..text_length).unwrap_or(""));
+            text
+        }
+
+        fn generate_no_beam_search(&mut self, input_ids: Tensor, encoder_outputs: Option<Tensor>, past: Option<Vec<Tensor>>,
+                                   cur_len: i64, max_length: i64, do_sample: bool,
+                                   temperature: f64, sampling_processors: Vec<Box<dyn LogitsProcessor>>, logits_processors: Vec<Box<dyn LogitsProcessor>>,
+                                   pad_token_id: Option<i64>, eos_token_ids: Option<Vec<i64>>,
+                                   batch_size: i64, attention_mask: Tensor, mut streamer: Option<TokenStreamer>,
+                                   mut rng: Option<SeededRng>, stopping_criteria: Vec<Box<dyn StoppingCriteria>>,
+                                   start_time: Instant) -> (Tensor, Vec<SequenceDetails>) {
+            let mut unfinished_sentences = Tensor::ones(&[batch_size], (Int64, self.get_var_store().device()));
+//            The maximum length of each sequence does not count the masked positions of its prompt (left padding),
+//            so that each prompt of a batch generates as many tokens as when generated alone
+            let max_lengths: Tensor = if self.is_encoder_decoder() {
+                Tensor::ones(&[batch_size], (Int64, self.get_var_store().device())) * max_length
+            } else {
+                attention_mask.eq(0).sum1(&[-1], false, Int64) + max_length
+            };
+            let batch_max_length = i64::from(max_lengths.max());
+            let mut sentence_lengths = max_lengths.copy();
+            let mut attention_mask = attention_mask.copy();
+            let mut input_ids = input_ids.copy();
+            let mut past = past;
+            let mut outputs: Tensor;
+            let mut token_logprobs: Vec<Tensor> = vec!();
+            let mut stop_signals: Vec<Option<StopSignal>> = vec!(None; batch_size as usize);
+            let has_stopping_criteria = self.has_stopping_criteria(&stopping_criteria);
+            let mut current_length = cur_len;
 
-//            Top-k and top-p sampling
+            while current_length < batch_max_length {
+                let (prepared_input,
+                    prepared_encoder_output,
+                    prepared_decoder_input,
+                    prepared_past,
+                    prepared_attention_mask,
+                    prepared_position_ids) = self.prepare_inputs_for_generation(input_ids.copy(),
+                                                                                encoder_outputs.as_ref(),
+                                                                                past,
+                                                                                attention_mask.copy());
+                let temp = self.get_model().forward_t(&prepared_input,
+                                                      &prepared_past,
+                                                      &prepared_attention_mask,
+                                                      &None,
+                                                      &prepared_position_ids,
+                                                      &None,
+                                                      prepared_encoder_output,
+                                                      &prepared_decoder_input,
+                                                      false).unwrap();
+                outputs = temp.0;
+                past = temp.2;
+                let mut next_token_logits = outputs.select(1, -1);
+//            Apply the logits processors (repetition penalty, banned n-grams, minimum length and processors attached to the generator)
+                self.process_logits(&logits_processors, &mut next_token_logits, &input_ids, cur_len);
+
+//            Sampling from the distribution truncated by the sampling processors (e.g. top-k and top-p)
                 let next_token = if do_sample {
                     if temperature > 1f64 {
                         next_token_logits = next_token_logits / temperature;
                     }
-                    self.top_k_top_p_filtering(&mut next_token_logits, top_k as i64, top_p, 1);
+                    for sampling_processor in sampling_processors.iter() {
+                        sampling_processor.process(&mut next_token_logits, &input_ids, cur_len);
+                    }
                     let probabilities = next_token_logits.softmax(-1, Float);
-                    probabilities.multinomial(1, false).squeeze1(1)
+                    sample(&probabilities, 1, rng.as_mut()).squeeze1(1)
                 } else {
                     next_token_logits.argmax(-1, false)
                 };
+                token_logprobs.push(next_token_logits.log_softmax(-1, Float).gather(1, &next_token.unsqueeze(-1), false).squeeze1(1));
 
 //            Add tokens to unfinished sentences
-                let tokens_to_add = match &eos_token_ids {
-                    Some(_) => next_token * &unfinished_sentences - pad_token_id.unwrap() * (&unfinished_sentences - 1),
+                let tokens_to_add = match pad_token_id {
+                    Some(pad_token_id) => next_token * &unfinished_sentences - pad_token_id * (&unfinished_sentences - 1),
                     None => next_token
                 };
 
                 input_ids = Tensor::cat(&[input_ids, tokens_to_add.unsqueeze(-1)], -1);
+//            Stream the new tokens of the sentences that were not finished before this step
+                let continue_generation = match streamer.as_mut() {
+                    Some(streamer) => {
+                        let generated_ids = input_ids.slice(1, cur_len, current_length + 1, 1).to(Device::Cpu);
+                        let unfinished = unfinished_sentences.to(Device::Cpu);
+                        let sequences = (0..batch_size)
+                            .map(|sequence_index| if unfinished.int64_value(&[sequence_index]) > 0 {
+                                Some(generated_ids.get(sequence_index).iter::<i64>().unwrap().collect::<Vec<i64>>())
+                            } else {
+                                None
+                            })
+                            .collect::<Vec<Option<Vec<i64>>>>();
+                        streamer.emit(self.get_tokenizer(), sequences)
+                    }
+                    None => true
+                };
                 if eos_token_ids.is_some() {
                     for eos_token_id in eos_token_ids.as_ref().unwrap() {
                         let sentence_with_eos = tokens_to_add.eq(*eos_token_id).to_kind(Int64);
@@ -747,10 +2441,42 @@ mod private_generation_utils {
                         let _ = sentence_lengths.masked_fill_(&sentence_with_eos.to_kind(Bool).to_device(sentence_lengths.device()), current_length as i64 + 1);
                         unfinished_sentences = -unfinished_sentences * (sentence_with_eos - 1);
                     }
-                    if i64::from(unfinished_sentences.max()) == 0 {
-                        break;
+                }
+//            Sentences of padded prompts reach their maximum length before the end of the loop
+                if current_length + 1 < batch_max_length {
+                    let sentence_at_max_length = max_lengths.eq(current_length + 1).to_kind(Int64) * &unfinished_sentences;
+                    if i64::from(sentence_at_max_length.max()) > 0 {
+                        let sentence_at_max_length = sentence_at_max_length.to(Device::Cpu);
+                        for sequence_index in 0..batch_size {
+                            if sentence_at_max_length.int64_value(&[sequence_index]) > 0 {
+                                let _ = unfinished_sentences.get(sequence_index).fill_(0);
+                                stop_signals[sequence_index as usize] = Some(StopSignal { finish_reason: FinishReason::MaxLength, text_length: None });
+                            }
+                        }
+                    }
+                }
+//            Check the stopping criteria for the sentences still being generated
+                if has_stopping_criteria {
+                    let generated_ids = input_ids.slice(1, cur_len, current_length + 1, 1).to(Device::Cpu);
+                    let unfinished = unfinished_sentences.to(Device::Cpu);
+                    for sequence_index in 0..batch_size {
+                        if unfinished.int64_value(&[sequence_index]) == 0 {
+                            continue;
+                        }
+                        let sequence = generated_ids.get(sequence_index).iter::<i64>().unwrap().collect::<Vec<i64>>();
+                        if let Some(stop_signal) = self.check_stopping_criteria(&stopping_criteria, &sequence, start_time) {
+                            let _ = unfinished_sentences.get(sequence_index).fill_(0);
+                            let _ = sentence_lengths.get(sequence_index).fill_(current_length + 1);
+                            stop_signals[sequence_index as usize] = Some(stop_signal);
+                        }
                     }
                 }
+                if (eos_token_ids.is_some() | has_stopping_criteria) & (i64::from(unfinished_sentences.max()) == 0) {
+                    break;
+                }
+                if !continue_generation {
+                    break;
+                }
                 if !self.is_encoder_decoder() {
                     attention_mask = Tensor::cat(&[attention_mask.as_ref(), Tensor::ones(&[*attention_mask.size().first().unwrap(), 1],
                                                                                          (Int64, attention_mask.device())).as_ref()], -1);
@@ -758,6 +2484,43 @@ mod private_generation_utils {
                 current_length += 1;
             }
 
+//            Generation may have been interrupted before max_length was reached
+            let sentence_lengths = sentence_lengths.clamp_max(*input_ids.size().last().unwrap());
+
+            let cpu_input_ids = input_ids.to(Device::Cpu);
+            let token_logprobs = if token_logprobs.is_empty() {
+                Tensor::zeros(&[batch_size, 0], (Float, Device::Cpu))
+            } else {
+                Tensor::stack(&token_logprobs, 1).to(Device::Cpu)
+            };
+            let unfinished_sentences = unfinished_sentences.to(Device::Cpu);
+            let details = (0..batch_size)
+                .map(|sequence_index| {
+                    let num_generated_tokens = sentence_lengths.int64_value(&[sequence_index]) - cur_len;
+                    let token_ids = cpu_input_ids
+                        .get(sequence_index)
+                        .slice(0, cur_len, cur_len + num_generated_tokens, 1)
+                        .iter::<i64>()
+                        .unwrap()
+                        .collect::<Vec<i64>>();
+                    let token_logprobs = token_logprobs
+                        .get(sequence_index)
+                        .slice(0, 0, num_generated_tokens, 1)
+                        .iter::<f64>()
+                        .unwrap()
+                        .collect::<Vec<f64>>();
+                    let score = token_logprobs.iter().sum();
+                    let (finish_reason, text_length) = match stop_signals[sequence_index as usize] {
+                        Some(stop_signal) => (stop_signal.finish_reason, stop_signal.text_length),
+                        None => if unfinished_sentences.int64_value(&[sequence_index]) == 0 {
+                            (FinishReason::Eos, None)
+                        } else {
+                            (FinishReason::MaxLength, None)
+                        }
+                    };
+                    SequenceDetails { prompt_length: cur_len as usize, token_ids, token_logprobs, score, finish_reason, text_length }
+                })
+                .collect::<Vec<SequenceDetails>>();
             let decoded = if i64::from(&sentence_lengths.min().ne1(&sentence_lengths.max())) > 0 {
                 match pad_token_id {
                     Some(pad_value) => {
@@ -776,14 +2539,20 @@ mod private_generation_utils {
             } else {
                 input_ids
             };
-            decoded
+            (decoded, details)
         }
 
-        fn generate_beam_search(&mut self, input_ids: Tensor, encoder_outputs: Option<Tensor>,
-                                cur_len: i64, min_length: i64, max_length: i64, do_sample: bool, early_stopping: bool,
-                                temperature: f64, top_k: i64, top_p: f64, repetition_penalty: f64, no_repeat_ngram_size: i64,
-                                pad_token_id: Option<i64>, eos_token_ids: Option<Vec<i64>>,
-                                batch_size: i64, num_return_sequences: i64, length_penalty: f64, num_beams: i64, attention_mask: Tensor) -> Tensor {
+        fn generate_beam_search(&mut self, input_ids: Tensor, encoder_outputs: Option<Tensor>, past: Option<Vec<Tensor>>,
+                                cur_len: i64, max_length: i64, do_sample: bool, early_stopping: bool,
+                                temperature: f64, sampling_processors: Vec<Box<dyn LogitsProcessor>>, penalty_processors: Vec<Box<dyn LogitsProcessor>>,
+                                logits_processors: Vec<Box<dyn LogitsProcessor>>, force_words_ids: Option<Vec<Vec<Vec<i64>>>>, pad_token_id: Option<i64>, eos_token_ids: Option<Vec<i64>>,
+                                batch_size: i64, num_return_sequences: i64, length_penalty: f64, num_beams: i64, num_beam_groups: i64, diversity_penalty: f64,
+                                attention_mask: Tensor, mut streamer: Option<TokenStreamer>, mut rng: Option<SeededRng>,
+                                stopping_criteria: Vec<Box<dyn StoppingCriteria>>, start_time: Instant) -> (Tensor, Vec<SequenceDetails>) {
+//            Diverse beam search: each group of beams is searched as a separate batch item (the beams of an input and of its groups are contiguous).
+//            The batch size and number of beams below refer to the groups, the hypotheses of the groups of an input are merged at the end.
+            let input_batch_size = batch_size;
+            let (batch_size, num_beams) = (batch_size * num_beam_groups, num_beams / num_beam_groups);
             let mut hypotheses = (0..batch_size)
                 .map(|_| BeamHypotheses::new(num_beams, max_length, length_penalty, early_stopping))
                 .collect::<Vec<BeamHypotheses>>();
@@ -797,28 +2566,32 @@ mod private_generation_utils {
             let mut beam_scores = beam_scores.view_(&[-1]);
             let mut beam_tokens: Tensor;
             let mut beam_indices: Tensor;
-            let mut past: Option<Vec<Tensor>> = None;
+            let mut past = past;
             let mut done = vec!(false; batch_size as usize);
+            let mut beam_logprobs: Vec<Vec<f64>> = vec!(vec!(); (batch_size * num_beams) as usize);
 
             let mut attention_mask = attention_mask.copy();
             let mut input_ids = input_ids.copy();
             let mut outputs: Tensor;
             let mut encoder_outputs = encoder_outputs;
+            let has_stopping_criteria = self.has_stopping_criteria(&stopping_criteria);
             let mut current_length = cur_len;
 
             while current_length < max_length {
                 let (prepared_input,
                     prepared_encoder_output,
                     prepared_decoder_input,
-                    prepared_past) = self.prepare_inputs_for_generation(input_ids.copy(),
-                                                                        encoder_outputs.as_ref(),
-                                                                        past,
-                                                                        attention_mask.copy());
+                    prepared_past,
+                    prepared_attention_mask,
+                    prepared_position_ids) = self.prepare_inputs_for_generation(input_ids.copy(),
+                                                                                encoder_outputs.as_ref(),
+                                                                                past,
+                                                                                attention_mask.copy());
                 let temp = self.get_model().forward_t(&prepared_input,
                                                       &prepared_past,
+                                                      &prepared_attention_mask,
                                                       &None,
-                                                      &None,
-                                                      &None,
+                                                      &prepared_position_ids,
                                                       &None,
                                                       prepared_encoder_output,
                                                       &prepared_decoder_input,
@@ -828,10 +2601,9 @@ mod private_generation_utils {
                 past = temp.2;
 
                 let mut next_token_logits = outputs.select(1, -1);
-
-//            Reduce probability for repeated inputs
-                if repetition_penalty > 1f64 {
-                    self.enforce_repetition_penalty(&mut next_token_logits, batch_size, 1, &input_ids, repetition_penalty)
+//            Reduce probability for repeated inputs (applied to the logits, as the penalty depends on their sign)
+                for penalty_processor in penalty_processors.iter() {
+                    penalty_processor.process(&mut next_token_logits, &input_ids, cur_len);
                 }
 
                 if temperature > 1f64 {
@@ -841,23 +2613,19 @@ mod private_generation_utils {
                 if self.is_encoder_decoder() & !do_sample {
                     self.prepare_scores_for_generation(&mut scores, current_length, max_length);
                 }
-//            Do not allow eos token if min length is not reached
-                if (&eos_token_ids.is_some()) & (current_length < min_length) {
-                    &scores.index_fill_(1, &Tensor::of_slice(eos_token_ids.as_ref().unwrap()).to(scores.device()), std::f64::NEG_INFINITY);
-                }
-//            Get banned tokens and set their probability to 0
-                let banned_tokens = self.get_banned_tokens(&input_ids, no_repeat_ngram_size as i64, current_length as i64);
-                for (batch_index, index_banned_token) in (0..banned_tokens.len() as i64).zip(banned_tokens) {
-                    &scores.get(batch_index).index_fill_(0, &Tensor::of_slice(&index_banned_token).to_device(next_token_logits.device()), std::f64::NEG_INFINITY);
-                }
+//            Apply the logits processors (banned n-grams, minimum length and processors attached to the generator)
+                self.process_logits(&logits_processors, &mut scores, &input_ids, cur_len);
 
                 let (next_scores, next_tokens) = if do_sample {
-                    let mut _scores: Tensor = &scores + &beam_scores.unsqueeze(-1).expand_as(&scores);
-                    self.top_k_top_p_filtering(&mut _scores, top_k as i64, top_p, 2);
+//                Filtering the token scores is equivalent to filtering the beam scores (constant for a given beam)
+                    for sampling_processor in sampling_processors.iter() {
+                        sampling_processor.process(&mut scores, &input_ids, cur_len);
+                    }
+                    let _scores: Tensor = &scores + &beam_scores.unsqueeze(-1).expand_as(&scores);
                     let _scores = _scores.contiguous().view((batch_size, num_beams * vocab_size));
 
                     let probabilities = _scores.softmax(-1, Float);
-                    let next_tokens = probabilities.multinomial(2 * num_beams, false);
+                    let next_tokens = sample(&probabilities, 2 * num_beams, rng.as_mut());
                     let next_scores = _scores.gather(-1, &next_tokens, false);
                     let (next_scores, next_scores_indices) = next_scores.sort(1, true);
                     let next_tokens = next_tokens.gather(-1, &next_scores_indices, false);
@@ -868,12 +2636,22 @@ mod private_generation_utils {
                     next_scores.topk(2 * num_beams, 1, true, true)
                 };
 
+                let previous_beam_scores = beam_scores.to(Device::Cpu).iter::<f64>().unwrap().collect::<Vec<f64>>();
+                let generated_ids = if force_words_ids.is_some() | has_stopping_criteria {
+                    let generated_ids = input_ids.slice(1, cur_len, current_length, 1).to(Device::Cpu);
+                    (0..*generated_ids.size().first().unwrap())
+                        .map(|beam_index| generated_ids.get(beam_index).iter::<i64>().unwrap().collect::<Vec<i64>>())
+                        .collect::<Vec<Vec<i64>>>()
+                } else {
+                    vec!()
+                };
                 let mut next_batch_beam: Vec<(f64, i64, i64)> = vec!();
+                let mut group_tokens: Vec<Vec<i64>> = vec!(vec!(); input_batch_size as usize);
                 for batch_index in 0..batch_size {
                     if done[batch_index as usize] {
                         assert!(hypotheses[batch_index as usize].len() >= num_beams,
                                 "Batch cannot be completed if all beams have not been generated");
-                        assert!(eos_token_ids.is_some() & pad_token_id.is_some(),
+                        assert!((eos_token_ids.is_some() | has_stopping_criteria) & pad_token_id.is_some(),
                                 "EOS and Padding tokens need to be defined if the number of generated \
                             beams is greater than the target number fo beams");
                         next_batch_beam.append(&mut
@@ -883,41 +2661,159 @@ mod private_generation_utils {
                     }
 
                     let mut next_sentence_beam: Vec<(f64, i64, i64)> = vec!();
+//                Diverse beam search: the scores of the tokens selected by the previous groups of the same input at this step are penalized
+                    let previous_group_tokens = &group_tokens[(batch_index / num_beam_groups) as usize];
+                    let (batch_next_scores, batch_next_tokens) = if (diversity_penalty > 0f64) & !previous_group_tokens.is_empty() {
+                        let group_token_scores = scores.slice(0, batch_index * num_beams, (batch_index + 1) * num_beams, 1);
+                        let group_beam_scores = beam_scores.slice(0, batch_index * num_beams, (batch_index + 1) * num_beams, 1).to_kind(Float);
+                        let previous_group_tokens = Tensor::of_slice(previous_group_tokens).to(scores.device());
+                        let token_frequencies = Tensor::zeros(&[vocab_size], (Float, scores.device()))
+                            .scatter_add(0, &previous_group_tokens, &previous_group_tokens.ones_like().to_kind(Float));
+                        let group_scores: Tensor = group_token_scores + group_beam_scores.unsqueeze(-1) - token_frequencies.unsqueeze(0) * diversity_penalty;
+                        group_scores.contiguous().view(-1).topk(2 * num_beams, 0, true, true)
+                    } else {
+                        (next_scores.get(batch_index), next_tokens.get(batch_index))
+                    };
 
-                    let mut beam_token_rank = 0;
-                    let beam_token_rank_max_value = *next_tokens.get(batch_index).size().first().unwrap() - 1;
-                    loop {
-                        let beam_token_id = next_tokens.int64_value(&[batch_index, beam_token_rank]);
-                        let beam_token_score = next_scores.double_value(&[batch_index, beam_token_rank]);
-                        let beam_id = beam_token_id / vocab_size;
-                        let token_id = beam_token_id % vocab_size;
-
-                        let effective_beam_id = batch_index * num_beams + beam_id;
+                    if let Some(force_words_ids) = &force_words_ids {
+//                    Constrained beam search: the tokens advancing the forced words are considered in addition to the top candidates and the
+//                    next beams are picked in turn from banks grouping the candidates by constraint progress (Hokamp & Liu, https://arxiv.org/abs/1704.07138)
+                        let mut candidates = (0..*batch_next_tokens.size().first().unwrap())
+                            .map(|beam_token_rank| {
+                                let beam_token_id = batch_next_tokens.int64_value(&[beam_token_rank]);
+                                (batch_next_scores.double_value(&[beam_token_rank]),
+                                 beam_token_id % vocab_size,
+                                 batch_index * num_beams + beam_token_id / vocab_size)
+                            })
+                            .collect::<Vec<(f64, i64, i64)>>();
+                        for beam_id in 0..num_beams {
+                            let effective_beam_id = batch_index * num_beams + beam_id;
+                            let (_, _, advancing_tokens) = get_constraints_progress(force_words_ids, &generated_ids[effective_beam_id as usize]);
+                            for token_id in advancing_tokens {
+                                let beam_token_score = scores.double_value(&[effective_beam_id, token_id]) + previous_beam_scores[effective_beam_id as usize];
+                                candidates.push((beam_token_score, token_id, effective_beam_id));
+                            }
+                        }
+                        candidates.sort_by_key(|(beam_token_score, _, _)| Reverse(OrderedFloat(*beam_token_score)));
+
+//                    Identical beams (e.g. at the first step) lead to duplicate candidates, only the best scoring one is kept
+                        let mut candidate_sequences: HashSet<Vec<i64>> = HashSet::new();
+                        let mut banks: BTreeMap<usize, Vec<(f64, i64, i64)>> = BTreeMap::new();
+                        for (beam_token_rank, (beam_token_score, token_id, effective_beam_id)) in candidates.into_iter().enumerate() {
+                            if beam_token_score == std::f64::NEG_INFINITY {
+                                continue;
+                            }
+                            let mut sequence = generated_ids[effective_beam_id as usize].clone();
+                            sequence.push(token_id);
+                            if !candidate_sequences.insert(sequence.clone()) {
+                                continue;
+                            }
+                            if eos_token_ids.as_ref().map_or(false, |eos_ids| eos_ids.contains(&token_id)) {
+                                let (_, constraints_fulfilled, _) = get_constraints_progress(force_words_ids, &generated_ids[effective_beam_id as usize]);
+                                if constraints_fulfilled & (beam_token_rank < num_beams as usize) {
+                                    let mut hypothesis_logprobs = beam_logprobs[effective_beam_id as usize].clone();
+                                    hypothesis_logprobs.push(scores.double_value(&[effective_beam_id, token_id]));
+                                    hypotheses[batch_index as usize].add(input_ids.get(effective_beam_id).copy(), beam_token_score, hypothesis_logprobs, Some(token_id), None)
+                                }
+                                continue;
+                            }
+//                        Sequences ended by the stopping criteria are kept if they satisfy the constraints or if not enough hypotheses were found
+                            if let Some(stop_signal) = self.check_stopping_criteria(&stopping_criteria, &sequence, start_time) {
+                                let (_, constraints_fulfilled, _) = get_constraints_progress(force_words_ids, &sequence);
+                                if constraints_fulfilled | (hypotheses[batch_index as usize].len() < num_beams) {
+                                    let mut hypothesis_logprobs = beam_logprobs[effective_beam_id as usize].clone();
+                                    hypothesis_logprobs.push(scores.double_value(&[effective_beam_id, token_id]));
+                                    let hypothesis = Tensor::cat(&[input_ids.get(effective_beam_id), Tensor::of_slice(&[token_id]).to(input_ids.device())], 0);
+                                    hypotheses[batch_index as usize].add(hypothesis, beam_token_score, hypothesis_logprobs, None, Some(stop_signal));
+                                }
+                                continue;
+                            }
+                            let (progress, _, _) = get_constraints_progress(force_words_ids, &sequence);
+                            banks.entry(progress).or_insert_with(Vec::new).push((beam_token_score, token_id, effective_beam_id));
+                        }
 
-                        if eos_token_ids.as_ref().is_some() {
-                            if eos_token_ids.as_ref().unwrap().contains(&token_id) {
-                                if beam_token_rank >= num_beams {
-                                    beam_token_rank += 1;
-                                    continue;
+                        let mut banks = banks
+                            .into_iter()
+                            .rev()
+                            .map(|(_, bank)| bank.into_iter())
+                            .collect::<Vec<_>>();
+                        while (next_sentence_beam.len() as i64) < num_beams {
+                            let mut bank_candidates = banks
+                                .iter_mut()
+                                .filter_map(|bank| bank.next())
+                                .collect::<Vec<(f64, i64, i64)>>();
+                            if bank_candidates.is_empty() {
+                                break;
+                            }
+                            bank_candidates.truncate(num_beams as usize - next_sentence_beam.len());
+                            next_sentence_beam.append(&mut bank_candidates);
+                        }
+//                    Beams are kept sorted by decreasing score
+                        next_sentence_beam.sort_by_key(|(beam_token_score, _, _)| Reverse(OrderedFloat(*beam_token_score)));
+                    } else {
+                        let mut beam_token_rank = 0;
+                        let beam_token_rank_max_value = *batch_next_tokens.size().first().unwrap() - 1;
+                        loop {
+                            let beam_token_id = batch_next_tokens.int64_value(&[beam_token_rank]);
+                            let beam_token_score = batch_next_scores.double_value(&[beam_token_rank]);
+                            let beam_id = beam_token_id / vocab_size;
+                            let token_id = beam_token_id % vocab_size;
+
+                            let effective_beam_id = batch_index * num_beams + beam_id;
+                            let is_eos = eos_token_ids.as_ref().map_or(false, |eos_ids| eos_ids.contains(&token_id));
+                            let stop_signal = if has_stopping_criteria & !is_eos {
+                                let mut sequence = generated_ids[effective_beam_id as usize].clone();
+                                sequence.push(token_id);
+                                self.check_stopping_criteria(&stopping_criteria, &sequence, start_time)
+                            } else {
+                                None
+                            };
+
+                            if is_eos {
+                                if beam_token_rank < num_beams {
+                                    let mut hypothesis_logprobs = beam_logprobs[effective_beam_id as usize].clone();
+                                    hypothesis_logprobs.push(scores.double_value(&[effective_beam_id, token_id]));
+                                    hypotheses[batch_index as usize].add(input_ids.get(effective_beam_id).copy(), beam_token_score, hypothesis_logprobs, Some(token_id), None)
                                 }
-                                hypotheses[batch_index as usize].add(input_ids.get(effective_beam_id).copy(), beam_token_score)
+                            } else if let Some(stop_signal) = stop_signal {
+//                            Sequences ended by the stopping criteria include their last token
+                                let mut hypothesis_logprobs = beam_logprobs[effective_beam_id as usize].clone();
+                                hypothesis_logprobs.push(scores.double_value(&[effective_beam_id, token_id]));
+                                let hypothesis = Tensor::cat(&[input_ids.get(effective_beam_id), Tensor::of_slice(&[token_id]).to(input_ids.device())], 0);
+                                hypotheses[batch_index as usize].add(hypothesis, beam_token_score, hypothesis_logprobs, None, Some(stop_signal));
                             } else {
                                 next_sentence_beam.push((beam_token_score, token_id, effective_beam_id));
                             }
-                        } else {
-                            next_sentence_beam.push((beam_token_score, token_id, effective_beam_id));
+
+                            if (next_sentence_beam.len() as i64 == num_beams) |
+                                (beam_token_rank == beam_token_rank_max_value) {
+                                break;
+                            }
+                            beam_token_rank += 1;
                         }
+                    }
 
-                        if (next_sentence_beam.len() as i64 == num_beams) |
-                            (beam_token_rank == beam_token_rank_max_value) {
-                            break;
+                    group_tokens[(batch_index / num_beam_groups) as usize].extend(next_sentence_beam.iter().map(|(_, token_id, _)| *token_id));
+
+//                Candidates ended by the stopping criteria may leave the beam incomplete. The generation of the batch item is complete if all
+//                candidates ended, the beam is otherwise completed with copies of its best candidate that are never selected (as the initial beams)
+                    if (next_sentence_beam.len() as i64) < num_beams {
+                        match next_sentence_beam.first().cloned() {
+                            Some((_, token_id, effective_beam_id)) => {
+                                while (next_sentence_beam.len() as i64) < num_beams {
+                                    next_sentence_beam.push((-1e9, token_id, effective_beam_id));
+                                }
+                            }
+                            None => {
+                                done[batch_index as usize] = true;
+                                next_sentence_beam = (0..num_beams).map(|_| (0f64, pad_token_id.unwrap(), batch_index * num_beams)).collect();
+                            }
                         }
-                        beam_token_rank += 1;
                     }
 
                     done[batch_index as usize] = done[batch_index as usize] |
                         hypotheses[batch_index as usize].is_done(
-                            f64::from(next_scores.get(batch_index).max()),
+                            f64::from(batch_next_scores.max()),
                             current_length);
 
                     assert_eq!(next_sentence_beam.len() as i64, num_beams, "Beam incomplete");
@@ -930,9 +2826,32 @@ mod private_generation_utils {
                 beam_scores = Tensor::of_slice(&next_batch_beam.iter().map(|(score, _, _)| *score).collect_vec()).to(input_ids.device());
                 beam_tokens = Tensor::of_slice(&next_batch_beam.iter().map(|(_, token, _)| *token).collect_vec()).to(input_ids.device());
                 beam_indices = Tensor::of_slice(&next_batch_beam.iter().map(|(_, _, index)| *index).collect_vec()).to(input_ids.device());
+                beam_logprobs = next_batch_beam
+                    .iter()
+                    .map(|(_, token_id, index)| {
+                        let mut logprobs = beam_logprobs[*index as usize].clone();
+                        logprobs.push(scores.double_value(&[*index, *token_id]));
+                        logprobs
+                    })
+                    .collect();
 
                 input_ids = input_ids.index_select(0, &beam_indices);
                 input_ids = Tensor::cat(&[input_ids, beam_tokens.unsqueeze(1)], -1);
+//            Stream the current best beam (beams are sorted by decreasing score) of each batch item still being generated (of its first group for diverse beam search)
+                if let Some(streamer) = streamer.as_mut() {
+                    let generated_ids = input_ids.slice(1, cur_len, current_length + 1, 1).to(Device::Cpu);
+                    let sequences = (0..input_batch_size)
+                        .map(|input_index| input_index * num_beam_groups)
+                        .map(|batch_index| if done[batch_index as usize] {
+                            None
+                        } else {
+                            Some(generated_ids.get(batch_index * num_beams).iter::<i64>().unwrap().collect::<Vec<i64>>())
+                        })
+                        .collect::<Vec<Option<Vec<i64>>>>();
+                    if !streamer.emit(self.get_tokenizer(), sequences) {
+                        break;
+                    }
+                }
                 let temp_past = self.reorder_cache(past, encoder_outputs, &beam_indices);
                 past = temp_past.0;
                 encoder_outputs = temp_past.1;
@@ -953,34 +2872,82 @@ mod private_generation_utils {
                     batch_index += 1;
                     continue;
                 }
+                let mut unconstrained_beam_ids = vec!();
                 for beam_index in 0..num_beams {
                     let effective_beam_id = batch_index * num_beams + beam_index;
                     let final_score = f64::from(beam_scores.get(effective_beam_id));
                     let final_tokens = input_ids.get(effective_beam_id);
-                    hypotheses[batch_index as usize].add(final_tokens, final_score);
+                    if let Some(force_words_ids) = &force_words_ids {
+                        let generated_ids = final_tokens
+                            .slice(0, cur_len, *final_tokens.size().first().unwrap(), 1)
+                            .to(Device::Cpu)
+                            .iter::<i64>()
+                            .unwrap()
+                            .collect::<Vec<i64>>();
+                        if !get_constraints_progress(force_words_ids, &generated_ids).1 {
+                            unconstrained_beam_ids.push(effective_beam_id);
+                            continue;
+                        }
+                    }
+                    hypotheses[batch_index as usize].add(final_tokens, final_score, beam_logprobs[effective_beam_id as usize].clone(), None, None);
+                }
+//            Hypotheses not satisfying the constraints are only returned if not enough hypotheses satisfy them
+                for effective_beam_id in unconstrained_beam_ids {
+                    if hypotheses[batch_index as usize].len() >= num_return_sequences {
+                        break;
+                    }
+                    let final_score = f64::from(beam_scores.get(effective_beam_id));
+                    let final_tokens = input_ids.get(effective_beam_id);
+                    hypotheses[batch_index as usize].add(final_tokens, final_score, beam_logprobs[effective_beam_id as usize].clone(), None, None);
                 }
                 batch_index += 1;
             }
 
             let (output_batch_size, output_num_return_sequences_per_batch) = if do_sample {
-                (batch_size, 1)
+                (input_batch_size, 1)
             } else {
-                (batch_size * num_return_sequences, num_return_sequences)
+                (input_batch_size * num_return_sequences, num_return_sequences)
             };
 
             let mut sentence_lengths = Tensor::zeros(&[output_batch_size], (Int64, input_ids.device()));
             let mut best_ids = vec!();
+            let mut details = vec!();
 
-            for (hypothesis_index, hypothesis) in hypotheses.iter().enumerate() {
-                let mut sorted_hypotheses = hypothesis.clone();
-                &sorted_hypotheses.beams.sort_by_key(|(score, _)| OrderedFloat(*score));
+            for (hypothesis_index, group_hypotheses) in hypotheses.chunks(num_beam_groups as usize).enumerate() {
+                let mut sorted_hypotheses = group_hypotheses
+                    .iter()
+                    .flat_map(|hypothesis| hypothesis.beams.iter().cloned())
+                    .collect::<Vec<BeamHypothesis>>();
+                sorted_hypotheses.sort_by_key(|hypothesis| OrderedFloat(hypothesis.score));
                 for j in 0..output_num_return_sequences_per_batch {
                     let effective_batch_index = output_num_return_sequences_per_batch * hypothesis_index as i64 + j;
-                    let (_, best_hyp) = sorted_hypotheses.beams.pop().unwrap();
+                    let best_hyp = sorted_hypotheses.pop().unwrap();
                     let _ = sentence_lengths.index_fill_(0,
                                                          &Tensor::of_slice(&[effective_batch_index]).to(sentence_lengths.device()),
-                                                         *best_hyp.size().first().unwrap());
-                    best_ids.push(best_hyp);
+                                                         *best_hyp.token_ids.size().first().unwrap());
+                    let mut token_ids = best_hyp.token_ids
+                        .to(Device::Cpu)
+                        .slice(0, cur_len, *best_hyp.token_ids.size().first().unwrap(), 1)
+                        .iter::<i64>()
+                        .unwrap()
+                        .collect::<Vec<i64>>();
+                    let (finish_reason, text_length) = match (best_hyp.eos_token_id, best_hyp.stop_signal) {
+                        (Some(eos_token_id), _) => {
+                            token_ids.push(eos_token_id);
+                            (FinishReason::Eos, None)
+                        }
+                        (None, Some(stop_signal)) => (stop_signal.finish_reason, stop_signal.text_length),
+                        (None, None) => (FinishReason::MaxLength, None)
+                    };
+                    details.push(SequenceDetails {
+                        prompt_length: cur_len as usize,
+                        token_ids,
+                        token_logprobs: best_hyp.token_logprobs,
+                        score: best_hyp.score,
+                        finish_reason,
+                        text_length,
+                    });
+                    best_ids.push(best_hyp.token_ids);
                 }
             }
 
@@ -996,7 +2963,7 @@ mod private_generation_utils {
                                                       (Int64, input_ids.device())),
                                      &best_ids[hypothesis_index]);
                     let sentence_length = i64::from(sentence_lengths.get(hypothesis_index as i64));
-                    if sentence_length < max_length {
+                    if (sentence_length < max_length) & (details[hypothesis_index].finish_reason == FinishReason::Eos) {
                         let _ = decoded
                             .get(hypothesis_index as i64)
                             .index_fill_(0, &Tensor::of_slice(&[sentence_length]).to_device(input_ids.device()), eos_token_ids.as_ref().unwrap()[0]);
@@ -1007,7 +2974,7 @@ mod private_generation_utils {
                 Tensor::stack(&best_ids, 0).to_kind(Int64).to(input_ids.device())
             };
 
-            decoded
+            (decoded, details)
         }
 
         fn reorder_cache(&mut self, past: Option<Vec<Tensor>>, _encoder_outputs: Option<Tensor>, beam_indices: &Tensor) -> (Option<Vec<Tensor>>, Option<Tensor>) {
@@ -1024,6 +2991,170 @@ mod private_generation_utils {
         }
 
         fn reset_cache(&mut self) {}
+
+        fn generate_indices(&mut self, prompt_texts: Option<Vec<&str>>, attention_mask: Option<Tensor>, streamer: Option<TokenStreamer>,
+                            seed: Option<u64>, prefix: Option<&PrefixCache>) -> (Tensor, Vec<SequenceDetails>) {
+            let start_time = Instant::now();
+            let eos_token_ids = PrivateLanguageGenerator::get_eos_ids(self).clone();
+
+            let config = PrivateLanguageGenerator::get_config(self);
+            let do_sample = config.do_sample;
+            let num_return_sequences = config.num_return_sequences;
+            let num_beams = config.num_beams;
+            let encoding_max_len = if self.is_encoder_decoder() {
+                1024u64
+            } else {
+                config.max_length
+            };
+//            The maximum length is measured from the end of a cached prefix
+            let max_length = config.max_length + prefix.map_or(0, |prefix| prefix.len() as u64);
+            let early_stopping = config.early_stopping;
+            let temperature = config.temperature;
+            let length_penalty = config.length_penalty;
+            let num_beam_groups = config.num_beam_groups;
+            let diversity_penalty = config.diversity_penalty;
+            let force_words_ids = config.force_words_ids.clone();
+            let rng = seed.or(config.seed).map(SeededRng::new);
+
+
+            let pad_token_id = match self.get_pad_id() {
+                Some(value) => Some(*value),
+                None => match &eos_token_ids {
+                    Some(eos_ids) => Some(eos_ids[0]),
+                    None => None
+                }
+            };
+
+            let stopping_criteria = self.build_stopping_criteria(config);
+//            Sequences ended by a stopping criteria or by the maximum length of a padded prompt are padded,
+//            with the unknown token for models without padding and end of sequence tokens
+            let pad_token_id = match pad_token_id {
+                Some(value) => Some(value),
+                None => Some(self.get_tokenizer().vocab().token_to_id(V::unknown_value()))
+            };
+
+            let (input_ids, prompt_attention_mask) = match prompt_texts {
+                Some(text) => self.encode_prompt_text(text, encoding_max_len, pad_token_id),
+                None => match (prefix, self.get_bos_id()) {
+                    (Some(_), _) => {
+                        let input_ids = Tensor::zeros(&[1, 0], (Int64, self.get_var_store().device()));
+                        let attention_mask = input_ids.ones_like();
+                        (input_ids, attention_mask)
+                    }
+                    (None, Some(bos_id)) => {
+                        let input_ids = Tensor::ones(&[1, 1], (Int64, self.get_var_store().device())) * *bos_id;
+                        let attention_mask = input_ids.ones_like();
+                        (input_ids, attention_mask)
+                    }
+                    (None, None) => panic!("A model with a BOS token must be used to start generation with an empty input")
+                }
+            };
+
+//            The prompts are left-padded, padding positions are masked
+            let attention_mask = match attention_mask {
+                Some(value) => value,
+                None => prompt_attention_mask
+            };
+
+//            Prompts following a cached prefix are appended to the prefix tokens
+            let (input_ids, attention_mask) = match prefix {
+                Some(prefix) => {
+                    let prefix_ids = Tensor::of_slice(&prefix.token_ids)
+                        .to(input_ids.device())
+                        .unsqueeze(0)
+                        .repeat(&[*input_ids.size().first().unwrap(), 1]);
+                    let prefix_attention_mask = prefix_ids.ones_like().to_kind(attention_mask.kind());
+                    (Tensor::cat(&[prefix_ids, input_ids], 1), Tensor::cat(&[prefix_attention_mask, attention_mask], 1))
+                }
+                None => (input_ids, attention_mask)
+            };
+
+            let cur_len = if !self.is_encoder_decoder() { *input_ids.size().last().unwrap() } else { 1 };
+            let batch_size = *input_ids.size().first().unwrap();
+
+            let (effective_batch_size, effective_batch_mult) = match do_sample {
+                true => (batch_size * num_return_sequences as i64, num_return_sequences as i64),
+                false => (batch_size, 1)
+            };
+
+            let encoder_outputs = if self.is_encoder_decoder() {
+                let encoder_outputs = self.encode(&input_ids, Some(&attention_mask)).unwrap();
+                let expanded_batch_indices = Tensor::arange(batch_size, (Int64, input_ids.device()))
+                    .view((-1, 1))
+                    .repeat(&[1, num_beams as i64 * effective_batch_mult])
+                    .view(-1);
+                Some(encoder_outputs.index_select(0, &expanded_batch_indices))
+            } else {
+                None
+            };
+
+            let (input_ids, attention_mask) = if !self.is_encoder_decoder() {
+                if (num_return_sequences > 1) | (num_beams > 1) {
+                    (input_ids
+                         .unsqueeze(1)
+                         .expand(&[batch_size, effective_batch_mult * num_beams as i64, cur_len], true)
+                         .contiguous()
+                         .view((effective_batch_size * num_beams as i64, cur_len)),
+                     attention_mask
+                         .unsqueeze(1)
+                         .expand(&[batch_size, effective_batch_mult * num_beams as i64, cur_len], true)
+                         .contiguous()
+                         .view((effective_batch_size * num_beams as i64, cur_len))
+                    )
+                } else {
+                    (input_ids, attention_mask)
+                }
+            } else {
+                let decoder_start_token_id = self.get_decoder_start_id().expect("decoder start id must be specified for encoder decoders");
+                let input_ids = Tensor::full(&[effective_batch_size * num_beams as i64, 1], decoder_start_token_id, (Int64, input_ids.device()));
+                (input_ids, attention_mask)
+            };
+
+//            Beam search applies the repetition penalty to the logits and the other processors to the log-probabilities
+            let (penalty_processors, logits_processors) = if num_beams > 1 {
+                (self.build_penalty_processors(self.get_config()), self.build_constraint_processors(self.get_config(), effective_batch_mult * num_beams as i64))
+            } else {
+                (vec!(), self.build_logits_processors(self.get_config(), effective_batch_mult * num_beams as i64))
+            };
+//            Truncation of the distribution the tokens are sampled from. Beam search keeps at least 2 tokens per beam.
+            let sampling_processors = self.build_sampling_processors(self.get_config(), if num_beams > 1 { 2 } else { 1 });
+
+//            The cache of the prefix is shared by all sequences. The last prompt token is not cached if the prompts are empty,
+//            as the first generation step requires at least one input token.
+            let past = prefix.map(|prefix| {
+                let num_sequences = effective_batch_size * num_beams as i64;
+                let cached_length = min(prefix.len() as i64, cur_len - 1);
+                prefix.past
+                    .iter()
+                    .map(|layer_past| layer_past.narrow(3, 0, cached_length).repeat(&[1, num_sequences, 1, 1, 1]))
+                    .collect::<Vec<Tensor>>()
+            });
+
+            self.reset_cache();
+            let (decoded, mut details) = no_grad(|| {
+                if num_beams > 1 {
+                    self.generate_beam_search(input_ids, encoder_outputs, past, cur_len, max_length as i64, do_sample, early_stopping, temperature, sampling_processors, penalty_processors,
+                                              logits_processors, force_words_ids, pad_token_id, eos_token_ids, effective_batch_size, num_return_sequences as i64, length_penalty, num_beams as i64,
+                                              num_beam_groups as i64, diversity_penalty, attention_mask, streamer, rng,
+                                              stopping_criteria, start_time)
+                } else {
+                    self.generate_no_beam_search(input_ids, encoder_outputs, past, cur_len, max_length as i64, do_sample, temperature, sampling_processors, logits_processors,
+                                                 pad_token_id, eos_token_ids, effective_batch_size, attention_mask, streamer, rng, stopping_criteria, start_time)
+                }
+            });
+            self.mark_incomplete_sequences(&mut details);
+            (decoded, details)
+        }
+
+        /// Reports the sequences stopped by the maximum length or the time budget that do not satisfy an attached logits processor with `FinishReason::Incomplete`
+        fn mark_incomplete_sequences(&self, details: &mut [SequenceDetails]) {
+            for sequence_details in details.iter_mut() {
+                if ((sequence_details.finish_reason == FinishReason::MaxLength) | (sequence_details.finish_reason == FinishReason::TimeLimit))
+                    & !self.get_logits_processors().iter().all(|logits_processor| logits_processor.is_complete(&sequence_details.token_ids)) {
+                    sequence_details.finish_reason = FinishReason::Incomplete;
+                }
+            }
+        }
     }
 }
 
@@ -1087,117 +3218,564 @@ pub trait LanguageGenerator<T: LMHeadModel, V: Vocab, U: Tokenizer<V>>: PrivateL
     ///
     fn generate(&mut self, prompt_texts: Option<Vec<&str>>, attention_mask: Option<Tensor>)
                 -> Vec<String> {
-        let eos_token_ids = PrivateLanguageGenerator::get_eos_ids(self).clone();
+        let (decoded, details) = self.generate_indices(prompt_texts, attention_mask, None, None, None);
+        self.decode_sequences(&decoded, &details)
+    }
 
-        let config = PrivateLanguageGenerator::get_config(self);
-        let do_sample = config.do_sample;
-        let num_return_sequences = config.num_return_sequences;
-        let num_beams = config.num_beams;
-        let min_length = config.min_length;
-        let max_length = config.max_length;
-        let encoding_max_len = if self.is_encoder_decoder() {
-            1024u64
-        } else {
-            max_length
-        };
-        let early_stopping = config.early_stopping;
-        let temperature = config.temperature;
-        let top_k = config.top_k;
-        let top_p = config.top_p;
-        let repetition_penalty = config.repetition_penalty;
-        let length_penalty = config.length_penalty;
-        let no_repeat_ngram_size = config.no_repeat_ngram_size;
-
-
-        let pad_token_id = match self.get_pad_id() {
-            Some(value) => Some(*value),
-            None => match &eos_token_ids {
-                Some(eos_ids) => Some(eos_ids[0]),
-                None => None
-            }
-        };
+    /// Generate text based on a vector of promp texts, sampling from a random stream seeded with `seed`. The seed overrides the
+    /// `seed` of the generator configuration for this call only: identical seeds yield identical outputs on CPU, without affecting
+    /// the random state of other generators or threads.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt_texts` - `Option<Vec<&str>>` Optional vector of text prompts. An empty prompt to the model may be passed if the model implement a `bos_id`.
+    /// * `attention_mask` - `Option<Tensor>` Optional attention mask to hide portions of the prompt.
+    /// * `seed` - `u64` seed of the random number generator used for sampling
+    ///
+    /// # Returns
+    /// * `Vec<String>` Vector of generated strings based on the prompts of length *number_of_prompts* x *num_return_sequences*.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# use std::path::PathBuf;
+    ///# use tch::Device;
+    ///# fn main() -> failure::Fallible<()> {
+    /// use rust_bert::pipelines::generation::{GenerateConfig, GPT2Generator, LanguageGenerator};
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# home.push("rustbert");
+    ///# home.push("gpt2");
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let merges_path = &home.as_path().join("merges.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::Cpu;
+    /// let generate_config = GenerateConfig {
+    ///    max_length: 30,
+    ///    do_sample: true,
+    ///    num_beams: 1,
+    ///    ..Default::default()
+    /// };
+    /// let mut gpt2_generator = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+    ///                                         generate_config, device)?;
+    /// let input_context = "The dog";
+    /// let output = gpt2_generator.generate_with_seed(Some(vec!(input_context)), None, 42);
+    /// let same_output = gpt2_generator.generate_with_seed(Some(vec!(input_context)), None, 42);
+    /// assert_eq!(output, same_output);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    fn generate_with_seed(&mut self, prompt_texts: Option<Vec<&str>>, attention_mask: Option<Tensor>, seed: u64)
+                          -> Vec<String> {
+        let (decoded, details) = self.generate_indices(prompt_texts, attention_mask, None, Some(seed), None);
+        self.decode_sequences(&decoded, &details)
+    }
 
-        let input_ids = match prompt_texts {
-            Some(text) => self.encode_prompt_text(text, encoding_max_len, pad_token_id),
-            None => match self.get_bos_id() {
-                Some(bos_id) => Tensor::ones(&[1, 1], (Int64, self.get_var_store().device())) * *bos_id,
-                None => panic!("A model with a BOS token must be used to start generation with an empty input")
+    /// Generate text based on a vector of promp texts, returning the generated token ids, their log-probabilities and the sequence scores
+    /// in addition to the generated text.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt_texts` - `Option<Vec<&str>>` Optional vector of text prompts. An empty prompt to the model may be passed if the model implement a `bos_id`.
+    /// * `attention_mask` - `Option<Tensor>` Optional attention mask to hide portions of the prompt.
+    ///
+    /// # Returns
+    /// * `Vec<Vec<GeneratedOutput>>` Vector of length *number_of_prompts* containing for each prompt a vector of length *num_return_sequences*
+    /// with the generated sequences, ordered as in the output of `generate`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# use std::path::PathBuf;
+    ///# use tch::Device;
+    ///# fn main() -> failure::Fallible<()> {
+    /// use rust_bert::pipelines::generation::{GenerateConfig, GPT2Generator, LanguageGenerator};
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# home.push("rustbert");
+    ///# home.push("gpt2");
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let merges_path = &home.as_path().join("merges.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::cuda_if_available();
+    /// let generate_config = GenerateConfig {
+    ///    max_length: 30,
+    ///    do_sample: false,
+    ///    num_beams: 5,
+    ///    num_return_sequences: 3,
+    ///    ..Default::default()
+    /// };
+    /// let mut gpt2_generator = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+    ///                                         generate_config, device)?;
+    /// let input_context = "The dog";
+    /// let second_input_context = "The cat was";
+    /// let output = gpt2_generator.generate_with_details(Some(vec!(input_context, second_input_context)), None);
+    /// for prompt_outputs in output {
+    ///     for generated in prompt_outputs {
+    ///         println!("{} ({:.3}, {:?})", generated.text, generated.score, generated.finish_reason);
+    ///     }
+    /// }
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    fn generate_with_details(&mut self, prompt_texts: Option<Vec<&str>>, attention_mask: Option<Tensor>)
+                             -> Vec<Vec<GeneratedOutput>> {
+        let num_return_sequences = self.get_config().num_return_sequences as usize;
+        let (decoded, details) = self.generate_indices(prompt_texts, attention_mask, None, None, None);
+        let texts = self.decode_sequences(&decoded, &details);
+        let mut output: Vec<Vec<GeneratedOutput>> = vec!();
+        for (sequence_index, (sequence_details, text)) in details.into_iter().zip(texts).enumerate() {
+            let prompt_index = sequence_index / num_return_sequences;
+            if output.len() <= prompt_index {
+                output.push(Vec::with_capacity(num_return_sequences));
             }
-        };
+            output[prompt_index].push(GeneratedOutput {
+                prompt_index,
+                text,
+                token_ids: sequence_details.token_ids,
+                token_logprobs: sequence_details.token_logprobs,
+                score: sequence_details.score,
+                finish_reason: sequence_details.finish_reason,
+            });
+        }
+        output
+    }
 
-        let cur_len = if !self.is_encoder_decoder() { *input_ids.size().last().unwrap() } else { 1 };
-        let batch_size = *input_ids.size().first().unwrap();
+    /// Generate text based on a vector of promp texts, streaming the tokens as soon as they are generated.
+    /// The callback is called after each generation step with the new token of every sequence still being generated.
+    /// For beam search, the current best beam of each input is streamed.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt_texts` - `Option<Vec<&str>>` Optional vector of text prompts. An empty prompt to the model may be passed if the model implement a `bos_id`.
+    /// * `attention_mask` - `Option<Tensor>` Optional attention mask to hide portions of the prompt.
+    /// * `callback` - `FnMut(&[StreamToken]) -> bool` called with the tokens generated at each step. Returning `false` stops the generation.
+    ///
+    /// # Returns
+    /// * `Vec<String>` Vector of generated strings (up to the point where the generation was stopped), as returned by `generate`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# use std::path::PathBuf;
+    ///# use tch::Device;
+    ///# fn main() -> failure::Fallible<()> {
+    /// use rust_bert::pipelines::generation::{GenerateConfig, GPT2Generator, LanguageGenerator};
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# home.push("rustbert");
+    ///# home.push("gpt2");
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let merges_path = &home.as_path().join("merges.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::cuda_if_available();
+    /// let generate_config = GenerateConfig {
+    ///    max_length: 30,
+    ///    do_sample: false,
+    ///    num_beams: 1,
+    ///    ..Default::default()
+    /// };
+    /// let mut gpt2_generator = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+    ///                                         generate_config, device)?;
+    /// let input_context = "The dog";
+    /// let mut streamed_tokens = 0;
+    /// let output = gpt2_generator.generate_stream(Some(vec!(input_context)), None, |tokens| {
+    ///     for token in tokens {
+    ///         print!("{}", token.text);
+    ///     }
+    ///     streamed_tokens += 1;
+    ///     streamed_tokens < 10
+    /// });
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    fn generate_stream<F>(&mut self, prompt_texts: Option<Vec<&str>>, attention_mask: Option<Tensor>, mut callback: F)
+                          -> Vec<String>
+        where F: FnMut(&[StreamToken]) -> bool {
+        let (decoded, details) = self.generate_indices(prompt_texts, attention_mask, Some(TokenStreamer::new(&mut callback)), None, None);
+        self.decode_sequences(&decoded, &details)
+    }
 
-        let (effective_batch_size, effective_batch_mult) = match do_sample {
-            true => (batch_size * num_return_sequences as i64, num_return_sequences as i64),
-            false => (batch_size, 1)
-        };
+    /// Precompute the keys and values (`layer_past`) of a prompt prefix shared by later generations (e.g. a system preamble), so that they are
+    /// not recomputed at each generation (see `generate_with_prefix`). Only available for decoder-only models returning their cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix_text` - `&str` Prefix text. It is not truncated to the maximum length of the generation configuration.
+    ///
+    /// # Returns
+    /// * `PrefixCache` Token ids and cached keys and values of the prefix
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# use std::path::PathBuf;
+    ///# use tch::Device;
+    ///# fn main() -> failure::Fallible<()> {
+    /// use rust_bert::pipelines::generation::{GenerateConfig, GPT2Generator, LanguageGenerator};
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# home.push("rustbert");
+    ///# home.push("gpt2");
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let merges_path = &home.as_path().join("merges.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::cuda_if_available();
+    /// let generate_config = GenerateConfig {
+    ///    max_length: 60,
+    ///    do_sample: false,
+    ///    num_beams: 3,
+    ///    ..Default::default()
+    /// };
+    /// let mut gpt2_generator = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+    ///                                         generate_config, device)?;
+    /// let prefix = gpt2_generator.cache_prefix("The following is a conversation with a helpful assistant.");
+    /// let output = gpt2_generator.generate_with_prefix(&prefix, Some(vec!(" User: hello!", " User: what time is it?")), None);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    fn cache_prefix(&mut self, prefix_text: &str) -> PrefixCache {
+        assert!(!self.is_encoder_decoder(), "prefix caching is only supported for decoder-only models");
+        let tokens = self.get_tokenizer().tokenize(prefix_text);
+        let token_ids = self.get_tokenizer().convert_tokens_to_ids(&tokens);
+        assert!(!token_ids.is_empty(), "the prefix must contain at least one token");
+
+        let input_ids = Tensor::of_slice(&token_ids).unsqueeze(0).to(self.get_var_store().device());
+        let past = no_grad(|| {
+            self.get_model().forward_t(&Some(input_ids), &None, &None, &None, &None, &None, None, &None, false).unwrap().2
+        });
+        let past = past.expect("the model must return its cache for the prefix to be cached");
+        PrefixCache { token_ids, past }
+    }
 
-        let attention_mask = match attention_mask {
-            Some(value) => value,
-            None => {
-                match self.get_pad_id() {
-                    Some(pad_id) => input_ids.ne(*pad_id).to_kind(Int64),
-                    None => input_ids.ones_like()
-                }
-            }
-        };
+    /// Generate text based on a vector of prompt texts following a cached prefix. Generation starts from the cached keys and values of the prefix,
+    /// which are shared by all the prompts (and beams for beam search). The prompts are tokenized separately and appended to the prefix tokens:
+    /// the result is identical to the generation from the concatenated texts as long as the prompts start at a token boundary (e.g. with a space for GPT2).
+    /// The `max_length` of the generation configuration is measured from the end of the prefix: the prefix tokens are not counted,
+    /// and up to `max_length` prompt and generated tokens follow the prefix, however long the prefix is.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - `&PrefixCache` Cached prefix, created by `cache_prefix` with the same generator
+    /// * `prompt_texts` - `Option<Vec<&str>>` Optional vector of text prompts following the prefix. If None, the generation continues the prefix.
+    /// * `attention_mask` - `Option<Tensor>` Optional attention mask to hide portions of the prompts (excluding the prefix).
+    ///
+    /// # Returns
+    /// * `Vec<String>` Vector of generated strings (including the prefix) of length *number_of_prompts* x *num_return_sequences*.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# use std::path::PathBuf;
+    ///# use tch::Device;
+    ///# fn main() -> failure::Fallible<()> {
+    /// use rust_bert::pipelines::generation::{GenerateConfig, GPT2Generator, LanguageGenerator};
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# home.push("rustbert");
+    ///# home.push("gpt2");
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let merges_path = &home.as_path().join("merges.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::cuda_if_available();
+    /// let generate_config = GenerateConfig {
+    ///    max_length: 20,
+    ///    do_sample: false,
+    ///    num_beams: 1,
+    ///    ..Default::default()
+    /// };
+    /// let mut gpt2_generator = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+    ///                                         generate_config, device)?;
+    /// let prefix = gpt2_generator.cache_prefix("The following is a conversation between a user and a helpful assistant, \
+    ///                                           who answers the questions of the user politely and concisely.");
+    ///
+    /// // Each output continues the prefix with up to 20 tokens (prompt and generated tokens), although the prefix is longer than 20 tokens
+    /// let output = gpt2_generator.generate_with_prefix(&prefix, Some(vec!(" User: hello!", " User: what time is it?")), None);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    fn generate_with_prefix(&mut self, prefix: &PrefixCache, prompt_texts: Option<Vec<&str>>, attention_mask: Option<Tensor>)
+                            -> Vec<String> {
+        assert!(!self.is_encoder_decoder(), "prefix caching is only supported for decoder-only models");
+        let (decoded, details) = self.generate_indices(prompt_texts, attention_mask, None, None, Some(prefix));
+        self.decode_sequences(&decoded, &details)
+    }
 
-        let encoder_outputs = if self.is_encoder_decoder() {
-            let encoder_outputs = self.encode(&input_ids, Some(&attention_mask)).unwrap();
-            let expanded_batch_indices = Tensor::arange(batch_size, (Int64, input_ids.device()))
-                .view((-1, 1))
-                .repeat(&[1, num_beams as i64 * effective_batch_mult])
-                .view(-1);
-            Some(encoder_outputs.index_select(0, &expanded_batch_indices))
-        } else {
-            None
-        };
+    /// Attach an ordered list of logits processors to the generator, replacing the processors previously attached.
+    /// The processors are applied in order at each generation step, after the built-in processors derived from the `GenerateConfig`.
+    ///
+    /// # Arguments
+    ///
+    /// * `logits_processors` - `Vec<Box<dyn LogitsProcessor>>` Ordered list of logits processors
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# use std::path::PathBuf;
+    ///# use tch::Device;
+    ///# fn main() -> failure::Fallible<()> {
+    /// use rust_bert::pipelines::generation::{GenerateConfig, GPT2Generator, LanguageGenerator, LogitsProcessor,
+    ///                                        FrequencyPresencePenaltyProcessor, BannedTokensProcessor};
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# home.push("rustbert");
+    ///# home.push("gpt2");
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let merges_path = &home.as_path().join("merges.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::cuda_if_available();
+    /// let mut gpt2_generator = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+    ///                                         GenerateConfig::default(), device)?;
+    /// let logits_processors: Vec<Box<dyn LogitsProcessor>> = vec!(
+    ///     Box::new(FrequencyPresencePenaltyProcessor::new(0.5, 0.5)),
+    ///     Box::new(BannedTokensProcessor::new(vec!(198)))
+    /// );
+    /// gpt2_generator.set_logits_processors(logits_processors);
+    /// let output = gpt2_generator.generate(Some(vec!("The dog")), None);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    fn set_logits_processors(&mut self, logits_processors: Vec<Box<dyn LogitsProcessor>>) {
+        *self.get_logits_processors_mut() = logits_processors;
+    }
 
-        let (input_ids, attention_mask) = if !self.is_encoder_decoder() {
-            if (num_return_sequences > 1) | (num_beams > 1) {
-                (input_ids
-                     .unsqueeze(1)
-                     .expand(&[batch_size, effective_batch_mult * num_beams as i64, cur_len], true)
-                     .contiguous()
-                     .view((effective_batch_size * num_beams as i64, cur_len)),
-                 attention_mask
-                     .unsqueeze(1)
-                     .expand(&[batch_size, effective_batch_mult * num_beams as i64, cur_len], true)
-                     .contiguous()
-                     .view((effective_batch_size * num_beams as i64, cur_len))
-                )
-            } else {
-                (input_ids, attention_mask)
-            }
-        } else {
-            let decoder_start_token_id = self.get_decoder_start_id().expect("decoder start id must be specified for encoder decoders");
-            let input_ids = Tensor::full(&[effective_batch_size * num_beams as i64, 1], decoder_start_token_id, (Int64, input_ids.device()));
-            (input_ids, attention_mask)
-        };
+    /// Append a logits processor to the list of processors attached to the generator.
+    ///
+    /// # Arguments
+    ///
+    /// * `logits_processor` - `Box<dyn LogitsProcessor>` Logits processor applied after the processors already attached
+    ///
+    fn add_logits_processor(&mut self, logits_processor: Box<dyn LogitsProcessor>) {
+        self.get_logits_processors_mut().push(logits_processor);
+    }
 
-        self.reset_cache();
-        let decoded = no_grad(|| {
-            if num_beams > 1 {
-                self.generate_beam_search(input_ids, encoder_outputs, cur_len, min_length as i64, max_length as i64, do_sample, early_stopping, temperature, top_k as i64, top_p, repetition_penalty,
-                                          no_repeat_ngram_size as i64, pad_token_id, eos_token_ids, effective_batch_size, num_return_sequences as i64, length_penalty, num_beams as i64, attention_mask)
-            } else {
-                self.generate_no_beam_search(input_ids, encoder_outputs, cur_len, min_length as i64, max_length as i64, do_sample, temperature, top_k as i64, top_p, repetition_penalty,
-                                             no_repeat_ngram_size as i64, pad_token_id, eos_token_ids, effective_batch_size, attention_mask)
-            }
-        });
+    /// Attach an ordered list of stopping criteria to the generator, replacing the criteria previously attached.
+    /// The criteria are checked in order for each sequence after each generation step, after the built-in criteria derived from the `GenerateConfig`.
+    ///
+    /// # Arguments
+    ///
+    /// * `stopping_criteria` - `Vec<Box<dyn StoppingCriteria>>` Ordered list of stopping criteria
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# use std::path::PathBuf;
+    ///# use std::time::Duration;
+    ///# use tch::Device;
+    ///# fn main() -> failure::Fallible<()> {
+    /// use rust_bert::pipelines::generation::{GenerateConfig, GPT2Generator, LanguageGenerator, StoppingCriteria,
+    ///                                        StopSequencesCriteria, MaxTimeCriteria};
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# home.push("rustbert");
+    ///# home.push("gpt2");
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let merges_path = &home.as_path().join("merges.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::cuda_if_available();
+    /// let mut gpt2_generator = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+    ///                                         GenerateConfig::default(), device)?;
+    /// let stopping_criteria: Vec<Box<dyn StoppingCriteria>> = vec!(
+    ///     Box::new(StopSequencesCriteria::new(vec!("\n\n".to_string(), "User:".to_string()))),
+    ///     Box::new(MaxTimeCriteria::new(Duration::from_secs(2)))
+    /// );
+    /// gpt2_generator.set_stopping_criteria(stopping_criteria);
+    /// let output = gpt2_generator.generate(Some(vec!("User: Hello!\nAssistant:")), None);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    fn set_stopping_criteria(&mut self, stopping_criteria: Vec<Box<dyn StoppingCriteria>>) {
+        *self.get_stopping_criteria_mut() = stopping_criteria;
+    }
+
+    /// Append a stopping criteria to the list of criteria attached to the generator.
+    ///
+    /// # Arguments
+    ///
+    /// * `stopping_criteria` - `Box<dyn StoppingCriteria>` Stopping criteria checked after the criteria already attached
+    ///
+    fn add_stopping_criteria(&mut self, stopping_criteria: Box<dyn StoppingCriteria>) {
+        self.get_stopping_criteria_mut().push(stopping_criteria);
+    }
+
+    /// Constrain the generated text (excluding the prompt) to match a regular expression, by attaching a `GrammarProcessor` to the generator.
+    /// Supported for byte-level BPE vocabularies (GPT2 and BART generators), see the `grammar` module for the supported syntax.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - `&str` Regular expression the generated text must match
+    ///
+    /// # Returns
+    /// * `Fallible<()>` Error if the regular expression is not supported or if the model has no end of sequence token
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# use std::path::PathBuf;
+    ///# use tch::Device;
+    ///# fn main() -> failure::Fallible<()> {
+    /// use rust_bert::pipelines::generation::{GenerateConfig, GPT2Generator, LanguageGenerator};
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# home.push("rustbert");
+    ///# home.push("gpt2");
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let merges_path = &home.as_path().join("merges.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::cuda_if_available();
+    /// let generate_config = GenerateConfig {
+    ///    no_repeat_ngram_size: 0,
+    ///    ..Default::default()
+    /// };
+    /// let mut gpt2_generator = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+    ///                                         generate_config, device)?;
+    /// gpt2_generator.add_regex_constraint(r" (yes|no), [0-9]{1,3} (cats|dogs)\.")?;
+    /// let output = gpt2_generator.generate(Some(vec!("Do you have pets? Answer:")), None);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    fn add_regex_constraint(&mut self, pattern: &str) -> failure::Fallible<()>
+        where V: ByteLevelVocab {
+        let eos_token_ids = self.get_eos_ids().clone().unwrap_or_default();
+        let forced_bos_token_id = if self.is_encoder_decoder() { *self.get_bos_id() } else { None };
+        let grammar_processor = GrammarProcessor::from_regex(pattern, self.get_tokenizer().vocab(), eos_token_ids, forced_bos_token_id)?;
+        self.add_logits_processor(Box::new(grammar_processor));
+        Ok(())
+    }
+
+    /// Constrain the generated text (excluding the prompt) to be a JSON document valid for a schema, by attaching a `GrammarProcessor` to the generator.
+    /// Supported for byte-level BPE vocabularies (GPT2 and BART generators), see the `grammar` module for the supported schemas.
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - `&str` JSON schema the generated document must be valid for
+    ///
+    /// # Returns
+    /// * `Fallible<()>` Error if the schema is not supported or if the model has no end of sequence token
+    ///
+    fn add_json_schema_constraint(&mut self, schema: &str) -> failure::Fallible<()>
+        where V: ByteLevelVocab {
+        let eos_token_ids = self.get_eos_ids().clone().unwrap_or_default();
+        let forced_bos_token_id = if self.is_encoder_decoder() { *self.get_bos_id() } else { None };
+        let grammar_processor = GrammarProcessor::from_json_schema(schema, self.get_tokenizer().vocab(), eos_token_ids, forced_bos_token_id)?;
+        self.add_logits_processor(Box::new(grammar_processor));
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// # Reason for the end of the generation of a sequence
+pub enum FinishReason {
+    /// An end of sequence token was generated
+    Eos,
+    /// The maximum length (or the maximum number of new tokens) was reached
+    MaxLength,
+    /// A stop sequence was generated
+    StopSequence,
+    /// The time budget of the generation was exhausted
+    TimeLimit,
+    /// The maximum length or the time budget was reached before the constraint of an attached logits processor was satisfied
+    /// (e.g. incomplete match of a grammar, see `LogitsProcessor::is_complete`)
+    Incomplete,
+}
+
+#[derive(Debug, Clone)]
+/// # Generated sequence with its token ids, log-probabilities and score (see `LanguageGenerator::generate_with_details`)
+pub struct GeneratedOutput {
+    /// Index of the prompt the sequence was generated from
+    pub prompt_index: usize,
+    /// Generated text (including the prompt for decoder-only models), as returned by `generate`
+    pub text: String,
+    /// Generated token ids, excluding the prompt. The tokens of a trimmed stop sequence are included.
+    pub token_ids: Vec<i64>,
+    /// Log-probability of each generated token (same length as `token_ids`), after application of the generation settings (e.g. temperature, repetition penalty).
+    /// The diversity penalty of diverse beam search only affects the beam scores and is not included.
+    pub token_logprobs: Vec<f64>,
+    /// Sequence score: length-penalized sum of the token log-probabilities for beam search (including the diversity penalty of diverse beam search),
+    /// sum of the token log-probabilities otherwise
+    pub score: f64,
+    /// Reason for the end of the generation
+    pub finish_reason: FinishReason,
+}
+
+#[derive(Debug, Clone)]
+/// # Token generated by a streaming generation (see `LanguageGenerator::generate_stream`)
+pub struct StreamToken {
+    /// Index of the sequence the token belongs to. Matches the position of the sequence in the output of `generate` for greedy decoding and sampling,
+    /// and the index of the input for beam search.
+    pub sequence_index: usize,
+    /// Generated token id
+    pub token_id: i64,
+    /// Text added to the sequence by this token. May be empty if the token is a special token or only contains part of a multi-byte character.
+    pub text: String,
+    /// Flag set when the best beam switched to a different hypothesis (beam search only). `text` then contains the full text generated by the
+    /// new best beam, replacing all the text previously streamed for this sequence.
+    pub restart: bool,
+}
+
+/// # Cached keys and values of a prompt prefix (see `LanguageGenerator::cache_prefix`)
+/// Holds the token ids of the prefix and its keys and values for each layer of the model (`layer_past`), from which later generations can start.
+pub struct PrefixCache {
+    token_ids: Vec<i64>,
+    past: Vec<Tensor>,
+}
 
-        let num_sequences = *decoded.size().first().unwrap();
-        let mut output = Vec::with_capacity(num_sequences as usize);
-        for sequence_index in 0..num_sequences {
-            output.push(self.get_tokenizer().decode(decoded
-                                                        .as_ref()
-                                                        .get(sequence_index)
-                                                        .iter::<i64>()
-                                                        .unwrap()
-                                                        .collect::<Vec<i64>>(), true, true));
+impl PrefixCache {
+    /// Token ids of the prefix
+    pub fn token_ids(&self) -> &[i64] {
+        &self.token_ids
+    }
+
+    /// Number of tokens of the prefix
+    pub fn len(&self) -> usize {
+        self.token_ids.len()
+    }
+
+    /// Returns true if the prefix has no token
+    pub fn is_empty(&self) -> bool {
+        self.token_ids.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+/// # Output of a speculative decoding generation (see `GPT2Generator::generate_speculative`)
+pub struct SpeculativeOutput {
+    /// Generated texts (including the prompts), one per prompt
+    pub texts: Vec<String>,
+    /// Number of tokens proposed by the draft model
+    pub num_draft_tokens: usize,
+    /// Number of proposed tokens accepted by the model
+    pub num_accepted_tokens: usize,
+    /// Share of the proposed tokens accepted by the model (0 if no token was proposed)
+    pub acceptance_rate: f64,
+}
+
+#[derive(Debug)]
+struct BeamHypothesis {
+    score: f64,
+    token_ids: Tensor,
+    token_logprobs: Vec<f64>,
+    eos_token_id: Option<i64>,
+    stop_signal: Option<StopSignal>,
+}
+
+impl Clone for BeamHypothesis {
+    fn clone(&self) -> Self {
+        BeamHypothesis {
+            score: self.score,
+            token_ids: self.token_ids.copy(),
+            token_logprobs: self.token_logprobs.clone(),
+            eos_token_id: self.eos_token_id,
+            stop_signal: self.stop_signal,
         }
-        output
     }
 }
 
@@ -1207,7 +3785,7 @@ struct BeamHypotheses {
     length_penalty: f64,
     early_stopping: bool,
     num_beams: i64,
-    beams: Vec<(f64, Tensor)>,
+    beams: Vec<BeamHypothesis>,
     worst_score: f64,
 }
 
@@ -1218,10 +3796,7 @@ impl Clone for BeamHypotheses {
             length_penalty: self.length_penalty,
             early_stopping: self.early_stopping,
             num_beams: self.num_beams,
-            beams: self.beams
-                .iter()
-                .map(|(score, tensor)| (*score, tensor.copy()))
-                .collect_vec(),
+            beams: self.beams.clone(),
             worst_score: self.worst_score,
         }
     }
@@ -1243,19 +3818,19 @@ impl BeamHypotheses {
         self.beams.len() as i64
     }
 
-    fn add(&mut self, hypothesis: Tensor, sum_log_probabilities: f64) {
+    fn add(&mut self, hypothesis: Tensor, sum_log_probabilities: f64, token_logprobs: Vec<f64>, eos_token_id: Option<i64>, stop_signal: Option<StopSignal>) {
         let score = sum_log_probabilities / ((*hypothesis.size().first().unwrap() as f64).powf(self.length_penalty));
         if (self.len() < self.num_beams) | (score > self.worst_score) {
-            self.beams.push((score, hypothesis));
+            self.beams.push(BeamHypothesis { score, token_ids: hypothesis, token_logprobs, eos_token_id, stop_signal });
             if self.len() > self.num_beams {
                 let (worst_score_position, _) = self.beams
                     .iter()
                     .enumerate()
-                    .min_by_key(|(_, (score, _))| OrderedFloat(*score))
+                    .min_by_key(|(_, hypothesis)| OrderedFloat(hypothesis.score))
                     .unwrap();
                 let _ = self.beams.remove(worst_score_position);
             }
-            self.worst_score = self.beams.iter().min_by_key(|(score, _)| OrderedFloat(*score)).unwrap().0;
+            self.worst_score = self.beams.iter().min_by_key(|hypothesis| OrderedFloat(hypothesis.score)).unwrap().score;
         }
     }
 
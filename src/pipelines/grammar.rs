@@ -0,0 +1,1081 @@
+// Copyright 2020 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Grammar constrained generation
+//! Constrains the text generated by a `LanguageGenerator` to match a regular expression or a JSON schema.
+//! The grammar is compiled to a deterministic finite-state machine over bytes, which is then mapped to the tokens of a
+//! byte-level BPE vocabulary (GPT2 and RoBERTa/BART vocabularies). At each generation step the scores of the tokens that
+//! cannot lead to a match are masked, for greedy decoding, sampling and beam search alike.
+//!
+//! Supported regular expression syntax: literals, `.`, character classes (`[a-z]`, `[^"]`, ASCII ranges only), escapes
+//! (`\d`, `\w`, `\s` and their negations, `\n`, `\t`, `\xHH`, `\uHHHH`), groups (`(...)`, `(?:...)`), alternation (`|`) and
+//! quantifiers (`*`, `+`, `?`, `{m}`, `{m,}`, `{m,n}`). The expression must match the entire generated text.
+//!
+//! Supported JSON schema subset: `type` (`object`, `array`, `string`, `integer`, `number`, `boolean`, `null` or a list of types),
+//! `properties`, `required`, `items`, `minItems`, `maxItems`, `minLength`, `maxLength`, `enum`, `const`, `anyOf` and `oneOf`.
+//! Annotations (`title`, `description`, `default`, `examples`, `$schema`, `$id`, `$comment`) and `additionalProperties` are accepted,
+//! additional properties are never generated. Schemas using any other keyword (e.g. `pattern`, `minimum` or `$ref`) are rejected.
+//! Objects contain the `required` properties in the order they are listed, followed by the optional properties (which may be omitted).
+//! All `properties` are required if the schema does not list the `required` properties.
+//!
+//! The generated text is a complete match unless the generation is stopped first (maximum length or time budget), in which case
+//! the sequence is reported with `FinishReason::Incomplete` by `LanguageGenerator::generate_with_details`.
+//!
+//! ```no_run
+//!# use std::path::PathBuf;
+//!# use tch::Device;
+//!# fn main() -> failure::Fallible<()> {
+//! use rust_bert::pipelines::generation::{GenerateConfig, GPT2Generator, LanguageGenerator};
+//!# let mut home: PathBuf = dirs::home_dir().unwrap();
+//!# home.push("rustbert");
+//!# home.push("gpt2");
+//!# let config_path = &home.as_path().join("config.json");
+//!# let vocab_path = &home.as_path().join("vocab.txt");
+//!# let merges_path = &home.as_path().join("merges.txt");
+//!# let weights_path = &home.as_path().join("model.ot");
+//! let device = Device::cuda_if_available();
+//! let generate_config = GenerateConfig {
+//!    max_length: 40,
+//!    do_sample: false,
+//!    num_beams: 1,
+//!    no_repeat_ngram_size: 0,
+//!    ..Default::default()
+//! };
+//! let mut gpt2_generator = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+//!                                             generate_config, device)?;
+//! gpt2_generator.add_json_schema_constraint(r#"{
+//!     "type": "object",
+//!     "properties": {"name": {"type": "string", "maxLength": 20}, "age": {"type": "integer"}},
+//!     "required": ["name", "age"]
+//! }"#)?;
+//! let output = gpt2_generator.generate(Some(vec!("The person record:")), None);
+//!# Ok(())
+//!# }
+//! ```
+
+use std::collections::{HashMap, HashSet, BTreeSet, VecDeque};
+use std::cell::RefCell;
+use std::char;
+use failure::{err_msg, Fallible};
+use rust_tokenizers::{Vocab, Gpt2Vocab, RobertaVocab};
+use serde_json::Value;
+use tch::{Tensor, Device};
+use crate::pipelines::generation::LogitsProcessor;
+
+/// # Byte-level BPE vocabulary
+/// Vocabularies whose tokens are byte sequences mapped to printable characters (GPT2 and RoBERTa), supported by grammar constrained generation.
+pub trait ByteLevelVocab: Vocab {}
+
+impl ByteLevelVocab for Gpt2Vocab {}
+
+impl ByteLevelVocab for RobertaVocab {}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct ByteSet([u64; 4]);
+
+impl ByteSet {
+    fn empty() -> ByteSet {
+        ByteSet([0; 4])
+    }
+
+    fn insert(&mut self, byte: u8) {
+        self.0[(byte / 64) as usize] |= 1u64 << (byte % 64) as u64;
+    }
+
+    fn insert_range(&mut self, start: u8, end: u8) {
+        for byte in start..=end {
+            self.insert(byte);
+        }
+    }
+
+    fn contains(&self, byte: u8) -> bool {
+        self.0[(byte / 64) as usize] & (1u64 << (byte % 64) as u64) != 0
+    }
+
+    fn union(&mut self, other: &ByteSet) {
+        for (block, other_block) in self.0.iter_mut().zip(other.0.iter()) {
+            *block |= *other_block;
+        }
+    }
+
+    fn ascii_complement(&self) -> ByteSet {
+        let mut complement = ByteSet::empty();
+        for byte in 0..128u8 {
+            if !self.contains(byte) {
+                complement.insert(byte);
+            }
+        }
+        complement
+    }
+}
+
+enum RegexNode {
+    Empty,
+    Bytes(ByteSet),
+    Concat(Vec<RegexNode>),
+    Alternation(Vec<RegexNode>),
+    Repeat(Box<RegexNode>, usize, Option<usize>),
+}
+
+impl RegexNode {
+    fn literal(character: char) -> RegexNode {
+        let mut buffer = [0u8; 4];
+        let bytes = character.encode_utf8(&mut buffer).as_bytes();
+        RegexNode::Concat(bytes
+            .iter()
+            .map(|byte| {
+                let mut byte_set = ByteSet::empty();
+                byte_set.insert(*byte);
+                RegexNode::Bytes(byte_set)
+            })
+            .collect())
+    }
+
+//    A set of ASCII characters, optionally completed by any non-ASCII character (encoded as a multi-byte UTF-8 sequence)
+    fn character_set(ascii_set: ByteSet, non_ascii: bool) -> RegexNode {
+        if !non_ascii {
+            return RegexNode::Bytes(ascii_set);
+        }
+        let mut continuation = ByteSet::empty();
+        continuation.insert_range(0x80, 0xBF);
+        let multi_byte_sequences = [(0xC2u8, 0xDFu8, 1usize), (0xE0, 0xEF, 2), (0xF0, 0xF4, 3)]
+            .iter()
+            .map(|(lead_start, lead_end, num_continuation)| {
+                let mut lead = ByteSet::empty();
+                lead.insert_range(*lead_start, *lead_end);
+                let mut sequence = vec!(RegexNode::Bytes(lead));
+                sequence.extend((0..*num_continuation).map(|_| RegexNode::Bytes(continuation)));
+                RegexNode::Concat(sequence)
+            })
+            .collect::<Vec<RegexNode>>();
+        let mut alternatives = vec!(RegexNode::Bytes(ascii_set));
+        alternatives.extend(multi_byte_sequences);
+        RegexNode::Alternation(alternatives)
+    }
+}
+
+struct RegexParser {
+    characters: Vec<char>,
+    position: usize,
+}
+
+impl RegexParser {
+    fn parse(pattern: &str) -> Fallible<RegexNode> {
+        let mut parser = RegexParser { characters: pattern.chars().collect(), position: 0 };
+        let node = parser.parse_alternation()?;
+        if parser.position < parser.characters.len() {
+            return Err(err_msg(format!("unexpected character '{}' at position {} of the regular expression",
+                                       parser.characters[parser.position], parser.position)));
+        }
+        Ok(node)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.characters.get(self.position).cloned()
+    }
+
+    fn next(&mut self) -> Fallible<char> {
+        let character = self.peek().ok_or_else(|| err_msg("unexpected end of the regular expression"))?;
+        self.position += 1;
+        Ok(character)
+    }
+
+    fn parse_alternation(&mut self) -> Fallible<RegexNode> {
+        let mut alternatives = vec!(self.parse_concatenation()?);
+        while self.peek() == Some('|') {
+            self.position += 1;
+            alternatives.push(self.parse_concatenation()?);
+        }
+        Ok(if alternatives.len() == 1 { alternatives.pop().unwrap() } else { RegexNode::Alternation(alternatives) })
+    }
+
+    fn parse_concatenation(&mut self) -> Fallible<RegexNode> {
+        let mut nodes = vec!();
+        while let Some(character) = self.peek() {
+            if (character == '|') | (character == ')') {
+                break;
+            }
+            nodes.push(self.parse_repetition()?);
+        }
+        Ok(RegexNode::Concat(nodes))
+    }
+
+    fn parse_repetition(&mut self) -> Fallible<RegexNode> {
+        let mut node = self.parse_atom()?;
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => {
+                    self.position += 1;
+                    self.parse_bounds()?
+                }
+                _ => break
+            };
+//            Consumes the quantifier (or the closing brace of the bounds)
+            self.position += 1;
+//            Lazy quantifiers match the same language
+            if self.peek() == Some('?') {
+                self.position += 1;
+            }
+            node = RegexNode::Repeat(Box::new(node), min, max);
+        }
+        Ok(node)
+    }
+
+    fn parse_number(&mut self) -> Option<usize> {
+        let start = self.position;
+        while self.peek().map_or(false, |character| character.is_ascii_digit()) {
+            self.position += 1;
+        }
+        self.characters[start..self.position].iter().collect::<String>().parse::<usize>().ok()
+    }
+
+//    Parses `m}`, `m,}` or `m,n}` (the opening brace was consumed), leaving the closing brace to be consumed
+    fn parse_bounds(&mut self) -> Fallible<(usize, Option<usize>)> {
+        let min = self.parse_number().ok_or_else(|| err_msg("invalid repetition bounds in the regular expression"))?;
+        let max = if self.peek() == Some(',') {
+            self.position += 1;
+            self.parse_number()
+        } else {
+            Some(min)
+        };
+        if self.peek() != Some('}') {
+            return Err(err_msg("invalid repetition bounds in the regular expression"));
+        }
+        if let Some(max) = max {
+            if max < min {
+                return Err(err_msg(format!("invalid repetition bounds {{{},{}}} in the regular expression", min, max)));
+            }
+        }
+        Ok((min, max))
+    }
+
+    fn parse_atom(&mut self) -> Fallible<RegexNode> {
+        let character = self.next()?;
+        match character {
+            '(' => {
+                if self.characters[self.position..].starts_with(&['?', ':']) {
+                    self.position += 2;
+                }
+                let node = self.parse_alternation()?;
+                if self.peek() != Some(')') {
+                    return Err(err_msg("unbalanced parenthesis in the regular expression"));
+                }
+                self.position += 1;
+                Ok(node)
+            }
+            '[' => self.parse_class(),
+            '.' => {
+                let mut newline = ByteSet::empty();
+                newline.insert(b'\n');
+                Ok(RegexNode::character_set(newline.ascii_complement(), true))
+            }
+            '^' if self.position == 1 => Ok(RegexNode::Empty),
+            '$' if self.position == self.characters.len() => Ok(RegexNode::Empty),
+            '\\' => {
+                let (ascii_set, non_ascii, literal) = self.parse_escape()?;
+                Ok(match literal {
+                    Some(literal) => RegexNode::literal(literal),
+                    None => RegexNode::character_set(ascii_set, non_ascii)
+                })
+            }
+            '*' | '+' | '?' | '{' | ')' => Err(err_msg(format!("unexpected character '{}' at position {} of the regular expression",
+                                                                  character, self.position - 1))),
+            _ => Ok(RegexNode::literal(character))
+        }
+    }
+
+//    Returns the ASCII set and non-ASCII flag of a class escape (e.g. `\d`), or the escaped literal character
+    fn parse_escape(&mut self) -> Fallible<(ByteSet, bool, Option<char>)> {
+        let character = self.next()?;
+        let mut ascii_set = ByteSet::empty();
+        match character {
+            'd' | 'D' => ascii_set.insert_range(b'0', b'9'),
+            'w' | 'W' => {
+                ascii_set.insert_range(b'a', b'z');
+                ascii_set.insert_range(b'A', b'Z');
+                ascii_set.insert_range(b'0', b'9');
+                ascii_set.insert(b'_');
+            }
+            's' | 'S' => {
+                for byte in b" \t\n\r\x0B\x0C".iter() {
+                    ascii_set.insert(*byte);
+                }
+            }
+            'n' => return Ok((ascii_set, false, Some('\n'))),
+            'r' => return Ok((ascii_set, false, Some('\r'))),
+            't' => return Ok((ascii_set, false, Some('\t'))),
+            'f' => return Ok((ascii_set, false, Some('\x0C'))),
+            'v' => return Ok((ascii_set, false, Some('\x0B'))),
+            '0' => return Ok((ascii_set, false, Some('\0'))),
+            'x' | 'u' => {
+                let num_digits = if character == 'x' { 2 } else { 4 };
+                let mut code = String::with_capacity(num_digits);
+                for _ in 0..num_digits {
+                    code.push(self.next()?);
+                }
+                let literal = u32::from_str_radix(&code, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| err_msg(format!("invalid escape \\{}{} in the regular expression", character, code)))?;
+                return Ok((ascii_set, false, Some(literal)));
+            }
+            _ => return Ok((ascii_set, false, Some(character)))
+        };
+        if character.is_uppercase() {
+            Ok((ascii_set.ascii_complement(), true, None))
+        } else {
+            Ok((ascii_set, false, None))
+        }
+    }
+
+    fn parse_class_character(&mut self, ascii_set: &mut ByteSet, non_ascii: &mut bool) -> Fallible<Option<char>> {
+        let character = self.next()?;
+        if character != '\\' {
+            return Ok(Some(character));
+        }
+        let (escape_set, escape_non_ascii, literal) = self.parse_escape()?;
+        if literal.is_none() {
+            ascii_set.union(&escape_set);
+            *non_ascii |= escape_non_ascii;
+        }
+        Ok(literal)
+    }
+
+    fn parse_class(&mut self) -> Fallible<RegexNode> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.position += 1;
+        }
+        let mut ascii_set = ByteSet::empty();
+        let mut non_ascii = false;
+        let mut first = true;
+        loop {
+            if (self.peek() == Some(']')) & !first {
+                self.position += 1;
+                break;
+            }
+            first = false;
+            let start = match self.parse_class_character(&mut ascii_set, &mut non_ascii)? {
+                Some(start) => start,
+                None => continue
+            };
+            let end = if (self.peek() == Some('-')) & (self.characters.get(self.position + 1).map_or(false, |next| *next != ']')) {
+                self.position += 1;
+                self.parse_class_character(&mut ascii_set, &mut non_ascii)?
+                    .ok_or_else(|| err_msg("invalid character range in the regular expression"))?
+            } else {
+                start
+            };
+            if !start.is_ascii() | !end.is_ascii() | (start > end) {
+                return Err(err_msg(format!("invalid character range {}-{} in the regular expression (only ASCII ranges are supported in classes)", start, end)));
+            }
+            ascii_set.insert_range(start as u8, end as u8);
+        }
+        Ok(if negated {
+            RegexNode::character_set(ascii_set.ascii_complement(), !non_ascii)
+        } else {
+            RegexNode::character_set(ascii_set, non_ascii)
+        })
+    }
+}
+
+#[derive(Default)]
+struct Nfa {
+    epsilon_transitions: Vec<Vec<usize>>,
+    byte_transitions: Vec<Vec<(ByteSet, usize)>>,
+}
+
+impl Nfa {
+    fn add_state(&mut self) -> usize {
+        self.epsilon_transitions.push(vec!());
+        self.byte_transitions.push(vec!());
+        self.epsilon_transitions.len() - 1
+    }
+
+//    Thompson construction: returns the start and end states of the fragment matching the node
+    fn add_node(&mut self, node: &RegexNode) -> (usize, usize) {
+        match node {
+            RegexNode::Empty => {
+                let state = self.add_state();
+                (state, state)
+            }
+            RegexNode::Bytes(byte_set) => {
+                let (start, end) = (self.add_state(), self.add_state());
+                self.byte_transitions[start].push((*byte_set, end));
+                (start, end)
+            }
+            RegexNode::Concat(nodes) => {
+                let start = self.add_state();
+                let mut end = start;
+                for node in nodes {
+                    let (node_start, node_end) = self.add_node(node);
+                    self.epsilon_transitions[end].push(node_start);
+                    end = node_end;
+                }
+                (start, end)
+            }
+            RegexNode::Alternation(nodes) => {
+                let (start, end) = (self.add_state(), self.add_state());
+                for node in nodes {
+                    let (node_start, node_end) = self.add_node(node);
+                    self.epsilon_transitions[start].push(node_start);
+                    self.epsilon_transitions[node_end].push(end);
+                }
+                (start, end)
+            }
+            RegexNode::Repeat(node, min, max) => {
+                let start = self.add_state();
+                let mut end = start;
+                for _ in 0..*min {
+                    let (node_start, node_end) = self.add_node(node);
+                    self.epsilon_transitions[end].push(node_start);
+                    end = node_end;
+                }
+                match max {
+                    None => {
+                        let (node_start, node_end) = self.add_node(node);
+                        let loop_end = self.add_state();
+                        self.epsilon_transitions[end].push(node_start);
+                        self.epsilon_transitions[end].push(loop_end);
+                        self.epsilon_transitions[node_end].push(node_start);
+                        self.epsilon_transitions[node_end].push(loop_end);
+                        end = loop_end;
+                    }
+                    Some(max) => {
+                        let optional_end = self.add_state();
+                        for _ in *min..*max {
+                            let (node_start, node_end) = self.add_node(node);
+                            self.epsilon_transitions[end].push(node_start);
+                            self.epsilon_transitions[end].push(optional_end);
+                            end = node_end;
+                        }
+                        self.epsilon_transitions[end].push(optional_end);
+                        end = optional_end;
+                    }
+                }
+                (start, end)
+            }
+        }
+    }
+
+    fn epsilon_closure(&self, states: impl IntoIterator<Item=usize>) -> BTreeSet<usize> {
+        let mut closure = BTreeSet::new();
+        let mut stack = states.into_iter().collect::<Vec<usize>>();
+        while let Some(state) = stack.pop() {
+            if closure.insert(state) {
+                stack.extend(self.epsilon_transitions[state].iter().cloned());
+            }
+        }
+        closure
+    }
+}
+
+const MAX_DFA_STATES: usize = 10_000;
+
+/// Deterministic finite-state machine over bytes, restricted to the states from which a match can still be reached.
+/// Bytes that are not distinguished by any set of the expression share a byte class, and transitions are stored per class.
+struct Dfa {
+    byte_classes: Vec<usize>,
+    transitions: Vec<Vec<Option<usize>>>,
+    accepting: Vec<bool>,
+}
+
+impl Dfa {
+//    Partitions the bytes in classes of bytes contained in the same sets of the NFA transitions, returning the class of each byte
+//    and the number of classes
+    fn byte_classes(nfa: &Nfa) -> (Vec<usize>, usize) {
+        let mut byte_classes = vec!(0usize; 256);
+        let mut num_classes = 1;
+        let byte_sets = nfa.byte_transitions
+            .iter()
+            .flat_map(|transitions| transitions.iter().map(|(byte_set, _)| *byte_set))
+            .collect::<HashSet<ByteSet>>();
+        for byte_set in byte_sets.iter() {
+            let mut refined_classes: HashMap<(usize, bool), usize> = HashMap::new();
+            for byte in 0..=255u8 {
+                let key = (byte_classes[byte as usize], byte_set.contains(byte));
+                let next_class = refined_classes.len();
+                byte_classes[byte as usize] = *refined_classes.entry(key).or_insert(next_class);
+            }
+            num_classes = refined_classes.len();
+        }
+        (byte_classes, num_classes)
+    }
+
+    fn from_regex(pattern: &str) -> Fallible<Dfa> {
+        let node = RegexParser::parse(pattern)?;
+        let mut nfa = Nfa::default();
+        let (nfa_start, nfa_end) = nfa.add_node(&node);
+        let (byte_classes, num_classes) = Dfa::byte_classes(&nfa);
+        let mut class_representatives = vec!(0u8; num_classes);
+        for byte in (0..=255u8).rev() {
+            class_representatives[byte_classes[byte as usize]] = byte;
+        }
+
+//        Subset construction
+        let start = nfa.epsilon_closure(vec!(nfa_start));
+        let mut state_indices: HashMap<BTreeSet<usize>, usize> = HashMap::new();
+        let mut state_sets = vec!(start.clone());
+        state_indices.insert(start, 0);
+        let mut transitions: Vec<Vec<Option<usize>>> = vec!();
+        let mut state_index = 0;
+        while state_index < state_sets.len() {
+            let mut state_transitions = vec!(None; num_classes);
+            for (byte_class, byte) in class_representatives.iter().cloned().enumerate() {
+                let targets = state_sets[state_index]
+                    .iter()
+                    .flat_map(|state| nfa.byte_transitions[*state].iter())
+                    .filter(|(byte_set, _)| byte_set.contains(byte))
+                    .map(|(_, target)| *target)
+                    .collect::<Vec<usize>>();
+                if targets.is_empty() {
+                    continue;
+                }
+                let target_set = nfa.epsilon_closure(targets);
+                let target_index = match state_indices.get(&target_set) {
+                    Some(target_index) => *target_index,
+                    None => {
+                        if state_sets.len() >= MAX_DFA_STATES {
+                            return Err(err_msg("the grammar is too large to be compiled"));
+                        }
+                        state_sets.push(target_set.clone());
+                        state_indices.insert(target_set, state_sets.len() - 1);
+                        state_sets.len() - 1
+                    }
+                };
+                state_transitions[byte_class] = Some(target_index);
+            }
+            transitions.push(state_transitions);
+            state_index += 1;
+        }
+        let accepting = state_sets.iter().map(|state_set| state_set.contains(&nfa_end)).collect::<Vec<bool>>();
+
+//        Remove the transitions to states from which no match can be reached
+        let mut predecessors: Vec<Vec<usize>> = vec!(vec!(); transitions.len());
+        for (state, state_transitions) in transitions.iter().enumerate() {
+            for target in state_transitions.iter().filter_map(|target| *target) {
+                predecessors[target].push(state);
+            }
+        }
+        let mut live = accepting.clone();
+        let mut queue = (0..live.len()).filter(|state| live[*state]).collect::<VecDeque<usize>>();
+        while let Some(state) = queue.pop_front() {
+            for predecessor in predecessors[state].iter() {
+                if !live[*predecessor] {
+                    live[*predecessor] = true;
+                    queue.push_back(*predecessor);
+                }
+            }
+        }
+        if !live[0] {
+            return Err(err_msg("the grammar does not match any text"));
+        }
+        for state_transitions in transitions.iter_mut() {
+            for target in state_transitions.iter_mut() {
+                if target.map_or(false, |target| !live[target]) {
+                    *target = None;
+                }
+            }
+        }
+        Ok(Dfa { byte_classes, transitions, accepting })
+    }
+
+    fn walk(&self, state: usize, bytes: &[u8]) -> Option<usize> {
+        bytes.iter().try_fold(state, |state, byte| self.transitions[state][self.byte_classes[*byte as usize]])
+    }
+}
+
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        if "\\.^$|?*+()[]{}-".contains(character) {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
+
+//    Regular expression of the members of an object (each a `"name": value` expression and a flag indicating if it is required), in order.
+//    `separated` indicates if a member was already generated, in which case the next members are preceded by a comma.
+fn object_members_regex(members: &[(String, bool)], separated: bool) -> String {
+    const SEPARATOR: &str = ", ?";
+    match members.split_first() {
+        None => String::new(),
+        Some(((member, required), remaining_members)) => match (*required, separated) {
+            (true, true) => format!("{}{}{}", SEPARATOR, member, object_members_regex(remaining_members, true)),
+            (true, false) => format!("{}{}", member, object_members_regex(remaining_members, true)),
+            (false, true) => format!("(?:{}{})?{}", SEPARATOR, member, object_members_regex(remaining_members, true)),
+            (false, false) => format!("(?:{}{}|{})", member, object_members_regex(remaining_members, true),
+                                      object_members_regex(remaining_members, false))
+        }
+    }
+}
+
+fn literal_alternatives(values: &[Value]) -> Fallible<String> {
+    if values.is_empty() {
+        return Err(err_msg("enum must contain at least one value"));
+    }
+    let alternatives = values
+        .iter()
+        .map(|value| escape_regex(&value.to_string()))
+        .collect::<Vec<String>>();
+    Ok(format!("(?:{})", alternatives.join("|")))
+}
+
+fn get_bound(schema: &Value, key: &str) -> Fallible<Option<usize>> {
+    match schema.get(key) {
+        Some(value) => value
+            .as_u64()
+            .map(|bound| Some(bound as usize))
+            .ok_or_else(|| err_msg(format!("{} must be a non-negative integer", key))),
+        None => Ok(None)
+    }
+}
+
+//    Keywords of the supported JSON schema subset, and annotations that do not constrain the documents (additional properties are never generated)
+const SUPPORTED_KEYWORDS: [&str; 20] = ["type", "properties", "required", "items", "minItems", "maxItems", "minLength", "maxLength", "enum", "const",
+    "anyOf", "oneOf", "additionalProperties", "title", "description", "default", "examples", "$schema", "$id", "$comment"];
+
+fn repetition_bounds(min: usize, max: Option<usize>) -> String {
+    match max {
+        Some(max) => format!("{{{},{}}}", min, max),
+        None => format!("{{{},}}", min)
+    }
+}
+
+/// Converts a JSON schema (supported subset described in the module documentation) to an equivalent regular expression.
+///
+/// # Arguments
+///
+/// * `schema` - JSON schema
+///
+/// # Returns
+/// * `Fallible<String>` Regular expression matching the JSON documents valid for the schema, or an error if the schema uses unsupported keywords or is contradictory
+pub fn json_schema_to_regex(schema: &Value) -> Fallible<String> {
+    const WHITESPACE: &str = " ?";
+    if let Some(keyword) = schema
+        .as_object()
+        .ok_or_else(|| err_msg(format!("JSON schemas must be objects: {}", schema)))?
+        .keys()
+        .find(|keyword| !SUPPORTED_KEYWORDS.contains(&keyword.as_str())) {
+        return Err(err_msg(format!("unsupported JSON schema keyword: {}", keyword)));
+    }
+    if let Some(value) = schema.get("const") {
+        return literal_alternatives(std::slice::from_ref(value));
+    }
+    if let Some(values) = schema.get("enum") {
+        return literal_alternatives(values.as_array().ok_or_else(|| err_msg("enum must be an array"))?);
+    }
+    for key in ["anyOf", "oneOf"].iter() {
+        if let Some(schemas) = schema.get(key) {
+            let alternatives = schemas
+                .as_array()
+                .ok_or_else(|| err_msg(format!("{} must be an array", key)))?
+                .iter()
+                .map(json_schema_to_regex)
+                .collect::<Fallible<Vec<String>>>()?;
+            return Ok(format!("(?:{})", alternatives.join("|")));
+        }
+    }
+    let schema_type = schema.get("type").ok_or_else(|| err_msg(format!("unsupported JSON schema (no type): {}", schema)))?;
+    if let Some(schema_types) = schema_type.as_array() {
+        let alternatives = schema_types
+            .iter()
+            .map(|schema_type| {
+                let mut type_schema = schema.clone();
+                type_schema["type"] = schema_type.clone();
+                json_schema_to_regex(&type_schema)
+            })
+            .collect::<Fallible<Vec<String>>>()?;
+        return Ok(format!("(?:{})", alternatives.join("|")));
+    }
+    match schema_type.as_str() {
+        Some("null") => Ok("null".to_string()),
+        Some("boolean") => Ok("(?:true|false)".to_string()),
+        Some("integer") => Ok("-?(?:0|[1-9][0-9]*)".to_string()),
+        Some("number") => Ok("-?(?:0|[1-9][0-9]*)(?:\\.[0-9]+)?(?:[eE][+-]?[0-9]+)?".to_string()),
+        Some("string") => {
+            let min_length = get_bound(schema, "minLength")?.unwrap_or(0);
+            let max_length = get_bound(schema, "maxLength")?;
+            if max_length.map_or(false, |max_length| min_length > max_length) {
+                return Err(err_msg(format!("minLength ({}) must not be greater than maxLength ({})", min_length, max_length.unwrap())));
+            }
+            Ok(format!("\"(?:[^\"\\\\\\x00-\\x1F]|\\\\[\"\\\\/bfnrt]|\\\\u[0-9a-fA-F]{{4}}){}\"", repetition_bounds(min_length, max_length)))
+        }
+        Some("array") => {
+            let item = match schema.get("items") {
+                Some(items) => json_schema_to_regex(items)?,
+                None => return Err(err_msg("array schemas must define items"))
+            };
+            let min_items = get_bound(schema, "minItems")?.unwrap_or(0);
+            let max_items = get_bound(schema, "maxItems")?;
+            if max_items.map_or(false, |max_items| min_items > max_items) {
+                return Err(err_msg(format!("minItems ({}) must not be greater than maxItems ({})", min_items, max_items.unwrap())));
+            }
+            let items = match (min_items, max_items) {
+                (_, Some(0)) => String::new(),
+                (0, max_items) => format!("(?:{}(?:,{}{}){})?", item, WHITESPACE, item, repetition_bounds(0, max_items.map(|max_items| max_items - 1))),
+                (min_items, max_items) => format!("{}(?:,{}{}){}", item, WHITESPACE, item, repetition_bounds(min_items - 1, max_items.map(|max_items| max_items - 1)))
+            };
+            Ok(format!("\\[{}{}{}\\]", WHITESPACE, items, WHITESPACE))
+        }
+        Some("object") => {
+            let empty_properties = serde_json::Map::new();
+            let properties = match schema.get("properties") {
+                Some(properties) => properties.as_object().ok_or_else(|| err_msg("properties must be an object"))?,
+                None => &empty_properties
+            };
+//            Required properties in the listed order, followed by the optional properties
+            let required_names = match schema.get("required") {
+                Some(required) => required
+                    .as_array()
+                    .ok_or_else(|| err_msg("required must be an array"))?
+                    .iter()
+                    .map(|name| name.as_str().ok_or_else(|| err_msg("required must contain property names")))
+                    .collect::<Fallible<Vec<&str>>>()?,
+                None => properties.keys().map(|name| name.as_str()).collect::<Vec<&str>>()
+            };
+            let mut property_names = required_names.iter().map(|name| (*name, true)).collect::<Vec<(&str, bool)>>();
+            property_names.extend(properties
+                .keys()
+                .filter(|name| !required_names.contains(&name.as_str()))
+                .map(|name| (name.as_str(), false)));
+            let members = property_names
+                .iter()
+                .map(|(name, required)| {
+                    let property_schema = properties
+                        .get(*name)
+                        .ok_or_else(|| err_msg(format!("required property {} is not defined", name)))?;
+                    Ok((format!("{}:{}{}", escape_regex(&Value::String(name.to_string()).to_string()), WHITESPACE, json_schema_to_regex(property_schema)?),
+                        *required))
+                })
+                .collect::<Fallible<Vec<(String, bool)>>>()?;
+            Ok(format!("\\{{{}{}{}\\}}", WHITESPACE, object_members_regex(&members, false), WHITESPACE))
+        }
+        _ => Err(err_msg(format!("unsupported JSON schema type: {}", schema_type)))
+    }
+}
+
+//    Byte to printable character mapping of byte-level BPE vocabularies (Radford et al.)
+fn unicode_to_bytes() -> HashMap<char, u8> {
+    let mut printable_bytes = (b'!'..=b'~').collect::<Vec<u8>>();
+    printable_bytes.extend(0xA1..=0xAC);
+    printable_bytes.extend(0xAE..=0xFF);
+    let mut mapping = printable_bytes
+        .iter()
+        .map(|byte| (char::from_u32(*byte as u32).unwrap(), *byte))
+        .collect::<HashMap<char, u8>>();
+    let mut offset = 0;
+    for byte in 0..=255u8 {
+        if !printable_bytes.contains(&byte) {
+            mapping.insert(char::from_u32(256 + offset).unwrap(), byte);
+            offset += 1;
+        }
+    }
+    mapping
+}
+
+/// # Grammar constrained generation processor
+/// Logits processor restricting the generated text (excluding the prompt) to match a regular expression or a JSON schema
+/// (see the module documentation for the supported syntax). The end of sequence tokens are only allowed once the generated text
+/// is a complete match, and are the only tokens allowed once no other token can extend the match.
+/// The match may be incomplete if the generation reaches `max_length` first, the sequence is then reported with `FinishReason::Incomplete`.
+/// Other settings banning tokens (e.g. `no_repeat_ngram_size`) may conflict with the grammar and should be disabled.
+pub struct GrammarProcessor {
+    dfa: Dfa,
+    token_bytes: Vec<Option<Vec<u8>>>,
+    eos_token_ids: Vec<i64>,
+    forced_bos_token_id: Option<i64>,
+    allowed_tokens: RefCell<HashMap<usize, Vec<i64>>>,
+}
+
+impl GrammarProcessor {
+    /// Build a new `GrammarProcessor` from a regular expression
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - Regular expression the generated text must match
+    /// * `vocab` - Byte-level BPE vocabulary of the generator
+    /// * `eos_token_ids` - End of sequence token ids (at least one), generated once the text matches
+    /// * `forced_bos_token_id` - Optional token forced by the model at the first generation step (e.g. BOS token of BART decoders), allowed before the grammar
+    ///
+    /// # Returns
+    /// * `Fallible<GrammarProcessor>` Processor, or an error if the regular expression is not supported
+    pub fn from_regex<V: ByteLevelVocab>(pattern: &str, vocab: &V, eos_token_ids: Vec<i64>, forced_bos_token_id: Option<i64>)
+                                         -> Fallible<GrammarProcessor> {
+        if eos_token_ids.is_empty() {
+            return Err(err_msg("grammar constrained generation requires an end of sequence token"));
+        }
+        let dfa = Dfa::from_regex(pattern)?;
+        let unicode_to_bytes = unicode_to_bytes();
+        let vocab_size = vocab.values().values().max().map_or(0, |max_id| *max_id + 1);
+        let mut token_bytes: Vec<Option<Vec<u8>>> = vec!(None; vocab_size as usize);
+        for (token, token_id) in vocab.values().iter() {
+            if vocab.special_values().contains_key(token) | eos_token_ids.contains(token_id) {
+                continue;
+            }
+            token_bytes[*token_id as usize] = token
+                .chars()
+                .map(|character| unicode_to_bytes.get(&character).cloned())
+                .collect::<Option<Vec<u8>>>();
+        }
+        Ok(GrammarProcessor { dfa, token_bytes, eos_token_ids, forced_bos_token_id, allowed_tokens: RefCell::new(HashMap::new()) })
+    }
+
+    /// Build a new `GrammarProcessor` from a JSON schema
+    ///
+    /// # Arguments
+    ///
+    /// * `schema` - JSON schema the generated text must be valid for
+    /// * `vocab` - Byte-level BPE vocabulary of the generator
+    /// * `eos_token_ids` - End of sequence token ids (at least one), generated once the JSON document is complete
+    /// * `forced_bos_token_id` - Optional token forced by the model at the first generation step (e.g. BOS token of BART decoders), allowed before the grammar
+    ///
+    /// # Returns
+    /// * `Fallible<GrammarProcessor>` Processor, or an error if the schema is not supported
+    pub fn from_json_schema<V: ByteLevelVocab>(schema: &str, vocab: &V, eos_token_ids: Vec<i64>, forced_bos_token_id: Option<i64>)
+                                               -> Fallible<GrammarProcessor> {
+        let schema: Value = serde_json::from_str(schema)?;
+        GrammarProcessor::from_regex(&json_schema_to_regex(&schema)?, vocab, eos_token_ids, forced_bos_token_id)
+    }
+
+//    Returns the state reached after the generated tokens, or None if the generation is finished (end of sequence token generated)
+    fn get_state(&self, generated_ids: &[i64]) -> Option<usize> {
+        let mut state = 0;
+        for (position, token_id) in generated_ids.iter().enumerate() {
+            if (position == 0) & (Some(*token_id) == self.forced_bos_token_id) {
+                continue;
+            }
+            if self.eos_token_ids.contains(token_id) {
+                return None;
+            }
+            state = self.token_bytes
+                .get(*token_id as usize)
+                .and_then(|bytes| bytes.as_ref())
+                .and_then(|bytes| self.dfa.walk(state, bytes))?;
+        }
+        Some(state)
+    }
+
+    fn get_allowed_tokens(&self, state: usize) -> Vec<i64> {
+        self.allowed_tokens
+            .borrow_mut()
+            .entry(state)
+            .or_insert_with(|| {
+                let mut allowed_tokens = self.token_bytes
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, bytes)| bytes.as_ref().map_or(false, |bytes| self.dfa.walk(state, bytes).is_some()))
+                    .map(|(token_id, _)| token_id as i64)
+                    .collect::<Vec<i64>>();
+                if self.dfa.accepting[state] | allowed_tokens.is_empty() {
+                    allowed_tokens.extend(self.eos_token_ids.iter());
+                }
+                allowed_tokens
+            })
+            .clone()
+    }
+}
+
+impl LogitsProcessor for GrammarProcessor {
+    fn is_complete(&self, generated_ids: &[i64]) -> bool {
+        if generated_ids.iter().any(|token_id| self.eos_token_ids.contains(token_id)) {
+            return true;
+        }
+        self.get_state(generated_ids).map_or(false, |state| self.dfa.accepting[state])
+    }
+
+    fn process(&self, scores: &mut Tensor, input_ids: &Tensor, prompt_length: i64) {
+        let generated_ids = input_ids.slice(1, prompt_length, *input_ids.size().last().unwrap(), 1).to(Device::Cpu);
+        let vocab_size = *scores.size().last().unwrap();
+        let mask = scores.ones_like() * std::f64::NEG_INFINITY;
+        for sequence_index in 0..*scores.size().first().unwrap() {
+            let generated_ids = generated_ids
+                .get(sequence_index)
+                .iter::<i64>()
+                .unwrap()
+                .collect::<Vec<i64>>();
+            let allowed_tokens = match (generated_ids.is_empty(), self.forced_bos_token_id) {
+                (true, Some(forced_bos_token_id)) => vec!(forced_bos_token_id),
+                _ => match self.get_state(&generated_ids) {
+                    Some(state) => self.get_allowed_tokens(state),
+                    None => self.eos_token_ids.clone()
+                }
+            };
+            let allowed_tokens = allowed_tokens
+                .into_iter()
+                .filter(|token_id| *token_id < vocab_size)
+                .collect::<HashSet<i64>>()
+                .into_iter()
+                .collect::<Vec<i64>>();
+            let _ = mask.get(sequence_index).index_fill_(0, &Tensor::of_slice(&allowed_tokens).to_device(scores.device()), 0f64);
+        }
+        *scores = &*scores + mask;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn matches(dfa: &Dfa, text: &str) -> bool {
+        dfa.walk(0, text.as_bytes()).map_or(false, |state| dfa.accepting[state])
+    }
+
+    fn schema_dfa(schema: Value) -> Dfa {
+        Dfa::from_regex(&json_schema_to_regex(&schema).unwrap()).unwrap()
+    }
+
+    fn error_message<T>(result: Fallible<T>) -> String {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(error) => error.to_string()
+        }
+    }
+
+    #[test]
+    fn regex_matching() {
+        let dfa = Dfa::from_regex(r" (yes|no), [0-9]{1,3} (?:cats|dogs)\.").unwrap();
+
+        assert!(matches(&dfa, " yes, 12 cats."));
+        assert!(matches(&dfa, " no, 0 dogs."));
+        assert!(!matches(&dfa, " yes, 1234 cats."));
+        assert!(!matches(&dfa, " maybe, 1 cats."));
+        assert!(!matches(&dfa, " yes, 12 cats"));
+        assert!(dfa.walk(0, b" yes, 1").is_some());
+        assert!(dfa.walk(0, b" yes, x").is_none());
+    }
+
+    #[test]
+    fn regex_classes_escapes_and_quantifiers() {
+        let dfa = Dfa::from_regex(r"^[^a-z\s]+\d*\w?$").unwrap();
+        assert!(matches(&dfa, "ABC"));
+        assert!(matches(&dfa, "ÉÈ12_"));
+        assert!(!matches(&dfa, "abc"));
+        assert!(!matches(&dfa, "A B"));
+
+        let dfa = Dfa::from_regex(r"a{2,}b{0,1}\x41é.").unwrap();
+        assert!(matches(&dfa, "aaAé日"));
+        assert!(matches(&dfa, "aaabAéx"));
+        assert!(!matches(&dfa, "aAéx"));
+        assert!(!matches(&dfa, "aabbAéx"));
+        assert!(!matches(&dfa, "aaAé\n"));
+    }
+
+    #[test]
+    fn regex_byte_classes() {
+        let dfa = Dfa::from_regex("[a-c]x").unwrap();
+
+//        Classes: {a, b, c}, {x} and all other bytes
+        assert!(dfa.transitions.iter().all(|state_transitions| state_transitions.len() == 3));
+        assert_eq!(dfa.byte_classes[b'a' as usize], dfa.byte_classes[b'c' as usize]);
+        assert_ne!(dfa.byte_classes[b'a' as usize], dfa.byte_classes[b'x' as usize]);
+        assert_eq!(dfa.byte_classes[b'd' as usize], dfa.byte_classes[0xFF]);
+    }
+
+    #[test]
+    fn regex_errors() {
+        assert!(error_message(Dfa::from_regex("(ab")).contains("unbalanced parenthesis"));
+        assert!(error_message(Dfa::from_regex("ab)")).contains("unexpected character ')'"));
+        assert!(error_message(Dfa::from_regex("a{3,1}")).contains("invalid repetition bounds"));
+        assert!(error_message(Dfa::from_regex("a{x}")).contains("invalid repetition bounds"));
+        assert!(error_message(Dfa::from_regex("*a")).contains("unexpected character '*'"));
+        assert!(error_message(Dfa::from_regex("[z-a]")).contains("invalid character range"));
+        assert!(error_message(Dfa::from_regex("[é-ü]")).contains("only ASCII ranges"));
+        assert!(error_message(Dfa::from_regex(r"\xZZ")).contains("invalid escape"));
+        assert!(error_message(Dfa::from_regex("[ab]*a[ab]{20}")).contains("too large"));
+    }
+
+    #[test]
+    fn schema_scalar_types() {
+        assert_eq!(json_schema_to_regex(&json!({"type": "integer"})).unwrap(), "-?(?:0|[1-9][0-9]*)");
+        assert_eq!(json_schema_to_regex(&json!({"type": "boolean"})).unwrap(), "(?:true|false)");
+        assert_eq!(json_schema_to_regex(&json!({"type": "null"})).unwrap(), "null");
+        assert_eq!(json_schema_to_regex(&json!({"enum": ["a.b", 1]})).unwrap(), "(?:\"a\\.b\"|1)");
+        assert_eq!(json_schema_to_regex(&json!({"type": ["integer", "null"]})).unwrap(), "(?:-?(?:0|[1-9][0-9]*)|null)");
+
+        let dfa = schema_dfa(json!({"type": "number"}));
+        assert!(matches(&dfa, "-12.5e3"));
+        assert!(!matches(&dfa, "012"));
+
+        let dfa = schema_dfa(json!({"type": "string", "minLength": 1, "maxLength": 3}));
+        assert!(matches(&dfa, "\"ab\""));
+        assert!(matches(&dfa, "\"\\\"é\""));
+        assert!(!matches(&dfa, "\"\""));
+        assert!(!matches(&dfa, "\"abcd\""));
+    }
+
+    #[test]
+    fn schema_arrays() {
+        let dfa = schema_dfa(json!({"type": "array", "items": {"type": "integer"}, "minItems": 1, "maxItems": 2}));
+        assert!(matches(&dfa, "[1]"));
+        assert!(matches(&dfa, "[ 1, 2 ]"));
+        assert!(!matches(&dfa, "[]"));
+        assert!(!matches(&dfa, "[1,2,3]"));
+
+        let dfa = schema_dfa(json!({"type": "array", "items": {"type": "boolean"}, "maxItems": 0}));
+        assert!(matches(&dfa, "[]"));
+        assert!(!matches(&dfa, "[true]"));
+    }
+
+    #[test]
+    fn schema_objects() {
+        let dfa = schema_dfa(json!({
+            "type": "object",
+            "title": "Person",
+            "properties": {"age": {"type": "integer"}, "name": {"type": "string"}, "pet": {"enum": ["cat", "dog"]}},
+            "required": ["name"],
+            "additionalProperties": false
+        }));
+        assert!(matches(&dfa, r#"{"name": "Ann"}"#));
+        assert!(matches(&dfa, r#"{"name": "Ann", "age": 3}"#));
+        assert!(matches(&dfa, r#"{"name": "Ann", "pet": "cat"}"#));
+        assert!(matches(&dfa, r#"{"name": "Ann", "age": 3, "pet": "dog"}"#));
+        assert!(!matches(&dfa, r#"{"age": 3}"#));
+        assert!(!matches(&dfa, r#"{"name": "Ann",}"#));
+        assert!(!matches(&dfa, r#"{"name": "Ann", "pet": "cow"}"#));
+
+        let dfa = schema_dfa(json!({"type": "object", "properties": {"a": {"type": "null"}, "b": {"type": "null"}}, "required": []}));
+        assert!(matches(&dfa, "{}"));
+        assert!(matches(&dfa, r#"{"b": null}"#));
+        assert!(matches(&dfa, r#"{"a": null, "b": null}"#));
+        assert!(!matches(&dfa, r#"{, "b": null}"#));
+
+        let dfa = schema_dfa(json!({"type": "object", "properties": {"a": {"type": "null"}, "b": {"type": "null"}}}));
+        assert!(matches(&dfa, r#"{"a": null, "b": null}"#));
+        assert!(!matches(&dfa, r#"{"a": null}"#));
+    }
+
+    #[test]
+    fn schema_errors() {
+        assert!(error_message(json_schema_to_regex(&json!({"type": "array", "items": {"type": "null"}, "minItems": 2, "maxItems": 1})))
+            .contains("minItems (2) must not be greater than maxItems (1)"));
+        assert!(error_message(json_schema_to_regex(&json!({"type": "string", "minLength": 3, "maxLength": 1})))
+            .contains("minLength (3) must not be greater than maxLength (1)"));
+        assert!(error_message(json_schema_to_regex(&json!({"type": "string", "pattern": "[a-z]+"})))
+            .contains("unsupported JSON schema keyword: pattern"));
+        assert!(error_message(json_schema_to_regex(&json!({"$ref": "#/definitions/item"})))
+            .contains("unsupported JSON schema keyword: $ref"));
+        assert!(error_message(json_schema_to_regex(&json!({"type": "array"}))).contains("must define items"));
+        assert!(error_message(json_schema_to_regex(&json!({"type": "object", "properties": {}, "required": ["name"]})))
+            .contains("required property name is not defined"));
+        assert!(error_message(json_schema_to_regex(&json!({"type": "tuple"}))).contains("unsupported JSON schema type"));
+        assert!(error_message(json_schema_to_regex(&json!({"enum": []}))).contains("at least one value"));
+        assert!(error_message(json_schema_to_regex(&json!({"maxItems": -1, "type": "array", "items": {"type": "null"}})))
+            .contains("maxItems must be a non-negative integer"));
+        assert!(error_message(json_schema_to_regex(&json!(true))).contains("JSON schemas must be objects"));
+    }
+}
@@ -3,7 +3,7 @@
 //! Based on Huggingface's pipelines, ready to use end-to-end NLP pipelines are available as part of this crate. The following capabilities are currently available:
 //!
 //! #### 1. Question Answering
-//! Extractive question answering from a given question and context. DistilBERT model finetuned on SQuAD (Stanford Question Answering Dataset)
+//! Extractive question answering from a given question and context. DistilBERT model finetuned on SQuAD (Stanford Question Answering Dataset). BERT and RoBERTa readers are also supported.
 //!
 //! ```no_run
 //!# use std::path::PathBuf;
@@ -18,8 +18,11 @@
 //!# let weights_path = &home.as_path().join("model.ot");
 //! let device = Device::cuda_if_available();
 //! let qa_model = QuestionAnsweringModel::new(vocab_path,
+//!                                            None,
 //!                                            config_path,
-//!                                            weights_path, device)?;
+//!                                            weights_path,
+//!                                            Default::default(),
+//!                                            device)?;
 //!
 //! let question = String::from("Where does Amy live ?");
 //! let context = String::from("Amy lives in Amsterdam");
@@ -107,7 +110,8 @@
 //! Generate language based on a prompt. GPT2 and GPT available as base models.
 //! Include techniques such as beam search, top-k and nucleus sampling, temperature setting and repetition penalty.
 //! Supports batch generation of sentences from several prompts. Sequences will be left-padded with the model's padding token if present, the unknown token otherwise.
-//! This may impact the results and it is recommended to submit prompts of similar length for best results. Additional information on the input parameters for generation is provided in this module's documentation.
+//! Padded positions are masked and not counted in the maximum length of greedy decoding and sampling, so that greedy decoding of a batch returns the same sequences as the generation of each prompt alone.
+//! Additional information on the input parameters for generation is provided in this module's documentation.
 //!
 //! ```no_run
 //!# use std::path::PathBuf;
@@ -214,19 +218,223 @@
 //! ```no_run
 //!# use rust_bert::pipelines::question_answering::Answer;
 //!# use rust_bert::pipelines::ner::Entity;
+//!# use rust_bert::pipelines::common::Offset;
 //!# let output =
 //! [
-//!    Entity { word: String::from("Amy"), score: 0.9986, label: String::from("I-PER") },
-//!    Entity { word: String::from("Paris"), score: 0.9985, label: String::from("I-LOC") },
-//!    Entity { word: String::from("Paris"), score: 0.9988, label: String::from("I-LOC") },
-//!    Entity { word: String::from("France"), score: 0.9993, label: String::from("I-LOC") },
+//!    Entity { word: String::from("Amy"), score: 0.9986, label: String::from("I-PER"), sentence: 0, offset: Some(Offset { begin: 11, end: 14 }) },
+//!    Entity { word: String::from("Paris"), score: 0.9985, label: String::from("I-LOC"), sentence: 0, offset: Some(Offset { begin: 26, end: 31 }) },
+//!    Entity { word: String::from("Paris"), score: 0.9988, label: String::from("I-LOC"), sentence: 1, offset: Some(Offset { begin: 0, end: 5 }) },
+//!    Entity { word: String::from("France"), score: 0.9993, label: String::from("I-LOC"), sentence: 1, offset: Some(Offset { begin: 19, end: 25 }) },
 //! ]
 //!# ;
 //! ```
 //!
+//! #### 6. Zero-shot classification
+//! Performs zero-shot classification on input sentences with provided labels using a model fine-tuned for Natural Language Inference.
+//! ```no_run
+//!# use std::path::PathBuf;
+//!# use tch::Device;
+//! use rust_bert::pipelines::zero_shot_classification::ZeroShotClassificationModel;
+//!# fn main() -> failure::Fallible<()> {
+//!# let mut home: PathBuf = dirs::home_dir().unwrap();
+//!# home.push("rustbert");
+//!# home.push("bart-large-mnli");
+//!# let config_path = &home.as_path().join("config.json");
+//!# let vocab_path = &home.as_path().join("vocab.txt");
+//!# let merges_path = &home.as_path().join("merges.txt");
+//!# let weights_path = &home.as_path().join("model.ot");
+//! let device = Device::cuda_if_available();
+//! let mut sequence_classification_model = ZeroShotClassificationModel::new(vocab_path,
+//!                                                                          merges_path,
+//!                                                                          config_path,
+//!                                                                          weights_path, device)?;
+//!
+//! let input_sentence = "Who are you voting for in 2020?";
+//! let input_sequence_2 = "The prime minister has announced a stimulus package which was widely criticized by the opposition.";
+//! let candidate_labels = &["politics", "public health", "economics", "sports"];
+//!
+//! let output = sequence_classification_model.predict(&[input_sentence, input_sequence_2], candidate_labels, None, 128);
+//!# Ok(())
+//!# }
+//! ```
+//!
+//! Output: \
+//! ```no_run
+//!# use rust_bert::pipelines::sequence_classification::Label;
+//!# let output =
+//! [
+//!     [
+//!         Label { text: "politics".to_string(), score: 0.972, id: 0, sentence: 0 },
+//!         Label { text: "public health".to_string(), score: 0.010, id: 1, sentence: 0 },
+//!         Label { text: "economics".to_string(), score: 0.011, id: 2, sentence: 0 },
+//!         Label { text: "sports".to_string(), score: 0.007, id: 3, sentence: 0 },
+//!     ],
+//!     [
+//!         Label { text: "politics".to_string(), score: 0.975, id: 0, sentence: 1 },
+//!         Label { text: "public health".to_string(), score: 0.002, id: 1, sentence: 1 },
+//!         Label { text: "economics".to_string(), score: 0.022, id: 2, sentence: 1 },
+//!         Label { text: "sports".to_string(), score: 0.001, id: 3, sentence: 1 },
+//!     ],
+//! ]
+//!# ;
+//! ```
+//!
+//! #### 7. Fill mask
+//! Predicts the most likely tokens for masked positions of input sentences, using a BERT, DistilBERT or RoBERTa masked language model.
+//! ```no_run
+//!# use std::path::PathBuf;
+//!# use tch::Device;
+//! use rust_bert::pipelines::fill_mask::{FillMaskModel, FillMaskConfig};
+//! use rust_bert::pipelines::common::ModelType;
+//!# fn main() -> failure::Fallible<()> {
+//!# let mut home: PathBuf = dirs::home_dir().unwrap();
+//!# home.push("rustbert");
+//!# home.push("distilbert");
+//!# let config_path = &home.as_path().join("config.json");
+//!# let vocab_path = &home.as_path().join("vocab.txt");
+//!# let weights_path = &home.as_path().join("model.ot");
+//! let device = Device::cuda_if_available();
+//! let fill_mask_config = FillMaskConfig { model_type: ModelType::DistilBert, ..Default::default() };
+//! let fill_mask_model = FillMaskModel::new(vocab_path,
+//!                                          None,
+//!                                          config_path,
+//!                                          weights_path,
+//!                                          fill_mask_config,
+//!                                          device)?;
+//!
+//! let input = ["Looks like one [MASK] is missing", "It was a very nice and [MASK] day"];
+//! let output = fill_mask_model.predict(&input, 1);
+//!# Ok(())
+//!# }
+//! ```
+//!
+//! Output: \
+//! ```no_run
+//!# use rust_bert::pipelines::fill_mask::{MaskPrediction, MaskedToken};
+//!# let output =
+//! [
+//!     [MaskPrediction { position: 4, candidates: vec![MaskedToken { token: String::from("person"), id: 2711, score: 0.0996, sequence: String::from("looks like one person is missing") }] }],
+//!     [MaskPrediction { position: 7, candidates: vec![MaskedToken { token: String::from("sunny"), id: 11559, score: 0.2310, sequence: String::from("it was a very nice and sunny day") }] }],
+//! ]
+//!# ;
+//! ```
+//!
+//! #### 8. Sentence embeddings
+//! Extracts fixed-size sentence embeddings from a BERT, DistilBERT or RoBERTa encoder, using CLS, mean or max pooling of the hidden states.
+//! ```no_run
+//!# use std::path::PathBuf;
+//!# use tch::Device;
+//! use rust_bert::pipelines::embeddings::{EmbeddingsModel, EmbeddingsConfig, PoolingStrategy};
+//! use rust_bert::pipelines::common::ModelType;
+//!# fn main() -> failure::Fallible<()> {
+//!# let mut home: PathBuf = dirs::home_dir().unwrap();
+//!# home.push("rustbert");
+//!# home.push("distilbert");
+//!# let config_path = &home.as_path().join("config.json");
+//!# let vocab_path = &home.as_path().join("vocab.txt");
+//!# let weights_path = &home.as_path().join("model.ot");
+//! let device = Device::cuda_if_available();
+//! let embeddings_config = EmbeddingsConfig {
+//!     model_type: ModelType::DistilBert,
+//!     pooling: PoolingStrategy::Mean,
+//!     normalize: true,
+//!     ..Default::default()
+//! };
+//! let embeddings_model = EmbeddingsModel::new(vocab_path,
+//!                                             None,
+//!                                             config_path,
+//!                                             weights_path,
+//!                                             embeddings_config,
+//!                                             device)?;
+//!
+//! let input = ["This is an example sentence", "Each sentence is converted"];
+//! let output: Vec<Vec<f32>> = embeddings_model.encode(&input);
+//!# Ok(())
+//!# }
+//! ```
+//!
+//! #### 9. Sequence classification
+//! Generic multi-class classification of sentences (e.g. sentiment, topic or intent classification), with labels read from the `id2label` mapping of the model configuration.
+//! BERT, DistilBERT, RoBERTa and BART classifiers are supported.
+//! ```no_run
+//!# use std::path::PathBuf;
+//!# use tch::Device;
+//! use rust_bert::pipelines::sequence_classification::{SequenceClassificationModel, SequenceClassificationConfig};
+//! use rust_bert::pipelines::common::ModelType;
+//!# fn main() -> failure::Fallible<()> {
+//!# let mut home: PathBuf = dirs::home_dir().unwrap();
+//!# home.push("rustbert");
+//!# home.push("distilbert_sst2");
+//!# let config_path = &home.as_path().join("config.json");
+//!# let vocab_path = &home.as_path().join("vocab.txt");
+//!# let weights_path = &home.as_path().join("model.ot");
+//! let device = Device::cuda_if_available();
+//! let sequence_classification_config = SequenceClassificationConfig { model_type: ModelType::DistilBert, ..Default::default() };
+//! let mut sequence_classification_model = SequenceClassificationModel::new(vocab_path,
+//!                                                                          None,
+//!                                                                          config_path,
+//!                                                                          weights_path,
+//!                                                                          sequence_classification_config,
+//!                                                                          device)?;
+//! let input = [
+//!     "Probably my all-time favorite movie, a story of selflessness, sacrifice and dedication to a noble cause, but it's not preachy or boring.",
+//!     "This film tried to be too many things all at once: stinging political satire, Hollywood blockbuster, sappy romantic comedy, family values promo...",
+//! ];
+//! let output = sequence_classification_model.predict(&input);
+//!# Ok(())
+//!# }
+//! ```
+//!
+//! Output: \
+//! ```no_run
+//!# use rust_bert::pipelines::sequence_classification::Label;
+//!# let output =
+//! [
+//!    Label { text: String::from("POSITIVE"), score: 0.9981, id: 1, sentence: 0 },
+//!    Label { text: String::from("NEGATIVE"), score: 0.9927, id: 0, sentence: 1 },
+//! ]
+//!# ;
+//! ```
+//!
+//! #### 10. Token classification
+//! Generic token classification (e.g. Named Entity Recognition, Part-of-Speech tagging or chunking) with BERT, DistilBERT or RoBERTa backends.
+//! Labels are read from the `id2label` mapping of the model configuration and can be reported at the word level, including tokens labelled as `O`.
+//! ```no_run
+//!# use std::path::PathBuf;
+//!# use tch::Device;
+//! use rust_bert::pipelines::token_classification::{TokenClassificationModel, TokenClassificationConfig};
+//! use rust_bert::pipelines::common::ModelType;
+//!# fn main() -> failure::Fallible<()> {
+//!# let mut home: PathBuf = dirs::home_dir().unwrap();
+//!# home.push("rustbert");
+//!# home.push("bert-ner");
+//!# let config_path = &home.as_path().join("config.json");
+//!# let vocab_path = &home.as_path().join("vocab.txt");
+//!# let weights_path = &home.as_path().join("model.ot");
+//! let device = Device::cuda_if_available();
+//! let token_classification_config = TokenClassificationConfig { model_type: ModelType::Bert, lower_case: false, ..Default::default() };
+//! let token_classification_model = TokenClassificationModel::new(vocab_path,
+//!                                                                None,
+//!                                                                config_path,
+//!                                                                weights_path,
+//!                                                                token_classification_config,
+//!                                                                device)?;
+//! let input = ["My name is Amy. I live in Paris."];
+//! let output = token_classification_model.predict(&input, true, true);
+//!# Ok(())
+//!# }
+//! ```
+//!
 
+pub mod common;
 pub mod sentiment;
 pub mod ner;
 pub mod question_answering;
 pub mod generation;
-pub mod summarization;
\ No newline at end of file
+pub mod grammar;
+pub mod summarization;
+pub mod zero_shot_classification;
+pub mod fill_mask;
+pub mod embeddings;
+pub mod sequence_classification;
+pub mod token_classification;
\ No newline at end of file
@@ -11,7 +11,8 @@
 // limitations under the License.
 
 //! # Named Entity Recognition pipeline
-//! Extracts entities (Person, Location, Organization, Miscellaneous) from text.
+//! Extracts entities (Person, Location, Organization, Miscellaneous) from text. The NER pipeline is a `TokenClassificationModel`
+//! using a cased BERT backend, other backends can be used through `pipelines::token_classification`.
 //! BERT cased large model finetuned on CoNNL03, contributed by the [MDZ Digital Library team at the Bavarian State Library](https://github.com/dbmdz)
 //! All resources for this model can be downloaded using the Python utility script included in this repository.
 //! 1. Set-up a Python virtual environment and install dependencies (in ./requirements.txt)
@@ -45,25 +46,26 @@
 //! ```no_run
 //!# use rust_bert::pipelines::question_answering::Answer;
 //!# use rust_bert::pipelines::ner::Entity;
+//!# use rust_bert::pipelines::common::Offset;
 //!# let output =
 //! [
-//!    Entity { word: String::from("Amy"), score: 0.9986, label: String::from("I-PER") },
-//!    Entity { word: String::from("Paris"), score: 0.9985, label: String::from("I-LOC") },
-//!    Entity { word: String::from("Paris"), score: 0.9988, label: String::from("I-LOC") },
-//!    Entity { word: String::from("France"), score: 0.9993, label: String::from("I-LOC") },
+//!    Entity { word: String::from("Amy"), score: 0.9986, label: String::from("I-PER"), sentence: 0, offset: Some(Offset { begin: 11, end: 14 }) },
+//!    Entity { word: String::from("Paris"), score: 0.9985, label: String::from("I-LOC"), sentence: 0, offset: Some(Offset { begin: 26, end: 31 }) },
+//!    Entity { word: String::from("Paris"), score: 0.9988, label: String::from("I-LOC"), sentence: 1, offset: Some(Offset { begin: 0, end: 5 }) },
+//!    Entity { word: String::from("France"), score: 0.9993, label: String::from("I-LOC"), sentence: 1, offset: Some(Offset { begin: 19, end: 25 }) },
 //! ]
 //!# ;
 //! ```
+//!
+//! `predict` returns one entity per sub-word token. Sub-word tokens and consecutive tags of the same entity can be merged into
+//! whole entity spans using `predict_entities`, given the tagging scheme of the model labels (`TaggingScheme::IOB1`, `IOB2` or `BIOES`)
+//! and a strategy to aggregate the token scores (`ScoreAggregation::First`, `Average` or `Max`). The entity label is then the
+//! entity type without tag prefix (e.g. `LOC`).
 
-use rust_tokenizers::bert_tokenizer::BertTokenizer;
 use std::path::Path;
-use tch::nn::VarStore;
-use rust_tokenizers::preprocessing::tokenizer::base_tokenizer::{TruncationStrategy, MultiThreadedTokenizer};
-use std::collections::HashMap;
-use tch::{Tensor, no_grad, Device};
-use tch::kind::Kind::Float;
-use crate::bert::{BertForTokenClassification, BertConfig};
-use crate::Config;
+use tch::Device;
+use crate::pipelines::common::{ModelType, Offset};
+use crate::pipelines::token_classification::{TokenClassificationModel, TokenClassificationConfig, Token};
 
 
 #[derive(Debug)]
@@ -75,14 +77,37 @@ pub struct Entity {
     pub score: f64,
     /// Entity label (e.g. ORG, LOC...)
     pub label: String,
+    /// Sentence index
+    pub sentence: usize,
+    /// Character offsets of the entity in the input sentence (None if the entity could not be aligned with the input text)
+    pub offset: Option<Offset>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// # Tagging scheme of the token classification labels
+pub enum TaggingScheme {
+    /// Inside-Outside-Beginning (`I-` tags, with `B-` only separating adjacent entities of the same type, e.g. CoNLL-03 models)
+    IOB1,
+    /// Inside-Outside-Beginning (each entity starts with a `B-` tag)
+    IOB2,
+    /// Beginning-Inside-Outside-End-Single
+    BIOES,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// # Strategy to aggregate the word scores of an entity spanning several words
+pub enum ScoreAggregation {
+    /// Score of the first word of the entity
+    First,
+    /// Average of the word scores
+    Average,
+    /// Maximum of the word scores
+    Max,
 }
 
 /// # NERModel to extract named entities
 pub struct NERModel {
-    tokenizer: BertTokenizer,
-    bert_sequence_classifier: BertForTokenClassification,
-    label_mapping: HashMap<i64, String>,
-    var_store: VarStore,
+    token_classification_model: TokenClassificationModel,
 }
 
 impl NERModel {
@@ -118,32 +143,18 @@ impl NERModel {
     ///
     pub fn new(vocab_path: &Path, config_path: &Path, weights_path: &Path, device: Device)
                -> failure::Fallible<NERModel> {
-        let tokenizer = BertTokenizer::from_file(vocab_path.to_str().unwrap(), false);
-        let mut var_store = VarStore::new(device);
-        let config = BertConfig::from_file(config_path);
-        let bert_sequence_classifier = BertForTokenClassification::new(&var_store.root(), &config);
-        let label_mapping = config.id2label.expect("No label dictionary (id2label) provided in configuration file");
-        var_store.load(weights_path)?;
-        Ok(NERModel { tokenizer, bert_sequence_classifier, label_mapping, var_store })
-    }
-
-    fn prepare_for_model(&self, input: Vec<&str>) -> Tensor {
-        let tokenized_input = self.tokenizer.encode_list(input.to_vec(),
-                                                         128,
-                                                         &TruncationStrategy::LongestFirst,
-                                                         0);
-        let max_len = tokenized_input.iter().map(|input| input.token_ids.len()).max().unwrap();
-        let tokenized_input = tokenized_input.
-            iter().
-            map(|input| input.token_ids.clone()).
-            map(|mut input| {
-                input.extend(vec![0; max_len - input.len()]);
-                input
-            }).
-            map(|input|
-                Tensor::of_slice(&(input))).
-            collect::<Vec<_>>();
-        Tensor::stack(tokenized_input.as_slice(), 0).to(self.var_store.device())
+        let token_classification_config = TokenClassificationConfig {
+            model_type: ModelType::Bert,
+            lower_case: false,
+            ..Default::default()
+        };
+        let token_classification_model = TokenClassificationModel::new(vocab_path,
+                                                                       None,
+                                                                       config_path,
+                                                                       weights_path,
+                                                                       token_classification_config,
+                                                                       device)?;
+        Ok(NERModel { token_classification_model })
     }
 
     /// Extract entities from a text
@@ -183,34 +194,129 @@ impl NERModel {
     /// ```
     ///
     pub fn predict(&self, input: &[&str]) -> Vec<Entity> {
-        let input_tensor = self.prepare_for_model(input.to_vec());
-        let (output, _, _) = no_grad(|| {
-            self.bert_sequence_classifier
-                .forward_t(Some(input_tensor.copy()),
-                           None,
-                           None,
-                           None,
-                           None,
-                           false)
-        });
-        let output = output.detach().to(Device::Cpu);
-        let score: Tensor = output.exp() / output.exp().sum1(&[-1], true, Float);
-        let labels_idx = &score.argmax(-1, true);
+        self.token_classification_model
+            .predict(input, false, false)
+            .into_iter()
+            .map(|token| Entity {
+                word: token.text,
+                score: token.score,
+                label: token.label,
+                sentence: token.sentence,
+                offset: token.offset,
+            })
+            .collect()
+    }
+
+    /// Extract entities from a text, merging sub-word tokens and consecutive tags of a same entity into whole entity spans.
+    /// The label of a word is the label predicted for its first sub-word token.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - `&[&str]` Array of texts to extract entities from.
+    /// * `tagging_scheme` - `TaggingScheme` used by the model labels (IOB1, IOB2 or BIOES)
+    /// * `score_aggregation` - `ScoreAggregation` strategy used to compute the entity score from its word scores (first, average or max)
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Entity>` containing extracted entities, with the entity type (without tag prefix) as label
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    ///# use tch::Device;
+    ///# use std::path::{Path, PathBuf};
+    ///# use rust_bert::pipelines::ner::{NERModel, TaggingScheme, ScoreAggregation};
+    ///#
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    ///# let device = Device::Cpu;
+    /// let ner_model =  NERModel::new(vocab_path,
+    ///                                config_path,
+    ///                                weights_path,
+    ///                                device)?;
+    /// let input = [
+    ///     "My name is Amy. I live in New York City.",
+    ///     "Amsterdam is a city in the Netherlands."
+    /// ];
+    /// let output = ner_model.predict_entities(&input, TaggingScheme::IOB1, ScoreAggregation::Average);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn predict_entities(&self, input: &[&str], tagging_scheme: TaggingScheme, score_aggregation: ScoreAggregation) -> Vec<Entity> {
+        let words = self.token_classification_model.predict(input, true, true);
 
         let mut entities: Vec<Entity> = vec!();
-        for sentence_idx in 0..labels_idx.size()[0] {
-            let labels = labels_idx.get(sentence_idx);
-            for position_idx in 0..labels.size()[0] {
-                let label = labels.int64_value(&[position_idx]);
-                if label != 0 {
-                    entities.push(Entity {
-                        word: rust_tokenizers::preprocessing::tokenizer::base_tokenizer::Tokenizer::decode(&self.tokenizer, vec!(input_tensor.int64_value(&[sentence_idx, position_idx])), true, true),
-                        score: score.double_value(&[sentence_idx, position_idx, label]),
-                        label: self.label_mapping.get(&label).expect("Index out of vocabulary bounds.").to_owned(),
-                    });
+        let mut current_entity: Vec<Token> = vec!();
+        let mut current_type = String::new();
+        for word in words {
+            if current_entity.last().map_or(false, |previous| previous.sentence != word.sentence) {
+                entities.extend(build_entity(&mut current_entity, &current_type, input, score_aggregation));
+            }
+            let (prefix, entity_type) = split_label(&word.label, tagging_scheme);
+            match prefix {
+                'B' | 'S' => {
+                    entities.extend(build_entity(&mut current_entity, &current_type, input, score_aggregation));
+                    current_type = entity_type;
+                    current_entity.push(word);
+                }
+                'I' | 'E' => {
+                    if entity_type != current_type {
+                        entities.extend(build_entity(&mut current_entity, &current_type, input, score_aggregation));
+                        current_type = entity_type;
+                    }
+                    current_entity.push(word);
+                }
+                _ => {
+                    entities.extend(build_entity(&mut current_entity, &current_type, input, score_aggregation));
                 }
             }
+            if (prefix == 'S') | (prefix == 'E') {
+                entities.extend(build_entity(&mut current_entity, &current_type, input, score_aggregation));
+            }
         }
+        entities.extend(build_entity(&mut current_entity, &current_type, input, score_aggregation));
         entities
     }
-}
\ No newline at end of file
+}
+
+/// Builds an entity from the words accumulated (clearing the buffer). Returns None if the buffer is empty.
+fn build_entity(words: &mut Vec<Token>, entity_type: &str, input: &[&str], score_aggregation: ScoreAggregation) -> Option<Entity> {
+    if words.is_empty() {
+        return None;
+    }
+    let score = match score_aggregation {
+        ScoreAggregation::First => words[0].score,
+        ScoreAggregation::Average => words.iter().map(|word| word.score).sum::<f64>() / words.len() as f64,
+        ScoreAggregation::Max => words.iter().map(|word| word.score).fold(std::f64::NEG_INFINITY, f64::max),
+    };
+    let sentence = words[0].sentence;
+    let (word, offset) = match (words.first().unwrap().offset, words.last().unwrap().offset) {
+        (Some(begin), Some(end)) => (input[sentence].chars().skip(begin.begin).take(end.end - begin.begin).collect::<String>(),
+                                     Some(Offset { begin: begin.begin, end: end.end })),
+        _ => (words.iter().map(|word| word.text.as_str()).collect::<Vec<&str>>().join(" "), None)
+    };
+    words.clear();
+    Some(Entity { word, score, label: entity_type.to_owned(), sentence, offset })
+}
+
+/// Splits a label into its tag prefix and entity type (e.g. `B-LOC` into `('B', "LOC")`). The `O` label returns an `'O'` prefix, and labels
+/// without a prefix valid for the tagging scheme are considered inside an entity.
+fn split_label(label: &str, tagging_scheme: TaggingScheme) -> (char, String) {
+    if label == "O" {
+        return ('O', String::new());
+    }
+    let valid_prefixes: &[char] = match tagging_scheme {
+        TaggingScheme::IOB1 | TaggingScheme::IOB2 => &['B', 'I'],
+        TaggingScheme::BIOES => &['B', 'I', 'E', 'S'],
+    };
+    let mut label_chars = label.chars();
+    match (label_chars.next(), label_chars.next()) {
+        (Some(prefix), Some(separator)) if valid_prefixes.contains(&prefix) & ((separator == '-') | (separator == '_')) =>
+            (prefix, label_chars.collect()),
+        _ => ('I', label.to_owned())
+    }
+}
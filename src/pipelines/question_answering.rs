@@ -12,6 +12,12 @@
 
 //! # Question Answering pipeline
 //! Extractive question answering from a given question and context. DistilBERT model finetuned on SQuAD (Stanford Question Answering Dataset).
+//! BERT and RoBERTa readers finetuned on SQuAD can be used instead by setting the `model_type` of the `QuestionAnsweringConfig`
+//! (RoBERTa models also require the path to the BPE merges file).
+//! Models trained on SQuAD 2.0 can flag questions that cannot be answered from the context by setting `handle_impossible_answer`:
+//! the score of the "no answer" option is then compared with the best span score, and a null answer (empty string) is returned when it wins.
+//! The sequence and answer length limits (`max_seq_len`, `doc_stride`, `max_query_length` and `max_answer_len`) are set in the `QuestionAnsweringConfig`
+//! and can be overridden for a single call using `predict_with_limits`.
 //! All resources for this model can be downloaded using the Python utility script included in this repository.
 //! 1. Set-up a Python virtual environment and install dependencies (in ./requirements.txt)
 //! 2. Run the conversion script python /utils/download-dependencies_distilbert-qa.py.
@@ -30,8 +36,11 @@
 //!# let weights_path = &home.as_path().join("model.ot");
 //! let device = Device::cuda_if_available();
 //! let qa_model = QuestionAnsweringModel::new(vocab_path,
+//!                                            None,
 //!                                            config_path,
-//!                                            weights_path, device)?;
+//!                                            weights_path,
+//!                                            Default::default(),
+//!                                            device)?;
 //!
 //! let question = String::from("Where does Amy live ?");
 //! let context = String::from("Amy lives in Amsterdam");
@@ -57,17 +66,20 @@
 //!# ;
 //! ```
 
-use rust_tokenizers::{BertTokenizer, Tokenizer, TruncationStrategy, TokenizedInput};
+use rust_tokenizers::{TruncationStrategy, TokenizedInput};
 use tch::{Device, Tensor, no_grad};
 use std::path::{Path, PathBuf};
 use rust_tokenizers::tokenization_utils::truncate_sequences;
 use std::collections::HashMap;
 use std::cmp::min;
 use tch::nn::VarStore;
-use tch::kind::Kind::Float;
+use tch::kind::Kind::{Int64, Float};
 use std::fs;
-use crate::Config;
-use crate::distilbert::{DistilBertForQuestionAnswering, DistilBertConfig};
+use failure::err_msg;
+use crate::pipelines::common::{ModelType, ConfigOption, TokenizerOption, Offset, get_token_offsets};
+use crate::bert::BertForQuestionAnswering;
+use crate::distilbert::DistilBertForQuestionAnswering;
+use crate::roberta::RobertaForQuestionAnswering;
 
 /// # Input for Question Answering
 /// Includes a context (containing the answer) and question strings
@@ -83,14 +95,16 @@ struct QaExample {
     pub question: String,
     pub context: String,
     pub doc_tokens: Vec<String>,
-    pub char_to_word_offset: Vec<i64>,
+    pub word_offsets: Vec<Offset>,
 }
 
 #[derive(Debug)]
 struct QaFeature {
     pub input_ids: Vec<i64>,
+    pub segment_ids: Vec<i8>,
     pub attention_mask: Vec<i64>,
     pub token_to_orig_map: HashMap<i64, i64>,
+    pub token_to_offset_map: HashMap<i64, Offset>,
     pub p_mask: Vec<i8>,
     pub example_index: i64,
 
@@ -101,49 +115,48 @@ struct QaFeature {
 pub struct Answer {
     /// Confidence score
     pub score: f64,
-    /// Start position of answer span
+    /// Start position (in characters) of the answer span in the context
     pub start: usize,
-    /// End position of answer span
+    /// End position (in characters) of the answer span in the context, pointing at the last character of the answer
     pub end: usize,
-    /// Answer span
+    /// Answer span, exact substring of the context
     pub answer: String,
 }
 
 impl QaExample {
     pub fn new(question: &str, context: &str) -> QaExample {
         let question = question.to_owned();
-        let (doc_tokens, char_to_word_offset) = QaExample::split_context(context);
-        QaExample { question, context: context.to_owned(), doc_tokens, char_to_word_offset }
+        let (doc_tokens, word_offsets) = QaExample::split_context(context);
+        QaExample { question, context: context.to_owned(), doc_tokens, word_offsets }
     }
 
-    fn split_context(context: &str) -> (Vec<String>, Vec<i64>) {
+    fn split_context(context: &str) -> (Vec<String>, Vec<Offset>) {
         let mut doc_tokens: Vec<String> = vec!();
-        let mut char_to_word_offset: Vec<i64> = vec!();
+        let mut word_offsets: Vec<Offset> = vec!();
         let max_length = context.len();
         let mut current_word = String::with_capacity(max_length);
-        let mut previous_whitespace = false;
+        let mut word_begin = 0usize;
 
-        for character in context.chars() {
-            char_to_word_offset.push(doc_tokens.len() as i64);
+        for (position, character) in context.chars().enumerate() {
             if QaExample::is_whitespace(&character) {
-                previous_whitespace = true;
                 if !current_word.is_empty() {
                     doc_tokens.push(current_word.clone());
+                    word_offsets.push(Offset { begin: word_begin, end: position });
                     current_word = String::with_capacity(max_length);
                 }
             } else {
-                if previous_whitespace {
-                    current_word = String::with_capacity(max_length);
+                if current_word.is_empty() {
+                    word_begin = position;
                 }
                 current_word.push(character);
-                previous_whitespace = false;
             }
         }
 
         if !current_word.is_empty() {
             doc_tokens.push(current_word.clone());
+            word_offsets.push(Offset { begin: word_begin, end: word_begin + current_word.chars().count() });
         }
-        (doc_tokens, char_to_word_offset)
+        (doc_tokens, word_offsets)
     }
 
     fn is_whitespace(character: &char) -> bool {
@@ -155,16 +168,181 @@ impl QaExample {
     }
 }
 
+/// # Configuration for QuestionAnsweringModel
+pub struct QuestionAnsweringConfig {
+    /// Model type used for the span extraction (default: `ModelType::DistilBert`)
+    pub model_type: ModelType,
+    /// Flag indicating if the tokenizer should lower case the input (default: false). Should be set to true for uncased models.
+    pub lower_case: bool,
+    /// Flag indicating if the model may predict that the context does not contain the answer, for models trained on SQuAD 2.0 (default: false)
+    pub handle_impossible_answer: bool,
+    /// Minimum margin by which the "no answer" score must exceed the best span score for the question to be deemed unanswerable (default: 0.0)
+    pub null_score_threshold: f64,
+    /// Maximum length of the encoded question and context span, including special tokens (default: 384)
+    pub max_seq_len: usize,
+    /// Stride between two consecutive context spans when the context does not fit in a single span (default: 128)
+    pub doc_stride: usize,
+    /// Maximum number of tokens for the question, longer questions will be truncated (default: 64)
+    pub max_query_length: usize,
+    /// Maximum length of the predicted answer, in tokens (default: 15)
+    pub max_answer_len: usize,
+}
+
+impl Default for QuestionAnsweringConfig {
+    fn default() -> QuestionAnsweringConfig {
+        QuestionAnsweringConfig {
+            model_type: ModelType::DistilBert,
+            lower_case: false,
+            handle_impossible_answer: false,
+            null_score_threshold: 0.0,
+            max_seq_len: 384,
+            doc_stride: 128,
+            max_query_length: 64,
+            max_answer_len: 15,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// # Sequence and answer length limits for Question Answering
+/// Can be passed to `QuestionAnsweringModel::predict_with_limits` to override the limits set in the `QuestionAnsweringConfig` for a single call.
+pub struct QaLimits {
+    /// Maximum length of the encoded question and context span, including special tokens
+    pub max_seq_len: usize,
+    /// Stride between two consecutive context spans when the context does not fit in a single span
+    pub doc_stride: usize,
+    /// Maximum number of tokens for the question, longer questions will be truncated
+    pub max_query_length: usize,
+    /// Maximum length of the predicted answer, in tokens
+    pub max_answer_len: usize,
+}
+
+impl Default for QaLimits {
+    fn default() -> QaLimits {
+        let config = QuestionAnsweringConfig::default();
+        QaLimits {
+            max_seq_len: config.max_seq_len,
+            doc_stride: config.doc_stride,
+            max_query_length: config.max_query_length,
+            max_answer_len: config.max_answer_len,
+        }
+    }
+}
+
+impl QaLimits {
+    /// Checks the limits are consistent with each other and with the maximum sequence length supported by the model.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_len` - maximum number of positions that can be encoded by the model
+    /// * `sequence_pair_added_tokens` - number of special tokens added by the tokenizer to a (question, context) pair
+    fn validate(&self, max_len: usize, sequence_pair_added_tokens: usize) -> failure::Fallible<()> {
+        if self.max_seq_len > max_len {
+            return Err(err_msg(format!("max_seq_len ({}) exceeds the maximum sequence length supported by the model ({})",
+                                       self.max_seq_len, max_len)));
+        }
+        if self.max_query_length + sequence_pair_added_tokens >= self.max_seq_len {
+            return Err(err_msg(format!("max_query_length ({}) and the {} special tokens leave no room for the context within max_seq_len ({})",
+                                       self.max_query_length, sequence_pair_added_tokens, self.max_seq_len)));
+        }
+        let max_doc_stride = self.max_seq_len - self.max_query_length - sequence_pair_added_tokens;
+        if (self.doc_stride == 0) | (self.doc_stride > max_doc_stride) {
+            return Err(err_msg(format!("doc_stride ({}) must be between 1 and the context length available in a span ({}: max_seq_len - max_query_length - {} special tokens)",
+                                       self.doc_stride, max_doc_stride, sequence_pair_added_tokens)));
+        }
+        if self.max_answer_len == 0 {
+            return Err(err_msg("max_answer_len must be strictly greater than 0"));
+        }
+        Ok(())
+    }
+}
+
+/// # Abstraction that holds one particular question answering model, for any of the supported models
+pub enum QuestionAnsweringOption {
+    /// Question answering based on BERT
+    Bert(BertForQuestionAnswering),
+    /// Question answering based on DistilBERT
+    DistilBert(DistilBertForQuestionAnswering),
+    /// Question answering based on RoBERTa
+    Roberta(RobertaForQuestionAnswering),
+}
+
+impl QuestionAnsweringOption {
+    /// Instantiate a new question answering model of the supplied type.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_type` - `ModelType` indicating the model type to load (must match with the actual data to be loaded)
+    /// * `p` - `tch::nn::Path` path to the model file to load (e.g. model.ot)
+    /// * `config` - A configuration (the model type of the configuration must be compatible with the value for
+    /// `model_type`)
+    ///
+    pub fn new(model_type: ModelType, p: &tch::nn::Path, config: &ConfigOption) -> Self {
+        match model_type {
+            ModelType::Bert => {
+                if let ConfigOption::Bert(config) = config {
+                    QuestionAnsweringOption::Bert(BertForQuestionAnswering::new(p, config))
+                } else {
+                    panic!("You can only supply a BertConfig for Bert!");
+                }
+            }
+            ModelType::DistilBert => {
+                if let ConfigOption::DistilBert(config) = config {
+                    QuestionAnsweringOption::DistilBert(DistilBertForQuestionAnswering::new(p, config))
+                } else {
+                    panic!("You can only supply a DistilBertConfig for DistilBert!");
+                }
+            }
+            ModelType::Roberta => {
+                if let ConfigOption::Bert(config) = config {
+                    QuestionAnsweringOption::Roberta(RobertaForQuestionAnswering::new(p, config))
+                } else {
+                    panic!("You can only supply a BertConfig for Roberta!");
+                }
+            }
+            ModelType::Bart => panic!("BART is not supported for question answering!"),
+        }
+    }
+
+    /// Returns the `ModelType` for this QuestionAnsweringOption
+    pub fn model_type(&self) -> ModelType {
+        match *self {
+            Self::Bert(_) => ModelType::Bert,
+            Self::Roberta(_) => ModelType::Roberta,
+            Self::DistilBert(_) => ModelType::DistilBert,
+        }
+    }
+
+    /// Interface method to forward_t() of the particular models. Returns the start and end logits of shape (*batch size*, *sequence_length*).
+    /// The token type ids are only used by BERT (RoBERTa does not use segment embeddings and DistilBERT has none).
+    pub fn forward_t(&self, input_ids: Option<Tensor>, mask: Option<Tensor>, token_type_ids: Option<Tensor>, train: bool) -> (Tensor, Tensor) {
+        match *self {
+            Self::Bert(ref model) => {
+                let (start_logits, end_logits, _, _) = model.forward_t(input_ids, mask, token_type_ids, None, None, train);
+                (start_logits, end_logits)
+            }
+            Self::DistilBert(ref model) => {
+                let (start_logits, end_logits, _, _) = model.forward_t(input_ids, mask, None, train).expect("Error in distilbert forward_t");
+                (start_logits, end_logits)
+            }
+            Self::Roberta(ref model) => {
+                let (start_logits, end_logits, _, _) = model.forward_t(input_ids, mask, None, None, None, train);
+                (start_logits, end_logits)
+            }
+        }
+    }
+}
+
 /// # QuestionAnsweringModel to perform extractive question answering
 pub struct QuestionAnsweringModel {
-    tokenizer: BertTokenizer,
+    tokenizer: TokenizerOption,
     pad_idx: i64,
-    sep_idx: i64,
-    max_seq_len: usize,
-    doc_stride: usize,
-    max_query_length: usize,
-    max_answer_len: usize,
-    distilbert_qa: DistilBertForQuestionAnswering,
+    lower_case: bool,
+    max_len: usize,
+    limits: QaLimits,
+    handle_impossible_answer: bool,
+    null_score_threshold: f64,
+    qa_model: QuestionAnsweringOption,
     var_store: VarStore,
 }
 
@@ -174,8 +352,11 @@ impl QuestionAnsweringModel {
     /// # Arguments
     ///
     /// * `vocab_path` - Path to the model vocabulary, expected to have a structure following the [Transformers library](https://github.com/huggingface/transformers) convention
+    /// * `merges_path` - Optional path to the bpe merges, required for RoBERTa models
     /// * `config_path` - Path to the model configuration, expected to have a structure following the [Transformers library](https://github.com/huggingface/transformers) convention
     /// * `weights_path` - Path to the model weight files. These need to be converted form the `.bin` to `.ot` format using the utility script provided.
+    /// * `qa_config` - `QuestionAnsweringConfig` defining the backend architecture, tokenization settings and sequence length limits.
+    /// An error is returned if the limits are inconsistent or exceed the maximum sequence length of the model.
     /// * `device` - Device to run the model on, e.g. `Device::Cpu` or `Device::Cuda(0)`
     ///
     /// # Example
@@ -184,50 +365,79 @@ impl QuestionAnsweringModel {
     ///# fn main() -> failure::Fallible<()> {
     /// use tch::Device;
     /// use std::path::{Path, PathBuf};
-    /// use rust_bert::pipelines::question_answering::QuestionAnsweringModel;
+    /// use rust_bert::pipelines::question_answering::{QuestionAnsweringModel, QuestionAnsweringConfig};
+    /// use rust_bert::pipelines::common::ModelType;
     ///
     /// let mut home: PathBuf = dirs::home_dir().unwrap();
     /// let config_path = &home.as_path().join("config.json");
-    /// let vocab_path = &home.as_path().join("vocab.txt");
+    /// let vocab_path = &home.as_path().join("vocab.json");
+    /// let merges_path = &home.as_path().join("merges.txt");
     /// let weights_path = &home.as_path().join("model.ot");
     /// let device = Device::Cpu;
+    /// let qa_config = QuestionAnsweringConfig { model_type: ModelType::Roberta, ..Default::default() };
     /// let qa_model =  QuestionAnsweringModel::new(vocab_path,
+    ///                                             Some(merges_path),
     ///                                             config_path,
     ///                                             weights_path,
+    ///                                             qa_config,
     ///                                             device)?;
     ///# Ok(())
     ///# }
     /// ```
     ///
-    pub fn new(vocab_path: &Path, config_path: &Path, weights_path: &Path, device: Device)
+    pub fn new(vocab_path: &Path, merges_path: Option<&Path>, config_path: &Path, weights_path: &Path,
+               qa_config: QuestionAnsweringConfig, device: Device)
                -> failure::Fallible<QuestionAnsweringModel> {
-        let tokenizer = BertTokenizer::from_file(vocab_path.to_str().unwrap(), false);
-        let pad_idx = *Tokenizer::vocab(&tokenizer).special_values.get("[PAD]").expect("[PAD] token not found in vocabulary");
-        let sep_idx = *Tokenizer::vocab(&tokenizer).special_values.get("[SEP]").expect("[SEP] token not found in vocabulary");
+        let model_type = qa_config.model_type;
+        let tokenizer = TokenizerOption::from_file(model_type,
+                                                   vocab_path.to_str().unwrap(),
+                                                   merges_path.map(|path| path.to_str().unwrap()),
+                                                   qa_config.lower_case);
+        let pad_idx = tokenizer.get_pad_id();
         let mut var_store = VarStore::new(device);
-        let mut config = DistilBertConfig::from_file(config_path);
-//        The config for the current pre-trained question answering model indicates position embeddings which does not seem accurate
-        config.sinusoidal_pos_embds = false;
-        let distilbert_qa = DistilBertForQuestionAnswering::new(&var_store.root(), &config);
+        let mut config = ConfigOption::from_file(model_type, config_path);
+        if let ConfigOption::DistilBert(ref mut config) = config {
+//            The config for the current pre-trained question answering model indicates position embeddings which does not seem accurate
+            config.sinusoidal_pos_embds = false;
+        }
+//        RoBERTa position ids start after the padding index, reducing the number of positions available
+        let max_len = match model_type {
+            ModelType::Roberta => config.get_max_len() as usize - 2,
+            _ => config.get_max_len() as usize
+        };
+        let limits = QaLimits {
+            max_seq_len: qa_config.max_seq_len,
+            doc_stride: qa_config.doc_stride,
+            max_query_length: qa_config.max_query_length,
+            max_answer_len: qa_config.max_answer_len,
+        };
+        limits.validate(max_len, tokenizer.build_input_with_special_tokens(vec!(), Some(vec!())).0.len())?;
+        let qa_model = QuestionAnsweringOption::new(model_type, &var_store.root(), &config);
         var_store.load(weights_path)?;
         Ok(QuestionAnsweringModel {
             tokenizer,
             pad_idx,
-            sep_idx,
-            max_seq_len: 384,
-            doc_stride: 128,
-            max_query_length: 64,
-            max_answer_len: 15,
-            distilbert_qa,
+            lower_case: qa_config.lower_case,
+            max_len,
+            limits,
+            handle_impossible_answer: qa_config.handle_impossible_answer,
+            null_score_threshold: qa_config.null_score_threshold,
+            qa_model,
             var_store,
         })
     }
 
     fn generate_batch_indices(&self, features: &Vec<QaFeature>, batch_size: usize) -> Vec<(usize, usize)> {
-        let mut example_features_length: HashMap<i64, usize> = HashMap::new();
+//        Features are generated in example order: batches are built so that all the features of an example fall in the same batch
+        let mut example_features_length: Vec<usize> = vec!();
+        let mut previous_example_index = None;
         for feature in features {
-            let count = example_features_length.entry(feature.example_index).or_insert(0);
-            *count += 1;
+            if previous_example_index == Some(feature.example_index) {
+                *example_features_length.last_mut().unwrap() += 1;
+            } else {
+                example_features_length.push(1);
+                previous_example_index = Some(feature.example_index);
+            }
         }
 
         let mut batch_indices: Vec<(usize, usize)> = Vec::with_capacity(features.len());
@@ -236,15 +446,15 @@ impl QuestionAnsweringModel {
         let mut start = 0usize;
         let mut end = 0usize;
 
-        for &feature_length in example_features_length.values() {
-            if batch_length + feature_length <= batch_size {
+        for feature_length in example_features_length {
+            if (batch_length + feature_length <= batch_size) | (batch_length == 0) {
                 end += feature_length;
                 batch_length += feature_length;
             } else {
                 batch_indices.push((start, end));
                 start = end;
                 end += feature_length;
-                batch_length = 1usize;
+                batch_length = feature_length;
             }
         }
         batch_indices.push((start, end));
@@ -261,6 +471,8 @@ impl QuestionAnsweringModel {
     ///
     /// # Returns
     /// * `Vec<Vec<Answer>>` Vector (same length as `qa_inputs`) of vectors (each of length `top_k`) containing the extracted answers.
+    /// If `handle_impossible_answer` is set and the "no answer" option wins over the best span, the first answer is a null answer
+    /// with an empty `answer` string and `start` and `end` set to 0.
     ///
     /// # Example
     ///
@@ -276,8 +488,10 @@ impl QuestionAnsweringModel {
     /// let weights_path = &home.as_path().join("model.ot");
     /// let device = Device::Cpu;
     /// let qa_model =  QuestionAnsweringModel::new(vocab_path,
+    ///                                             None,
     ///                                             config_path,
     ///                                             weights_path,
+    ///                                             Default::default(),
     ///                                             device)?;
     ///
     /// let question_1 = String::from("Where does Amy live ?");
@@ -294,6 +508,59 @@ impl QuestionAnsweringModel {
     /// ```
     ///
     pub fn predict(&self, qa_inputs: &[QaInput], top_k: i64, batch_size: usize) -> Vec<Vec<Answer>> {
+        self.predict_checked(qa_inputs, top_k, batch_size, &self.limits)
+    }
+
+    /// Perform extractive question answering given a list of `QaInputs`, overriding the sequence and answer length limits of the model configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `qa_inputs` - `&[QaInput]` Array of Question Answering inputs (context and question pairs)
+    /// * `top_k` - return the top-k answers for each QaInput. Set to 1 to return only the best answer.
+    /// * `batch_size` - maximum batch size for the model forward pass.
+    /// * `limits` - `&QaLimits` sequence and answer length limits to use for this call
+    ///
+    /// # Returns
+    /// * `failure::Fallible<Vec<Vec<Answer>>>` Answers as returned by `predict`, or an error if the limits are inconsistent
+    /// or exceed the maximum sequence length supported by the model.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    /// use tch::Device;
+    /// use std::path::{Path, PathBuf};
+    /// use rust_bert::pipelines::question_answering::{QuestionAnsweringModel, QaInput, QaLimits};
+    ///
+    /// let mut home: PathBuf = dirs::home_dir().unwrap();
+    /// let config_path = &home.as_path().join("config.json");
+    /// let vocab_path = &home.as_path().join("vocab.txt");
+    /// let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::Cpu;
+    /// let qa_model =  QuestionAnsweringModel::new(vocab_path,
+    ///                                             None,
+    ///                                             config_path,
+    ///                                             weights_path,
+    ///                                             Default::default(),
+    ///                                             device)?;
+    ///
+    /// let question = String::from("What does the contract cover ?");
+    /// let context = String::from("This contract covers the supply of office furniture to the customer.");
+    /// let limits = QaLimits { max_answer_len: 30, ..Default::default() };
+    ///
+    /// let answers = qa_model.predict_with_limits(&[QaInput { question, context }], 1, 32, &limits)?;
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn predict_with_limits(&self, qa_inputs: &[QaInput], top_k: i64, batch_size: usize, limits: &QaLimits)
+                               -> failure::Fallible<Vec<Vec<Answer>>> {
+        let sequence_pair_added_tokens = self.tokenizer.build_input_with_special_tokens(vec!(), Some(vec!())).0.len();
+        limits.validate(self.max_len, sequence_pair_added_tokens)?;
+        Ok(self.predict_checked(qa_inputs, top_k, batch_size, limits))
+    }
+
+    fn predict_checked(&self, qa_inputs: &[QaInput], top_k: i64, batch_size: usize, limits: &QaLimits) -> Vec<Vec<Answer>> {
         let examples: Vec<QaExample> = qa_inputs
             .iter()
             .map(|qa_input| QaExample::new(&qa_input.question, &qa_input.context))
@@ -302,7 +569,7 @@ impl QuestionAnsweringModel {
         let features: Vec<QaFeature> = examples
             .iter()
             .enumerate()
-            .map(|(example_index, qa_example)| self.generate_features(&qa_example, self.max_seq_len, self.doc_stride, self.max_query_length, example_index as i64))
+            .map(|(example_index, qa_example)| self.generate_features(&qa_example, limits.max_seq_len, limits.doc_stride, limits.max_query_length, example_index as i64))
             .flatten()
             .collect();
 
@@ -312,31 +579,39 @@ impl QuestionAnsweringModel {
         for (start, end) in batch_indices {
             let batch_features = &features[start..end];
             let mut input_ids = Vec::with_capacity(batch_features.len());
+            let mut segment_ids = Vec::with_capacity(batch_features.len());
             let mut attention_masks = Vec::with_capacity(batch_features.len());
 
             no_grad(|| {
                 for feature in batch_features {
                     input_ids.push(Tensor::of_slice(&feature.input_ids));
+                    segment_ids.push(Tensor::of_slice(&feature.segment_ids));
                     attention_masks.push(Tensor::of_slice(&feature.attention_mask));
                 }
 
                 let input_ids = Tensor::stack(&input_ids, 0).to(self.var_store.device());
+                let segment_ids = Tensor::stack(&segment_ids, 0).to_kind(Int64).to(self.var_store.device());
                 let attention_masks = Tensor::stack(&attention_masks, 0).to(self.var_store.device());
 
-                let (start_logits, end_logits, _, _) = self.distilbert_qa.forward_t(Some(input_ids), Some(attention_masks), None, false).unwrap();
+                let (start_logits, end_logits) = self.qa_model.forward_t(Some(input_ids), Some(attention_masks), Some(segment_ids), false);
 
                 let start_logits = start_logits.detach();
                 let end_logits = end_logits.detach();
-                let example_index_to_feature_end_position: Vec<(usize, i64)> = batch_features
-                    .iter()
-                    .enumerate()
-                    .map(|(feature_index, feature)| (feature.example_index as usize, feature_index as i64 + 1))
-                    .collect();
+                let mut example_index_to_feature_end_position: Vec<(usize, i64)> = vec!();
+                for (feature_index, feature) in batch_features.iter().enumerate() {
+                    let example_index = feature.example_index as usize;
+                    if example_index_to_feature_end_position.last().map_or(false, |&(index, _)| index == example_index) {
+                        example_index_to_feature_end_position.last_mut().unwrap().1 = feature_index as i64 + 1;
+                    } else {
+                        example_index_to_feature_end_position.push((example_index, feature_index as i64 + 1));
+                    }
+                }
 
                 let mut feature_id_start = 0;
 
                 for (example_id, max_feature_id) in example_index_to_feature_end_position {
                     let mut answers: Vec<Answer> = vec!();
+                    let mut null_score = f64::INFINITY;
                     let example = &examples[example_id];
                     for feature_idx in feature_id_start..max_feature_id {
                         let feature = &batch_features[feature_idx as usize];
@@ -344,42 +619,55 @@ impl QuestionAnsweringModel {
                         let end = end_logits.get(feature_idx);
                         let p_mask = (Tensor::of_slice(&feature.p_mask) - 1).abs().to_device(start.device());
 
-                        let start: Tensor = start.exp() / start.exp().sum(Float) * &p_mask;
-                        let end: Tensor = end.exp() / end.exp().sum(Float) * &p_mask;
-
-                        let (starts, ends, scores) = self.decode(&start, &end, top_k);
-
-                        for idx in 0..starts.len() {
-                            let start_pos = feature.token_to_orig_map[&starts[idx]] as usize;
-                            let end_pos = feature.token_to_orig_map[&ends[idx]] as usize;
-                            let answer = example.doc_tokens[start_pos..end_pos + 1].join(" ");
+                        let start: Tensor = start.exp() / start.exp().sum(Float);
+                        let end: Tensor = end.exp() / end.exp().sum(Float);
+//                        The "no answer" option is the span pointing at the first (CLS) token. The feature least confident in the absence of answer is kept.
+                        let feature_null_score = start.double_value(&[0]) * end.double_value(&[0]);
+                        if feature_null_score < null_score {
+                            null_score = feature_null_score;
+                        }
 
-                            let start = example.char_to_word_offset
-                                .iter()
-                                .position(|&v| v as usize == start_pos)
-                                .unwrap();
+                        let start = start * &p_mask;
+                        let end = end * &p_mask;
 
-                            let end = example.char_to_word_offset
-                                .iter()
-                                .rposition(|&v| v as usize == end_pos)
-                                .unwrap();
+                        let (starts, ends, scores) = self.decode(&start, &end, top_k, limits.max_answer_len);
 
-                            answers.push(Answer { score: scores[idx], start, end, answer });
+                        for idx in 0..starts.len() {
+//                            Sub-word tokens that could not be aligned with the context fall back to the boundaries of their word
+                            let start = match feature.token_to_offset_map.get(&starts[idx]) {
+                                Some(offset) => offset.begin,
+                                None => example.word_offsets[feature.token_to_orig_map[&starts[idx]] as usize].begin
+                            };
+                            let end = match feature.token_to_offset_map.get(&ends[idx]) {
+                                Some(offset) => offset.end,
+                                None => example.word_offsets[feature.token_to_orig_map[&ends[idx]] as usize].end
+                            };
+                            let answer: String = example.context.chars().skip(start).take(end - start).collect();
+
+                            answers.push(Answer { score: scores[idx], start, end: end - 1, answer });
                         }
                     }
                     feature_id_start = max_feature_id;
-                    all_answers.push(answers[..(top_k as usize)].to_vec());
+                    answers.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+                    if self.handle_impossible_answer {
+                        let best_span_score = answers.first().map_or(0f64, |answer| answer.score);
+                        if null_score - best_span_score > self.null_score_threshold {
+                            answers.insert(0, Answer { score: null_score, start: 0, end: 0, answer: String::new() });
+                        }
+                    }
+                    answers.truncate(top_k as usize);
+                    all_answers.push(answers);
                 }
             });
         }
         all_answers
     }
 
-    fn decode(&self, start: &Tensor, end: &Tensor, top_k: i64) -> (Vec<i64>, Vec<i64>, Vec<f64>) {
+    fn decode(&self, start: &Tensor, end: &Tensor, top_k: i64, max_answer_len: usize) -> (Vec<i64>, Vec<i64>, Vec<f64>) {
         let outer = start.unsqueeze(-1).matmul(&end.unsqueeze(0));
         let start_dim = start.size()[0];
         let end_dim = end.size()[0];
-        let candidates = outer.triu(0).tril(self.max_answer_len as i64 - 1).flatten(0, -1);
+        let candidates = outer.triu(0).tril(max_answer_len as i64 - 1).flatten(0, -1);
         let idx_sort = if top_k == 1 {
             candidates.argmax(0, true)
         } else if candidates.size()[0] < top_k {
@@ -402,19 +690,22 @@ impl QuestionAnsweringModel {
 
     fn generate_features(&self, qa_example: &QaExample, max_seq_length: usize, doc_stride: usize, max_query_length: usize, example_index: i64) -> Vec<QaFeature> {
         let mut tok_to_orig_index: Vec<i64> = vec!();
+        let mut tok_offsets: Vec<Option<Offset>> = vec!();
         let mut all_doc_tokens: Vec<String> = vec!();
 
         for (idx, token) in qa_example.doc_tokens.iter().enumerate() {
-            let sub_tokens = self.tokenizer.tokenize(token);
-            for sub_token in sub_tokens.into_iter() {
+            let sub_tokens = self.tokenize_doc_token(token);
+            let sub_token_offsets = self.get_sub_token_offsets(token, &sub_tokens);
+            let word_begin = qa_example.word_offsets[idx].begin;
+            for (sub_token, offset) in sub_tokens.into_iter().zip(sub_token_offsets.into_iter()) {
                 all_doc_tokens.push(sub_token);
                 tok_to_orig_index.push(idx as i64);
+                tok_offsets.push(offset.map(|offset| Offset { begin: word_begin + offset.begin, end: word_begin + offset.end }));
             }
         }
 
         let truncated_query = self.prepare_query(&qa_example.question, max_query_length);
 
-        let sequence_added_tokens = self.tokenizer.build_input_with_special_tokens(vec!(), None).0.len();
         let sequence_pair_added_tokens = self.tokenizer.build_input_with_special_tokens(vec!(), Some(vec!())).0.len();
 
         let mut spans: Vec<QaFeature> = vec!();
@@ -427,15 +718,28 @@ impl QuestionAnsweringModel {
                 all_doc_tokens.len() - spans.len() * doc_stride,
                 max_seq_length - truncated_query.len() - sequence_pair_added_tokens);
 
+            let context_start = self.get_context_start(&encoded_span, truncated_query.len());
             let mut token_to_orig_map = HashMap::new();
+            let mut token_to_offset_map = HashMap::new();
             for i in 0..paragraph_len {
-                let index = truncated_query.len() + sequence_added_tokens + i;
+                let index = context_start + i;
                 token_to_orig_map.insert(index as i64, tok_to_orig_index[spans.len() * doc_stride + i] as i64);
+                if let Some(offset) = tok_offsets[spans.len() * doc_stride + i] {
+                    token_to_offset_map.insert(index as i64, offset);
+                }
             }
 
-            let p_mask = self.get_mask(&encoded_span);
+            let p_mask = self.get_mask(&encoded_span, context_start, paragraph_len);
 
-            let qa_feature = QaFeature { input_ids: encoded_span.token_ids, attention_mask, token_to_orig_map, p_mask, example_index };
+            let qa_feature = QaFeature {
+                input_ids: encoded_span.token_ids,
+                segment_ids: encoded_span.segment_ids,
+                attention_mask,
+                token_to_orig_map,
+                token_to_offset_map,
+                p_mask,
+                example_index,
+            };
 
             spans.push(qa_feature);
             if encoded_span.num_truncated_tokens == 0 {
@@ -446,6 +750,25 @@ impl QuestionAnsweringModel {
         spans
     }
 
+    fn tokenize_doc_token(&self, token: &str) -> Vec<String> {
+        match self.tokenizer.model_type() {
+//            Byte-level BPE encodes the preceding space in the token: context words are tokenized as they appear within a sentence
+            ModelType::Roberta => self.tokenizer.tokenize(&format!(" {}", token)),
+            _ => self.tokenizer.tokenize(token)
+        }
+    }
+
+    /// Returns the character offsets of the sub-word tokens within the word they were tokenized from.
+    /// The tokens are decoded individually to recover their text (undoing the byte-level encoding of RoBERTa) before alignment.
+    fn get_sub_token_offsets(&self, word: &str, sub_tokens: &Vec<String>) -> Vec<Option<Offset>> {
+        let sub_token_texts: Vec<Option<String>> = self.tokenizer
+            .convert_tokens_to_ids(sub_tokens)
+            .into_iter()
+            .map(|token_id| Some(self.tokenizer.decode(vec!(token_id), false, false)))
+            .collect();
+        get_token_offsets(word, &sub_token_texts, self.tokenizer.get_unknown_value(), self.lower_case)
+    }
+
     fn prepare_query(&self, query: &str, max_query_length: usize) -> Vec<i64> {
         let truncated_query = self.tokenizer.convert_tokens_to_ids(&self.tokenizer.tokenize(&query));
         let num_query_tokens_to_remove = if truncated_query.len() > max_query_length as usize { truncated_query.len() - max_query_length } else { 0 };
@@ -485,21 +808,22 @@ impl QuestionAnsweringModel {
         (TokenizedInput { token_ids, segment_ids, special_tokens_mask, overflowing_tokens, num_truncated_tokens }, attention_mask)
     }
 
-    fn get_mask(&self, encoded_span: &TokenizedInput) -> Vec<i8> {
-        let sep_indices: Vec<usize> = encoded_span.token_ids
+    fn get_context_start(&self, encoded_span: &TokenizedInput, query_length: usize) -> usize {
+//        The context starts at the first non-special token following the query
+//        ([CLS] query [SEP] context [SEP] for BERT, <s> query </s></s> context </s> for RoBERTa)
+        encoded_span.special_tokens_mask
             .iter()
             .enumerate()
-            .filter(|(_, &value)| value == self.sep_idx)
+            .filter(|(_, &is_special)| is_special == 0)
             .map(|(position, _)| position)
-            .collect();
+            .nth(query_length)
+            .expect("Encoded span does not contain any context token")
+    }
 
-        let mut p_mask: Vec<i8> = encoded_span.segment_ids
-            .iter()
-            .map(|v| min(v, &1i8))
-            .map(|&v| 1i8 - v)
-            .collect();
-        for sep_position in sep_indices {
-            p_mask[sep_position] = 1;
+    fn get_mask(&self, encoded_span: &TokenizedInput, context_start: usize, context_length: usize) -> Vec<i8> {
+        let mut p_mask: Vec<i8> = vec![1; encoded_span.token_ids.len()];
+        for position in context_start..context_start + context_length {
+            p_mask[position] = 0;
         }
         p_mask
     }
@@ -0,0 +1,487 @@
+// Copyright 2019-present, the HuggingFace Inc. team, The Google AI Language Team and Facebook, Inc.
+// Copyright 2019 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Sequence classification pipeline (e.g. Sentiment Analysis, topic or intent classification)
+//! Generic multi-class classification of sentences. The classes are read from the `id2label` mapping of the model configuration,
+//! allowing any fine-tuned sequence classification model to be used. The backend architecture is selected using the `model_type`
+//! field of the `SequenceClassificationConfig`:
+//! - `ModelType::Bert`: `BertForSequenceClassification`
+//! - `ModelType::DistilBert`: `DistilBertModelClassifier`
+//! - `ModelType::Roberta`: `RobertaForSequenceClassification` (requires a merges file)
+//! - `ModelType::Bart`: `BartForSequenceClassification` (requires a merges file)
+//!
+//! Multi-label classification is available with `predict_multilabel`: a sigmoid is applied to each label logit and all labels above
+//! a global or per-label threshold (`LabelThreshold`) are returned.
+//!
+//! The default model is a DistilBERT model finetuned on SST-2, the dependencies for this model can be downloaded using the Python utility script
+//! (python /utils/download-dependencies_sst2_sentiment.py, with the dependencies downloaded under ~/rustbert/distilbert_sst2)
+//!
+//! ```no_run
+//!# use std::path::PathBuf;
+//!# use tch::Device;
+//! use rust_bert::pipelines::sequence_classification::{SequenceClassificationModel, SequenceClassificationConfig};
+//! use rust_bert::pipelines::common::ModelType;
+//!# fn main() -> failure::Fallible<()> {
+//!# let mut home: PathBuf = dirs::home_dir().unwrap();
+//!# home.push("rustbert");
+//!# home.push("distilbert_sst2");
+//!# let config_path = &home.as_path().join("config.json");
+//!# let vocab_path = &home.as_path().join("vocab.txt");
+//!# let weights_path = &home.as_path().join("model.ot");
+//! let device = Device::cuda_if_available();
+//! let sequence_classification_config = SequenceClassificationConfig { model_type: ModelType::DistilBert, ..Default::default() };
+//! let mut sequence_classification_model = SequenceClassificationModel::new(vocab_path,
+//!                                                                          None,
+//!                                                                          config_path,
+//!                                                                          weights_path,
+//!                                                                          sequence_classification_config,
+//!                                                                          device)?;
+//! let input = [
+//!     "Probably my all-time favorite movie, a story of selflessness, sacrifice and dedication to a noble cause, but it's not preachy or boring.",
+//!     "This film tried to be too many things all at once: stinging political satire, Hollywood blockbuster, sappy romantic comedy, family values promo...",
+//!     "If you like original gut wrenching laughter you will like this movie. If you are young or old then you will love this movie, hell even my mom liked it.",
+//! ];
+//! let output = sequence_classification_model.predict(&input);
+//!# Ok(())
+//!# }
+//! ```
+//! (Example courtesy of [IMDb](http://www.imdb.com))
+//!
+//! Output: \
+//! ```no_run
+//!# use rust_bert::pipelines::sequence_classification::Label;
+//!# let output =
+//! [
+//!    Label { text: String::from("POSITIVE"), score: 0.9981, id: 1, sentence: 0 },
+//!    Label { text: String::from("NEGATIVE"), score: 0.9927, id: 0, sentence: 1 },
+//!    Label { text: String::from("POSITIVE"), score: 0.9997, id: 1, sentence: 2 },
+//! ]
+//!# ;
+//! ```
+
+use std::path::Path;
+use std::collections::HashMap;
+use tch::{Device, Tensor, no_grad};
+use tch::nn::VarStore;
+use tch::kind::Kind::{Int64, Float};
+use rust_tokenizers::TruncationStrategy;
+use crate::pipelines::common::{ModelType, ConfigOption, TokenizerOption};
+use crate::bert::BertForSequenceClassification;
+use crate::distilbert::DistilBertModelClassifier;
+use crate::roberta::RobertaForSequenceClassification;
+use crate::bart::BartForSequenceClassification;
+
+#[derive(Debug, Clone)]
+/// # Label generated by a `SequenceClassificationModel` or a `ZeroShotClassificationModel`
+pub struct Label {
+    /// Label String representation
+    pub text: String,
+    /// Confidence score
+    pub score: f64,
+    /// Label id (position in the model label mapping, or in the candidate labels provided for zero-shot classification)
+    pub id: i64,
+    /// Sentence index
+    pub sentence: usize,
+}
+
+/// # Decision threshold(s) for multi-label classification
+pub enum LabelThreshold {
+    /// Single threshold applied to all labels
+    Global(f64),
+    /// Threshold for each label, keyed by the label names of the `id2label` mapping. Labels missing from the map use the `default` threshold.
+    PerLabel {
+        thresholds: HashMap<String, f64>,
+        default: f64,
+    },
+}
+
+impl Default for LabelThreshold {
+    fn default() -> LabelThreshold {
+        LabelThreshold::Global(0.5)
+    }
+}
+
+/// # Configuration for SequenceClassificationModel
+pub struct SequenceClassificationConfig {
+    /// Model type used for the classifier (default: `ModelType::DistilBert`)
+    pub model_type: ModelType,
+    /// Flag indicating if the tokenizer should lower case the input (default: true). Should be set to false for cased, RoBERTa and BART models.
+    pub lower_case: bool,
+    /// Maximum sequence length, longer inputs will be truncated (default: 128)
+    pub max_length: usize,
+}
+
+impl Default for SequenceClassificationConfig {
+    fn default() -> SequenceClassificationConfig {
+        SequenceClassificationConfig {
+            model_type: ModelType::DistilBert,
+            lower_case: true,
+            max_length: 128,
+        }
+    }
+}
+
+/// # Abstraction that holds one particular sequence classification model, for any of the supported models
+pub enum SequenceClassificationOption {
+    /// Sequence classifier based on BERT
+    Bert(BertForSequenceClassification),
+    /// Sequence classifier based on DistilBERT
+    DistilBert(DistilBertModelClassifier),
+    /// Sequence classifier based on RoBERTa
+    Roberta(RobertaForSequenceClassification),
+    /// Sequence classifier based on BART
+    Bart(BartForSequenceClassification),
+}
+
+impl SequenceClassificationOption {
+    /// Instantiate a new sequence classification model of the supplied type.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_type` - `ModelType` indicating the model type to load (must match with the actual data to be loaded)
+    /// * `p` - `tch::nn::Path` path to the model file to load (e.g. model.ot)
+    /// * `config` - A configuration (the model type of the configuration must be compatible with the value for
+    /// `model_type`)
+    ///
+    pub fn new(model_type: ModelType, p: &tch::nn::Path, config: &ConfigOption) -> Self {
+        match model_type {
+            ModelType::Bert => {
+                if let ConfigOption::Bert(config) = config {
+                    SequenceClassificationOption::Bert(BertForSequenceClassification::new(p, config))
+                } else {
+                    panic!("You can only supply a BertConfig for Bert!");
+                }
+            }
+            ModelType::DistilBert => {
+                if let ConfigOption::DistilBert(config) = config {
+                    SequenceClassificationOption::DistilBert(DistilBertModelClassifier::new(p, config))
+                } else {
+                    panic!("You can only supply a DistilBertConfig for DistilBert!");
+                }
+            }
+            ModelType::Roberta => {
+                if let ConfigOption::Bert(config) = config {
+                    SequenceClassificationOption::Roberta(RobertaForSequenceClassification::new(p, config))
+                } else {
+                    panic!("You can only supply a BertConfig for Roberta!");
+                }
+            }
+            ModelType::Bart => {
+                if let ConfigOption::Bart(config) = config {
+                    SequenceClassificationOption::Bart(BartForSequenceClassification::new(p, config))
+                } else {
+                    panic!("You can only supply a BartConfig for Bart!");
+                }
+            }
+        }
+    }
+
+    /// Returns the `ModelType` for this SequenceClassificationOption
+    pub fn model_type(&self) -> ModelType {
+        match *self {
+            Self::Bert(_) => ModelType::Bert,
+            Self::Roberta(_) => ModelType::Roberta,
+            Self::DistilBert(_) => ModelType::DistilBert,
+            Self::Bart(_) => ModelType::Bart,
+        }
+    }
+
+    /// Interface method to forward_t() of the particular models. Returns the classification logits of shape (*batch size*, *num_labels*)
+    pub fn forward_t(&mut self, input_ids: Tensor, mask: Tensor, train: bool) -> Tensor {
+        match *self {
+            Self::Bert(ref model) => model.forward_t(Some(input_ids), Some(mask), None, None, None, train).0,
+            Self::DistilBert(ref model) => model.forward_t(Some(input_ids), Some(mask), None, train).expect("Error in distilbert forward_t").0,
+            Self::Roberta(ref model) => model.forward_t(Some(input_ids), Some(mask), None, None, None, train).0,
+            Self::Bart(ref mut model) => model.forward_t(&input_ids, Some(&mask), None, None, None, train).0,
+        }
+    }
+}
+
+/// # SequenceClassificationModel for multi-class classification (e.g. Sentiment Analysis)
+pub struct SequenceClassificationModel {
+    tokenizer: TokenizerOption,
+    sequence_classifier: SequenceClassificationOption,
+    label_mapping: HashMap<i64, String>,
+    max_length: usize,
+    var_store: VarStore,
+}
+
+impl SequenceClassificationModel {
+    /// Build a new `SequenceClassificationModel`
+    ///
+    /// # Arguments
+    ///
+    /// * `vocab_path` - Path to the model vocabulary, expected to have a structure following the [Transformers library](https://github.com/huggingface/transformers) convention
+    /// * `merges_path` - Optional path to the bpe merges, required for RoBERTa and BART models
+    /// * `config_path` - Path to the model configuration, expected to have a structure following the [Transformers library](https://github.com/huggingface/transformers) convention. Must contain an `id2label` mapping.
+    /// * `weights_path` - Path to the model weight files. These need to be converted form the `.bin` to `.ot` format using the utility script provided.
+    /// * `sequence_classification_config` - `SequenceClassificationConfig` defining the backend architecture and tokenization settings
+    /// * `device` - Device to run the model on, e.g. `Device::Cpu` or `Device::Cuda(0)`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    /// use tch::Device;
+    /// use std::path::{Path, PathBuf};
+    /// use rust_bert::pipelines::sequence_classification::{SequenceClassificationModel, SequenceClassificationConfig};
+    /// use rust_bert::pipelines::common::ModelType;
+    ///
+    /// let mut home: PathBuf = dirs::home_dir().unwrap();
+    /// let config_path = &home.as_path().join("config.json");
+    /// let vocab_path = &home.as_path().join("vocab.txt");
+    /// let merges_path = &home.as_path().join("merges.txt");
+    /// let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::Cpu;
+    /// let sequence_classification_config = SequenceClassificationConfig { model_type: ModelType::Roberta, lower_case: false, ..Default::default() };
+    /// let sequence_classification_model =  SequenceClassificationModel::new(vocab_path,
+    ///                                                                       Some(merges_path),
+    ///                                                                       config_path,
+    ///                                                                       weights_path,
+    ///                                                                       sequence_classification_config,
+    ///                                                                       device)?;
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn new(vocab_path: &Path, merges_path: Option<&Path>, config_path: &Path, weights_path: &Path,
+               sequence_classification_config: SequenceClassificationConfig, device: Device)
+               -> failure::Fallible<SequenceClassificationModel> {
+        let model_type = sequence_classification_config.model_type;
+        let tokenizer = TokenizerOption::from_file(model_type,
+                                                   vocab_path.to_str().unwrap(),
+                                                   merges_path.map(|path| path.to_str().unwrap()),
+                                                   sequence_classification_config.lower_case);
+        let mut var_store = VarStore::new(device);
+        let config = ConfigOption::from_file(model_type, config_path);
+        let sequence_classifier = SequenceClassificationOption::new(model_type, &var_store.root(), &config);
+        let label_mapping = config.get_label_mapping();
+        var_store.load(weights_path)?;
+        Ok(SequenceClassificationModel {
+            tokenizer,
+            sequence_classifier,
+            label_mapping,
+            max_length: sequence_classification_config.max_length,
+            var_store,
+        })
+    }
+
+    fn prepare_for_model(&self, input: Vec<&str>) -> Tensor {
+        let tokenized_input = self.tokenizer.encode_list(input,
+                                                         self.max_length,
+                                                         &TruncationStrategy::LongestFirst,
+                                                         0);
+        let max_len = tokenized_input.iter().map(|input| input.token_ids.len()).max().unwrap();
+        let pad_id = self.tokenizer.get_pad_id();
+        let tokenized_input = tokenized_input
+            .into_iter()
+            .map(|input| input.token_ids)
+            .map(|mut input| {
+                input.extend(vec![pad_id; max_len - input.len()]);
+                input
+            })
+            .map(|input| Tensor::of_slice(&(input)))
+            .collect::<Vec<_>>();
+        Tensor::stack(tokenized_input.as_slice(), 0).to(self.var_store.device())
+    }
+
+    /// Returns the logits of shape (*batch size*, *num_labels*) for a set of input sentences
+    fn forward(&mut self, input: &[&str]) -> Tensor {
+        let input_tensor = self.prepare_for_model(input.to_vec());
+        let attention_mask = input_tensor.ne(self.tokenizer.get_pad_id()).to_kind(Int64);
+        let output = no_grad(|| {
+            self.sequence_classifier.forward_t(input_tensor, attention_mask, false)
+        });
+        output.detach().to(Device::Cpu)
+    }
+
+    fn build_label(&self, label_id: i64, score: f64, sentence: usize) -> Label {
+        let text = self.label_mapping
+            .get(&label_id)
+            .expect("Label not found in the model label mapping")
+            .clone();
+        Label { text, score, id: label_id, sentence }
+    }
+
+    /// Classify texts
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - `&[&str]` Array of texts to classify.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Label>` containing the most likely label (with its probability) for each input text
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    ///# use tch::Device;
+    ///# use std::path::{Path, PathBuf};
+    ///# use rust_bert::pipelines::sequence_classification::SequenceClassificationModel;
+    ///#
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    ///# let device = Device::Cpu;
+    /// let mut sequence_classification_model =  SequenceClassificationModel::new(vocab_path,
+    ///                                                                           None,
+    ///                                                                           config_path,
+    ///                                                                           weights_path,
+    ///                                                                           Default::default(),
+    ///                                                                           device)?;
+    /// let input = [
+    ///     "Probably my all-time favorite movie, a story of selflessness, sacrifice and dedication to a noble cause, but it's not preachy or boring.",
+    ///     "This film tried to be too many things all at once: stinging political satire, Hollywood blockbuster, sappy romantic comedy, family values promo...",
+    ///     "If you like original gut wrenching laughter you will like this movie. If you are young or old then you will love this movie, hell even my mom liked it.",
+    /// ];
+    /// let output = sequence_classification_model.predict(&input);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn predict(&mut self, input: &[&str]) -> Vec<Label> {
+        let output = self.forward(input).softmax(-1, Float);
+        let (scores, label_indices) = output.max2(-1, false);
+        let mut labels: Vec<Label> = Vec::with_capacity(input.len());
+        for sentence_idx in 0..label_indices.size()[0] {
+            labels.push(self.build_label(label_indices.int64_value(&[sentence_idx]),
+                                         scores.double_value(&[sentence_idx]),
+                                         sentence_idx as usize));
+        }
+        labels
+    }
+
+    /// Classify texts, returning the full probability distribution over labels
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - `&[&str]` Array of texts to classify.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Vec<Label>>` containing all labels of the model (ordered by label id) with their probability, for each input text
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    ///# use tch::Device;
+    ///# use std::path::{Path, PathBuf};
+    ///# use rust_bert::pipelines::sequence_classification::SequenceClassificationModel;
+    ///#
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    ///# let device = Device::Cpu;
+    /// let mut sequence_classification_model =  SequenceClassificationModel::new(vocab_path,
+    ///                                                                           None,
+    ///                                                                           config_path,
+    ///                                                                           weights_path,
+    ///                                                                           Default::default(),
+    ///                                                                           device)?;
+    /// let input = ["This film tried to be too many things all at once"];
+    /// let output = sequence_classification_model.predict_distribution(&input);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn predict_distribution(&mut self, input: &[&str]) -> Vec<Vec<Label>> {
+        let output = self.forward(input).softmax(-1, Float);
+        let (num_sentences, num_labels) = (output.size()[0], output.size()[1]);
+        let mut labels: Vec<Vec<Label>> = Vec::with_capacity(input.len());
+        for sentence_idx in 0..num_sentences {
+            let sentence_labels = (0..num_labels)
+                .map(|label_id| self.build_label(label_id,
+                                                 output.double_value(&[sentence_idx, label_id]),
+                                                 sentence_idx as usize))
+                .collect::<Vec<Label>>();
+            labels.push(sentence_labels);
+        }
+        labels
+    }
+
+    /// Multi-label classification of texts. A sigmoid is applied to each label logit independently, and all labels with a probability
+    /// above their decision threshold are returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - `&[&str]` Array of texts to classify.
+    /// * `threshold` - `&LabelThreshold` global or per-label decision threshold. Per-label thresholds are keyed by the label names of the model `id2label` mapping.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Vec<Label>>` containing the labels above threshold (ordered by label id) with their probability, for each input text
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    ///# use tch::Device;
+    ///# use std::path::{Path, PathBuf};
+    ///# use std::collections::HashMap;
+    ///# use rust_bert::pipelines::sequence_classification::{SequenceClassificationModel, LabelThreshold};
+    ///#
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    ///# let device = Device::Cpu;
+    /// let mut sequence_classification_model =  SequenceClassificationModel::new(vocab_path,
+    ///                                                                           None,
+    ///                                                                           config_path,
+    ///                                                                           weights_path,
+    ///                                                                           Default::default(),
+    ///                                                                           device)?;
+    /// let input = ["The invoice was charged twice and the service has been down since yesterday"];
+    /// let mut thresholds = HashMap::new();
+    /// thresholds.insert(String::from("billing"), 0.3);
+    /// thresholds.insert(String::from("outage"), 0.6);
+    /// let threshold = LabelThreshold::PerLabel { thresholds, default: 0.5 };
+    /// let output = sequence_classification_model.predict_multilabel(&input, &threshold);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn predict_multilabel(&mut self, input: &[&str], threshold: &LabelThreshold) -> Vec<Vec<Label>> {
+        if let LabelThreshold::PerLabel { thresholds, .. } = threshold {
+            for label_name in thresholds.keys() {
+                assert!(self.label_mapping.values().any(|label| label == label_name),
+                        "Threshold provided for label {} which is not in the model label mapping", label_name);
+            }
+        }
+
+        let output = self.forward(input).sigmoid();
+        let (num_sentences, num_labels) = (output.size()[0], output.size()[1]);
+        let mut labels: Vec<Vec<Label>> = Vec::with_capacity(input.len());
+        for sentence_idx in 0..num_sentences {
+            let mut sentence_labels: Vec<Label> = vec!();
+            for label_id in 0..num_labels {
+                let label = self.build_label(label_id,
+                                             output.double_value(&[sentence_idx, label_id]),
+                                             sentence_idx as usize);
+                let label_threshold = match threshold {
+                    LabelThreshold::Global(value) => *value,
+                    LabelThreshold::PerLabel { thresholds, default } => *thresholds.get(&label.text).unwrap_or(default),
+                };
+                if label.score > label_threshold {
+                    sentence_labels.push(label);
+                }
+            }
+            labels.push(sentence_labels);
+        }
+        labels
+    }
+}
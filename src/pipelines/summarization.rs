@@ -105,6 +105,12 @@ pub struct SummarizationConfig {
     pub no_repeat_ngram_size: u64,
     /// Number of sequences to return for each prompt text (default: 1)
     pub num_return_sequences: u64,
+    /// Number of groups the beams are split into for diverse beam search ([Vijayakumar et al.](https://arxiv.org/abs/1610.02424)). Must divide `num_beams`.
+    /// Values higher than 1 turn on this feature, which does not support sampling (default: 1)
+    pub num_beam_groups: u64,
+    /// Penalty subtracted from the score of a token for each time it was selected by the previous beam groups at the same step.
+    /// Only used for diverse beam search (default: 0.0)
+    pub diversity_penalty: f64,
 }
 
 impl Default for SummarizationConfig {
@@ -122,6 +128,8 @@ impl Default for SummarizationConfig {
             length_penalty: 1.0,
             no_repeat_ngram_size: 3,
             num_return_sequences: 1,
+            num_beam_groups: 1,
+            diversity_penalty: 0.0,
         }
     }
 }
@@ -177,11 +185,23 @@ impl SummarizationModel {
             temperature: summarization_config.temperature,
             top_k: summarization_config.top_k,
             top_p: summarization_config.top_p,
+            typical_p: 1.0,
+            min_p: 0.0,
+            epsilon_cutoff: 0.0,
+            eta_cutoff: 0.0,
             repetition_penalty: summarization_config.repetition_penalty,
             length_penalty: summarization_config.length_penalty,
             no_repeat_ngram_size: summarization_config.no_repeat_ngram_size,
             num_return_sequences: summarization_config.num_return_sequences,
-
+            num_beam_groups: summarization_config.num_beam_groups,
+            diversity_penalty: summarization_config.diversity_penalty,
+            bad_words_ids: None,
+            force_words_ids: None,
+            prefix_allowed_tokens: None,
+            seed: None,
+            max_new_tokens: None,
+            stop_sequences: None,
+            max_time: None,
         };
         let model = BartGenerator::new(vocab_path, merges_path, config_path, weights_path,
                                            generate_config, device)?;
@@ -0,0 +1,446 @@
+// Copyright 2019-present, the HuggingFace Inc. team, The Google AI Language Team and Facebook, Inc.
+// Copyright 2019 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Token classification pipeline (Named Entity Recognition, Part-of-Speech tagging, chunking...)
+//! Generic token classification, with labels read from the `id2label` mapping of the model configuration.
+//! The backend architecture is selected using the `model_type` field of the `TokenClassificationConfig`:
+//! - `ModelType::Bert`: `BertForTokenClassification`
+//! - `ModelType::DistilBert`: `DistilBertForTokenClassification`
+//! - `ModelType::Roberta`: `RobertaForTokenClassification` (requires a merges file)
+//!
+//! Labels can be reported for each sub-word token, or consolidated at the word level (using the label of the first sub-token
+//! or the average of the sub-tokens label probabilities, see `LabelAggregationOption`). Tokens labelled as outside of any
+//! entity (`O`) can optionally be returned, providing a label for every word of the input (e.g. for Part-of-Speech tagging).
+//!
+//! ```no_run
+//!# use std::path::PathBuf;
+//!# use tch::Device;
+//! use rust_bert::pipelines::token_classification::{TokenClassificationModel, TokenClassificationConfig};
+//! use rust_bert::pipelines::common::ModelType;
+//!# fn main() -> failure::Fallible<()> {
+//!# let mut home: PathBuf = dirs::home_dir().unwrap();
+//!# home.push("rustbert");
+//!# home.push("bert-ner");
+//!# let config_path = &home.as_path().join("config.json");
+//!# let vocab_path = &home.as_path().join("vocab.txt");
+//!# let weights_path = &home.as_path().join("model.ot");
+//! let device = Device::cuda_if_available();
+//! let token_classification_config = TokenClassificationConfig { model_type: ModelType::Bert, lower_case: false, ..Default::default() };
+//! let token_classification_model = TokenClassificationModel::new(vocab_path,
+//!                                                                None,
+//!                                                                config_path,
+//!                                                                weights_path,
+//!                                                                token_classification_config,
+//!                                                                device)?;
+//! let input = [
+//!     "My name is Amy. I live in Paris.",
+//!     "Paris is a city in France."
+//! ];
+//! let output = token_classification_model.predict(&input, true, true);
+//!# Ok(())
+//!# }
+//! ```
+//! Output (first tokens): \
+//! ```no_run
+//!# use rust_bert::pipelines::token_classification::Token;
+//!# use rust_bert::pipelines::common::Offset;
+//!# let output =
+//! [
+//!    Token { text: String::from("My"), score: 0.9998, label: String::from("O"), label_index: 0, sentence: 0, index: 1, word_index: 0, offset: Some(Offset { begin: 0, end: 2 }) },
+//!    Token { text: String::from("name"), score: 0.9997, label: String::from("O"), label_index: 0, sentence: 0, index: 2, word_index: 1, offset: Some(Offset { begin: 3, end: 7 }) },
+//!    Token { text: String::from("is"), score: 0.9998, label: String::from("O"), label_index: 0, sentence: 0, index: 3, word_index: 2, offset: Some(Offset { begin: 8, end: 10 }) },
+//!    Token { text: String::from("Amy"), score: 0.9986, label: String::from("I-PER"), label_index: 4, sentence: 0, index: 4, word_index: 3, offset: Some(Offset { begin: 11, end: 14 }) },
+//! ]
+//!# ;
+//! ```
+
+use std::path::Path;
+use std::collections::HashMap;
+use tch::{Device, Tensor, no_grad};
+use tch::nn::VarStore;
+use tch::kind::Kind::{Int64, Float};
+use rust_tokenizers::TruncationStrategy;
+use crate::pipelines::common::{ModelType, ConfigOption, TokenizerOption, Offset, get_token_offsets};
+use crate::bert::BertForTokenClassification;
+use crate::distilbert::DistilBertForTokenClassification;
+use crate::roberta::RobertaForTokenClassification;
+
+#[derive(Debug, Clone)]
+/// # Token generated by a `TokenClassificationModel`
+pub struct Token {
+    /// String representation of the Token (sub-word token or word if sub-tokens are consolidated)
+    pub text: String,
+    /// Confidence score
+    pub score: f64,
+    /// Token label (e.g. ORG, LOC in case of NER)
+    pub label: String,
+    /// Label index
+    pub label_index: i64,
+    /// Sentence index
+    pub sentence: usize,
+    /// Position of the (first sub-word) token in the tokenized input, including special tokens
+    pub index: usize,
+    /// Index of the word the token belongs to in the sentence
+    pub word_index: usize,
+    /// Character offsets of the token in the input sentence (None if the token could not be aligned with the input text)
+    pub offset: Option<Offset>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// # Aggregation of the sub-word token predictions when labels are consolidated at the word level
+pub enum LabelAggregationOption {
+    /// Label and score of the first sub-word token
+    First,
+    /// Label with the highest average probability over the sub-word tokens
+    Mean,
+}
+
+/// # Configuration for TokenClassificationModel
+pub struct TokenClassificationConfig {
+    /// Model type used for the token classifier (default: `ModelType::Bert`)
+    pub model_type: ModelType,
+    /// Flag indicating if the tokenizer should lower case the input (default: true). Should be set to false for cased and RoBERTa models.
+    pub lower_case: bool,
+    /// Maximum sequence length, longer inputs will be truncated (default: 128)
+    pub max_length: usize,
+    /// Aggregation of the sub-word token predictions into word predictions (default: `LabelAggregationOption::First`)
+    pub label_aggregation_function: LabelAggregationOption,
+}
+
+impl Default for TokenClassificationConfig {
+    fn default() -> TokenClassificationConfig {
+        TokenClassificationConfig {
+            model_type: ModelType::Bert,
+            lower_case: true,
+            max_length: 128,
+            label_aggregation_function: LabelAggregationOption::First,
+        }
+    }
+}
+
+/// # Abstraction that holds one particular token classification model, for any of the supported models
+pub enum TokenClassificationOption {
+    /// Token classifier based on BERT
+    Bert(BertForTokenClassification),
+    /// Token classifier based on DistilBERT
+    DistilBert(DistilBertForTokenClassification),
+    /// Token classifier based on RoBERTa
+    Roberta(RobertaForTokenClassification),
+}
+
+impl TokenClassificationOption {
+    /// Instantiate a new token classification model of the supplied type.
+    ///
+    /// # Arguments
+    ///
+    /// * `model_type` - `ModelType` indicating the model type to load (must match with the actual data to be loaded)
+    /// * `p` - `tch::nn::Path` path to the model file to load (e.g. model.ot)
+    /// * `config` - A configuration (the model type of the configuration must be compatible with the value for
+    /// `model_type`)
+    ///
+    pub fn new(model_type: ModelType, p: &tch::nn::Path, config: &ConfigOption) -> Self {
+        match model_type {
+            ModelType::Bert => {
+                if let ConfigOption::Bert(config) = config {
+                    TokenClassificationOption::Bert(BertForTokenClassification::new(p, config))
+                } else {
+                    panic!("You can only supply a BertConfig for Bert!");
+                }
+            }
+            ModelType::DistilBert => {
+                if let ConfigOption::DistilBert(config) = config {
+                    TokenClassificationOption::DistilBert(DistilBertForTokenClassification::new(p, config))
+                } else {
+                    panic!("You can only supply a DistilBertConfig for DistilBert!");
+                }
+            }
+            ModelType::Roberta => {
+                if let ConfigOption::Bert(config) = config {
+                    TokenClassificationOption::Roberta(RobertaForTokenClassification::new(p, config))
+                } else {
+                    panic!("You can only supply a BertConfig for Roberta!");
+                }
+            }
+            ModelType::Bart => panic!("BART is not supported for token classification!"),
+        }
+    }
+
+    /// Returns the `ModelType` for this TokenClassificationOption
+    pub fn model_type(&self) -> ModelType {
+        match *self {
+            Self::Bert(_) => ModelType::Bert,
+            Self::Roberta(_) => ModelType::Roberta,
+            Self::DistilBert(_) => ModelType::DistilBert,
+        }
+    }
+
+    /// Interface method to forward_t() of the particular models. Returns the logits of shape (*batch size*, *sequence_length*, *num_labels*)
+    pub fn forward_t(&self, input_ids: Option<Tensor>, mask: Option<Tensor>, train: bool) -> Tensor {
+        match *self {
+            Self::Bert(ref model) => model.forward_t(input_ids, mask, None, None, None, train).0,
+            Self::DistilBert(ref model) => model.forward_t(input_ids, mask, None, train).expect("Error in distilbert forward_t").0,
+            Self::Roberta(ref model) => model.forward_t(input_ids, mask, None, None, None, train).0,
+        }
+    }
+}
+
+/// # TokenClassificationModel for Named Entity Recognition, Part-of-Speech tagging or other token-level tasks
+pub struct TokenClassificationModel {
+    tokenizer: TokenizerOption,
+    token_sequence_classifier: TokenClassificationOption,
+    label_mapping: HashMap<i64, String>,
+    lower_case: bool,
+    max_length: usize,
+    label_aggregation_function: LabelAggregationOption,
+    var_store: VarStore,
+}
+
+impl TokenClassificationModel {
+    /// Build a new `TokenClassificationModel`
+    ///
+    /// # Arguments
+    ///
+    /// * `vocab_path` - Path to the model vocabulary, expected to have a structure following the [Transformers library](https://github.com/huggingface/transformers) convention
+    /// * `merges_path` - Optional path to the bpe merges, required for RoBERTa models
+    /// * `config_path` - Path to the model configuration, expected to have a structure following the [Transformers library](https://github.com/huggingface/transformers) convention. Must contain an `id2label` mapping.
+    /// * `weights_path` - Path to the model weight files. These need to be converted form the `.bin` to `.ot` format using the utility script provided.
+    /// * `token_classification_config` - `TokenClassificationConfig` defining the backend architecture, tokenization settings and label aggregation
+    /// * `device` - Device to run the model on, e.g. `Device::Cpu` or `Device::Cuda(0)`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    /// use tch::Device;
+    /// use std::path::{Path, PathBuf};
+    /// use rust_bert::pipelines::token_classification::{TokenClassificationModel, TokenClassificationConfig, LabelAggregationOption};
+    /// use rust_bert::pipelines::common::ModelType;
+    ///
+    /// let mut home: PathBuf = dirs::home_dir().unwrap();
+    /// let config_path = &home.as_path().join("config.json");
+    /// let vocab_path = &home.as_path().join("vocab.json");
+    /// let merges_path = &home.as_path().join("merges.txt");
+    /// let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::Cpu;
+    /// let token_classification_config = TokenClassificationConfig {
+    ///     model_type: ModelType::Roberta,
+    ///     lower_case: false,
+    ///     label_aggregation_function: LabelAggregationOption::Mean,
+    ///     ..Default::default()
+    /// };
+    /// let token_classification_model =  TokenClassificationModel::new(vocab_path,
+    ///                                                                 Some(merges_path),
+    ///                                                                 config_path,
+    ///                                                                 weights_path,
+    ///                                                                 token_classification_config,
+    ///                                                                 device)?;
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn new(vocab_path: &Path, merges_path: Option<&Path>, config_path: &Path, weights_path: &Path,
+               token_classification_config: TokenClassificationConfig, device: Device)
+               -> failure::Fallible<TokenClassificationModel> {
+        let model_type = token_classification_config.model_type;
+        let tokenizer = TokenizerOption::from_file(model_type,
+                                                   vocab_path.to_str().unwrap(),
+                                                   merges_path.map(|path| path.to_str().unwrap()),
+                                                   token_classification_config.lower_case);
+        let mut var_store = VarStore::new(device);
+        let config = ConfigOption::from_file(model_type, config_path);
+        let token_sequence_classifier = TokenClassificationOption::new(model_type, &var_store.root(), &config);
+        let label_mapping = config.get_label_mapping();
+        var_store.load(weights_path)?;
+        Ok(TokenClassificationModel {
+            tokenizer,
+            token_sequence_classifier,
+            label_mapping,
+            lower_case: token_classification_config.lower_case,
+            max_length: token_classification_config.max_length,
+            label_aggregation_function: token_classification_config.label_aggregation_function,
+            var_store,
+        })
+    }
+
+    fn prepare_for_model(&self, input: Vec<&str>) -> Tensor {
+        let tokenized_input = self.tokenizer.encode_list(input,
+                                                         self.max_length,
+                                                         &TruncationStrategy::LongestFirst,
+                                                         0);
+        let max_len = tokenized_input.iter().map(|input| input.token_ids.len()).max().unwrap();
+        let pad_id = self.tokenizer.get_pad_id();
+        let tokenized_input = tokenized_input
+            .into_iter()
+            .map(|input| input.token_ids)
+            .map(|mut input| {
+                input.extend(vec![pad_id; max_len - input.len()]);
+                input
+            })
+            .map(|input| Tensor::of_slice(&(input)))
+            .collect::<Vec<_>>();
+        Tensor::stack(tokenized_input.as_slice(), 0).to(self.var_store.device())
+    }
+
+    /// Returns the input token ids and the label probabilities of shape (*batch size*, *sequence_length*, *num_labels*)
+    fn forward(&self, input: &[&str]) -> (Tensor, Tensor) {
+        let input_tensor = self.prepare_for_model(input.to_vec());
+        let attention_mask = input_tensor.ne(self.tokenizer.get_pad_id()).to_kind(Int64);
+        let output = no_grad(|| {
+            self.token_sequence_classifier
+                .forward_t(Some(input_tensor.copy()), Some(attention_mask), false)
+                .softmax(-1, Float)
+        });
+        (input_tensor.to(Device::Cpu), output.detach().to(Device::Cpu))
+    }
+
+    /// Returns the offsets of the tokens of a sentence in the input text (None for special tokens) and flags indicating if
+    /// each token starts a new word. Words start at tokens without a `##` prefix for BERT-based tokenizers, and at tokens with
+    /// a leading space for the RoBERTa byte-level BPE tokenizer.
+    fn get_token_offsets(&self, token_ids: &[i64], text: &str) -> (Vec<Option<Offset>>, Vec<bool>) {
+        let special_ids = self.tokenizer.get_special_ids();
+        let tokens = token_ids
+            .iter()
+            .map(|token_id| if special_ids.contains(token_id) {
+                None
+            } else {
+                Some(self.tokenizer.decode(vec!(*token_id), false, false))
+            })
+            .collect::<Vec<Option<String>>>();
+
+        let mut word_starts: Vec<bool> = Vec::with_capacity(tokens.len());
+        let mut previous_is_special = true;
+        for (token_id, token) in token_ids.iter().zip(tokens.iter()) {
+            let is_word_start = match token {
+                None => false,
+                Some(token) => previous_is_special | match self.tokenizer.model_type() {
+                    ModelType::Roberta => self.tokenizer.id_to_token(*token_id).starts_with('\u{0120}'),
+                    _ => !token.starts_with("##"),
+                }
+            };
+            previous_is_special = token.is_none();
+            word_starts.push(is_word_start);
+        }
+
+        let offsets = get_token_offsets(text, &tokens, self.tokenizer.get_unknown_value(), self.lower_case);
+        (offsets, word_starts)
+    }
+
+    /// Classify tokens in a text sequence
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - `&[&str]` Array of texts to extract entities from.
+    /// * `consolidate_sub_tokens` - bool flag indicating if sub-word tokens should be merged and labelled at the word level
+    /// (using the `label_aggregation_function` of the configuration)
+    /// * `return_outside` - bool flag indicating if tokens labelled as outside of any entity (`O`) should be returned
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<Token>` containing the labelled tokens (or words) for all input sentences
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    ///# use tch::Device;
+    ///# use std::path::{Path, PathBuf};
+    ///# use rust_bert::pipelines::token_classification::TokenClassificationModel;
+    ///#
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    ///# let device = Device::Cpu;
+    /// let token_classification_model =  TokenClassificationModel::new(vocab_path,
+    ///                                                                 None,
+    ///                                                                 config_path,
+    ///                                                                 weights_path,
+    ///                                                                 Default::default(),
+    ///                                                                 device)?;
+    /// let input = [
+    ///     "My name is Amy. I live in Paris.",
+    ///     "Paris is a city in France."
+    /// ];
+    /// let output = token_classification_model.predict(&input, true, false);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn predict(&self, input: &[&str], consolidate_sub_tokens: bool, return_outside: bool) -> Vec<Token> {
+        let (input_tensor, scores) = self.forward(input);
+        let special_ids = self.tokenizer.get_special_ids();
+
+        let mut tokens: Vec<Token> = vec!();
+        for sentence_idx in 0..input_tensor.size()[0] {
+            let text = input[sentence_idx as usize];
+            let token_ids = input_tensor.get(sentence_idx).iter::<i64>().unwrap().collect::<Vec<i64>>();
+            let (offsets, word_starts) = self.get_token_offsets(&token_ids, text);
+
+//            Groups of token positions labelled together, with the index of the word they belong to
+            let mut groups: Vec<(usize, Vec<usize>)> = vec!();
+            let mut word_index = 0usize;
+            let mut previous_position: Option<usize> = None;
+            for position in 0..token_ids.len() {
+                if special_ids.contains(&token_ids[position]) {
+                    continue;
+                }
+                if word_starts[position] & previous_position.is_some() {
+                    word_index += 1;
+                }
+                let continues_group = consolidate_sub_tokens
+                    & !word_starts[position]
+                    & previous_position.map_or(false, |previous| previous + 1 == position);
+                if continues_group {
+                    groups.last_mut().unwrap().1.push(position);
+                } else {
+                    groups.push((word_index, vec!(position)));
+                }
+                previous_position = Some(position);
+            }
+
+            let sentence_scores = scores.get(sentence_idx);
+            for (word_index, group) in groups {
+                let label_scores = match self.label_aggregation_function {
+                    LabelAggregationOption::First => sentence_scores.get(group[0] as i64),
+                    LabelAggregationOption::Mean => {
+                        let positions = group.iter().map(|&position| position as i64).collect::<Vec<i64>>();
+                        sentence_scores.index_select(0, &Tensor::of_slice(&positions)).mean1(&[0], false, Float)
+                    }
+                };
+                let (score, label_index) = label_scores.max2(-1, false);
+                let label_index = label_index.int64_value(&[]);
+                let label = self.label_mapping.get(&label_index).expect("Index out of vocabulary bounds.").to_owned();
+                if !return_outside & (label == "O") {
+                    continue;
+                }
+
+                let (begin, end) = (offsets[group[0]], offsets[*group.last().unwrap()]);
+                let (text, offset) = match (begin, end) {
+                    (Some(begin), Some(end)) => (text.chars().skip(begin.begin).take(end.end - begin.begin).collect::<String>(),
+                                                 Some(Offset { begin: begin.begin, end: end.end })),
+                    _ => (self.tokenizer.decode(group.iter().map(|&position| token_ids[position]).collect(), true, true), None)
+                };
+                tokens.push(Token {
+                    text,
+                    score: score.double_value(&[]),
+                    label,
+                    label_index,
+                    sentence: sentence_idx as usize,
+                    index: group[0],
+                    word_index,
+                    offset,
+                });
+            }
+        }
+        tokens
+    }
+}
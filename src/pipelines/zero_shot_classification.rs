@@ -0,0 +1,328 @@
+// Copyright 2020 The Facebook AI Research Team Authors
+// Copyright 2020-present, the HuggingFace Inc. team.
+// Copyright 2020 Guillaume Becquin
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//     http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Zero-shot classification pipeline
+//! Performs zero-shot classification on input sentences with provided labels using a model fine-tuned for Natural Language Inference.
+//! The classifier is based on BART fine-tuned on MNLI: each candidate label is turned into an hypothesis (e.g. `This example is about sports.`)
+//! and the entailment score of the (sentence, hypothesis) pair is used as the label score. Two modes are available:
+//! - single-label: the entailment logits are normalized (softmax) across all candidate labels for a given input
+//! - multi-label: each label is scored independently using the entailment versus contradiction probability
+//!
+//! All resources for this model can be downloaded using the Python utility script included in this repository.
+//! 1. Set-up a Python virtual environment and install dependencies (in ./requirements.txt)
+//! 2. Run the conversion script python /utils/download-dependencies_bart_mnli.py.
+//! The dependencies will be downloaded to the user's home directory, under ~/rustbert/bart-large-mnli
+//!
+//! ```no_run
+//!# use std::path::PathBuf;
+//!# use tch::Device;
+//! use rust_bert::pipelines::zero_shot_classification::ZeroShotClassificationModel;
+//!# fn main() -> failure::Fallible<()> {
+//!# let mut home: PathBuf = dirs::home_dir().unwrap();
+//!# home.push("rustbert");
+//!# home.push("bart-large-mnli");
+//!# let config_path = &home.as_path().join("config.json");
+//!# let vocab_path = &home.as_path().join("vocab.txt");
+//!# let merges_path = &home.as_path().join("merges.txt");
+//!# let weights_path = &home.as_path().join("model.ot");
+//! let device = Device::cuda_if_available();
+//! let mut sequence_classification_model = ZeroShotClassificationModel::new(vocab_path,
+//!                                                                          merges_path,
+//!                                                                          config_path,
+//!                                                                          weights_path, device)?;
+//!
+//! let input_sentence = "Who are you voting for in 2020?";
+//! let input_sequence_2 = "The prime minister has announced a stimulus package which was widely criticized by the opposition.";
+//! let candidate_labels = &["politics", "public health", "economics", "sports"];
+//!
+//! let output = sequence_classification_model.predict(&[input_sentence, input_sequence_2], candidate_labels, None, 128);
+//!# Ok(())
+//!# }
+//! ```
+//!
+//! outputs:
+//! ```no_run
+//!# use rust_bert::pipelines::sequence_classification::Label;
+//!# let output =
+//! [
+//!     [
+//!         Label { text: "politics".to_string(), score: 0.972, id: 0, sentence: 0 },
+//!         Label { text: "public health".to_string(), score: 0.010, id: 1, sentence: 0 },
+//!         Label { text: "economics".to_string(), score: 0.011, id: 2, sentence: 0 },
+//!         Label { text: "sports".to_string(), score: 0.007, id: 3, sentence: 0 },
+//!     ],
+//!     [
+//!         Label { text: "politics".to_string(), score: 0.975, id: 0, sentence: 1 },
+//!         Label { text: "public health".to_string(), score: 0.002, id: 1, sentence: 1 },
+//!         Label { text: "economics".to_string(), score: 0.022, id: 2, sentence: 1 },
+//!         Label { text: "sports".to_string(), score: 0.001, id: 3, sentence: 1 },
+//!     ],
+//! ]
+//!# ;
+//! ```
+
+use rust_tokenizers::{RobertaTokenizer, Tokenizer, TruncationStrategy, RobertaVocab};
+use std::path::Path;
+use tch::{Device, Tensor, no_grad};
+use tch::nn::VarStore;
+use tch::kind::Kind::{Int64, Float};
+use crate::bart::{BartForSequenceClassification, BartConfig};
+use crate::Config;
+use itertools::Itertools;
+use crate::pipelines::sequence_classification::Label;
+
+/// # ZeroShotClassificationModel for zero-shot classification
+pub struct ZeroShotClassificationModel {
+    tokenizer: RobertaTokenizer,
+    zero_shot_classifier: BartForSequenceClassification,
+    pad_idx: i64,
+    entailment_idx: i64,
+    contradiction_idx: i64,
+    var_store: VarStore,
+}
+
+impl ZeroShotClassificationModel {
+    /// Build a new `ZeroShotClassificationModel`
+    ///
+    /// # Arguments
+    ///
+    /// * `vocab_path` - Path to the model vocabulary, expected to have a structure following the [Transformers library](https://github.com/huggingface/transformers) convention
+    /// * `merges_path` - Path to the bpe merges, expected to have a structure following the [Transformers library](https://github.com/huggingface/transformers) convention
+    /// * `config_path` - Path to the model configuration, expected to have a structure following the [Transformers library](https://github.com/huggingface/transformers) convention
+    /// * `weights_path` - Path to the model weight files. These need to be converted form the `.bin` to `.ot` format using the utility script provided.
+    /// * `device` - Device to run the model on, e.g. `Device::Cpu` or `Device::Cuda(0)`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    /// use tch::Device;
+    /// use std::path::{Path, PathBuf};
+    /// use rust_bert::pipelines::zero_shot_classification::ZeroShotClassificationModel;
+    ///
+    /// let mut home: PathBuf = dirs::home_dir().unwrap();
+    /// let config_path = &home.as_path().join("config.json");
+    /// let vocab_path = &home.as_path().join("vocab.txt");
+    /// let merges_path = &home.as_path().join("merges.txt");
+    /// let weights_path = &home.as_path().join("model.ot");
+    /// let device = Device::Cpu;
+    /// let zero_shot_model =  ZeroShotClassificationModel::new(vocab_path,
+    ///                                                         merges_path,
+    ///                                                         config_path,
+    ///                                                         weights_path,
+    ///                                                         device)?;
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn new(vocab_path: &Path, merges_path: &Path, config_path: &Path, weights_path: &Path, device: Device)
+               -> failure::Fallible<ZeroShotClassificationModel> {
+        let tokenizer = RobertaTokenizer::from_file(vocab_path.to_str().unwrap(), merges_path.to_str().unwrap(), false);
+        let pad_idx = match tokenizer.vocab().special_values.get(RobertaVocab::pad_value()) {
+            Some(index) => *index,
+            None => 1
+        };
+        let mut var_store = VarStore::new(device);
+        let config = BartConfig::from_file(config_path);
+        let (entailment_idx, contradiction_idx) = ZeroShotClassificationModel::get_nli_indices(&config);
+        let zero_shot_classifier = BartForSequenceClassification::new(&var_store.root(), &config);
+        var_store.load(weights_path)?;
+        Ok(ZeroShotClassificationModel { tokenizer, zero_shot_classifier, pad_idx, entailment_idx, contradiction_idx, var_store })
+    }
+
+    fn get_nli_indices(config: &BartConfig) -> (i64, i64) {
+//        Defaults to the MNLI label ordering (contradiction, neutral, entailment) if the configuration does not provide a label mapping
+        let mut entailment_idx = 2i64;
+        let mut contradiction_idx = 0i64;
+        if let Some(label2id) = &config.label2id {
+            for (label, id) in label2id.iter() {
+                let label = label.to_lowercase();
+                if label.starts_with("entail") {
+                    entailment_idx = *id;
+                } else if label.starts_with("contradict") {
+                    contradiction_idx = *id;
+                }
+            }
+        }
+        (entailment_idx, contradiction_idx)
+    }
+
+    fn prepare_for_model(&self, inputs: &[&str], labels: &[&str], template: &Option<Box<dyn Fn(&str) -> String>>, max_len: usize) -> (Tensor, Tensor) {
+        let label_sentences: Vec<String> = match template {
+            Some(function) => labels
+                .iter()
+                .map(|label| function(label))
+                .collect(),
+            None => labels
+                .iter()
+                .map(|label| format!("This example is about {}.", label))
+                .collect()
+        };
+
+        let text_pair_list = inputs
+            .iter()
+            .cartesian_product(label_sentences.iter())
+            .map(|(&s, label)| (s, label.as_str()))
+            .collect::<Vec<(&str, &str)>>();
+
+        let tokenized_input = self.tokenizer.encode_pair_list(text_pair_list,
+                                                              max_len,
+                                                              &TruncationStrategy::LongestFirst,
+                                                              0);
+        let max_len = tokenized_input.iter().map(|input| input.token_ids.len()).max().unwrap();
+        let tokenized_input = tokenized_input
+            .iter()
+            .map(|input| input.token_ids.clone())
+            .map(|mut input| {
+                input.extend(vec![self.pad_idx; max_len - input.len()]);
+                input
+            })
+            .map(|input| Tensor::of_slice(&(input)))
+            .collect::<Vec<_>>();
+        let input_tensor = Tensor::stack(tokenized_input.as_slice(), 0).to(self.var_store.device());
+        let attention_mask = input_tensor.ne(self.pad_idx).to_kind(Int64);
+        (input_tensor, attention_mask)
+    }
+
+    fn forward(&mut self, inputs: &[&str], labels: &[&str], template: Option<Box<dyn Fn(&str) -> String>>, max_len: usize) -> Tensor {
+        assert!(!labels.is_empty(), "At least one candidate label must be provided");
+        let (input_tensor, attention_mask) = self.prepare_for_model(inputs, labels, &template, max_len);
+        let (output, _, _, _, _, _) = no_grad(|| {
+            self.zero_shot_classifier.forward_t(&input_tensor,
+                                                Some(&attention_mask),
+                                                None,
+                                                None,
+                                                None,
+                                                false)
+        });
+        output.detach().to(Device::Cpu).view((inputs.len() as i64, labels.len() as i64, -1))
+    }
+
+    fn build_labels(&self, scores: &Tensor, labels: &[&str]) -> Vec<Vec<Label>> {
+        let mut output_labels: Vec<Vec<Label>> = vec!();
+        for sentence_idx in 0..scores.size()[0] {
+            let mut sentence_labels: Vec<Label> = vec!();
+            for (label_index, label) in labels.iter().enumerate() {
+                sentence_labels.push(Label {
+                    text: label.to_string(),
+                    score: scores.double_value(&[sentence_idx, label_index as i64]),
+                    id: label_index as i64,
+                    sentence: sentence_idx as usize,
+                });
+            }
+            output_labels.push(sentence_labels);
+        }
+        output_labels
+    }
+
+    /// Zero shot classification with a single active label per input. The entailment logits are normalized across the candidate labels
+    /// so that the scores for a given input sum to 1.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - `&[&str]` Array of texts to classify.
+    /// * `labels` - `&[&str]` Possible labels for the inputs.
+    /// * `template` - `Option<Box<dyn Fn(&str) -> String>>` closure to build label propositions. If None, will default to `"This example is about {}."`.
+    /// * `max_length` -`usize` Maximum sequence length for the inputs. If input exceed this length, they will be truncated.
+    ///
+    /// # Returns
+    /// * `Vec<Vec<Label>>` containing a score for each candidate label (in the order provided), for each input.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    ///# use tch::Device;
+    ///# use std::path::{Path, PathBuf};
+    ///# use rust_bert::pipelines::zero_shot_classification::ZeroShotClassificationModel;
+    ///#
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let merges_path = &home.as_path().join("merges.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    ///# let device = Device::Cpu;
+    /// let mut zero_shot_model =  ZeroShotClassificationModel::new(vocab_path,
+    ///                                                             merges_path,
+    ///                                                             config_path,
+    ///                                                             weights_path,
+    ///                                                             device)?;
+    /// let input_sentence = "Who are you voting for in 2020?";
+    /// let candidate_labels = &["politics", "public health", "economics", "sports"];
+    ///
+    /// let output = zero_shot_model.predict(&[input_sentence],
+    ///                                      candidate_labels,
+    ///                                      Some(Box::new(|label: &str| format!("This ticket is about {}.", label))),
+    ///                                      128);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn predict(&mut self, inputs: &[&str], labels: &[&str], template: Option<Box<dyn Fn(&str) -> String>>, max_length: usize) -> Vec<Vec<Label>> {
+        let output = self.forward(inputs, labels, template, max_length);
+        let scores = output
+            .select(-1, self.entailment_idx)
+            .softmax(-1, Float);
+        self.build_labels(&scores, labels)
+    }
+
+    /// Zero shot multi-label classification. Each candidate label is scored independently using the entailment probability
+    /// against the contradiction probability for the (input, hypothesis) pair. Several labels may therefore have a high score for a given input.
+    ///
+    /// # Arguments
+    ///
+    /// * `inputs` - `&[&str]` Array of texts to classify.
+    /// * `labels` - `&[&str]` Possible labels for the inputs.
+    /// * `template` - `Option<Box<dyn Fn(&str) -> String>>` closure to build label propositions. If None, will default to `"This example is about {}."`.
+    /// * `max_length` -`usize` Maximum sequence length for the inputs. If input exceed this length, they will be truncated.
+    ///
+    /// # Returns
+    /// * `Vec<Vec<Label>>` containing an independent score for each candidate label (in the order provided), for each input.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    ///# fn main() -> failure::Fallible<()> {
+    ///# use tch::Device;
+    ///# use std::path::{Path, PathBuf};
+    ///# use rust_bert::pipelines::zero_shot_classification::ZeroShotClassificationModel;
+    ///#
+    ///# let mut home: PathBuf = dirs::home_dir().unwrap();
+    ///# let config_path = &home.as_path().join("config.json");
+    ///# let vocab_path = &home.as_path().join("vocab.txt");
+    ///# let merges_path = &home.as_path().join("merges.txt");
+    ///# let weights_path = &home.as_path().join("model.ot");
+    ///# let device = Device::Cpu;
+    /// let mut zero_shot_model =  ZeroShotClassificationModel::new(vocab_path,
+    ///                                                             merges_path,
+    ///                                                             config_path,
+    ///                                                             weights_path,
+    ///                                                             device)?;
+    /// let input_sentence = "My invoice was charged twice and the website has been down all morning.";
+    /// let candidate_labels = &["billing", "outage", "feature request"];
+    ///
+    /// let output = zero_shot_model.predict_multilabel(&[input_sentence], candidate_labels, None, 128);
+    ///# Ok(())
+    ///# }
+    /// ```
+    ///
+    pub fn predict_multilabel(&mut self, inputs: &[&str], labels: &[&str], template: Option<Box<dyn Fn(&str) -> String>>, max_length: usize) -> Vec<Vec<Label>> {
+        let output = self.forward(inputs, labels, template, max_length);
+        let nli_indices = Tensor::of_slice(&[self.contradiction_idx, self.entailment_idx]);
+        let scores = output
+            .index_select(-1, &nli_indices)
+            .softmax(-1, Float)
+            .select(-1, 1);
+        self.build_labels(&scores, labels)
+    }
+}
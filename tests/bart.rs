@@ -4,6 +4,7 @@ use rust_tokenizers::{TruncationStrategy, Tokenizer, RobertaTokenizer};
 use rust_bert::Config;
 use rust_bert::bart::{BartConfig, BartForConditionalGeneration};
 use rust_bert::pipelines::summarization::{SummarizationConfig, SummarizationModel};
+use rust_bert::pipelines::zero_shot_classification::ZeroShotClassificationModel;
 
 #[test]
 #[cfg_attr(not(feature = "all-tests"), ignore)]
@@ -169,4 +170,92 @@ about exoplanets like K2-18b."];
     star as the planet passed between it and Earth.");
 
     Ok(())
-}
\ No newline at end of file
+}
+#[test]
+#[cfg_attr(not(feature = "all-tests"), ignore)]
+fn bart_summarization_diverse_beam_search() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("bart-large-cnn");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up masked LM model
+    let device = Device::Cpu;
+    let summarization_config = SummarizationConfig {
+        min_length: 10,
+        max_length: 60,
+        num_beams: 4,
+        num_beam_groups: 2,
+        diversity_penalty: 1.0,
+        num_return_sequences: 2,
+        ..Default::default()
+    };
+    let mut model = SummarizationModel::new(vocab_path, merges_path, config_path, weights_path,
+                                            summarization_config, device)?;
+
+    let input = ["In findings published Tuesday in Cornell University's arXiv by a team of scientists \
+from the University of Montreal and a separate report published Wednesday in Nature Astronomy by a team \
+from University College London (UCL), the presence of water vapour was confirmed in the atmosphere of K2-18b, \
+a planet circling a star in the constellation Leo. This is the first such discovery in a planet in its star's \
+habitable zone — not too hot and not too cold for liquid water to exist."];
+
+//    Credits: WikiNews, CC BY 2.5 license (https://en.wikinews.org/wiki/Astronomers_find_water_vapour_in_atmosphere_of_exoplanet_K2-18b)
+    let output = model.summarize(&input);
+
+    assert_eq!(output.len(), 2);
+    assert_ne!(output[0], output[1]);
+    assert!(output.iter().all(|summary| summary.contains("K2-18b")));
+
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(not(feature = "all-tests"), ignore)]
+fn bart_zero_shot_classification() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("bart-large-mnli");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up model
+    let device = Device::Cpu;
+    let mut model = ZeroShotClassificationModel::new(vocab_path, merges_path, config_path, weights_path, device)?;
+
+    let input_sentence = "Who are you voting for in 2020?";
+    let input_sequence_2 = "The home team scored twice in the last minutes of the match to win the football championship.";
+    let candidate_labels = &["politics", "public health", "economics", "sports"];
+
+    let output = model.predict(&[input_sentence, input_sequence_2], candidate_labels, None, 128);
+
+    assert_eq!(output.len(), 2);
+    for sentence_labels in output.iter() {
+        assert_eq!(sentence_labels.len(), 4);
+        assert!((sentence_labels.iter().map(|label| label.score).sum::<f64>() - 1.0).abs() < 1e-4);
+        assert!(sentence_labels.iter().all(|label| label.score <= sentence_labels[0].score));
+    }
+    assert_eq!(output[0][0].text, "politics");
+    assert_eq!(output[1][0].text, "sports");
+
+    let output = model.predict_multilabel(&[input_sentence, input_sequence_2], candidate_labels, None, 128);
+
+    assert_eq!(output.len(), 2);
+//    Labels are scored independently and returned in the order of the candidate labels
+    for sentence_labels in output.iter() {
+        assert_eq!(sentence_labels.len(), 4);
+        assert_eq!(sentence_labels.iter().map(|label| label.text.as_str()).collect::<Vec<&str>>(), candidate_labels.to_vec());
+    }
+    assert!(output[0][0].score > 0.9);
+    assert!(output[0][3].score < 0.1);
+    assert!(output[1][3].score > 0.9);
+    assert!(output[1][0].score < 0.1);
+
+    Ok(())
+}
@@ -6,7 +6,9 @@ use tch::{Device, nn, Tensor, no_grad};
 use rust_tokenizers::{BertTokenizer, TruncationStrategy, Tokenizer, Vocab};
 use rust_bert::Config;
 use rust_bert::bert::{BertConfig, BertForMaskedLM, BertForSequenceClassification, BertForMultipleChoice, BertForTokenClassification, BertForQuestionAnswering};
-use rust_bert::pipelines::ner::NERModel;
+use rust_bert::pipelines::ner::{NERModel, TaggingScheme, ScoreAggregation};
+use rust_bert::pipelines::token_classification::{TokenClassificationModel, TokenClassificationConfig, LabelAggregationOption};
+use rust_bert::pipelines::common::ModelType;
 
 
 #[test]
@@ -339,4 +341,101 @@ fn bert_pre_trained_ner() -> failure::Fallible<()> {
     assert_eq!(output[3].label, "I-LOC");
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn bert_pre_trained_ner_entities() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("bert-ner");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up model
+    let device = Device::cuda_if_available();
+    let ner_model = NERModel::new(vocab_path,
+                                  config_path,
+                                  weights_path, device)?;
+
+//    Define input
+    let input = [
+        "My name is Amy. I live in New York City.",
+        "Paris is a city in France."
+    ];
+
+//    Run model
+    let output = ner_model.predict_entities(&input, TaggingScheme::IOB1, ScoreAggregation::Average);
+
+    assert_eq!(output.len(), 4);
+
+    assert_eq!(output[0].word, "Amy");
+    assert_eq!(output[0].label, "PER");
+    assert_eq!(output[0].sentence, 0);
+    assert_eq!(output[0].offset.unwrap().begin, 11);
+    assert_eq!(output[0].offset.unwrap().end, 14);
+
+    assert_eq!(output[1].word, "New York City");
+    assert_eq!(output[1].label, "LOC");
+    assert_eq!(output[1].sentence, 0);
+    assert_eq!(output[1].offset.unwrap().begin, 26);
+    assert_eq!(output[1].offset.unwrap().end, 39);
+
+    assert_eq!(output[2].word, "Paris");
+    assert_eq!(output[2].sentence, 1);
+    assert_eq!(output[3].word, "France");
+    assert_eq!(output[3].offset.unwrap().begin, 19);
+    assert_eq!(output[3].offset.unwrap().end, 25);
+
+    Ok(())
+}
+
+#[test]
+fn bert_token_classification_all_words() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("bert-ner");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up model
+    let device = Device::cuda_if_available();
+    let token_classification_config = TokenClassificationConfig {
+        model_type: ModelType::Bert,
+        lower_case: false,
+        label_aggregation_function: LabelAggregationOption::Mean,
+        ..Default::default()
+    };
+    let token_classification_model = TokenClassificationModel::new(vocab_path,
+                                                                   None,
+                                                                   config_path,
+                                                                   weights_path,
+                                                                   token_classification_config,
+                                                                   device)?;
+
+//    Define input
+    let input = [
+        "My name is Amy. I live in Amsterdam.",
+    ];
+
+//    Run model
+    let output = token_classification_model.predict(&input, true, true);
+
+    assert_eq!(output.len(), 10);
+    for (word_index, token) in output.iter().enumerate() {
+        assert_eq!(token.word_index, word_index);
+        assert_eq!(token.sentence, 0);
+    }
+    assert_eq!(output[0].text, "My");
+    assert_eq!(output[0].label, "O");
+    assert_eq!(output[3].text, "Amy");
+    assert_eq!(output[3].label, "I-PER");
+    assert_eq!(output[8].text, "Amsterdam");
+    assert_eq!(output[8].label, "I-LOC");
+    assert_eq!(output[9].text, ".");
+
+    Ok(())
+}
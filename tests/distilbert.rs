@@ -6,7 +6,12 @@ use rust_tokenizers::preprocessing::vocab::base_vocab::Vocab;
 use rust_bert::Config;
 use rust_bert::distilbert::{DistilBertConfig, DistilBertModelMaskedLM, DistilBertForQuestionAnswering, DistilBertForTokenClassification};
 use rust_bert::pipelines::sentiment::{SentimentClassifier, SentimentPolarity};
-use rust_bert::pipelines::question_answering::{QuestionAnsweringModel, QaInput};
+use rust_bert::pipelines::question_answering::{QuestionAnsweringModel, QuestionAnsweringConfig, QaInput, QaLimits};
+use rust_bert::pipelines::fill_mask::{FillMaskModel, FillMaskConfig};
+use rust_bert::pipelines::common::ModelType;
+use rust_bert::pipelines::sequence_classification::{SequenceClassificationModel, SequenceClassificationConfig, LabelThreshold};
+use std::collections::HashMap;
+use rust_bert::pipelines::embeddings::{EmbeddingsModel, EmbeddingsConfig, PoolingStrategy};
 
 extern crate failure;
 extern crate dirs;
@@ -49,6 +54,69 @@ fn distilbert_sentiment_classifier() -> failure::Fallible<()> {
 }
 
 
+#[test]
+fn distilbert_sequence_classification() -> failure::Fallible<()> {
+
+//    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("distilbert_sst2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up classifier
+    let device = Device::cuda_if_available();
+    let sequence_classification_config = SequenceClassificationConfig { model_type: ModelType::DistilBert, ..Default::default() };
+    let mut sequence_classification_model = SequenceClassificationModel::new(vocab_path,
+                                                                             None,
+                                                                             config_path,
+                                                                             weights_path,
+                                                                             sequence_classification_config,
+                                                                             device)?;
+
+//    Get labels
+    let input = [
+        "Probably my all-time favorite movie, a story of selflessness, sacrifice and dedication to a noble cause, but it's not preachy or boring.",
+        "This film tried to be too many things all at once: stinging political satire, Hollywood blockbuster, sappy romantic comedy, family values promo...",
+        "If you like original gut wrenching laughter you will like this movie. If you are young or old then you will love this movie, hell even my mom liked it.",
+    ];
+
+    let output = sequence_classification_model.predict(&input);
+
+    assert_eq!(output.len(), 3 as usize);
+    assert_eq!(output[0].text, "POSITIVE");
+    assert!((output[0].score - 0.9981).abs() < 1e-4);
+    assert_eq!(output[1].text, "NEGATIVE");
+    assert!((output[1].score - 0.9927).abs() < 1e-4);
+    assert_eq!(output[2].text, "POSITIVE");
+    assert!((output[2].score - 0.9997).abs() < 1e-4);
+
+    let output = sequence_classification_model.predict_distribution(&input);
+
+    assert_eq!(output.len(), 3 as usize);
+    assert_eq!(output[1].len(), 2 as usize);
+    assert_eq!(output[1][0].text, "NEGATIVE");
+    assert!((output[1][0].score + output[1][1].score - 1.0).abs() < 1e-4);
+
+    let output = sequence_classification_model.predict_multilabel(&input, &LabelThreshold::Global(0.5));
+
+    assert_eq!(output.len(), 3 as usize);
+    assert_eq!(output[0].len(), 1 as usize);
+    assert_eq!(output[0][0].text, "POSITIVE");
+    assert_eq!(output[1][0].text, "NEGATIVE");
+
+    let mut thresholds = HashMap::new();
+    thresholds.insert(String::from("POSITIVE"), 1.0);
+    let threshold = LabelThreshold::PerLabel { thresholds, default: 0.5 };
+    let output = sequence_classification_model.predict_multilabel(&input, &threshold);
+
+    assert!(output[0].is_empty());
+    assert_eq!(output[1][0].text, "NEGATIVE");
+
+    Ok(())
+}
+
 #[test]
 fn distilbert_masked_lm() -> failure::Fallible<()> {
 
@@ -111,6 +179,124 @@ fn distilbert_masked_lm() -> failure::Fallible<()> {
     Ok(())
 }
 
+#[test]
+fn distilbert_fill_mask() -> failure::Fallible<()> {
+
+//    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("distilbert");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up fill mask model
+    let device = Device::cuda_if_available();
+    let fill_mask_config = FillMaskConfig { model_type: ModelType::DistilBert, ..Default::default() };
+    let fill_mask_model = FillMaskModel::new(vocab_path, None, config_path, weights_path, fill_mask_config, device)?;
+
+//    Define input
+    let input = ["Looks like one [MASK] is missing", "It\'s like comparing [MASK] to apples"];
+
+//    Run model
+    let output = fill_mask_model.predict(&input, 5);
+
+    assert_eq!(output.len(), 2);
+    assert_eq!(output[0].len(), 1);
+    assert_eq!(output[0][0].position, 4);
+    assert_eq!(output[0][0].candidates.len(), 5);
+    assert_eq!(output[0][0].candidates[0].token, "person");
+    assert_eq!(output[0][0].candidates[0].sequence, "looks like one person is missing");
+    assert!(output[0][0].candidates[0].score >= output[0][0].candidates[1].score);
+    assert_eq!(output[1][0].position, 6);
+    assert_eq!(output[1][0].candidates[0].token, "pear");
+
+    Ok(())
+}
+
+#[test]
+fn distilbert_fill_mask_multiple_masks() -> failure::Fallible<()> {
+
+//    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("distilbert");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up fill mask model
+    let device = Device::cuda_if_available();
+    let fill_mask_config = FillMaskConfig { model_type: ModelType::DistilBert, ..Default::default() };
+    let fill_mask_model = FillMaskModel::new(vocab_path, None, config_path, weights_path, fill_mask_config, device)?;
+
+//    Define input
+    let input = ["Paris is the [MASK] of [MASK]."];
+
+//    Run model
+    let output = fill_mask_model.predict(&input, 3);
+
+    assert_eq!(output.len(), 1);
+    assert_eq!(output[0].len(), 2);
+    assert_eq!(output[0][0].position, 4);
+    assert_eq!(output[0][1].position, 6);
+    assert_eq!(output[0][0].candidates.len(), 3);
+    assert_eq!(output[0][1].candidates.len(), 3);
+    assert_eq!(output[0][0].candidates[0].token, "capital");
+    assert_eq!(output[0][1].candidates[0].token, "france");
+    assert_eq!(output[0][0].candidates[0].sequence, "paris is the capital of france.");
+    assert_eq!(output[0][1].candidates[0].sequence, "paris is the capital of france.");
+//    Each candidate sequence fills its own mask with the candidate and the other mask with its most likely token
+    for candidate in output[0][0].candidates.iter() {
+        assert_eq!(candidate.sequence, format!("paris is the {} of france.", candidate.token));
+    }
+    for candidate in output[0][1].candidates.iter() {
+        assert_eq!(candidate.sequence, format!("paris is the capital of {}.", candidate.token));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn distilbert_sentence_embeddings() -> failure::Fallible<()> {
+
+//    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("distilbert");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up embeddings model
+    let device = Device::cuda_if_available();
+    let embeddings_config = EmbeddingsConfig {
+        model_type: ModelType::DistilBert,
+        pooling: PoolingStrategy::Mean,
+        normalize: true,
+        layers: Some(vec!(-1, -2)),
+        ..Default::default()
+    };
+    let embeddings_model = EmbeddingsModel::new(vocab_path, None, config_path, weights_path, embeddings_config, device)?;
+
+//    Define input
+    let input = ["This is a short sentence", "This is a much longer sentence, which will be padded differently than the first one"];
+
+//    Run model
+    let output = embeddings_model.encode(&input);
+    let single_output = embeddings_model.encode(&input[..1]);
+
+    assert_eq!(output.len(), 2);
+    assert_eq!(output[0].len(), 768);
+    let norm: f32 = output[1].iter().map(|value| value * value).sum::<f32>().sqrt();
+    assert!((norm - 1.0).abs() < 1e-4);
+    for (batched_value, single_value) in output[0].iter().zip(single_output[0].iter()) {
+        assert!((batched_value - single_value).abs() < 1e-4);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn distilbert_for_question_answering() -> failure::Fallible<()> {
 
@@ -223,7 +409,7 @@ fn distilbert_question_answering() -> failure::Fallible<()> {
 
 //    Set-up masked LM model
     let device = Device::Cpu;
-    let qa_model = QuestionAnsweringModel::new(vocab_path, config_path, weights_path, device)?;
+    let qa_model = QuestionAnsweringModel::new(vocab_path, None, config_path, weights_path, Default::default(), device)?;
 
 //    Define input
     let question = String::from("Where does Amy live ?");
@@ -239,5 +425,80 @@ fn distilbert_question_answering() -> failure::Fallible<()> {
     assert!((answers[0][0].score - 0.9977).abs() < 1e-4);
     assert_eq!(answers[0][0].answer, "Amsterdam");
 
+    Ok(())
+}
+
+#[test]
+fn distilbert_question_answering_exact_span() -> failure::Fallible<()> {
+
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("distilbert-qa");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up question answering model
+    let device = Device::Cpu;
+    let qa_model = QuestionAnsweringModel::new(vocab_path, None, config_path, weights_path, Default::default(), device)?;
+
+//    Define input (the answer is followed by punctuation and preceded by repeated whitespaces)
+    let question = String::from("Where does Amy live ?");
+    let context = String::from("Amy lives in   Amsterdam, Netherlands.");
+    let qa_input = QaInput { question, context };
+
+    let answers = qa_model.predict(&vec!(qa_input), 1, 32);
+
+    assert_eq!(answers.len(), 1 as usize);
+    assert_eq!(answers[0].len(), 1 as usize);
+    assert_eq!(answers[0][0].start, 15);
+    assert_eq!(answers[0][0].end, 23);
+    assert_eq!(answers[0][0].answer, "Amsterdam");
+
+    Ok(())
+}
+
+#[test]
+fn distilbert_question_answering_limits() -> failure::Fallible<()> {
+
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("distilbert-qa");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Inconsistent limits are rejected at construction
+    let device = Device::Cpu;
+    let qa_config = QuestionAnsweringConfig { max_seq_len: 1024, ..Default::default() };
+    assert!(QuestionAnsweringModel::new(vocab_path, None, config_path, weights_path, qa_config, device).is_err());
+
+//    Set-up question answering model with short spans
+    let qa_config = QuestionAnsweringConfig { max_seq_len: 32, doc_stride: 8, max_query_length: 16, ..Default::default() };
+    let qa_model = QuestionAnsweringModel::new(vocab_path, None, config_path, weights_path, qa_config, device)?;
+
+//    Define input
+    let question = String::from("Where does Amy live ?");
+    let context = String::from("While Eric lives in The Hague and Bob lives in Rotterdam, Amy lives in Amsterdam");
+    let answers = qa_model.predict(&[QaInput { question, context }], 1, 32);
+
+    assert_eq!(answers.len(), 1 as usize);
+    assert_eq!(answers[0].len(), 1 as usize);
+    assert_eq!(answers[0][0].answer, "Amsterdam");
+
+//    Per-call overrides are validated against the model
+    let question = String::from("Where does Amy live ?");
+    let context = String::from("Amy lives in Amsterdam");
+    let limits = QaLimits { doc_stride: 0, ..Default::default() };
+    assert!(qa_model.predict_with_limits(&[QaInput { question, context }], 1, 32, &limits).is_err());
+
+    let question = String::from("Where does Amy live ?");
+    let context = String::from("Amy lives in Amsterdam");
+    let limits = QaLimits { max_answer_len: 30, ..Default::default() };
+    let answers = qa_model.predict_with_limits(&[QaInput { question, context }], 1, 32, &limits)?;
+    assert_eq!(answers[0][0].answer, "Amsterdam");
+
     Ok(())
 }
\ No newline at end of file
@@ -2,7 +2,13 @@ use std::path::PathBuf;
 use tch::{Device, nn, Tensor};
 use rust_tokenizers::{Gpt2Tokenizer, TruncationStrategy, Tokenizer};
 use rust_bert::Config;
-use rust_bert::pipelines::generation::{GPT2Generator, LanguageGenerator, GenerateConfig, LMHeadModel};
+use rust_bert::pipelines::generation::{GPT2Generator, LanguageGenerator, GenerateConfig, LMHeadModel, FinishReason,
+                                        BannedTokensProcessor, LogitBiasProcessor, FrequencyPresencePenaltyProcessor,
+                                        StoppingCriteria, StopSignal, LogitsProcessor, TypicalProcessor, MinPProcessor,
+                                        EpsilonProcessor, EtaProcessor, ContinuousBatchingScheduler};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::time::Duration;
 use rust_bert::gpt2::{Gpt2Config, GPT2LMHeadModel};
 
 #[test]
@@ -100,6 +106,638 @@ fn gpt2_generation_greedy() -> failure::Fallible<()> {
     Ok(())
 }
 
+#[test]
+fn gpt2_generation_greedy_multiple_prompts_with_padding() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("gpt2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up masked LM model
+    let device = Device::cuda_if_available();
+    let input_contexts = vec!("The cat", "The dog was running in the", "Yesterday");
+
+//    Each prompt of the (left-padded) batch is generated as if it was generated alone, the padding not being counted in the maximum length
+    for max_new_tokens in [None, Some(15)].iter() {
+        let generate_config = GenerateConfig {
+            max_length: 20,
+            max_new_tokens: *max_new_tokens,
+            do_sample: false,
+            num_beams: 1,
+            repetition_penalty: 1.1,
+            ..Default::default()
+        };
+        let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                           generate_config, device)?;
+        let output = model.generate_with_details(Some(input_contexts.clone()), None);
+
+        assert_eq!(output.len(), 3);
+        for (prompt_index, input_context) in input_contexts.iter().enumerate() {
+            let single_output = model.generate_with_details(Some(vec!(*input_context)), None);
+            assert_eq!(output[prompt_index][0].text, single_output[0][0].text);
+            assert_eq!(output[prompt_index][0].token_ids, single_output[0][0].token_ids);
+            assert_eq!(output[prompt_index][0].finish_reason, single_output[0][0].finish_reason);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_stream() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("gpt2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up masked LM model
+    let device = Device::cuda_if_available();
+    let generate_config = GenerateConfig {
+        max_length: 40,
+        do_sample: false,
+        num_beams: 1,
+        temperature: 1.1,
+        repetition_penalty: 1.1,
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                   generate_config, device)?;
+
+    let input_context = "The cat";
+    let mut streamed_text = String::from(input_context);
+    let output = model.generate_stream(Some(vec!(input_context)), None, |tokens| {
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].sequence_index, 0);
+        assert!(!tokens[0].restart);
+        streamed_text.push_str(&tokens[0].text);
+        true
+    });
+
+    assert_eq!(output.len(), 1);
+    assert_eq!(output[0], "The cat was found in a field near the town of Keflavik, about 30 miles (48 kilometers) south-east of Moscow.\n\n\n");
+    assert_eq!(streamed_text, output[0]);
+
+//    Generation stops when requested by the consumer
+    let mut num_steps = 0;
+    let output = model.generate_stream(Some(vec!(input_context)), None, |_| {
+        num_steps += 1;
+        num_steps < 3
+    });
+
+    assert_eq!(num_steps, 3);
+    assert_eq!(output[0], "The cat was found in");
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_details() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("gpt2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up masked LM model
+    let device = Device::cuda_if_available();
+    let generate_config = GenerateConfig {
+        max_length: 20,
+        do_sample: false,
+        num_beams: 1,
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                   generate_config, device)?;
+
+    let input_context = "The cat";
+    let output = model.generate_with_details(Some(vec!(input_context)), None);
+
+    assert_eq!(output.len(), 1);
+    assert_eq!(output[0].len(), 1);
+    let generated = &output[0][0];
+    assert_eq!(generated.prompt_index, 0);
+    assert_eq!(generated.text, model.generate(Some(vec!(input_context)), None)[0]);
+    assert_eq!(generated.token_ids.len(), 18);
+    assert_eq!(generated.token_logprobs.len(), generated.token_ids.len());
+    assert!(generated.token_logprobs.iter().all(|logprob| *logprob <= 0.0));
+    assert!((generated.score - generated.token_logprobs.iter().sum::<f64>()).abs() < 1e-6);
+    assert_eq!(generated.finish_reason, FinishReason::MaxLength);
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_beam_search_details() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("gpt2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up masked LM model
+    let device = Device::cuda_if_available();
+    let generate_config = GenerateConfig {
+        max_length: 20,
+        do_sample: false,
+        num_beams: 5,
+        num_return_sequences: 3,
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                   generate_config, device)?;
+
+    let input_context_1 = "The dog";
+    let input_context_2 = "The cat";
+    let output = model.generate_with_details(Some(vec!(input_context_1, input_context_2)), None);
+
+    assert_eq!(output.len(), 2);
+    for (prompt_index, prompt_outputs) in output.iter().enumerate() {
+        assert_eq!(prompt_outputs.len(), 3);
+        for generated in prompt_outputs {
+            assert_eq!(generated.prompt_index, prompt_index);
+            assert_eq!(generated.token_logprobs.len(), generated.token_ids.len());
+        }
+        assert!(prompt_outputs[0].score >= prompt_outputs[1].score);
+        assert!(prompt_outputs[1].score >= prompt_outputs[2].score);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_logits_processors() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("gpt2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up masked LM model
+    let device = Device::cuda_if_available();
+    let generate_config = GenerateConfig {
+        max_length: 10,
+        do_sample: false,
+        num_beams: 1,
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                   generate_config, device)?;
+
+    let input_context = "The cat";
+    let reference_output = model.generate_with_details(Some(vec!(input_context)), None);
+    let first_token_id = reference_output[0][0].token_ids[0];
+
+//    Banning the first generated token changes the generation
+    model.set_logits_processors(vec!(Box::new(BannedTokensProcessor::new(vec!(first_token_id)))));
+    let output = model.generate_with_details(Some(vec!(input_context)), None);
+    assert_ne!(output[0][0].token_ids[0], first_token_id);
+
+//    Processors are applied in order: the bias forces a token that is not banned
+    let forced_token_id = 3290;
+    let mut logit_bias = HashMap::new();
+    logit_bias.insert(forced_token_id, 1000.0);
+    model.add_logits_processor(Box::new(LogitBiasProcessor::new(logit_bias)));
+    let output = model.generate_with_details(Some(vec!(input_context)), None);
+    assert_eq!(output[0][0].token_ids[0], forced_token_id);
+
+//    Frequency and presence penalties with no effect leave the generation unchanged
+    model.set_logits_processors(vec!(Box::new(FrequencyPresencePenaltyProcessor::new(0.0, 0.0))));
+    let output = model.generate_with_details(Some(vec!(input_context)), None);
+    assert_eq!(output[0][0].token_ids, reference_output[0][0].token_ids);
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_constraints() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("gpt2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+    let device = Device::cuda_if_available();
+    let input_context = "The cat";
+
+//    Reference greedy generation
+    let generate_config = GenerateConfig {
+        max_length: 10,
+        do_sample: false,
+        num_beams: 1,
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                   generate_config, device)?;
+    let reference_ids = model.generate_with_details(Some(vec!(input_context)), None)[0][0].token_ids.clone();
+
+//    Bad words: the reference bigram cannot be generated
+    let generate_config = GenerateConfig {
+        max_length: 10,
+        do_sample: false,
+        num_beams: 1,
+        bad_words_ids: Some(vec!(vec!(reference_ids[0], reference_ids[1]))),
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                   generate_config, device)?;
+    let token_ids = model.generate_with_details(Some(vec!(input_context)), None)[0][0].token_ids.clone();
+    assert_eq!(token_ids[0], reference_ids[0]);
+    assert_ne!(token_ids[1], reference_ids[1]);
+
+//    Prefix allowed tokens: only the allowed tokens are generated
+    let allowed_tokens = vec!(3290, 290, 13);
+    let callback_allowed_tokens = allowed_tokens.clone();
+    let generate_config = GenerateConfig {
+        max_length: 10,
+        do_sample: false,
+        num_beams: 1,
+        no_repeat_ngram_size: 0,
+        prefix_allowed_tokens: Some(Rc::new(move |_: usize, _: &[i64]| callback_allowed_tokens.clone())),
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                   generate_config, device)?;
+    let token_ids = model.generate_with_details(Some(vec!(input_context)), None)[0][0].token_ids.clone();
+    assert_eq!(token_ids.len(), 8);
+    assert!(token_ids.iter().all(|token_id| allowed_tokens.contains(token_id)));
+
+//    Forced words: one of the alternatives must be generated by each returned sequence
+    let forced_phrases = vec!(vec!(3290), vec!(290, 3290));
+    let generate_config = GenerateConfig {
+        max_length: 20,
+        do_sample: false,
+        num_beams: 5,
+        num_return_sequences: 2,
+        force_words_ids: Some(vec!(forced_phrases)),
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                   generate_config, device)?;
+    let output = model.generate_with_details(Some(vec!(input_context, "The dog")), None);
+    assert_eq!(output.len(), 2);
+    for prompt_outputs in output.iter() {
+        assert_eq!(prompt_outputs.len(), 2);
+        for generated in prompt_outputs {
+            assert!(generated.token_ids.contains(&3290));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_grammar() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("gpt2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+    let device = Device::cuda_if_available();
+    let input_context = "The person record:";
+    let schema = r#"{
+        "type": "object",
+        "properties": {"name": {"type": "string", "maxLength": 10}, "age": {"type": "integer"}, "pet": {"enum": ["cat", "dog"]}},
+        "required": ["name", "age", "pet"]
+    }"#;
+
+//    Greedy decoding, sampling and beam search
+    for (do_sample, num_beams) in [(false, 1), (true, 1), (false, 3)].iter() {
+        let generate_config = GenerateConfig {
+            max_length: 60,
+            do_sample: *do_sample,
+            num_beams: *num_beams,
+            no_repeat_ngram_size: 0,
+            ..Default::default()
+        };
+        let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                       generate_config, device)?;
+        model.add_json_schema_constraint(schema)?;
+        let output = model.generate(Some(vec!(input_context)), None);
+
+        assert_eq!(output.len(), 1);
+        assert!(output[0].starts_with(input_context));
+        let record: serde_json::Value = serde_json::from_str(&output[0][input_context.len()..])?;
+        assert!(record["name"].is_string());
+        assert!(record["age"].is_i64());
+        assert!((record["pet"] == "cat") | (record["pet"] == "dog"));
+    }
+
+    let generate_config = GenerateConfig {
+        max_length: 30,
+        do_sample: false,
+        num_beams: 1,
+        no_repeat_ngram_size: 0,
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                   generate_config, device)?;
+    model.add_regex_constraint(r" (yes|no), [0-9]{1,3} (cats|dogs)\.")?;
+    let output = model.generate(Some(vec!("Do you have pets? Answer:")), None);
+    let answer = &output[0]["Do you have pets? Answer:".len()..];
+    assert!(answer.starts_with(" yes, ") | answer.starts_with(" no, "));
+    assert!(answer.ends_with(" cats.") | answer.ends_with(" dogs."));
+
+//    Generation stopped by the maximum length before the document is complete
+    let generate_config = GenerateConfig {
+        max_length: 10,
+        do_sample: false,
+        num_beams: 1,
+        no_repeat_ngram_size: 0,
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                   generate_config, device)?;
+    model.add_json_schema_constraint(schema)?;
+    let output = model.generate_with_details(Some(vec!(input_context)), None);
+    assert_eq!(output[0][0].finish_reason, FinishReason::Incomplete);
+    assert!(serde_json::from_str::<serde_json::Value>(&output[0][0].text[input_context.len()..]).is_err());
+
+    assert!(model.add_regex_constraint("(unbalanced").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_seeded_sampling() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("gpt2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+    let device = Device::Cpu;
+    let input_context = "The cat";
+    let second_input_context = "The dog was";
+
+//    Sampling with and without beam search, seeded from the configuration
+    for num_beams in [1, 3].iter() {
+        let generate_config = || GenerateConfig {
+            max_length: 20,
+            do_sample: true,
+            num_beams: *num_beams,
+            num_return_sequences: 2,
+            seed: Some(42),
+            ..Default::default()
+        };
+        let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                           generate_config(), device)?;
+        let mut other_model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                                 generate_config(), device)?;
+        let output = model.generate(Some(vec!(input_context, second_input_context)), None);
+        tch::manual_seed(7);
+        let _ = tch::Tensor::rand(&[16], (tch::Kind::Float, device));
+        let other_output = other_model.generate(Some(vec!(input_context, second_input_context)), None);
+        let repeated_output = model.generate(Some(vec!(input_context, second_input_context)), None);
+
+        assert_eq!(output.len(), 4);
+        assert_eq!(output, other_output);
+        assert_eq!(output, repeated_output);
+    }
+
+//    Per-call seed overriding the configuration
+    let generate_config = GenerateConfig {
+        max_length: 20,
+        do_sample: true,
+        num_beams: 1,
+        num_return_sequences: 3,
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                       generate_config, device)?;
+    let output = model.generate_with_seed(Some(vec!(input_context)), None, 1234);
+    let _ = model.generate(Some(vec!(input_context)), None);
+    let repeated_output = model.generate_with_seed(Some(vec!(input_context)), None, 1234);
+    let other_output = model.generate_with_seed(Some(vec!(input_context)), None, 4321);
+
+    assert_eq!(output, repeated_output);
+    assert_ne!(output, other_output);
+
+    Ok(())
+}
+
+struct StopAfterTokenCriteria {
+    token_id: i64,
+}
+
+impl StoppingCriteria for StopAfterTokenCriteria {
+    fn check(&self, generated_ids: &[i64], _generated_text: &str, _elapsed: Duration) -> Option<StopSignal> {
+        if generated_ids.last() == Some(&self.token_id) {
+            Some(StopSignal { finish_reason: FinishReason::StopSequence, text_length: None })
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn gpt2_generation_stopping_criteria() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("gpt2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+    let device = Device::cuda_if_available();
+    let input_context = "The cat";
+    let second_input_context = "The dog was found in the garden of a house in";
+
+//    Stop sequences and maximum number of new tokens, checked independently for each sequence, with greedy decoding and beam search
+    for num_beams in [1, 3].iter() {
+        let generate_config = GenerateConfig {
+            max_length: 60,
+            do_sample: false,
+            num_beams: *num_beams,
+            max_new_tokens: Some(25),
+            stop_sequences: Some(vec!(".".to_string())),
+            ..Default::default()
+        };
+        let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                           generate_config, device)?;
+        let output = model.generate_with_details(Some(vec!(input_context, second_input_context)), None);
+
+        assert_eq!(output.len(), 2);
+        for (prompt_outputs, prompt) in output.iter().zip([input_context, second_input_context].iter()) {
+            let generated = &prompt_outputs[0];
+            assert!(generated.text.starts_with(prompt));
+            assert!(!generated.text[prompt.len()..].contains('.'));
+            assert!(generated.token_ids.len() <= 25);
+            match generated.finish_reason {
+                FinishReason::StopSequence => assert!(!generated.token_ids.is_empty()),
+                FinishReason::MaxLength => assert_eq!(generated.token_ids.len(), 25),
+                FinishReason::Eos => {}
+                FinishReason::TimeLimit => panic!("unexpected time limit"),
+                FinishReason::Incomplete => panic!("unexpected incomplete sequence"),
+            }
+        }
+        assert_eq!(model.generate(Some(vec!(input_context, second_input_context)), None),
+                   output.iter().map(|prompt_outputs| prompt_outputs[0].text.clone()).collect::<Vec<String>>());
+    }
+
+//    Exhausted time budget: generation stops after the first token
+    let generate_config = GenerateConfig {
+        max_length: 30,
+        do_sample: false,
+        num_beams: 1,
+        max_time: Some(Duration::from_secs(0)),
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                       generate_config, device)?;
+    let output = model.generate_with_details(Some(vec!(input_context, second_input_context)), None);
+
+    assert!(output.iter().all(|prompt_outputs| prompt_outputs[0].finish_reason == FinishReason::TimeLimit));
+    assert!(output.iter().all(|prompt_outputs| prompt_outputs[0].token_ids.len() == 1));
+
+//    Custom stopping criteria attached to the generator
+    let generate_config = GenerateConfig {
+        max_length: 60,
+        do_sample: false,
+        num_beams: 1,
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                       generate_config, device)?;
+    let period_token_id = 13;
+    model.add_stopping_criteria(Box::new(StopAfterTokenCriteria { token_id: period_token_id }));
+    let output = model.generate_with_details(Some(vec!(input_context)), None);
+    let generated = &output[0][0];
+
+    assert_eq!(generated.finish_reason, FinishReason::StopSequence);
+    assert_eq!(generated.token_ids.last(), Some(&period_token_id));
+    assert!(generated.text.ends_with('.'));
+
+//    Custom stopping criteria trimming the output in the middle of a generated token
+    let generate_config = GenerateConfig {
+        max_length: 60,
+        do_sample: false,
+        num_beams: 1,
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                       generate_config, device)?;
+    model.add_stopping_criteria(Box::new(TrimmingCriteria { num_tokens: 4, text_length: 7 }));
+    let output = model.generate_with_details(Some(vec!(input_context)), None);
+    let generated = &output[0][0];
+    let tokenizer = Gpt2Tokenizer::from_file(vocab_path.to_str().unwrap(), merges_path.to_str().unwrap(), false);
+    let generated_text = tokenizer.decode(generated.token_ids.clone(), true, true);
+
+    assert_eq!(generated.finish_reason, FinishReason::StopSequence);
+    assert_eq!(generated.token_ids.len(), 4);
+    assert!(generated_text.len() > 7);
+    assert_eq!(generated.text, format!("{}{}", input_context, &generated_text[..7]));
+
+    Ok(())
+}
+
+struct TrimmingCriteria {
+    num_tokens: usize,
+    text_length: usize,
+}
+
+impl StoppingCriteria for TrimmingCriteria {
+    fn check(&self, generated_ids: &[i64], _generated_text: &str, _elapsed: Duration) -> Option<StopSignal> {
+        if generated_ids.len() >= self.num_tokens {
+            Some(StopSignal { finish_reason: FinishReason::StopSequence, text_length: Some(self.text_length) })
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn gpt2_generation_truncation_sampling() -> failure::Fallible<()> {
+//    Filtering of a known distribution
+    let probabilities = [0.5f64, 0.3, 0.15, 0.05];
+    let log_probabilities = probabilities.iter().map(|probability| probability.ln()).collect::<Vec<f64>>();
+    let input_ids = Tensor::of_slice(&[0i64]).view((1, 1));
+    let processors: Vec<Box<dyn LogitsProcessor>> = vec!(
+        Box::new(TypicalProcessor::new(0.5, 1)),
+        Box::new(MinPProcessor::new(0.2, 1)),
+        Box::new(EpsilonProcessor::new(0.2, 1)),
+        Box::new(EtaProcessor::new(0.1, 1)),
+        Box::new(EpsilonProcessor::new(0.9, 2)),
+    );
+    let expected_kept_tokens = [
+        [true, true, false, false],
+        [true, true, true, false],
+        [true, true, false, false],
+        [true, true, true, false],
+        [true, true, false, false],
+    ];
+    for (processor, expected_kept_tokens) in processors.iter().zip(expected_kept_tokens.iter()) {
+        let mut scores = Tensor::of_slice(&log_probabilities).to_kind(tch::Kind::Float).view((1, 4));
+        processor.process(&mut scores, &input_ids, 1);
+        for (token_index, expected_kept) in expected_kept_tokens.iter().enumerate() {
+            assert_eq!(scores.double_value(&[0, token_index as i64]) > std::f64::NEG_INFINITY, *expected_kept);
+        }
+    }
+
+//    Open-ended generation combined with temperature and repetition penalty
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("gpt2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+    let device = Device::Cpu;
+    let input_context = "The dog";
+
+    let truncation_configs = [(0.9, 0.0, 0.0, 0.0), (1.0, 0.1, 0.0, 0.0), (1.0, 0.0, 3e-4, 0.0), (1.0, 0.0, 0.0, 3e-4)];
+    for (typical_p, min_p, epsilon_cutoff, eta_cutoff) in truncation_configs.iter() {
+        let generate_config = GenerateConfig {
+            max_length: 20,
+            do_sample: true,
+            num_beams: 1,
+            temperature: 1.2,
+            repetition_penalty: 1.2,
+            top_p: 1.0,
+            typical_p: *typical_p,
+            min_p: *min_p,
+            epsilon_cutoff: *epsilon_cutoff,
+            eta_cutoff: *eta_cutoff,
+            num_return_sequences: 2,
+            seed: Some(42),
+            ..Default::default()
+        };
+        let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                           generate_config, device)?;
+        let output = model.generate(Some(vec!(input_context)), None);
+
+        assert_eq!(output.len(), 2);
+        assert!(output.iter().all(|text| text.starts_with(input_context) & (text.len() > input_context.len())));
+        assert_eq!(output, model.generate(Some(vec!(input_context)), None));
+    }
+
+    Ok(())
+}
+
 #[test]
 fn gpt2_generation_beam_search() -> failure::Fallible<()> {
     //    Resources paths
@@ -135,6 +773,316 @@ fn gpt2_generation_beam_search() -> failure::Fallible<()> {
     Ok(())
 }
 
+#[test]
+fn gpt2_generation_diverse_beam_search() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("gpt2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+    let device = Device::cuda_if_available();
+    let input_context = "The dog";
+    let second_input_context = "The cat was";
+
+    let generate_config = GenerateConfig {
+        max_length: 20,
+        do_sample: false,
+        num_beams: 6,
+        num_beam_groups: 3,
+        diversity_penalty: 2.0,
+        num_return_sequences: 3,
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                       generate_config, device)?;
+    let output = model.generate_with_details(Some(vec!(input_context, second_input_context)), None);
+
+    assert_eq!(output.len(), 2);
+    for prompt_outputs in output.iter() {
+        assert_eq!(prompt_outputs.len(), 3);
+//        Sequences are sorted by decreasing score and the diversity penalty leads to different continuations
+        assert!(prompt_outputs.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        let texts = prompt_outputs.iter().map(|generated| generated.text.as_str()).collect::<HashSet<&str>>();
+        assert_eq!(texts.len(), 3);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_diverse_beam_search_logprobs() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("gpt2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+    let device = Device::Cpu;
+    let input_context = "The dog";
+
+//    Reference model scoring the generated sequences
+    let tokenizer = Gpt2Tokenizer::from_file(vocab_path.to_str().unwrap(), merges_path.to_str().unwrap(), false);
+    let mut vs = nn::VarStore::new(device);
+    let config = Gpt2Config::from_file(config_path);
+    let mut gpt2_model = GPT2LMHeadModel::new(&vs.root(), &config);
+    vs.load(weights_path)?;
+    let prompt_ids = tokenizer.convert_tokens_to_ids(&tokenizer.tokenize(input_context));
+
+    let mut outputs = vec!();
+    for diversity_penalty in [0.0, 2.0].iter() {
+        let generate_config = GenerateConfig {
+            max_length: 20,
+            do_sample: false,
+            num_beams: 4,
+            num_beam_groups: 2,
+            diversity_penalty: *diversity_penalty,
+            num_return_sequences: 4,
+            no_repeat_ngram_size: 0,
+            ..Default::default()
+        };
+        let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                           generate_config, device)?;
+        let output = model.generate_with_details(Some(vec!(input_context)), None).remove(0);
+
+//        The log-probabilities are the model log-probabilities of the generated tokens, without the diversity penalty
+        for generated in output.iter() {
+            assert_eq!(generated.token_logprobs.len(), generated.token_ids.len());
+            let mut sequence_ids = prompt_ids.clone();
+            sequence_ids.extend_from_slice(&generated.token_ids[..generated.token_ids.len() - 1]);
+            let (logits, _, _, _, _) = gpt2_model.forward_t(&Some(Tensor::of_slice(&sequence_ids).unsqueeze(0)),
+                                                            &None, &None, &None, &None, &None, None, &None, false).unwrap();
+            let log_probabilities = logits.get(0).log_softmax(-1, tch::Kind::Float);
+            for (position, (token_id, token_logprob)) in generated.token_ids.iter().zip(generated.token_logprobs.iter()).enumerate() {
+                let expected_logprob = log_probabilities.double_value(&[(prompt_ids.len() + position - 1) as i64, *token_id]);
+                assert!((token_logprob - expected_logprob).abs() < 1e-3);
+            }
+        }
+        outputs.push(output);
+    }
+
+//    Sequences generated with and without the penalty have the same log-probabilities
+    let mut num_common_sequences = 0;
+    for penalized in outputs[1].iter() {
+        if let Some(unpenalized) = outputs[0].iter().find(|generated| generated.token_ids == penalized.token_ids) {
+            num_common_sequences += 1;
+            for (penalized_logprob, unpenalized_logprob) in penalized.token_logprobs.iter().zip(unpenalized.token_logprobs.iter()) {
+                assert!((penalized_logprob - unpenalized_logprob).abs() < 1e-6);
+            }
+        }
+    }
+//    The first group is not penalized
+    assert!(num_common_sequences >= 1);
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_speculative() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    let config_path = &home.as_path().join("gpt2").join("config.json");
+    let vocab_path = &home.as_path().join("gpt2").join("vocab.txt");
+    let merges_path = &home.as_path().join("gpt2").join("merges.txt");
+    let weights_path = &home.as_path().join("gpt2").join("model.ot");
+    let draft_config_path = &home.as_path().join("distilgpt2").join("config.json");
+    let draft_weights_path = &home.as_path().join("distilgpt2").join("model.ot");
+
+//    Set-up the DistilGPT2 draft model
+    let device = Device::Cpu;
+    let mut draft_vs = nn::VarStore::new(device);
+    let draft_config = Gpt2Config::from_file(draft_config_path);
+    let mut draft_model = GPT2LMHeadModel::new(&draft_vs.root(), &draft_config);
+    draft_vs.load(draft_weights_path)?;
+    let input_context = "The cat";
+    let second_input_context = "The dog was";
+
+//    Greedy speculative decoding returns the same sequences as greedy decoding with the model alone
+    let generate_config = GenerateConfig {
+        max_length: 40,
+        do_sample: false,
+        num_beams: 1,
+        repetition_penalty: 1.1,
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                       generate_config, device)?;
+    let output = model.generate_speculative(&mut draft_model, Some(vec!(input_context, second_input_context)), 4);
+    let expected_output = vec!(model.generate(Some(vec!(input_context)), None).remove(0),
+                               model.generate(Some(vec!(second_input_context)), None).remove(0));
+
+    assert_eq!(output.texts, expected_output);
+    assert!(output.num_draft_tokens > 0);
+    assert!(output.num_accepted_tokens <= output.num_draft_tokens);
+    assert!((output.acceptance_rate >= 0f64) & (output.acceptance_rate <= 1f64));
+
+//    Seeded speculative sampling is reproducible
+    let generate_config = GenerateConfig {
+        max_length: 30,
+        do_sample: true,
+        num_beams: 1,
+        seed: Some(42),
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                       generate_config, device)?;
+    let output = model.generate_speculative(&mut draft_model, Some(vec!(input_context)), 3);
+    let repeated_output = model.generate_speculative(&mut draft_model, Some(vec!(input_context)), 3);
+
+    assert_eq!(output.texts.len(), 1);
+    assert_eq!(output.texts, repeated_output.texts);
+    assert_eq!(output.acceptance_rate, repeated_output.acceptance_rate);
+    assert!((output.acceptance_rate >= 0f64) & (output.acceptance_rate <= 1f64));
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_continuous_batching() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("gpt2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up the scheduler, running at most 2 requests concurrently
+    let device = Device::Cpu;
+    let generate_config = |max_length: u64| GenerateConfig {
+        max_length,
+        do_sample: false,
+        num_beams: 1,
+        repetition_penalty: 1.1,
+        ..Default::default()
+    };
+    let generator = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                       GenerateConfig::default(), device)?;
+    let mut scheduler = ContinuousBatchingScheduler::new(generator, 2);
+
+//    The last request has a prompt filling its maximum length and completes without generating any token
+    let requests = [("The cat", 20), ("The dog was running in the", 30), ("Yesterday", 25), ("It was", 15), ("The dog was running in the", 3)];
+    let mut request_ids = vec!();
+    for (prompt, max_length) in requests[..3].iter() {
+        request_ids.push(scheduler.submit(prompt, generate_config(*max_length)));
+    }
+    assert_eq!(scheduler.num_waiting(), 3);
+    let mut completed_requests = scheduler.step();
+    assert_eq!(scheduler.num_running() + completed_requests.len(), 2);
+    assert_eq!(scheduler.num_waiting(), 1);
+
+//    Requests submitted while the batch is running
+    for (prompt, max_length) in requests[3..].iter() {
+        request_ids.push(scheduler.submit(prompt, generate_config(*max_length)));
+    }
+    completed_requests.extend(scheduler.run());
+    assert!(scheduler.is_idle());
+    assert_eq!(completed_requests.len(), 5);
+
+//    Each request generates the same sequence as when generated alone
+    for ((prompt, max_length), request_id) in requests.iter().zip(request_ids.iter()) {
+        let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                           generate_config(*max_length), device)?;
+        let expected_output = model.generate_with_details(Some(vec!(*prompt)), None).remove(0).remove(0);
+        let output = &completed_requests
+            .iter()
+            .find(|completed_request| completed_request.request_id == *request_id)
+            .unwrap()
+            .output;
+        assert_eq!(output.text, expected_output.text);
+        assert_eq!(output.token_ids, expected_output.token_ids);
+        assert_eq!(output.finish_reason, expected_output.finish_reason);
+    }
+    let last_output = &completed_requests.iter().find(|completed_request| completed_request.request_id == request_ids[4]).unwrap().output;
+    assert!(last_output.token_ids.is_empty());
+    assert_eq!(last_output.finish_reason, FinishReason::MaxLength);
+
+    Ok(())
+}
+
+#[test]
+fn gpt2_generation_prefix_cache() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("gpt2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+    let device = Device::cuda_if_available();
+    let prefix_text = "The following story was published in the local newspaper this morning.";
+    let suffixes = vec!(" The cat", " The dog was running in the");
+
+//    Generation from the cached prefix matches the generation from the full prompts, for greedy decoding and beam search.
+//    The maximum length is measured from the end of the prefix.
+    for num_beams in [1, 3].iter() {
+        let generate_config = |max_length: u64| GenerateConfig {
+            max_length,
+            do_sample: false,
+            num_beams: *num_beams,
+            num_return_sequences: *num_beams,
+            ..Default::default()
+        };
+        let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                           generate_config(20), device)?;
+
+        let prefix = model.cache_prefix(prefix_text);
+        let output = model.generate_with_prefix(&prefix, Some(suffixes.clone()), None);
+        let full_prompts = suffixes.iter().map(|suffix| format!("{}{}", prefix_text, suffix)).collect::<Vec<String>>();
+        let mut reference_model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                                     generate_config(20 + prefix.len() as u64), device)?;
+        let expected_output = reference_model.generate(Some(full_prompts.iter().map(|prompt| prompt.as_str()).collect()), None);
+
+        assert_eq!(output.len(), 2 * *num_beams as usize);
+        assert_eq!(output, expected_output);
+
+//        The prefix cache can be reused across generate calls, and continued without prompt
+        let repeated_output = model.generate_with_prefix(&prefix, Some(suffixes.clone()), None);
+        let continued_output = model.generate_with_prefix(&prefix, None, None);
+        let expected_continued_output = reference_model.generate(Some(vec!(prefix_text)), None);
+
+        assert_eq!(output, repeated_output);
+        assert_eq!(continued_output, expected_continued_output);
+    }
+
+//    A prefix longer than the maximum length is followed by up to `max_length` prompt and generated tokens
+    let generate_config = GenerateConfig {
+        max_length: 8,
+        do_sample: false,
+        num_beams: 1,
+        ..Default::default()
+    };
+    let mut model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                       generate_config, device)?;
+    let prefix = model.cache_prefix(prefix_text);
+    assert!(prefix.len() > 8);
+
+    let output = model.generate_with_prefix(&prefix, Some(vec!(suffixes[0])), None);
+    let full_prompt = format!("{}{}", prefix_text, suffixes[0]);
+    assert!(output[0].starts_with(&full_prompt));
+    assert!(output[0].len() > full_prompt.len());
+
+    let generate_config = GenerateConfig {
+        max_length: 8 + prefix.len() as u64,
+        do_sample: false,
+        num_beams: 1,
+        ..Default::default()
+    };
+    let mut reference_model = GPT2Generator::new(vocab_path, merges_path, config_path, weights_path,
+                                                 generate_config, device)?;
+    assert_eq!(output, reference_model.generate(Some(vec!(full_prompt.as_str())), None));
+
+    Ok(())
+}
+
 #[test]
 fn gpt2_generation_beam_search_multiple_prompts_without_padding() -> failure::Fallible<()> {
     //    Resources paths
@@ -42,7 +42,7 @@ fn openai_gpt_lm_model() -> failure::Fallible<()> {
     let input_tensor = Tensor::stack(tokenized_input.as_slice(), 0).to(device);
 
 //    Forward pass
-    let (output,_,  _, _, _) = openai_gpt.forward_t(
+    let (output, _, past, _, _) = openai_gpt.forward_t(
         &Some(input_tensor),
         &None,
         &None,
@@ -57,6 +57,9 @@ fn openai_gpt_lm_model() -> failure::Fallible<()> {
     let next_word = tokenizer.decode(vec!(next_word_id), true, true);
 
     assert_eq!(output.size(), vec!(1, 6, 40478));
+    assert!(past.is_some());
+    assert_eq!(past.as_ref().unwrap().len(), config.n_layer as usize);
+    assert_eq!(past.as_ref().unwrap()[0].size(), vec!(2, 1, config.n_head, 6, 64));
     assert!((output.double_value(&[0, output.size()[1] - 1, next_word_id]) - (9.1056)).abs() < 1e-4);
     assert_eq!(next_word_id, 580i64);
     assert_eq!(next_word, String::from("be"));
@@ -64,6 +67,71 @@ fn openai_gpt_lm_model() -> failure::Fallible<()> {
     Ok(())
 }
 
+#[test]
+fn openai_gpt_lm_model_incremental_past() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("openai-gpt");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up masked LM model
+    let device = Device::Cpu;
+    let mut vs = nn::VarStore::new(device);
+    let tokenizer = OpenAiGptTokenizer::from_file(vocab_path.to_str().unwrap(), merges_path.to_str().unwrap(), true);
+    let config = Gpt2Config::from_file(config_path);
+    let mut openai_gpt = OpenAIGPTLMHeadModel::new(&vs.root(), &config);
+    vs.load(weights_path)?;
+
+//    Define input
+    let input = ["Wondering what the next word will"];
+    let tokenized_input = tokenizer.encode_list(input.to_vec(), 128, &TruncationStrategy::LongestFirst, 0);
+    let input_tensor = Tensor::of_slice(&tokenized_input[0].token_ids).unsqueeze(0).to(device);
+    let sequence_length = input_tensor.size()[1];
+
+//    Forward pass on the full sequence, and on the last token re-using the cache of the previous tokens
+    let (full_output, _, _, _, _) = openai_gpt.forward_t(
+        &Some(input_tensor.copy()),
+        &None,
+        &None,
+        &None,
+        &None,
+        &None,
+        None,
+        &None,
+        false).unwrap();
+    let (_, _, past, _, _) = openai_gpt.forward_t(
+        &Some(input_tensor.narrow(1, 0, sequence_length - 1)),
+        &None,
+        &None,
+        &None,
+        &None,
+        &None,
+        None,
+        &None,
+        false).unwrap();
+    let (incremental_output, _, past, _, _) = openai_gpt.forward_t(
+        &Some(input_tensor.narrow(1, sequence_length - 1, 1)),
+        &past,
+        &None,
+        &None,
+        &None,
+        &None,
+        None,
+        &None,
+        false).unwrap();
+
+    assert_eq!(incremental_output.size(), vec!(1, 1, 40478));
+    assert_eq!(past.as_ref().unwrap()[0].size(), vec!(2, 1, config.n_head, sequence_length, 64));
+    let max_difference = (full_output.get(0).get(-1) - incremental_output.get(0).get(0)).abs().max().double_value(&[]);
+    assert!(max_difference < 1e-4);
+
+    Ok(())
+}
+
 #[test]
 fn openai_gpt_generation_greedy() -> failure::Fallible<()> {
     //    Resources paths
@@ -98,6 +166,46 @@ fn openai_gpt_generation_greedy() -> failure::Fallible<()> {
     Ok(())
 }
 
+#[test]
+fn openai_gpt_generation_greedy_multiple_prompts_with_padding() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("openai-gpt");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.txt");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up masked LM model
+    let device = Device::cuda_if_available();
+    let input_contexts = vec!("The dog is", "It was an intense machine dialogue. ", "The cat was in");
+
+//    Each prompt of the (left-padded) batch is generated as if it was generated alone, the padding not being counted in the maximum length
+    for max_new_tokens in [None, Some(15)].iter() {
+        let generate_config = GenerateConfig {
+            max_length: 20,
+            max_new_tokens: *max_new_tokens,
+            do_sample: false,
+            num_beams: 1,
+            no_repeat_ngram_size: 1,
+            ..Default::default()
+        };
+        let mut model = OpenAIGenerator::new(vocab_path, merges_path, config_path, weights_path,
+                                             generate_config, device)?;
+        let output = model.generate_with_details(Some(input_contexts.clone()), None);
+
+        assert_eq!(output.len(), 3);
+        for (prompt_index, input_context) in input_contexts.iter().enumerate() {
+            let single_output = model.generate_with_details(Some(vec!(*input_context)), None);
+            assert_eq!(output[prompt_index][0].token_ids, single_output[0][0].token_ids);
+            assert_eq!(output[prompt_index][0].finish_reason, single_output[0][0].finish_reason);
+        }
+    }
+
+    Ok(())
+}
+
 #[test]
 fn openai_gpt_generation_beam_search() -> failure::Fallible<()> {
     //    Resources paths
@@ -4,6 +4,8 @@ use rust_tokenizers::{RobertaTokenizer, TruncationStrategy, Tokenizer, Vocab};
 use rust_bert::Config;
 use rust_bert::bert::BertConfig;
 use rust_bert::roberta::{RobertaForMaskedLM, RobertaForSequenceClassification, RobertaForMultipleChoice, RobertaForTokenClassification, RobertaForQuestionAnswering};
+use rust_bert::pipelines::question_answering::{QuestionAnsweringModel, QuestionAnsweringConfig, QaInput};
+use rust_bert::pipelines::common::ModelType;
 
 #[test]
 fn roberta_masked_lm() -> failure::Fallible<()> {
@@ -294,4 +296,40 @@ fn roberta_for_question_answering() -> failure::Fallible<()> {
     assert_eq!(config.num_hidden_layers as usize, all_attentions.unwrap().len());
 
     Ok(())
-}
\ No newline at end of file
+}
+#[test]
+fn roberta_question_answering_impossible_answer() -> failure::Fallible<()> {
+    //    Resources paths
+    let mut home: PathBuf = dirs::home_dir().unwrap();
+    home.push("rustbert");
+    home.push("roberta-squad2");
+    let config_path = &home.as_path().join("config.json");
+    let vocab_path = &home.as_path().join("vocab.json");
+    let merges_path = &home.as_path().join("merges.txt");
+    let weights_path = &home.as_path().join("model.ot");
+
+//    Set-up question answering model
+    let device = Device::Cpu;
+    let qa_config = QuestionAnsweringConfig {
+        model_type: ModelType::Roberta,
+        handle_impossible_answer: true,
+        ..Default::default()
+    };
+    let qa_model = QuestionAnsweringModel::new(vocab_path, Some(merges_path), config_path, weights_path, qa_config, device)?;
+
+//    Define input
+    let answerable_input = QaInput { question: String::from("Where does Amy live ?"), context: String::from("Amy lives in Amsterdam") };
+    let unanswerable_input = QaInput { question: String::from("Where does Eric live ?"), context: String::from("Amy lives in Amsterdam") };
+
+    let answers = qa_model.predict(&[answerable_input, unanswerable_input], 1, 32);
+
+    assert_eq!(answers.len(), 2 as usize);
+    assert_eq!(answers[0].len(), 1 as usize);
+    assert_eq!(answers[0][0].answer, "Amsterdam");
+    assert_eq!(answers[1].len(), 1 as usize);
+    assert_eq!(answers[1][0].answer, "");
+    assert_eq!(answers[1][0].start, 0);
+    assert_eq!(answers[1][0].end, 0);
+
+    Ok(())
+}
@@ -0,0 +1,47 @@
+from transformers.file_utils import get_from_cache, hf_bucket_url
+from pathlib import Path
+import shutil
+import os
+import numpy as np
+import torch
+import subprocess
+
+model_name = 'deepset/roberta-base-squad2'
+config_path = hf_bucket_url(model_name, 'config.json')
+vocab_path = hf_bucket_url(model_name, 'vocab.json')
+merges_path = hf_bucket_url(model_name, 'merges.txt')
+weights_path = hf_bucket_url(model_name, 'pytorch_model.bin')
+
+target_path = Path.home() / 'rustbert' / 'roberta-squad2'
+
+temp_config = get_from_cache(config_path)
+temp_vocab = get_from_cache(vocab_path)
+temp_merges = get_from_cache(merges_path)
+temp_weights = get_from_cache(weights_path)
+
+os.makedirs(str(target_path), exist_ok=True)
+
+config_path = str(target_path / 'config.json')
+vocab_path = str(target_path / 'vocab.json')
+merges_path = str(target_path / 'merges.txt')
+model_path = str(target_path / 'model.bin')
+
+shutil.copy(temp_config, config_path)
+shutil.copy(temp_vocab, vocab_path)
+shutil.copy(temp_merges, merges_path)
+shutil.copy(temp_weights, model_path)
+
+weights = torch.load(temp_weights, map_location='cpu')
+nps = {}
+for k, v in weights.items():
+    nps[k] = np.ascontiguousarray(v.cpu().numpy())
+
+np.savez(target_path / 'model.npz', **nps)
+
+source = str(target_path / 'model.npz')
+target = str(target_path / 'model.ot')
+
+toml_location = (Path(__file__).resolve() / '..' / '..' / 'Cargo.toml').resolve()
+
+subprocess.call(
+    ['cargo', 'run', '--bin=convert-tensor', '--manifest-path=%s' % toml_location, '--', source, target])